@@ -0,0 +1,376 @@
+use crate::config::{Config, Rules};
+use crate::engine::ParallelEngine;
+use crate::ports::fs::RealFs;
+use crate::walker::FileWalker;
+use colored::*;
+use eyre::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Files per generated directory; deeper trees exercise the walker without changing the totals.
+const FILES_PER_DIR: usize = 100;
+
+const WORDS: &[&str] = &[
+    "let", "value", "return", "config", "match", "self", "path", "result", "error", "file", "line", "the", "and",
+    "process", "=", "{", "}", "//", "fn", "struct",
+];
+
+/// Shape of the synthetic tree and which measurements to take.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchOptions {
+    /// Number of files to generate
+    pub files: usize,
+    /// Smallest file size in bytes
+    pub min_size: usize,
+    /// Largest file size in bytes; sizes are spread log-uniformly, so small files dominate
+    pub max_size: usize,
+    /// Percentage of files with trailing whitespace
+    pub dirty_percent: u8,
+    /// Thread counts to compare (empty = 1, 2, 4, ... up to the number of CPUs)
+    pub thread_counts: Vec<usize>,
+    /// Times each measurement is repeated; the fastest is reported
+    pub runs: usize,
+    /// Seed for the generator, so trees can be reproduced
+    pub seed: u64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            files: 2000,
+            min_size: 256,
+            max_size: 64 * 1024,
+            dirty_percent: 20,
+            thread_counts: Vec::new(),
+            runs: 3,
+            seed: 0,
+        }
+    }
+}
+
+impl BenchOptions {
+    fn validate(&self) -> Result<()> {
+        if self.files == 0 {
+            eyre::bail!("Cannot benchmark an empty tree: --files must be at least 1");
+        }
+        if self.min_size > self.max_size {
+            eyre::bail!(
+                "--min-size ({}) is larger than --max-size ({})",
+                self.min_size,
+                self.max_size
+            );
+        }
+        if self.runs == 0 {
+            eyre::bail!("--runs must be at least 1");
+        }
+        if self.thread_counts.contains(&0) {
+            eyre::bail!("Thread counts must be at least 1");
+        }
+        Ok(())
+    }
+
+    /// The requested thread counts, or powers of two up to the number of CPUs.
+    fn thread_counts(&self) -> Vec<usize> {
+        if !self.thread_counts.is_empty() {
+            return self.thread_counts.clone();
+        }
+        let cpus = num_cpus::get();
+        let mut counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+            .take_while(|&n| n < cpus)
+            .collect();
+        counts.push(cpus);
+        counts
+    }
+}
+
+/// What [`generate_tree`] wrote.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TreeSummary {
+    pub files: usize,
+    pub dirty_files: usize,
+    pub bytes: u64,
+}
+
+/// Processing time with one thread count.
+#[derive(Debug)]
+pub struct ThreadResult {
+    pub threads: usize,
+    pub elapsed: Duration,
+}
+
+/// Everything a benchmark measured.
+#[derive(Debug)]
+pub struct BenchReport {
+    pub tree: TreeSummary,
+    pub walk: Duration,
+    /// Files found by the walk and then processed
+    pub files_walked: usize,
+    pub processing: Vec<ThreadResult>,
+}
+
+/// Small deterministic generator (SplitMix64); benchmarks only need repeatable noise.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Write a tree of text files under `root` shaped by `options`.
+pub fn generate_tree(root: &Path, options: &BenchOptions) -> Result<TreeSummary> {
+    let mut rng = Rng(options.seed);
+    let mut summary = TreeSummary::default();
+    let (min, max) = (options.min_size.max(1) as f64, options.max_size.max(1) as f64);
+
+    for index in 0..options.files {
+        let dir = root.join(format!("d{:03}", index / FILES_PER_DIR));
+        if index % FILES_PER_DIR == 0 {
+            fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let size = (min.ln() + rng.unit() * (max.ln() - min.ln())).exp() as usize;
+        let dirty = rng.below(100) < usize::from(options.dirty_percent);
+        let content = file_content(&mut rng, size, dirty);
+
+        let path = dir.join(format!("f{:05}.txt", index));
+        fs::write(&path, &content).with_context(|| format!("Failed to write {}", path.display()))?;
+        summary.files += 1;
+        summary.dirty_files += usize::from(dirty);
+        summary.bytes += content.len() as u64;
+    }
+    Ok(summary)
+}
+
+/// Roughly `size` bytes of code-like lines. Dirty files get trailing whitespace on about one
+/// line in ten, and always on the first so every one of them needs cleaning.
+fn file_content(rng: &mut Rng, size: usize, dirty: bool) -> String {
+    let mut content = String::with_capacity(size + 80);
+    let mut line_num = 0;
+    while content.len() < size {
+        let indent = rng.below(4) * 4;
+        content.extend(std::iter::repeat_n(' ', indent));
+        for word in 0..1 + rng.below(10) {
+            if word > 0 {
+                content.push(' ');
+            }
+            content.push_str(WORDS[rng.below(WORDS.len())]);
+        }
+        if dirty && (line_num == 0 || rng.below(10) == 0) {
+            content.push_str(if rng.below(2) == 0 { "  " } else { "\t" });
+        }
+        content.push('\n');
+        line_num += 1;
+    }
+    content
+}
+
+/// Generate a tree in a temporary directory, time walking it and then processing it with each
+/// thread count, and remove it again. Processing is a dry run, so every pass sees the same
+/// dirty files and write time is not measured.
+pub fn run(options: &BenchOptions, rules: &Rules) -> Result<BenchReport> {
+    options.validate()?;
+
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    let root = std::env::temp_dir().join(format!("whitespace-bench-{}-{}", std::process::id(), nanos));
+    let _cleanup = RemoveOnDrop(root.clone());
+    info!("Generating {} files under {}", options.files, root.display());
+    let tree = generate_tree(&root, options)?;
+
+    // File selection uses the defaults so every generated file is included; the rules are the
+    // user's, since they decide how much work each line takes
+    let config = Arc::new(Config {
+        rules: rules.clone(),
+        ..Config::default()
+    });
+    let fs = Arc::new(RealFs::default());
+    let walker = FileWalker::new(Arc::clone(&config), Arc::clone(&fs));
+
+    let mut files = Vec::new();
+    let walk = fastest(options.runs, || {
+        files = walker.collect_files(&root, None)?;
+        Ok(())
+    })?;
+
+    let engine = ParallelEngine::new(config, fs, 0).context("Failed to initialize parallel engine")?;
+    let mut processing = Vec::new();
+    for threads in options.thread_counts() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .context("Failed to build thread pool")?;
+        let elapsed = fastest(options.runs, || {
+            pool.install(|| engine.process_files_with_results(files.clone(), true))
+                .map(drop)
+        })?;
+        debug!("Processed with {} threads in {:?}", threads, elapsed);
+        processing.push(ThreadResult { threads, elapsed });
+    }
+
+    Ok(BenchReport {
+        tree,
+        walk,
+        files_walked: files.len(),
+        processing,
+    })
+}
+
+/// The shortest of `runs` timings of `f`.
+fn fastest(runs: usize, mut f: impl FnMut() -> Result<()>) -> Result<Duration> {
+    let mut best = Duration::MAX;
+    for _ in 0..runs {
+        let start = Instant::now();
+        f()?;
+        best = best.min(start.elapsed());
+    }
+    Ok(best)
+}
+
+struct RemoveOnDrop(PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            tracing::warn!("Failed to remove benchmark tree {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+pub fn display_bench(report: &BenchReport) {
+    let mib = report.tree.bytes as f64 / (1024.0 * 1024.0);
+    let per_second = |count: f64, elapsed: Duration| count / elapsed.as_secs_f64().max(f64::EPSILON);
+
+    println!("{}", "Benchmark".cyan().bold());
+    println!(
+        "  Tree:  {} files, {:.1} MiB, {} with trailing whitespace",
+        report.tree.files, mib, report.tree.dirty_files
+    );
+    println!(
+        "  Walk:  {} files in {:.1}ms ({:.0} files/s)",
+        report.files_walked,
+        report.walk.as_secs_f64() * 1000.0,
+        per_second(report.files_walked as f64, report.walk)
+    );
+
+    println!("\n{}", "Processing (dry run)".cyan().bold());
+    println!(
+        "  {:>7}  {:>10}  {:>10}  {:>8}  {:>7}",
+        "Threads", "Time", "Files/s", "MiB/s", "Speedup"
+    );
+    let baseline = report.processing.first().map(|result| result.elapsed);
+    for result in &report.processing {
+        let speedup = baseline.map_or(1.0, |baseline| {
+            baseline.as_secs_f64() / result.elapsed.as_secs_f64().max(f64::EPSILON)
+        });
+        println!(
+            "  {:>7}  {:>8.1}ms  {:>10.0}  {:>8.1}  {:>6.2}x",
+            result.threads,
+            result.elapsed.as_secs_f64() * 1000.0,
+            per_second(report.files_walked as f64, result.elapsed),
+            per_second(mib, result.elapsed),
+            speedup
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::process_lines;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn test_generate_tree() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let options = BenchOptions {
+            files: 150,
+            min_size: 100,
+            max_size: 2000,
+            dirty_percent: 50,
+            seed: 7,
+            ..BenchOptions::default()
+        };
+
+        let summary = generate_tree(temp_dir.path(), &options).unwrap();
+
+        assert_eq!(summary.files, 150);
+        assert!(temp_dir.path().join("d001/f00149.txt").is_file());
+        assert!((40..=110).contains(&summary.dirty_files));
+
+        // Exactly the dirty files need cleaning
+        let rule_set = RuleSet::new(&Rules::default());
+        let rules = rule_set.for_file(Path::new("a.txt"));
+        let mut dirty = 0;
+        let mut bytes = 0;
+        for dir in ["d000", "d001"] {
+            for entry in fs::read_dir(temp_dir.path().join(dir)).unwrap() {
+                let content = fs::read_to_string(entry.unwrap().path()).unwrap();
+                assert!(content.len() >= 100);
+                bytes += content.len();
+                dirty += usize::from(!process_lines(&content, &rules).1.is_empty());
+            }
+        }
+        assert_eq!(dirty, summary.dirty_files);
+        assert_eq!(bytes as u64, summary.bytes);
+
+        // The same seed gives the same tree
+        let again = tempfile::TempDir::new().unwrap();
+        assert_eq!(generate_tree(again.path(), &options).unwrap(), summary);
+    }
+
+    #[test]
+    fn test_run_measures_each_thread_count() {
+        let options = BenchOptions {
+            files: 20,
+            max_size: 1024,
+            thread_counts: vec![1, 2],
+            runs: 1,
+            ..BenchOptions::default()
+        };
+
+        let report = run(&options, &Rules::default()).unwrap();
+
+        assert_eq!(report.files_walked, 20);
+        let threads: Vec<usize> = report.processing.iter().map(|result| result.threads).collect();
+        assert_eq!(threads, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let invalid = [
+            BenchOptions {
+                files: 0,
+                ..BenchOptions::default()
+            },
+            BenchOptions {
+                min_size: 10,
+                max_size: 5,
+                ..BenchOptions::default()
+            },
+            BenchOptions {
+                thread_counts: vec![0],
+                ..BenchOptions::default()
+            },
+        ];
+        for options in invalid {
+            assert!(options.validate().is_err(), "{:?}", options);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::config::Config;
+use crate::ports::fs::FsMetadata;
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// Bump when the on-disk format or the cleaning rules change incompatibly.
+const CACHE_VERSION: u32 = 1;
+
+/// Incremental cache of files known to be free of trailing whitespace.
+///
+/// Entries are keyed by path relative to the target root and record the size,
+/// modification time and content hash observed when the file was last clean.
+/// A matching size and mtime lets a file be skipped without reading it; a
+/// matching size and hash lets it be skipped without processing it.
+#[derive(Debug)]
+pub struct Cache {
+    root: PathBuf,
+    cache_file: PathBuf,
+    fingerprint: u64,
+    trust_mtime: bool,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    dirty: AtomicBool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub size: u64,
+    pub mtime_secs: u64,
+    pub mtime_nanos: u32,
+    pub hash: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    fingerprint: u64,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl Cache {
+    /// Open the cache for `root` under `~/.local/share/whitespace/cache`.
+    pub fn open(root: &Path, config: &Config) -> Result<Self> {
+        let cache_dir = dirs::data_local_dir()
+            .ok_or_else(|| eyre::eyre!("Could not determine local data directory"))?
+            .join("whitespace")
+            .join("cache");
+        let canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut hasher = ContentHasher::new();
+        hasher.update(canonical_root.to_string_lossy().as_bytes());
+        let cache_file = cache_dir.join(format!("{:016x}.json", hasher.finish()));
+
+        Self::load_from(root, &cache_file, config)
+    }
+
+    /// Load a cache from an explicit file, discarding it if it was built with a different config.
+    pub fn load_from(root: &Path, cache_file: &Path, config: &Config) -> Result<Self> {
+        let fingerprint = config_fingerprint(config)?;
+
+        let entries = match fs::read(cache_file) {
+            Ok(bytes) => match serde_json::from_slice::<CacheFile>(&bytes) {
+                Ok(file) if file.version == CACHE_VERSION && file.fingerprint == fingerprint => {
+                    debug!(
+                        "Loaded {} cache entries from {}",
+                        file.entries.len(),
+                        cache_file.display()
+                    );
+                    file.entries
+                }
+                Ok(_) => {
+                    info!("Cache {} is stale, starting fresh", cache_file.display());
+                    HashMap::new()
+                }
+                Err(e) => {
+                    warn!("Ignoring unreadable cache {}: {}", cache_file.display(), e);
+                    HashMap::new()
+                }
+            },
+            Err(_) => HashMap::new(),
+        };
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            cache_file: cache_file.to_path_buf(),
+            fingerprint,
+            trust_mtime: config.processing.trust_mtime,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    /// True if the file's size and mtime match a recorded clean state. Always false when
+    /// `processing.trust-mtime` is off, so every file is read and checked by its content hash.
+    pub fn is_fresh(&self, path: &Path, metadata: &FsMetadata) -> bool {
+        if !self.trust_mtime {
+            return false;
+        }
+        let (Some(key), Some((mtime_secs, mtime_nanos))) = (self.key(path), mtime_parts(metadata.modified)) else {
+            return false;
+        };
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(&key).is_some_and(|entry| {
+            entry.size == metadata.len && entry.mtime_secs == mtime_secs && entry.mtime_nanos == mtime_nanos
+        })
+    }
+
+    /// True if the file's content hash matches a recorded clean state (e.g. after a touch).
+    pub fn matches_content(&self, path: &Path, size: u64, hash: u64) -> bool {
+        let Some(key) = self.key(path) else {
+            return false;
+        };
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(&key)
+            .is_some_and(|entry| entry.size == size && entry.hash == hash)
+    }
+
+    /// Record that the file is clean with the given metadata and content hash.
+    pub fn record_clean(&self, path: &Path, metadata: &FsMetadata, hash: u64) {
+        let (Some(key), Some((mtime_secs, mtime_nanos))) = (self.key(path), mtime_parts(metadata.modified)) else {
+            return;
+        };
+        let entry = CacheEntry {
+            size: metadata.len,
+            mtime_secs,
+            mtime_nanos,
+            hash,
+        };
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.insert(key, entry) != Some(entry) {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Number of entries currently held.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Persist the cache if anything changed since it was loaded.
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            fingerprint: self.fingerprint,
+            entries,
+        };
+
+        if let Some(parent) = self.cache_file.parent() {
+            fs::create_dir_all(parent).context("Failed to create cache directory")?;
+        }
+        let temp_file = self.cache_file.with_extension("json.tmp");
+        fs::write(&temp_file, serde_json::to_vec(&file)?).context("Failed to write cache file")?;
+        fs::rename(&temp_file, &self.cache_file).context("Failed to replace cache file")?;
+
+        debug!(
+            "Saved {} cache entries to {}",
+            file.entries.len(),
+            self.cache_file.display()
+        );
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn key(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        relative.to_str().map(str::to_string)
+    }
+}
+
+/// Incremental FNV-1a 64-bit hasher, stable across runs and Rust versions.
+#[derive(Debug, Clone)]
+pub struct ContentHasher(u64);
+
+impl ContentHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    pub fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= u64::from(byte);
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hash a complete buffer.
+pub fn hash_content(bytes: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+/// Any config change can alter what counts as clean, so entries are only valid for one config.
+fn config_fingerprint(config: &Config) -> Result<u64> {
+    let serialized = serde_yaml::to_string(config).context("Failed to serialize config for cache")?;
+    let mut hasher = ContentHasher::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(serialized.as_bytes());
+    Ok(hasher.finish())
+}
+
+fn mtime_parts(modified: Option<SystemTime>) -> Option<(u64, u32)> {
+    let since_epoch = modified?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn metadata(len: u64, secs: u64) -> FsMetadata {
+        FsMetadata {
+            len,
+            is_file: true,
+            is_dir: false,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            file_id: None,
+            readonly: false,
+            links: 1,
+            special: false,
+            allocated: None,
+        }
+    }
+
+    #[test]
+    fn test_fresh_after_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = Path::new("/project");
+        let cache = Cache::load_from(root, &temp_dir.path().join("cache.json"), &Config::default()).unwrap();
+        let path = root.join("src/main.rs");
+
+        assert!(!cache.is_fresh(&path, &metadata(10, 100)));
+        cache.record_clean(&path, &metadata(10, 100), 42);
+
+        assert!(cache.is_fresh(&path, &metadata(10, 100)));
+        assert!(!cache.is_fresh(&path, &metadata(10, 101)));
+        assert!(!cache.is_fresh(&path, &metadata(11, 100)));
+        assert!(cache.matches_content(&path, 10, 42));
+        assert!(!cache.matches_content(&path, 10, 43));
+    }
+
+    #[test]
+    fn test_save_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_file = temp_dir.path().join("nested").join("cache.json");
+        let root = Path::new("/project");
+        let path = root.join("README.md");
+
+        let cache = Cache::load_from(root, &cache_file, &Config::default()).unwrap();
+        cache.record_clean(&path, &metadata(5, 200), 7);
+        cache.save().unwrap();
+
+        let reloaded = Cache::load_from(root, &cache_file, &Config::default()).unwrap();
+        assert_eq!(reloaded.len(), 1);
+        assert!(reloaded.is_fresh(&path, &metadata(5, 200)));
+    }
+
+    #[test]
+    fn test_config_change_invalidates() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_file = temp_dir.path().join("cache.json");
+        let root = Path::new("/project");
+
+        let cache = Cache::load_from(root, &cache_file, &Config::default()).unwrap();
+        cache.record_clean(&root.join("a.txt"), &metadata(1, 1), 1);
+        cache.save().unwrap();
+
+        let mut config = Config::default();
+        config.exclude_files.push("*.generated".to_string());
+        let reloaded = Cache::load_from(root, &cache_file, &config).unwrap();
+        assert!(reloaded.is_empty());
+    }
+
+    #[test]
+    fn test_untrusted_mtime_checks_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.processing.trust_mtime = false;
+        let cache = Cache::load_from(Path::new("/"), &temp_dir.path().join("c.json"), &config).unwrap();
+
+        cache.record_clean(Path::new("/a.txt"), &metadata(3, 100), 9);
+        assert!(!cache.is_fresh(Path::new("/a.txt"), &metadata(3, 100)));
+        assert!(cache.matches_content(Path::new("/a.txt"), 3, 9));
+    }
+
+    #[test]
+    fn test_missing_mtime_is_never_fresh() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Cache::load_from(Path::new("/"), &temp_dir.path().join("c.json"), &Config::default()).unwrap();
+        let no_mtime = FsMetadata {
+            modified: None,
+            ..metadata(1, 1)
+        };
+
+        cache.record_clean(Path::new("/a.txt"), &no_mtime, 1);
+        assert!(cache.is_empty());
+        assert!(!cache.is_fresh(Path::new("/a.txt"), &no_mtime));
+    }
+
+    #[test]
+    fn test_hash_content_is_stable() {
+        assert_eq!(hash_content(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash_content(b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let mut hasher = ContentHasher::new();
+        hasher.update(b"hello ");
+        hasher.update(b"world");
+        assert_eq!(hasher.finish(), hash_content(b"hello world"));
+    }
+}
@@ -1,4 +1,11 @@
-use clap::Parser;
+use crate::config::Threads;
+use crate::hooks::HookType;
+use crate::logging::TraceFormat;
+use crate::output::{ColorChoice, OutputFormat, SortOrder, StreamOrder};
+use crate::preset::Preset;
+use crate::rules::LineRanges;
+use clap::builder::BoolishValueParser;
+use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -6,30 +13,727 @@ use std::path::PathBuf;
     name = "whitespace",
     about = "Recursively remove trailing whitespace from files",
     version = env!("GIT_DESCRIBE"),
-    after_help = "Logs are written to: ~/.local/share/whitespace/logs/whitespace.log"
+    after_help = "Logs are written to: ~/.local/share/whitespace/logs/whitespace.log (see --log-file)",
+    args_conflicts_with_subcommands = true
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Target directories to process
     #[arg(help = "Target directories to process [default: .]")]
     pub directories: Vec<PathBuf>,
 
+    /// Process the files named in this list instead of walking directories
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "directories",
+        help = "Process exactly the files listed in PATH, one per line (- reads stdin)"
+    )]
+    pub files_from: Option<PathBuf>,
+
+    /// The --files-from list is NUL-delimited
+    #[arg(
+        short = '0',
+        long = "null",
+        requires = "files_from",
+        help = "Paths in --files-from are separated by NUL bytes (find -print0, git ls-files -z)"
+    )]
+    pub null: bool,
+
+    /// Clean only these lines of a single file
+    #[arg(
+        long,
+        value_name = "RANGES",
+        value_parser = crate::rules::parse_line_ranges,
+        conflicts_with = "files_from",
+        help = "Clean only lines in RANGES, e.g. 100-200 or 1-10,40, of the one file given as the target"
+    )]
+    pub lines: Option<LineRanges>,
+
+    /// Clean only lines added or changed since a git ref
+    #[arg(
+        long,
+        value_name = "REF",
+        num_args = 0..=1,
+        default_missing_value = "HEAD",
+        conflicts_with = "lines",
+        help = "Clean only lines added or changed since REF [default: HEAD], staged or not; new files count in full"
+    )]
+    pub diff_only: Option<String>,
+
+    /// Run only these rules
+    #[arg(
+        long,
+        value_name = "RULE",
+        value_delimiter = ',',
+        help = "Run only RULE, e.g. end-of-file; repeat or separate with commas for several"
+    )]
+    pub only: Vec<String>,
+
+    /// Leave these rules out
+    #[arg(
+        long,
+        value_name = "RULE",
+        value_delimiter = ',',
+        help = "Don't run RULE; repeat or separate with commas for several"
+    )]
+    pub skip: Vec<String>,
+
+    /// Clean the files HEAD and the index change, staging the cleanup
+    #[arg(
+        long,
+        conflicts_with_all = ["directories", "files_from", "lines"],
+        help = "Clean the files changed in HEAD or staged, and stage the cleanup for a commit of its own"
+    )]
+    pub fixup: bool,
+
+    /// With --fixup, fold the cleanup into HEAD
+    #[arg(
+        long,
+        requires = "fixup",
+        conflicts_with = "dry_run",
+        help = "With --fixup, amend HEAD with the cleanup (nothing else may be staged)"
+    )]
+    pub amend: bool,
+
+    /// With --fixup, commit the cleanup as a fixup of HEAD
+    #[arg(
+        long = "commit",
+        requires = "fixup",
+        conflicts_with_all = ["amend", "dry_run"],
+        help = "With --fixup, commit the cleanup with `git commit --fixup=HEAD` (nothing else may be staged)"
+    )]
+    pub fixup_commit: bool,
+
     /// Path to config file
-    #[arg(short, long, help = "Path to config file")]
+    #[arg(short, long, env = "WHITESPACE_CONFIG", global = true, help = "Path to config file")]
     pub config: Option<PathBuf>,
 
+    /// Apply a built-in rule preset over the config file
+    #[arg(
+        long,
+        env = "WHITESPACE_PRESET",
+        global = true,
+        value_enum,
+        value_name = "NAME",
+        help = "Apply a built-in rule preset on top of the config file"
+    )]
+    pub preset: Option<Preset>,
+
     /// Perform dry run (show what would be changed)
-    #[arg(short = 'n', long, help = "Dry run - show files that would be modified")]
+    #[arg(
+        short = 'n',
+        long,
+        env = "WHITESPACE_DRY_RUN",
+        value_parser = BoolishValueParser::new(),
+        help = "Dry run - show files that would be modified"
+    )]
     pub dry_run: bool,
 
-    /// Enable verbose output
-    #[arg(short, long, help = "Enable verbose output")]
-    pub verbose: bool,
+    /// Scan rewritten files again and fail if a second pass would still change them
+    #[arg(
+        long,
+        env = "WHITESPACE_VERIFY",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["dry_run", "write_to"],
+        help = "Scan each rewritten file again and fail if it still isn't clean, as a self-test of the rules"
+    )]
+    pub verify: bool,
+
+    /// Write cleaned copies into a mirrored tree instead of modifying files in place
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with_all = ["dry_run", "fixup"],
+        help = "Write cleaned copies of the files into a mirrored tree under DIR, leaving the originals alone"
+    )]
+    pub write_to: Option<PathBuf>,
+
+    /// Copy each file to FILE<SUFFIX> before rewriting it
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        conflicts_with_all = ["dry_run", "write_to"],
+        help = "Copy each file to be rewritten to FILE<SUFFIX> first, e.g. --backup .bak"
+    )]
+    pub backup: Option<String>,
+
+    /// Put each file's original in the OS trash before rewriting it
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "write_to"],
+        help = "Put a copy of each file in the OS trash before rewriting it, to restore from there"
+    )]
+    pub trash: bool,
+
+    /// Resume a run that stopped before it was done
+    #[arg(
+        long,
+        value_name = "RUN_ID",
+        num_args = 0..=1,
+        default_missing_value = crate::resume::LATEST,
+        conflicts_with_all = ["directories", "dry_run", "files_from", "lines", "fixup"],
+        help = "Resume the run RUN_ID [default: latest] where it stopped, over its directories, skipping the files it finished"
+    )]
+    pub resume: Option<String>,
+
+    /// Increase console log verbosity (-v info, -vv debug, -vvv trace)
+    #[arg(
+        short,
+        long,
+        global = true,
+        action = clap::ArgAction::Count,
+        help = "Increase console log verbosity (-v info, -vv debug, -vvv trace)"
+    )]
+    pub verbose: u8,
+
+    /// Print nothing but errors; rely on the exit code
+    #[arg(
+        short,
+        long,
+        env = "WHITESPACE_QUIET",
+        value_parser = BoolishValueParser::new(),
+        global = true,
+        conflicts_with = "verbose",
+        help = "Suppress normal output and log only errors to the console"
+    )]
+    pub quiet: bool,
+
+    /// When to use colors and emoji
+    #[arg(
+        long,
+        env = "WHITESPACE_COLOR",
+        global = true,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t = ColorChoice::Auto,
+        help = "When to use colors and emoji"
+    )]
+    pub color: ColorChoice,
+
+    /// Write the log to this file instead of the default location
+    #[arg(
+        long,
+        env = "WHITESPACE_LOG_FILE",
+        global = true,
+        value_name = "PATH",
+        help = "Write the log file to PATH"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    /// Disable the log file
+    #[arg(
+        long,
+        visible_alias = "no-log",
+        env = "WHITESPACE_NO_LOG_FILE",
+        value_parser = BoolishValueParser::new(),
+        global = true,
+        conflicts_with = "log_file",
+        help = "Don't write a log file"
+    )]
+    pub no_log_file: bool,
 
-    /// Process files recursively
-    #[arg(short, long, help = "Recurse into subdirectories", default_value = "true")]
+    /// Rotate the log file once it reaches this size
+    #[arg(
+        long,
+        env = "WHITESPACE_LOG_MAX_SIZE",
+        global = true,
+        value_name = "SIZE",
+        value_parser = crate::config::parse_size,
+        default_value = "10MB",
+        help = "Rotate the log file once it reaches SIZE, e.g. 512K or 10MB (0 = never)"
+    )]
+    pub log_max_size: u64,
+
+    /// Number of rotated log files kept
+    #[arg(
+        long,
+        env = "WHITESPACE_LOG_KEEP",
+        global = true,
+        value_name = "N",
+        default_value_t = 5,
+        help = "Keep N rotated log files besides the current one"
+    )]
+    pub log_keep: usize,
+
+    /// How log lines are written
+    #[arg(
+        long,
+        env = "WHITESPACE_TRACE_FORMAT",
+        global = true,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = TraceFormat::Text,
+        help = "Write log lines as text or as JSON objects, with per-directory and per-file spans and their timings"
+    )]
+    pub trace_format: TraceFormat,
+
+    /// Process files recursively with no depth limit (the default)
+    #[arg(
+        short,
+        long,
+        global = true,
+        conflicts_with = "max_depth",
+        help = "Recurse into subdirectories without a depth limit"
+    )]
     pub recursive: bool,
 
-    /// Number of parallel threads (0 = auto-detect)
-    #[arg(short = 'j', long, help = "Number of parallel threads", default_value_t = num_cpus::get())]
-    pub threads: usize,
+    /// Maximum directory depth to descend into
+    #[arg(
+        long,
+        env = "WHITESPACE_MAX_DEPTH",
+        global = true,
+        value_name = "N",
+        help = "Descend at most N levels (1 = only files directly in each target)"
+    )]
+    pub max_depth: Option<usize>,
+
+    /// Follow symlinks instead of skipping them
+    #[arg(
+        long,
+        env = "WHITESPACE_FOLLOW_SYMLINKS",
+        value_parser = BoolishValueParser::new(),
+        global = true,
+        help = "Follow symlinked files and directories (each file is processed once)"
+    )]
+    pub follow_symlinks: bool,
+
+    /// Override `processing.max-file-size` for this invocation
+    #[arg(
+        long,
+        env = "WHITESPACE_MAX_FILE_SIZE",
+        global = true,
+        value_name = "SIZE",
+        value_parser = crate::config::parse_size,
+        help = "Skip files larger than SIZE, e.g. 512K, 10MB or 1GiB (overrides the config file)"
+    )]
+    pub max_file_size: Option<u64>,
+
+    /// Fail instead of warning when a target is missing or not a directory
+    #[arg(
+        long,
+        env = "WHITESPACE_STRICT",
+        value_parser = BoolishValueParser::new(),
+        global = true,
+        help = "Fail if any target directory is missing or not a directory"
+    )]
+    pub strict: bool,
+
+    /// Number of parallel threads, or `auto-io` to choose from a sample of the files
+    #[arg(
+        short = 'j',
+        long,
+        env = "WHITESPACE_THREADS",
+        global = true,
+        help = "Number of parallel threads, or auto-io to choose from a sample of the files [default: nproc]",
+        value_parser = crate::config::parse_threads,
+        default_value_t = Threads::default(),
+        // The default depends on the machine, so the help names it rather than showing it
+        hide_default_value = true
+    )]
+    pub threads: Threads,
+
+    /// Files read or written at once, separately from the threads that process them
+    #[arg(
+        long,
+        env = "WHITESPACE_IO_THREADS",
+        global = true,
+        value_name = "N",
+        help = "Read or write at most N files at once, while --threads still sets how many are processed"
+    )]
+    pub io_threads: Option<usize>,
+
+    /// Work the way network filesystems need
+    #[arg(
+        long,
+        env = "WHITESPACE_NFS_SAFE",
+        value_parser = BoolishValueParser::new(),
+        global = true,
+        help = "For network filesystems: no memory-mapping, 4 files read or written at once, EIO/ESTALE retried, and no trusting mtimes"
+    )]
+    pub nfs_safe: bool,
+
+    /// Stop after this many files fail to be read or written
+    #[arg(
+        long,
+        env = "WHITESPACE_MAX_ERRORS",
+        value_name = "N",
+        help = "Abort the run once N files have failed"
+    )]
+    pub max_errors: Option<usize>,
+
+    /// Stop at the first file that fails to be read or written
+    #[arg(
+        long,
+        env = "WHITESPACE_FAIL_FAST",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "max_errors",
+        help = "Abort the run at the first failed file"
+    )]
+    pub fail_fast: bool,
+
+    /// Stop once this many files have been processed
+    #[arg(
+        long,
+        env = "WHITESPACE_MAX_FILES",
+        value_name = "N",
+        help = "Process at most N files, reporting the rest as skipped"
+    )]
+    pub max_files: Option<usize>,
+
+    /// Stop starting new work after this many seconds
+    #[arg(
+        long,
+        env = "WHITESPACE_TIMEOUT",
+        value_name = "SECS",
+        help = "Stop walking and processing after SECS seconds, reporting the rest as skipped"
+    )]
+    pub timeout: Option<u64>,
+
+    /// Process files that are skipped to protect their content, such as patches
+    #[arg(
+        long,
+        env = "WHITESPACE_FORCE",
+        value_parser = BoolishValueParser::new(),
+        help = "Also clean patch and diff files, which are skipped by default"
+    )]
+    pub force: bool,
+
+    /// Clean files that contain merge conflict markers
+    #[arg(
+        long,
+        env = "WHITESPACE_ALLOW_CONFLICTS",
+        value_parser = BoolishValueParser::new(),
+        help = "Also clean files with unresolved merge conflict markers"
+    )]
+    pub allow_conflicts: bool,
+
+    /// Clean generated and vendored files
+    #[arg(
+        long,
+        env = "WHITESPACE_INCLUDE_GENERATED",
+        value_parser = BoolishValueParser::new(),
+        help = "Also clean generated and vendored files, which are skipped by default"
+    )]
+    pub include_generated: bool,
+
+    /// Disable the incremental clean-file cache
+    #[arg(
+        long,
+        env = "WHITESPACE_NO_CACHE",
+        value_parser = BoolishValueParser::new(),
+        help = "Don't read or update the incremental cache"
+    )]
+    pub no_cache: bool,
+
+    /// Don't take the advisory lock that keeps concurrent runs on a directory from racing
+    #[arg(
+        long,
+        env = "WHITESPACE_NO_LOCK",
+        value_parser = BoolishValueParser::new(),
+        help = "Don't wait for other runs cleaning the same directory"
+    )]
+    pub no_lock: bool,
+
+    /// Don't keep a journal of the run's progress to resume it from
+    #[arg(
+        long,
+        env = "WHITESPACE_NO_JOURNAL",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "resume",
+        help = "Don't record the run's progress, so it can't be resumed with --resume"
+    )]
+    pub no_journal: bool,
+
+    /// List files that were skipped and why
+    #[arg(
+        long,
+        env = "WHITESPACE_SHOW_SKIPPED",
+        value_parser = BoolishValueParser::new(),
+        help = "List skipped files with the reason (binary, too large, invalid encoding, excluded, diff, merge conflict, read-only, limit reached)"
+    )]
+    pub show_skipped: bool,
+
+    /// Print a summary after every target directory, not just the whole run
+    #[arg(long, help = "Also print a summary after each target directory")]
+    pub per_directory: bool,
+
+    /// Group findings by directory
+    #[arg(
+        long,
+        conflicts_with_all = ["format", "files_with_matches", "print0"],
+        help = "Group changed files under a header per directory, with relative paths and subtotals"
+    )]
+    pub group_by_dir: bool,
+
+    /// Report files as they finish instead of once each directory is done
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ORDER",
+        num_args = 0..=1,
+        default_missing_value = "finished",
+        conflicts_with_all = ["group_by_dir", "quiet"],
+        help = "Report each file as soon as it finishes [default: finished], or in the order found with `ordered`, instead of once each directory is done"
+    )]
+    pub stream: Option<StreamOrder>,
+
+    /// Say why each file was included or skipped
+    #[arg(
+        long,
+        conflicts_with_all = ["format", "files_with_matches", "print0"],
+        help = "List every file with why it was included or skipped (which pattern, size, content)"
+    )]
+    pub why: bool,
+
+    /// Show and report paths relative to this directory
+    #[arg(
+        long,
+        env = "WHITESPACE_RELATIVE_TO",
+        global = true,
+        value_name = "DIR",
+        help = "Show and report paths relative to DIR, or to the current directory with `cwd`"
+    )]
+    pub relative_to: Option<PathBuf>,
+
+    /// Report time spent per phase
+    #[arg(
+        long,
+        help = "Print a breakdown of time spent walking, reading, processing and writing"
+    )]
+    pub timings: bool,
+
+    /// Output format for findings
+    #[arg(
+        long,
+        env = "WHITESPACE_FORMAT",
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        help = "Output format for findings"
+    )]
+    pub format: OutputFormat,
+
+    /// Order findings are listed and reported in
+    #[arg(
+        long,
+        env = "WHITESPACE_SORT",
+        global = true,
+        value_enum,
+        value_name = "ORDER",
+        default_value_t = SortOrder::Path,
+        help = "Order files are listed and reported in"
+    )]
+    pub sort: SortOrder,
+
+    /// Print only the changed files' paths, one per line
+    #[arg(
+        short = 'l',
+        long,
+        conflicts_with_all = ["format", "quiet", "timings"],
+        help = "Print only the paths of files with changes, one per line"
+    )]
+    pub files_with_matches: bool,
+
+    /// Print only the changed files' paths, NUL-delimited
+    #[arg(
+        long,
+        conflicts_with_all = ["format", "quiet", "timings"],
+        help = "Print only the paths of files with changes, each followed by NUL (for xargs -0)"
+    )]
+    pub print0: bool,
+
+    /// Write a report of the whole run to a file, whatever the console output
+    #[arg(
+        long,
+        env = "WHITESPACE_REPORT",
+        value_name = "PATH",
+        help = "Also write a full run report to PATH (.json, .sarif or .html)"
+    )]
+    pub report: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Scan without modifying and report aggregated whitespace metrics
+    Stats {
+        /// Target directories to scan
+        #[arg(help = "Target directories to scan [default: .]")]
+        directories: Vec<PathBuf>,
+
+        /// Number of worst offenders to list
+        #[arg(long, default_value_t = 10, help = "Number of worst offending files to list")]
+        top: usize,
+    },
+
+    /// Compare two trees and list the files that differ only in whitespace
+    Diff {
+        /// The trees to compare, such as checkouts from before and after a cleanup
+        #[arg(
+            num_args = 2,
+            required = true,
+            value_names = ["DIR_A", "DIR_B"],
+            help = "Trees to compare, e.g. checkouts from before and after a cleanup; exits 1 if anything but whitespace differs"
+        )]
+        trees: Vec<PathBuf>,
+    },
+
+    /// Show every decision a run would make about one file, and which setting made it
+    Explain {
+        /// File to explain
+        #[arg(help = "File to explain")]
+        path: PathBuf,
+
+        /// Directory the run would walk
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Explain the file as found by walking DIR, whose exclude patterns apply [default: .]"
+        )]
+        root: Option<PathBuf>,
+    },
+
+    /// Time walking and processing a generated tree under different thread counts
+    Bench {
+        /// Number of files to generate
+        #[arg(long, default_value_t = 2000, help = "Number of files to generate")]
+        files: usize,
+
+        /// Smallest generated file
+        #[arg(
+            long,
+            value_name = "BYTES",
+            default_value_t = 256,
+            help = "Smallest generated file size"
+        )]
+        min_size: usize,
+
+        /// Largest generated file; sizes are spread log-uniformly between the two
+        #[arg(
+            long,
+            value_name = "BYTES",
+            default_value_t = 64 * 1024,
+            help = "Largest generated file size (sizes are spread log-uniformly)"
+        )]
+        max_size: usize,
+
+        /// Percentage of files with trailing whitespace
+        #[arg(
+            long,
+            value_name = "PERCENT",
+            default_value_t = 20,
+            value_parser = clap::value_parser!(u8).range(0..=100),
+            help = "Percentage of files with trailing whitespace"
+        )]
+        dirty_percent: u8,
+
+        /// Thread counts to compare
+        #[arg(
+            long,
+            value_name = "N,...",
+            value_delimiter = ',',
+            help = "Thread counts to compare [default: 1, 2, 4, ... up to the number of CPUs]"
+        )]
+        thread_counts: Vec<usize>,
+
+        /// Repetitions per measurement
+        #[arg(
+            long,
+            default_value_t = 3,
+            help = "Repeat each measurement N times and report the fastest"
+        )]
+        runs: usize,
+
+        /// Generator seed
+        #[arg(long, default_value_t = 0, help = "Seed for the generated tree")]
+        seed: u64,
+    },
+
+    /// Clean exactly the given files, for use as a `pre-commit` hook; exits 1 if any changed
+    PreCommit {
+        /// Files to clean; directories are not walked
+        #[arg(help = "Files to clean (as passed by pre-commit)")]
+        files: Vec<PathBuf>,
+    },
+
+    /// Run a Language Server Protocol server on stdin/stdout for editors
+    Lsp,
+
+    /// Serve check and clean requests for one directory over a Unix domain socket or named pipe
+    Daemon {
+        /// Directory to serve
+        #[arg(help = "Directory whose files requests may name [default: .]")]
+        directory: Option<PathBuf>,
+
+        /// Socket to listen on
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Listen on PATH, a socket or on Windows a named pipe [default: $XDG_RUNTIME_DIR/whitespace/daemon.sock]"
+        )]
+        socket: Option<PathBuf>,
+    },
+
+    /// Install a git hook that runs whitespace before each commit or push
+    InstallHook {
+        /// Which hook to install
+        #[arg(long = "type", value_enum, default_value_t = HookType::PreCommit, help = "Hook to install")]
+        hook: HookType,
+
+        /// Replace an existing hook that whitespace didn't install
+        #[arg(long, help = "Replace an existing hook that wasn't installed by whitespace")]
+        force: bool,
+    },
+
+    /// Remove a git hook installed by install-hook
+    UninstallHook {
+        /// Which hook to remove
+        #[arg(long = "type", value_enum, default_value_t = HookType::PreCommit, help = "Hook to remove")]
+        hook: HookType,
+    },
+
+    /// Generate roff man pages from the CLI definition
+    #[command(hide = true)]
+    Man {
+        /// Write whitespace.1 and one page per subcommand into this directory instead of printing
+        #[arg(
+            long,
+            value_name = "DIR",
+            help = "Write man pages into DIR instead of printing to stdout"
+        )]
+        out_dir: Option<PathBuf>,
+    },
+
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Check a config file for unknown keys, invalid globs and conflicting settings
+    Validate {
+        /// Config file to check [default: --config or the default location]
+        path: Option<PathBuf>,
+    },
+
+    /// Print the effective configuration after merging defaults, the config file and CLI flags
+    Show,
+
+    /// Print a JSON Schema for config files, for editor validation and completion
+    Schema,
+
+    /// Write a commented default config file
+    Init {
+        /// Write ./whitespace.yml instead of ~/.config/whitespace/whitespace.yml
+        #[arg(long, help = "Write ./whitespace.yml for the current project (use with --config)")]
+        project: bool,
+
+        /// Overwrite an existing file
+        #[arg(long, help = "Overwrite an existing config file")]
+        force: bool,
+    },
 }
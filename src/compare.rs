@@ -0,0 +1,325 @@
+use crate::config::{Config, Rules};
+use crate::exit::ExitStatus;
+use crate::output;
+use crate::ports::fs::FileSystem;
+use crate::rules::trim_line;
+use crate::walker::FileWalker;
+use colored::*;
+use eyre::{Context, Result};
+use rayon::prelude::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// How a file differs between the two trees `whitespace diff` compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    /// Only trailing whitespace, line endings or blank lines at the end differ
+    WhitespaceOnly,
+    /// Something besides whitespace differs, or the file couldn't be read as text to tell
+    Content,
+    /// The file is only in the first tree
+    OnlyInLeft,
+    /// The file is only in the second tree
+    OnlyInRight,
+    /// The file couldn't be read from one of the trees
+    Unreadable,
+}
+
+/// What comparing two trees found.
+#[derive(Debug, Default)]
+pub struct TreeDiff {
+    /// Files with the same bytes in both trees
+    pub identical: usize,
+    /// Files that differ, by path relative to their tree, sorted by path
+    pub files: Vec<(PathBuf, Difference)>,
+}
+
+impl TreeDiff {
+    /// Files that differ in the way given.
+    pub fn count(&self, difference: Difference) -> usize {
+        self.files.iter().filter(|(_, found)| *found == difference).count()
+    }
+
+    /// Success if whitespace is all that differs between the trees, otherwise Findings.
+    pub fn status(&self) -> ExitStatus {
+        if self
+            .files
+            .iter()
+            .all(|(_, difference)| *difference == Difference::WhitespaceOnly)
+        {
+            ExitStatus::Success
+        } else {
+            ExitStatus::Findings
+        }
+    }
+}
+
+/// Version control metadata, which differs between checkouts of the same content and is never
+/// compared.
+const VCS_METADATA: &[&str] = &[".git", ".hg", ".svn"];
+
+/// Compare every file under `left` and `right`, pairing them by their paths relative to each
+/// tree. Files a run would process may differ in whitespace; those it would skip, such as
+/// binaries and excluded paths, must have the same bytes.
+pub fn compare_trees<F: FileSystem>(
+    left: &Path,
+    right: &Path,
+    max_depth: Option<usize>,
+    config: Arc<Config>,
+    fs: Arc<F>,
+) -> Result<TreeDiff> {
+    let walker = FileWalker::new(Arc::clone(&config), Arc::clone(&fs));
+    let relative_files = |root: &Path| -> Result<BTreeSet<PathBuf>> {
+        let files = walker
+            .collect_files(root, max_depth)
+            .with_context(|| format!("Failed to collect files from {}", root.display()))?;
+        Ok(files
+            .into_iter()
+            .filter_map(|file| file.strip_prefix(root).ok().map(Path::to_path_buf))
+            .filter(|file| !is_vcs_metadata(file))
+            .collect())
+    };
+    let left_cleaned = relative_files(left)?;
+    let right_cleaned = relative_files(right)?;
+    // Files the walk reaches through followed symlinks count too
+    let mut left_files = all_files(fs.as_ref(), left, max_depth)?;
+    left_files.extend(left_cleaned.iter().cloned());
+    let mut right_files = all_files(fs.as_ref(), right, max_depth)?;
+    right_files.extend(right_cleaned.iter().cloned());
+    debug!(
+        "Comparing {} files in {} with {} in {}",
+        left_files.len(),
+        left.display(),
+        right_files.len(),
+        right.display()
+    );
+
+    let paths: Vec<&PathBuf> = left_files.union(&right_files).collect();
+    let compared: Vec<(PathBuf, Option<Difference>)> = paths
+        .into_par_iter()
+        .map(|path| {
+            let cleaned = left_cleaned.contains(path) && right_cleaned.contains(path);
+            let difference = match (left_files.contains(path), right_files.contains(path)) {
+                (true, false) => Some(Difference::OnlyInLeft),
+                (false, true) => Some(Difference::OnlyInRight),
+                _ => match (fs.read(&left.join(path)), fs.read(&right.join(path))) {
+                    (Ok(left_bytes), Ok(right_bytes)) if cleaned => {
+                        compare_contents(&left_bytes, &right_bytes, &config.rules)
+                    }
+                    (Ok(left_bytes), Ok(right_bytes)) => (left_bytes != right_bytes).then_some(Difference::Content),
+                    (Err(e), _) | (_, Err(e)) => {
+                        warn!("Failed to compare {}: {:#}", path.display(), e);
+                        Some(Difference::Unreadable)
+                    }
+                },
+            };
+            (path.clone(), difference)
+        })
+        .collect();
+
+    let mut diff = TreeDiff::default();
+    for (path, difference) in compared {
+        match difference {
+            Some(difference) => diff.files.push((path, difference)),
+            None => diff.identical += 1,
+        }
+    }
+    Ok(diff)
+}
+
+/// The regular files under `root`, relative to it, at most `max_depth` levels down. Symlinked
+/// directories aren't descended into, and version control metadata is left out.
+fn all_files<F: FileSystem>(fs: &F, root: &Path, max_depth: Option<usize>) -> Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    let mut dirs = vec![(root.to_path_buf(), 1)];
+    while let Some((dir, depth)) = dirs.pop() {
+        let entries = fs
+            .read_dir(&dir)
+            .with_context(|| format!("Failed to list {}", dir.display()))?;
+        for entry in entries {
+            let Ok(relative) = entry.strip_prefix(root) else {
+                continue;
+            };
+            if is_vcs_metadata(relative) {
+                continue;
+            }
+            if fs.is_file(&entry) {
+                files.insert(relative.to_path_buf());
+            } else if fs.is_dir(&entry) && !fs.is_symlink(&entry) && max_depth.is_none_or(|max| depth < max) {
+                dirs.push((entry, depth + 1));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// True if `path` is version control metadata or inside it.
+fn is_vcs_metadata(path: &Path) -> bool {
+    path.components()
+        .any(|component| VCS_METADATA.iter().any(|vcs| component.as_os_str() == *vcs))
+}
+
+/// How `right` differs from `left`, or None if they are the same bytes. Trailing whitespace is
+/// what `rules` would strip.
+pub fn compare_contents(left: &[u8], right: &[u8], rules: &Rules) -> Option<Difference> {
+    if left == right {
+        return None;
+    }
+    let same_text = match (std::str::from_utf8(left), std::str::from_utf8(right)) {
+        (Ok(left), Ok(right)) => normalized_lines(left, rules).eq(normalized_lines(right, rules)),
+        _ => false,
+    };
+    Some(if same_text { Difference::WhitespaceOnly } else { Difference::Content })
+}
+
+/// The lines of `content` without their endings or trailing whitespace, leaving out the blank
+/// lines at the end.
+fn normalized_lines<'a>(content: &'a str, rules: &'a Rules) -> impl Iterator<Item = &'a str> {
+    let lines: Vec<&str> = content
+        .split('\n')
+        .map(|line| trim_line(line.strip_suffix('\r').unwrap_or(line), rules))
+        .collect();
+    let end = lines
+        .iter()
+        .rposition(|line| !line.is_empty())
+        .map_or(0, |last| last + 1);
+    lines.into_iter().take(end)
+}
+
+/// List the files that differ between the trees, with how, and a summary.
+pub fn display_tree_diff(left: &Path, right: &Path, diff: &TreeDiff) {
+    for (path, difference) in &diff.files {
+        let path = path.display().to_string();
+        match difference {
+            Difference::WhitespaceOnly => println!("{} {}", "≈".green(), path.blue()),
+            Difference::Content => println!("{} {} {}", "≠".red(), path.blue(), "(content differs)".red()),
+            Difference::OnlyInLeft => println!(
+                "{} {} {}",
+                "-".red(),
+                path.blue(),
+                format!("(only in {})", left.display()).dimmed()
+            ),
+            Difference::OnlyInRight => println!(
+                "{} {} {}",
+                "+".red(),
+                path.blue(),
+                format!("(only in {})", right.display()).dimmed()
+            ),
+            Difference::Unreadable => println!("{} {} {}", "?".yellow(), path.blue(), "(unreadable)".yellow()),
+        }
+    }
+
+    let whitespace_only = diff.count(Difference::WhitespaceOnly);
+    let other = diff.files.len() - whitespace_only;
+    let compared = diff.identical + diff.files.len();
+    if other == 0 {
+        println!(
+            "\n{}{}",
+            output::icon("✅"),
+            format!(
+                "Only whitespace differs: {} of {} files, the rest are identical",
+                whitespace_only, compared
+            )
+            .green()
+            .bold()
+        );
+    } else {
+        println!(
+            "\n{}{} {}",
+            output::icon("❌").red(),
+            format!("{} files differ in more than whitespace", other).red().bold(),
+            format!(
+                "({} identical, {} whitespace only, {} changed, {} in one tree only)",
+                diff.identical,
+                whitespace_only,
+                diff.count(Difference::Content) + diff.count(Difference::Unreadable),
+                diff.count(Difference::OnlyInLeft) + diff.count(Difference::OnlyInRight)
+            )
+            .dimmed()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+
+    #[test]
+    fn test_compare_contents() {
+        let rules = Rules::default();
+        assert_eq!(compare_contents(b"a\nb\n", b"a\nb\n", &rules), None);
+        for cleaned in [
+            &b"a  \r\nb\t\n\n\n"[..],
+            b"a\nb",
+            b"a \nb\n",
+            b"a\r\nb\r\n",
+            "a\u{A0}\nb\n".as_bytes(),
+        ] {
+            assert_eq!(
+                compare_contents(cleaned, b"a\nb\n", &rules),
+                Some(Difference::WhitespaceOnly),
+                "{:?}",
+                String::from_utf8_lossy(cleaned)
+            );
+        }
+        for changed in [&b"a\nc\n"[..], b"  a\nb\n", b"a\n\nb\n", b"a b\n", b"\xff\n"] {
+            assert_eq!(compare_contents(changed, b"a\nb\n", &rules), Some(Difference::Content));
+        }
+    }
+
+    #[test]
+    fn test_compare_trees() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/before/same.txt", b"same\n")
+                .with_file("/before/src/main.rs", b"fn main() {}  \r\n")
+                .with_file("/before/src/lib.rs", b"pub fn a() {}\n")
+                .with_file("/before/gone.txt", b"gone\n")
+                .with_file("/after/same.txt", b"same\n")
+                .with_file("/after/src/main.rs", b"fn main() {}\n")
+                .with_file("/after/src/lib.rs", b"pub fn b() {}\n")
+                .with_file("/after/new.txt", b"new\n")
+                // Skipped by a run, so compared byte for byte
+                .with_file("/before/img.png", b"\x89PNG\r\n\x1a\n1")
+                .with_file("/after/img.png", b"\x89PNG\r\n\x1a\n2")
+                .with_file("/before/node_modules/a.js", b"a;  \n")
+                .with_file("/after/node_modules/a.js", b"a;\n")
+                .with_file("/before/node_modules/b.js", b"b;\n")
+                .with_file("/after/node_modules/b.js", b"b;\n")
+                // Never compared
+                .with_file("/before/.git/index", b"1")
+                .with_file("/after/.git", b"gitdir: elsewhere"),
+        );
+        let diff = compare_trees(
+            Path::new("/before"),
+            Path::new("/after"),
+            None,
+            Arc::new(Config::default()),
+            fs,
+        )
+        .unwrap();
+
+        assert_eq!(diff.identical, 2);
+        assert_eq!(
+            diff.files,
+            vec![
+                (PathBuf::from("gone.txt"), Difference::OnlyInLeft),
+                (PathBuf::from("img.png"), Difference::Content),
+                (PathBuf::from("new.txt"), Difference::OnlyInRight),
+                (PathBuf::from("node_modules/a.js"), Difference::Content),
+                (PathBuf::from("src/lib.rs"), Difference::Content),
+                (PathBuf::from("src/main.rs"), Difference::WhitespaceOnly),
+            ]
+        );
+        assert_eq!(diff.status(), ExitStatus::Findings);
+
+        let whitespace_only = TreeDiff {
+            identical: 1,
+            files: vec![(PathBuf::from("src/main.rs"), Difference::WhitespaceOnly)],
+        };
+        assert_eq!(whitespace_only.status(), ExitStatus::Success);
+    }
+}
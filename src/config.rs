@@ -1,12 +1,32 @@
-use crate::cli::Cli;
+use crate::cli::{Cli, Command};
+use crate::fixup::FixupMode;
+use crate::literals::Syntax;
+use crate::logging::LoggingConfig;
+use crate::output::{ColorChoice, OutputFormat, SortOrder, StreamOrder};
+use crate::preset::Preset;
+use crate::report::ReportFormat;
+use crate::rules::LineRanges;
 use eyre::{Context, Result};
+use regex::{Regex, RegexSet};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// Default size above which files are streamed rather than read fully into memory (16MB)
+pub const DEFAULT_STREAMING_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize)]
 #[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
+    /// A built-in preset, or another config file relative to this one, to start from instead of
+    /// the defaults
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
     /// File extensions to process (empty = all text files)
     pub file_extensions: Vec<String>,
 
@@ -22,28 +42,633 @@ pub struct Config {
     /// Binary file detection settings
     pub binary_detection: BinaryDetection,
 
+    /// Patch and diff file detection settings
+    pub diff_detection: DiffDetection,
+
+    /// Generated and vendored code detection settings
+    pub generated_detection: GeneratedDetection,
+
+    /// Honor `binary`, `-text` and `eol` in .gitattributes files
+    pub gitattributes: bool,
+
+    /// File-type mappings tried before the built-in ones
+    pub file_types: FileTypeSettings,
+
     /// Processing settings
     pub processing: ProcessingSettings,
+
+    /// Optional rules applied alongside trailing whitespace removal
+    pub rules: Rules,
+
+    /// External commands that transform files after the built-in rules
+    pub custom_rules: Vec<CustomRule>,
+
+    /// Follow symlinked files and directories instead of skipping them
+    pub follow_symlinks: bool,
+
+    /// Leave files with unresolved merge conflict markers alone
+    pub skip_conflicts: bool,
+
+    /// What to do with read-only files that need cleaning
+    pub readonly: ReadOnlyPolicy,
+
+    /// What to do with files that need cleaning and have other hard links
+    pub hardlinks: HardLinkPolicy,
+
+    /// What to do with files holding nothing but whitespace, or only a byte order mark
+    pub whitespace_only_files: WhitespaceOnlyPolicy,
+
+    /// Give rewritten files the originals' extended attributes, such as SELinux labels and
+    /// macOS quarantine flags and resource forks
+    pub preserve_xattrs: bool,
+
+    /// Where the counters of each run are exported
+    pub metrics: MetricsSettings,
+}
+
+/// Where the counters of each run, such as files scanned and modified, are exported, for
+/// dashboards of scheduled runs. Nothing is exported by default.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct MetricsSettings {
+    /// File to write the counters to for the Prometheus node exporter's textfile collector
+    pub prometheus_textfile: Option<PathBuf>,
+
+    /// statsd server to send the counters to, such as `127.0.0.1:8125`
+    pub statsd: Option<String>,
+
+    /// Start of every metric's name
+    pub prefix: String,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            prometheus_textfile: None,
+            statsd: None,
+            prefix: "whitespace".to_string(),
+        }
+    }
+}
+
+/// How files with other hard links that need cleaning are handled. Rewriting one through a temp
+/// file gives it a new inode, leaving the other links with the old content; writing it in place
+/// changes every link, as in a backup tree that deduplicates files by linking them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HardLinkPolicy {
+    /// Leave them alone, with a warning, and report them as skipped
+    #[default]
+    Skip,
+    /// Rewrite them through a temp file, so only this link gets the cleaned content
+    Break,
+    /// Write the cleaned content into the file itself, so every link gets it
+    InPlace,
+}
+
+/// How files holding nothing but whitespace, or only a byte order mark, are handled, such as the
+/// leftovers of a generator that had nothing to write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WhitespaceOnlyPolicy {
+    /// Clean them like any other file
+    #[default]
+    Keep,
+    /// Truncate them to empty files
+    Empty,
+    /// Delete them
+    Delete,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+/// How read-only files that need cleaning are handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "kebab-case")]
+pub enum ReadOnlyPolicy {
+    /// Leave them alone and report them as skipped
+    Skip,
+    /// Like `skip`, but also log a warning for each one
+    #[default]
+    Warn,
+    /// Clear the read-only bit, write, and set it again
+    Force,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
 pub struct BinaryDetection {
     /// Check for null bytes to detect binary files
     pub check_null_bytes: bool,
 
     /// Maximum bytes to read for binary detection
     pub sample_size: usize,
+
+    /// Recognize binary formats by their magic numbers, and UTF-16/32 text by its byte order mark
+    pub inspect_content: bool,
+
+    /// Filename patterns never treated as binary, whatever their content
+    pub text_files: Vec<String>,
+}
+
+/// How to rewrite leading indentation that mixes tabs and spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IndentStyle {
+    Tabs,
+    Spaces,
+}
+
+/// What the end of a file must look like, for `rules.end-of-file`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EndOfFile {
+    /// The last line ends in a line break; one is added where it is missing
+    FinalNewline,
+    /// The last line doesn't end in a backslash, continuing it into nothing; only reported
+    NoContinuation,
+}
+
+/// Trailing whitespace in patches is part of their content, so diff files are skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct DiffDetection {
+    /// Skip patch and diff files (`--force` turns this off for a run)
+    pub enabled: bool,
+
+    /// Filename patterns always treated as diffs; other files are recognized by their content
+    pub files: Vec<String>,
+}
+
+/// Generated and vendored code belongs to its generator or upstream, so, as with GitHub's
+/// linguist, it is skipped. `linguist-generated` and `linguist-vendored` in `.gitattributes`
+/// mark files either way, overriding the file-name patterns.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct GeneratedDetection {
+    /// Skip generated and vendored files (`--include-generated` turns this off for a run)
+    pub enabled: bool,
+
+    /// Filename patterns of generated files
+    pub files: Vec<String>,
+
+    /// Text in the first lines of a file that marks it as generated, such as `@generated`
+    pub markers: Vec<String>,
+
+    /// Directory name patterns of vendored code, which the walk never enters
+    pub vendored_dirs: Vec<String>,
+}
+
+/// Extra ways to recognize a file's type, for rule overrides' `types`. See
+/// [`FileTypes`](crate::filetypes::FileTypes) for the built-in ones, which these come before.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct FileTypeSettings {
+    /// File-name patterns, such as `*.tpl` or `Brewfile`, and the type each gives
+    pub names: BTreeMap<String, String>,
+
+    /// Programs run by a `#!` line, such as `nu`, and the type each gives
+    pub interpreters: BTreeMap<String, String>,
+}
+
+/// Line endings written for the lines of a rewritten file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEndings {
+    /// Keep each line's `\n` or `\r\n`
+    Preserve,
+    /// Write `\n` for every line
+    Lf,
+    /// Write `\r\n` for every line
+    Crlf,
+}
+
+impl Default for LineEndings {
+    /// Windows files are typically CRLF, so they are preserved there
+    fn default() -> Self {
+        if cfg!(windows) { Self::Preserve } else { Self::Lf }
+    }
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Which characters count as trailing whitespace.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
 #[serde(rename_all = "kebab-case")]
+pub enum TrailingWhitespace {
+    /// Spaces, tabs, form feeds and carriage returns only; Unicode spaces like NBSP are kept
+    Ascii,
+    /// Any Unicode `White_Space` character, including NBSP and full-width spaces
+    #[default]
+    Unicode,
+}
+
+impl TrailingWhitespace {
+    pub fn matches(self, c: char) -> bool {
+        match self {
+            Self::Ascii => c.is_ascii_whitespace(),
+            Self::Unicode => c.is_whitespace(),
+        }
+    }
+}
+
+/// Regular expressions for lines whose trailing whitespace is kept, for
+/// `rules.preserve-line-patterns`. They are compiled once, when the config is loaded, so an
+/// invalid one fails the load.
+#[derive(Debug, Clone, Default)]
+pub struct LinePatterns {
+    patterns: Vec<String>,
+    compiled: RegexSet,
+}
+
+impl LinePatterns {
+    pub fn new(patterns: Vec<String>) -> Result<Self, regex::Error> {
+        let compiled = RegexSet::new(&patterns)?;
+        Ok(Self { patterns, compiled })
+    }
+
+    /// True if any pattern matches `line` (without its line ending).
+    pub fn matches(&self, line: &str) -> bool {
+        !self.patterns.is_empty() && self.compiled.is_match(line)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+impl PartialEq for LinePatterns {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Eq for LinePatterns {}
+
+impl Serialize for LinePatterns {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LinePatterns {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        Self::new(patterns).map_err(|e| de::Error::custom(format!("invalid line pattern: {}", e)))
+    }
+}
+
+/// Rules beyond trailing whitespace removal. All are off by default.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct Rules {
+    /// Character class stripped from line ends
+    pub trailing_whitespace: TrailingWhitespace,
+
+    /// Extra characters stripped from line ends, such as zero-width spaces (U+200B)
+    pub extra_trailing_chars: Vec<char>,
+
+    /// Line endings written when a file is rewritten
+    pub line_endings: LineEndings,
+
+    /// Normalize indentation that mixes tabs and spaces to this style (None = leave it alone)
+    pub indent_style: Option<IndentStyle>,
+
+    /// Columns per tab stop when normalizing indentation
+    pub indent_width: usize,
+
+    /// Squeeze runs of blank lines longer than this (None = leave them alone)
+    pub max_consecutive_blank_lines: Option<usize>,
+
+    /// Remove blank lines at the end of a file, leaving its last line's newline
+    pub trim_trailing_blank_lines: bool,
+
+    /// Keep two trailing spaces after content, which Markdown renders as a hard line break
+    pub preserve_hard_breaks: bool,
+
+    /// Leave lines inside multi-line string literals alone in Rust, Python and YAML files
+    pub preserve_string_literals: bool,
+
+    /// Report lines wider than this many columns, without changing them (None = don't check)
+    pub max_line_length: Option<usize>,
+
+    /// What the end of a file must look like, usually set for some file types by `overrides`
+    pub end_of_file: Vec<EndOfFile>,
+
+    /// Regular expressions for lines whose trailing whitespace is kept, such as `-- $` for SQL
+    /// comment markers
+    #[schemars(with = "Vec<String>")]
+    pub preserve_line_patterns: LinePatterns,
+
+    /// Regular expression replacements run on each line of the files they name, after the
+    /// rules above have cleaned it
+    pub replacements: Vec<Replacement>,
+
+    /// The file's language, set by [`RuleSet::for_file`](crate::rules::RuleSet::for_file) when
+    /// `preserve_string_literals` is on
+    #[serde(skip)]
+    pub literal_syntax: Option<Syntax>,
+
+    /// Only lines in these ranges are cleaned, set by
+    /// [`WhitespaceProcessor::with_line_ranges`](crate::processor::WhitespaceProcessor::with_line_ranges)
+    #[serde(skip)]
+    pub line_ranges: Option<LineRanges>,
+
+    /// The rules a run is limited to, set by
+    /// [`WhitespaceProcessor::with_rule_selection`](crate::processor::WhitespaceProcessor::with_rule_selection)
+    #[serde(skip)]
+    pub selection: Option<RuleSelection>,
+
+    /// Per-file-type rule changes, applied in order to files whose name matches
+    pub overrides: Vec<RuleOverride>,
+}
+
+impl Rules {
+    /// Whether the rule named `rule` runs, which all of them do unless a selection says otherwise.
+    pub fn runs(&self, rule: &str) -> bool {
+        self.selection.as_ref().is_none_or(|selection| selection.allows(rule))
+    }
+}
+
+/// Built-in rules `--only` and `--skip` can name; replacements and custom rules go by their own
+/// names.
+pub const RULE_NAMES: &[&str] = &[
+    "trailing-whitespace",
+    "indent-style",
+    "line-endings",
+    "max-consecutive-blank-lines",
+    "trim-trailing-blank-lines",
+    "end-of-file",
+    "max-line-length",
+    "whitespace-only-files",
+];
+
+/// The rules one run applies, from `--only` and `--skip`: those named by `only` (every rule, if
+/// it is empty) less those named by `skip`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleSelection {
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+}
+
+impl RuleSelection {
+    pub fn allows(&self, rule: &str) -> bool {
+        (self.only.is_empty() || self.only.iter().any(|name| name == rule))
+            && !self.skip.iter().any(|name| name == rule)
+    }
+
+    /// Fail on a name that is neither a built-in rule nor a replacement or custom rule in `config`.
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        let known: Vec<&str> = RULE_NAMES
+            .iter()
+            .copied()
+            .chain(config.rules.replacements.iter().map(|replacement| replacement.name))
+            .chain(config.custom_rules.iter().map(|rule| rule.name.as_str()))
+            .collect();
+        let named = self
+            .only
+            .iter()
+            .map(|name| ("only", name))
+            .chain(self.skip.iter().map(|name| ("skip", name)));
+        for (flag, name) in named {
+            if !known.contains(&name.as_str()) {
+                eyre::bail!("Unknown rule for --{}: {} (rules are {})", flag, name, known.join(", "));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A regular expression replacement run on every line of matching files, for
+/// `rules.replacements`. The pattern is compiled once, when the config is loaded, so an invalid
+/// one fails the load.
+#[derive(Debug, Clone, JsonSchema)]
+#[schemars(with = "ReplacementSpec")]
+pub struct Replacement {
+    /// Name reported for the lines the replacement changes (the pattern, if not given)
+    pub name: &'static str,
+    /// Filename glob patterns the replacement applies to (empty = every file)
+    pub files: Vec<String>,
+    pub pattern: Regex,
+    /// What each match becomes, with `$1` or `${name}` for its groups
+    pub replace: String,
+}
+
+impl PartialEq for Replacement {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.files == other.files
+            && self.pattern.as_str() == other.pattern.as_str()
+            && self.replace == other.replace
+    }
+}
+
+impl Eq for Replacement {}
+
+impl Serialize for Replacement {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ReplacementSpec::from(self.clone()).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Replacement {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::try_from(ReplacementSpec::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// How a [`Replacement`] is written in config files.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+struct ReplacementSpec {
+    /// Name used in reports; the pattern is used when it is empty
+    #[serde(skip_serializing_if = "String::is_empty")]
+    name: String,
+    /// Filename glob patterns the replacement applies to (empty = every file)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    files: Vec<String>,
+    /// Regular expression matched against each line, without its line ending
+    pattern: String,
+    /// What each match becomes, with `$1` or `${name}` for its groups
+    replace: String,
+}
+
+impl TryFrom<ReplacementSpec> for Replacement {
+    type Error = String;
+
+    fn try_from(spec: ReplacementSpec) -> Result<Self, String> {
+        let pattern = Regex::new(&spec.pattern)
+            .map_err(|e| format!("invalid pattern in replacement `{}`: {}", spec.pattern, e))?;
+        let name = if spec.name.is_empty() { &spec.pattern } else { &spec.name };
+        // Each line is replaced on its own, so a line break in its middle would split it
+        if spec.replace.contains(['\n', '\r']) {
+            return Err(format!("replacement `{}` can't put a line break in a line", name));
+        }
+        Ok(Self {
+            name: intern(name),
+            files: spec.files,
+            pattern,
+            replace: spec.replace,
+        })
+    }
+}
+
+impl From<Replacement> for ReplacementSpec {
+    fn from(replacement: Replacement) -> Self {
+        let pattern = replacement.pattern.as_str().to_string();
+        Self {
+            name: if replacement.name == pattern { String::new() } else { replacement.name.to_string() },
+            files: replacement.files,
+            pattern,
+            replace: replacement.replace,
+        }
+    }
+}
+
+/// `name` as a `&'static str`, which lines changed by a transform are attributed to. Each
+/// distinct name is leaked once, however many times configs are loaded.
+fn intern(name: &str) -> &'static str {
+    static NAMES: std::sync::Mutex<std::collections::BTreeSet<&'static str>> =
+        std::sync::Mutex::new(std::collections::BTreeSet::new());
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    match names.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(interned);
+            interned
+        }
+    }
+}
+
+/// An external command that transforms a file's content, run after the built-in rules. See
+/// [`custom::apply`](crate::custom::apply) for how it is run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct CustomRule {
+    /// Name used in logs and errors
+    pub name: String,
+
+    /// Program and arguments; the content arrives on stdin and the result is read from stdout
+    pub command: Vec<String>,
+
+    /// Filename glob patterns the rule applies to (empty = every file)
+    pub files: Vec<String>,
+}
+
+/// Rules that replace the defaults for files whose name matches any of `files`, or whose type
+/// is one of `types`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+pub struct RuleOverride {
+    /// Filename glob patterns, like `exclude-files`
+    pub files: Vec<String>,
+
+    /// File types, such as `shell` or `make`, recognized by name or shebang
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub types: Vec<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_consecutive_blank_lines: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trim_trailing_blank_lines: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_hard_breaks: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_string_literals: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_line_length: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_of_file: Option<Vec<EndOfFile>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<Vec<String>>")]
+    pub preserve_line_patterns: Option<LinePatterns>,
+}
+
+impl RuleOverride {
+    /// Replace the rules this override sets, leaving the rest untouched.
+    pub fn apply(&self, rules: &mut Rules) {
+        if let Some(max) = self.max_consecutive_blank_lines {
+            rules.max_consecutive_blank_lines = Some(max);
+        }
+        if let Some(trim) = self.trim_trailing_blank_lines {
+            rules.trim_trailing_blank_lines = trim;
+        }
+        if let Some(preserve) = self.preserve_hard_breaks {
+            rules.preserve_hard_breaks = preserve;
+        }
+        if let Some(preserve) = self.preserve_string_literals {
+            rules.preserve_string_literals = preserve;
+        }
+        if let Some(max) = self.max_line_length {
+            rules.max_line_length = Some(max);
+        }
+        if let Some(end_of_file) = &self.end_of_file {
+            rules.end_of_file = end_of_file.clone();
+        }
+        if let Some(patterns) = &self.preserve_line_patterns {
+            rules.preserve_line_patterns = patterns.clone();
+        }
+    }
+}
+
+#[derive(Debug, Clone, JsonSchema, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
 pub struct ProcessingSettings {
+    /// Files larger than this (in bytes) are skipped
+    #[schemars(schema_with = "size_schema")]
     pub max_file_size: u64,
+    /// Parallel threads
     #[serde(deserialize_with = "deserialize_threads")]
-    pub threads: usize,
+    #[schemars(schema_with = "threads_schema", extend("default" = "nproc"))]
+    pub threads: Threads,
+    /// Files larger than this (in bytes) are streamed line-by-line instead of read into memory
+    #[schemars(schema_with = "size_schema")]
+    pub streaming_threshold: u64,
+    /// Memory-map files above the streaming threshold instead of streaming them
+    pub mmap: bool,
+    /// Record each rewrite in an intent log before making it, so that one a crash interrupts
+    /// is finished or undone by the next run
+    pub intent_log: bool,
+    /// Files read or written at once, however many threads process them (None = one per thread)
+    pub io_threads: Option<usize>,
+    /// Times a read or write failing with a transient error, such as EIO or ESTALE from a
+    /// network filesystem, is tried again
+    pub io_retries: u32,
+    /// Take a file whose size and modification time are unchanged to be unchanged, so the
+    /// cache can skip it without reading it
+    pub trust_mtime: bool,
+}
+
+impl ProcessingSettings {
+    /// Settle for what network filesystems handle well, for `--nfs-safe`: no memory-mapping,
+    /// few files read or written at once, transient errors retried, and the cache checking
+    /// content rather than modification times, which clients cache and servers may round.
+    pub fn apply_nfs_safe(&mut self) {
+        self.mmap = false;
+        self.io_threads = Some(self.io_threads.map_or(NFS_IO_THREADS, |io| io.min(NFS_IO_THREADS)));
+        self.io_retries = self.io_retries.max(NFS_IO_RETRIES);
+        self.trust_mtime = false;
+    }
 }
 
+/// Files `--nfs-safe` reads or writes at once.
+const NFS_IO_THREADS: usize = 4;
+/// Retries `--nfs-safe` allows a read or write.
+const NFS_IO_RETRIES: u32 = 3;
+
 impl<'de> Deserialize<'de> for ProcessingSettings {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -57,6 +682,12 @@ impl<'de> Deserialize<'de> for ProcessingSettings {
         enum Field {
             MaxFileSize,
             Threads,
+            StreamingThreshold,
+            Mmap,
+            IntentLog,
+            IoThreads,
+            IoRetries,
+            TrustMtime,
         }
 
         struct ProcessingSettingsVisitor;
@@ -74,6 +705,12 @@ impl<'de> Deserialize<'de> for ProcessingSettings {
             {
                 let mut max_file_size = None;
                 let mut threads = None;
+                let mut streaming_threshold = None;
+                let mut mmap = None;
+                let mut intent_log = None;
+                let mut io_threads = None;
+                let mut io_retries = None;
+                let mut trust_mtime = None;
 
                 while let Some(key) = map.next_key()? {
                     match key {
@@ -81,7 +718,7 @@ impl<'de> Deserialize<'de> for ProcessingSettings {
                             if max_file_size.is_some() {
                                 return Err(de::Error::duplicate_field("max-file-size"));
                             }
-                            max_file_size = Some(map.next_value()?);
+                            max_file_size = Some(deserialize_size_value("max-file-size", map.next_value()?)?);
                         }
                         Field::Threads => {
                             if threads.is_some() {
@@ -89,24 +726,128 @@ impl<'de> Deserialize<'de> for ProcessingSettings {
                             }
                             threads = Some(deserialize_threads_value(map.next_value()?)?);
                         }
+                        Field::StreamingThreshold => {
+                            if streaming_threshold.is_some() {
+                                return Err(de::Error::duplicate_field("streaming-threshold"));
+                            }
+                            streaming_threshold =
+                                Some(deserialize_size_value("streaming-threshold", map.next_value()?)?);
+                        }
+                        Field::Mmap => {
+                            if mmap.is_some() {
+                                return Err(de::Error::duplicate_field("mmap"));
+                            }
+                            mmap = Some(map.next_value()?);
+                        }
+                        Field::IntentLog => {
+                            if intent_log.is_some() {
+                                return Err(de::Error::duplicate_field("intent-log"));
+                            }
+                            intent_log = Some(map.next_value()?);
+                        }
+                        Field::IoThreads => {
+                            if io_threads.is_some() {
+                                return Err(de::Error::duplicate_field("io-threads"));
+                            }
+                            let value: Option<usize> = map.next_value()?;
+                            if value == Some(0) {
+                                return Err(de::Error::custom("io-threads must be greater than 0"));
+                            }
+                            io_threads = Some(value);
+                        }
+                        Field::IoRetries => {
+                            if io_retries.is_some() {
+                                return Err(de::Error::duplicate_field("io-retries"));
+                            }
+                            io_retries = Some(map.next_value()?);
+                        }
+                        Field::TrustMtime => {
+                            if trust_mtime.is_some() {
+                                return Err(de::Error::duplicate_field("trust-mtime"));
+                            }
+                            trust_mtime = Some(map.next_value()?);
+                        }
                     }
                 }
 
                 let max_file_size = max_file_size.unwrap_or(100 * 1024 * 1024);
-                let threads = threads.unwrap_or_else(num_cpus::get);
+                let threads = threads.unwrap_or_default();
+                let streaming_threshold = streaming_threshold.unwrap_or(DEFAULT_STREAMING_THRESHOLD);
+                let mmap = mmap.unwrap_or(false);
+                let intent_log = intent_log.unwrap_or(false);
+                let io_threads = io_threads.unwrap_or(None);
+                let io_retries = io_retries.unwrap_or(0);
+                let trust_mtime = trust_mtime.unwrap_or(true);
 
-                Ok(ProcessingSettings { max_file_size, threads })
+                Ok(ProcessingSettings {
+                    max_file_size,
+                    threads,
+                    streaming_threshold,
+                    mmap,
+                    intent_log,
+                    io_threads,
+                    io_retries,
+                    trust_mtime,
+                })
             }
         }
 
-        const FIELDS: &[&str] = &["max-file-size", "threads"];
+        const FIELDS: &[&str] = &[
+            "max-file-size",
+            "threads",
+            "streaming-threshold",
+            "mmap",
+            "intent-log",
+            "io-threads",
+            "io-retries",
+            "trust-mtime",
+        ];
         deserializer.deserialize_struct("ProcessingSettings", FIELDS, ProcessingSettingsVisitor)
     }
 }
 
+/// How many threads process files: a fixed count, or `auto-io` to choose one by timing how
+/// long a sample of the files takes to read against how long it takes to scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threads {
+    Count(usize),
+    AutoIo,
+}
+
+impl Default for Threads {
+    fn default() -> Self {
+        Threads::Count(num_cpus::get())
+    }
+}
+
+impl From<usize> for Threads {
+    fn from(count: usize) -> Self {
+        Threads::Count(count)
+    }
+}
+
+impl std::fmt::Display for Threads {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Threads::Count(count) => write!(f, "{}", count),
+            Threads::AutoIo => f.write_str("auto-io"),
+        }
+    }
+}
+
+impl Serialize for Threads {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Threads::Count(count) => serializer.serialize_u64(*count as u64),
+            Threads::AutoIo => serializer.serialize_str("auto-io"),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
+            extends: None,
             file_extensions: vec![],
             exclude_paths: vec![
                 // Version control
@@ -198,7 +939,20 @@ impl Default for Config {
                 "*.jar".to_string(),
             ],
             binary_detection: BinaryDetection::default(),
+            diff_detection: DiffDetection::default(),
+            generated_detection: GeneratedDetection::default(),
+            gitattributes: true,
+            file_types: FileTypeSettings::default(),
             processing: ProcessingSettings::default(),
+            rules: Rules::default(),
+            custom_rules: Vec::new(),
+            follow_symlinks: false,
+            skip_conflicts: true,
+            readonly: ReadOnlyPolicy::Warn,
+            hardlinks: HardLinkPolicy::Skip,
+            whitespace_only_files: WhitespaceOnlyPolicy::Keep,
+            preserve_xattrs: true,
+            metrics: MetricsSettings::default(),
         }
     }
 }
@@ -208,6 +962,99 @@ impl Default for BinaryDetection {
         Self {
             check_null_bytes: true,
             sample_size: 8192,
+            inspect_content: true,
+            text_files: Vec::new(),
+        }
+    }
+}
+
+impl Default for DiffDetection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            files: vec!["*.patch".to_string(), "*.diff".to_string()],
+        }
+    }
+}
+
+impl Default for GeneratedDetection {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            files: vec![
+                // Protocol buffers and gRPC
+                "*.pb.go".to_string(),
+                "*.pb.cc".to_string(),
+                "*.pb.h".to_string(),
+                "*_pb2.py".to_string(),
+                "*_pb2_grpc.py".to_string(),
+                // Code generators' own conventions
+                "*.generated.*".to_string(),
+                "*.g.dart".to_string(),
+                "*.freezed.dart".to_string(),
+                "*.Designer.cs".to_string(),
+                // Lock files and source maps
+                "package-lock.json".to_string(),
+                "pnpm-lock.yaml".to_string(),
+                "go.sum".to_string(),
+                "*.js.map".to_string(),
+                "*.css.map".to_string(),
+            ],
+            markers: vec![
+                "@generated".to_string(),
+                "DO NOT EDIT".to_string(),
+                "Code generated by".to_string(),
+                "Autogenerated by".to_string(),
+            ],
+            vendored_dirs: vec![
+                "third_party".to_string(),
+                "third-party".to_string(),
+                "bower_components".to_string(),
+                "Godeps".to_string(),
+            ],
+        }
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            trailing_whitespace: TrailingWhitespace::Unicode,
+            extra_trailing_chars: Vec::new(),
+            line_endings: LineEndings::default(),
+            indent_style: None,
+            indent_width: 4,
+            max_consecutive_blank_lines: None,
+            trim_trailing_blank_lines: false,
+            preserve_hard_breaks: false,
+            preserve_string_literals: false,
+            max_line_length: None,
+            end_of_file: Vec::new(),
+            preserve_line_patterns: LinePatterns::default(),
+            replacements: Vec::new(),
+            literal_syntax: None,
+            line_ranges: None,
+            selection: None,
+            overrides: vec![
+                // Markdown renders two trailing spaces as a line break
+                RuleOverride {
+                    files: vec!["*.md".to_string(), "*.markdown".to_string()],
+                    preserve_hard_breaks: Some(true),
+                    ..RuleOverride::default()
+                },
+                // Trailing spaces and blank lines inside block scalars are part of their value
+                RuleOverride {
+                    files: vec!["*.yml".to_string(), "*.yaml".to_string()],
+                    preserve_string_literals: Some(true),
+                    ..RuleOverride::default()
+                },
+                // Trailing spaces inside quoted fields are data
+                RuleOverride {
+                    types: vec!["csv".to_string(), "tsv".to_string()],
+                    preserve_string_literals: Some(true),
+                    ..RuleOverride::default()
+                },
+            ],
         }
     }
 }
@@ -216,14 +1063,20 @@ impl Default for ProcessingSettings {
     fn default() -> Self {
         Self {
             max_file_size: 100 * 1024 * 1024, // 100MB
-            threads: num_cpus::get(),
+            threads: Threads::default(),
+            streaming_threshold: DEFAULT_STREAMING_THRESHOLD,
+            mmap: false,
+            intent_log: false,
+            io_threads: None,
+            io_retries: 0,
+            trust_mtime: true,
         }
     }
 }
 
 use serde::de;
 
-fn deserialize_threads_value<E>(value: serde_yaml::Value) -> Result<usize, E>
+fn deserialize_threads_value<E>(value: serde_yaml::Value) -> Result<Threads, E>
 where
     E: de::Error,
 {
@@ -233,56 +1086,578 @@ where
                 if u == 0 {
                     return Err(E::custom("threads must be greater than 0"));
                 }
-                Ok(u as usize)
+                Ok(Threads::Count(u as usize))
             } else {
                 Err(E::custom("threads must be a positive integer"))
             }
         }
-        serde_yaml::Value::String(s) => match s.as_str() {
-            "nproc" => Ok(num_cpus::get()),
-            _ => Err(E::custom(format!(
-                "invalid thread value: '{}', expected a positive integer or 'nproc'",
-                s
-            ))),
-        },
-        _ => Err(E::custom("threads must be a positive integer or the string 'nproc'")),
+        serde_yaml::Value::String(s) => parse_threads(&s).map_err(E::custom),
+        _ => Err(E::custom(
+            "threads must be a positive integer or the string 'nproc' or 'auto-io'",
+        )),
     }
 }
 
-impl Config {
-    /// Load configuration with fallback chain
-    pub fn load(config_path: Option<&PathBuf>) -> Result<Self> {
-        // If explicit config path provided, try to load it
-        if let Some(path) = config_path {
-            return Self::load_from_file(path).context(format!("Failed to load config from {}", path.display()));
-        }
-
-        // Try primary location: ~/.config/whitespace/whitespace.yml
-        if let Some(config_dir) = dirs::config_dir() {
-            let project_name = env!("CARGO_PKG_NAME");
-            let primary_config = config_dir.join(project_name).join(format!("{}.yml", project_name));
-            if primary_config.exists() {
-                match Self::load_from_file(&primary_config) {
-                    Ok(config) => return Ok(config),
-                    Err(e) => {
-                        log::warn!("Failed to load config from {}: {}", primary_config.display(), e);
-                    }
-                }
-            }
-        }
-
-        // No config file found, use defaults
-        log::info!("No config file found, using defaults");
-        Ok(Self::default())
-    }
-
-    fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = fs::read_to_string(&path).context("Failed to read config file")?;
-
-        let config: Self = serde_yaml::from_str(&content).context("Failed to parse config file")?;
-
-        log::info!("Loaded config from: {}", path.as_ref().display());
-        Ok(config)
+/// Parse a thread setting: a count, `nproc` for one per CPU, or `auto-io`.
+pub fn parse_threads(input: &str) -> Result<Threads, String> {
+    match input.trim() {
+        "nproc" => Ok(Threads::default()),
+        "auto-io" => Ok(Threads::AutoIo),
+        count => count.parse().map(Threads::Count).map_err(|_| {
+            format!(
+                "invalid thread value: '{}', expected a positive integer, 'nproc' or 'auto-io'",
+                input
+            )
+        }),
+    }
+}
+
+fn deserialize_size_value<E>(key: &str, value: serde_yaml::Value) -> Result<u64, E>
+where
+    E: de::Error,
+{
+    match value {
+        serde_yaml::Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| E::custom(format!("{} must be a non-negative number of bytes", key))),
+        serde_yaml::Value::String(s) => parse_size(&s).map_err(|e| E::custom(format!("{}: {}", key, e))),
+        _ => Err(E::custom(format!(
+            "{} must be a byte count or a size such as \"10MB\"",
+            key
+        ))),
+    }
+}
+
+/// Sizes are a byte count or a number with a unit, as [`parse_size`] reads them.
+fn size_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "oneOf": [
+            { "type": "integer", "minimum": 0 },
+            {
+                "type": "string",
+                "pattern": "^\\s*([0-9]+\\.?[0-9]*|\\.[0-9]+)\\s*([bB]|[kKmMgGtT]([iI]?[bB])?)?\\s*$"
+            }
+        ]
+    })
+}
+
+/// Threads are a positive count, `nproc` or `auto-io`, as [`deserialize_threads_value`] reads them.
+fn threads_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+    schemars::json_schema!({
+        "oneOf": [
+            { "type": "integer", "minimum": 1 },
+            { "enum": ["nproc", "auto-io"] }
+        ]
+    })
+}
+
+/// Parse a size such as `1048576`, `512K`, `10MB` or `1.5GiB`. Units are case-insensitive
+/// powers of 1024, so `KB` and `KiB` mean the same thing.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let trimmed = input.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+    let (number, unit) = trimmed.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("invalid size '{}': unknown unit '{}'", input, unit.trim())),
+    };
+
+    if let Ok(whole) = number.parse::<u64>() {
+        return whole
+            .checked_mul(multiplier)
+            .ok_or_else(|| format!("invalid size '{}': too large", input));
+    }
+    match number.parse::<f64>() {
+        Ok(fraction) if fraction.is_finite() && fraction >= 0.0 => {
+            let bytes = fraction * multiplier as f64;
+            if bytes >= u64::MAX as f64 {
+                return Err(format!("invalid size '{}': too large", input));
+            }
+            Ok(bytes.round() as u64)
+        }
+        _ => Err(format!(
+            "invalid size '{}', expected a byte count or a number with a unit such as 10MB",
+            input
+        )),
+    }
+}
+
+/// Comments written above each setting by [`Config::to_commented_yaml`].
+const SETTING_DOCS: &[(&str, &str)] = &[
+    ("file-extensions", "File extensions to process (empty = all text files)"),
+    (
+        "exclude-paths",
+        "Path patterns to exclude (glob patterns)\nRelative patterns match at any depth below each target directory;\npatterns starting with \"/\" are matched against the absolute path.",
+    ),
+    ("exclude-files", "Filename patterns to exclude"),
+    (
+        "exclude-binary-extensions",
+        "Binary file extensions to exclude (fast pre-filter)",
+    ),
+    ("binary-detection", "Binary file detection"),
+    (
+        "binary-detection.check-null-bytes",
+        "Treat files containing null bytes as binary",
+    ),
+    (
+        "binary-detection.sample-size",
+        "Bytes read from the start of each file for binary detection",
+    ),
+    (
+        "binary-detection.inspect-content",
+        "Recognize binaries by magic number, and UTF-16/32 text (which has null bytes) by its BOM",
+    ),
+    (
+        "binary-detection.text-files",
+        "Filename patterns never treated as binary by content detection",
+    ),
+    (
+        "diff-detection",
+        "Patch and diff files, whose trailing whitespace is meaningful",
+    ),
+    (
+        "diff-detection.enabled",
+        "Skip patch and diff files, recognized by name or content (--force overrides)",
+    ),
+    ("diff-detection.files", "Filename patterns always treated as diffs"),
+    (
+        "generated-detection",
+        "Generated and vendored code, which belongs to its generator or upstream",
+    ),
+    (
+        "generated-detection.enabled",
+        "Skip generated and vendored files, recognized by name, header or .gitattributes (--include-generated overrides)",
+    ),
+    ("generated-detection.files", "Filename patterns of generated files"),
+    (
+        "generated-detection.markers",
+        "Text in a file's first lines that marks it as generated",
+    ),
+    (
+        "generated-detection.vendored-dirs",
+        "Directory name patterns of vendored code, which is never entered",
+    ),
+    (
+        "gitattributes",
+        "Skip files .gitattributes marks `binary` or `-text`, and write the line endings its `eol` asks for",
+    ),
+    (
+        "file-types",
+        "Ways to recognize a file's type for `rules.overrides` `types`, tried before the built-in ones",
+    ),
+    (
+        "file-types.names",
+        "File-name patterns and the type each gives, e.g. {\"*.tpl\": html, Brewfile: ruby}",
+    ),
+    (
+        "file-types.interpreters",
+        "Programs a `#!` line runs and the type each gives, e.g. {nu: nushell}",
+    ),
+    ("processing", "Processing settings"),
+    (
+        "processing.max-file-size",
+        "Files larger than this are skipped: bytes, or a size such as \"100MB\"",
+    ),
+    (
+        "processing.threads",
+        "Parallel threads: a positive integer, \"nproc\", or \"auto-io\" to choose from a sample of the files",
+    ),
+    (
+        "processing.streaming-threshold",
+        "Files larger than this are streamed line-by-line instead of read into memory: bytes, or a size such as \"16MB\"",
+    ),
+    (
+        "processing.mmap",
+        "Memory-map files above the streaming threshold instead of streaming them",
+    ),
+    (
+        "processing.intent-log",
+        "Log each rewrite before making it, so the next run finishes or undoes any a crash interrupted",
+    ),
+    (
+        "processing.io-threads",
+        "Files read or written at once, however many threads process them; null allows one per thread",
+    ),
+    (
+        "processing.io-retries",
+        "Times a read or write failing with a transient error (EIO, ESTALE) is retried, with backoff",
+    ),
+    (
+        "processing.trust-mtime",
+        "Let the cache skip files whose size and modification time are unchanged without reading them",
+    ),
+    ("rules", "Rules beyond trailing whitespace removal (all off by default)"),
+    (
+        "rules.trailing-whitespace",
+        "Characters stripped from line ends: \"unicode\" (any whitespace, including NBSP) or \"ascii\"",
+    ),
+    (
+        "rules.extra-trailing-chars",
+        "Extra characters stripped from line ends, e.g. [\"\\u200B\"] for zero-width spaces",
+    ),
+    (
+        "rules.line-endings",
+        "Line endings in rewritten files: \"lf\", \"crlf\" or \"preserve\" (the default on Windows)",
+    ),
+    (
+        "rules.indent-style",
+        "Normalize indentation that mixes tabs and spaces: \"tabs\", \"spaces\" or null (off)",
+    ),
+    (
+        "rules.indent-width",
+        "Columns per tab stop when normalizing indentation",
+    ),
+    (
+        "rules.max-consecutive-blank-lines",
+        "Squeeze runs of blank lines longer than this; null leaves them alone",
+    ),
+    (
+        "rules.trim-trailing-blank-lines",
+        "Remove blank lines at the end of a file, leaving exactly one final newline",
+    ),
+    (
+        "rules.preserve-hard-breaks",
+        "Keep exactly two trailing spaces after content (a Markdown hard line break)",
+    ),
+    (
+        "rules.preserve-string-literals",
+        "Leave lines inside multi-line strings alone (Rust and Python strings, YAML block scalars, quoted\nCSV and TSV fields)",
+    ),
+    (
+        "rules.max-line-length",
+        "Report lines wider than this many columns (tabs stop every `indent-width`), never changing them;\nnull doesn't check",
+    ),
+    (
+        "rules.preserve-line-patterns",
+        "Regular expressions for lines whose trailing whitespace is kept, e.g. [\"-- $\"] for SQL comment markers",
+    ),
+    (
+        "rules.replacements",
+        "Regular expression replacements run on each line after the built-in rules: each has a `pattern`,\nwhat to `replace` matches with, and optional `name` and `files` globs",
+    ),
+    (
+        "rules.end-of-file",
+        "How files must end: \"final-newline\" (added where missing) and \"no-continuation\" (a last line\nending in a backslash is reported); usually set per file type in `overrides`",
+    ),
+    (
+        "rules.overrides",
+        "Rule changes for files whose name matches `files` globs or whose type is in `types`; replaces the default Markdown, YAML and CSV overrides",
+    ),
+    (
+        "custom-rules",
+        "External commands run after the built-in rules: each has a `name`, a `command` that reads the\nfile on stdin and prints the result, and optional `files` globs",
+    ),
+    (
+        "follow-symlinks",
+        "Follow symlinked files and directories instead of skipping them",
+    ),
+    (
+        "skip-conflicts",
+        "Skip files with unresolved merge conflict markers (--allow-conflicts overrides)",
+    ),
+    (
+        "readonly",
+        "Read-only files that need cleaning: \"skip\", \"warn\" (skip with a warning) or \"force\" (write anyway)",
+    ),
+    (
+        "hardlinks",
+        "Files with other hard links that need cleaning: \"skip\" (with a warning), \"break\" (only this link is cleaned) or \"in-place\" (every link is)",
+    ),
+    (
+        "whitespace-only-files",
+        "Files holding nothing but whitespace or a BOM: \"keep\" (clean as usual), \"empty\" (truncate) or \"delete\"",
+    ),
+    (
+        "preserve-xattrs",
+        "Give rewritten files the originals' extended attributes (SELinux labels, macOS quarantine flags and resource forks)",
+    ),
+    (
+        "metrics",
+        "Where the counters of each run (files scanned, modified and failed, duration) are exported",
+    ),
+    (
+        "metrics.prometheus-textfile",
+        "File rewritten after each run for the Prometheus node exporter's textfile collector, or null",
+    ),
+    (
+        "metrics.statsd",
+        "statsd server, such as \"127.0.0.1:8125\", sent the counters over UDP after each run, or null",
+    ),
+    (
+        "metrics.prefix",
+        "Start of every metric's name, e.g. whitespace_last_run_files_scanned or whitespace.files_scanned",
+    ),
+];
+
+fn push_comment(out: &mut String, indent: &str, key: &str) {
+    if let Some((_, doc)) = SETTING_DOCS.iter().find(|(name, _)| *name == key) {
+        for line in doc.lines() {
+            out.push_str(&format!("{}# {}\n", indent, line));
+        }
+    }
+}
+
+/// Lay `overlay` over `base`: mappings are merged key by key, and anything else, lists
+/// included, is replaced.
+fn merge_values(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    match (base, overlay) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// The environment variable that overrides a setting: `rules.indent-width` is
+/// `WHITESPACE_RULES_INDENT_WIDTH`.
+fn env_var_name(key: &str) -> String {
+    format!("WHITESPACE_{}", key.replace(['.', '-'], "_").to_uppercase())
+}
+
+/// Read an environment variable's value as the kind of value the setting holds now.
+fn parse_env_value(current: &serde_yaml::Value, raw: &str) -> Result<serde_yaml::Value> {
+    let raw = raw.trim();
+    match current {
+        serde_yaml::Value::Bool(_) => match raw.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true.into()),
+            "0" | "false" | "no" | "off" | "" => Ok(false.into()),
+            _ => eyre::bail!("expected true or false, got `{}`", raw),
+        },
+        serde_yaml::Value::Sequence(_) if !raw.starts_with('[') => Ok(raw
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(serde_yaml::Value::from)
+            .collect()),
+        _ => serde_yaml::from_str(raw).context("Failed to parse value"),
+    }
+}
+
+/// Config file extensions searched in the default location, in order of preference.
+const CONFIG_EXTENSIONS: &[&str] = &["yml", "toml", "json"];
+
+/// Config file syntax, chosen by extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// TOML for `.toml`, JSON for `.json`, YAML for anything else.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::Toml,
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration with fallback chain
+    pub fn load(config_path: Option<&PathBuf>) -> Result<Self> {
+        // If explicit config path provided, try to load it
+        if let Some(path) = config_path {
+            return Self::load_from_file(path).context(format!("Failed to load config from {}", path.display()));
+        }
+
+        // Try primary location: ~/.config/whitespace/whitespace.{yml,toml,json}
+        if let Some(primary_config) = Self::default_path() {
+            match Self::load_from_file(&primary_config) {
+                Ok(config) => return Ok(config),
+                Err(e) => {
+                    tracing::warn!("Failed to load config from {}: {}", primary_config.display(), e);
+                }
+            }
+        }
+
+        // No config file found, use defaults
+        tracing::info!("No config file found, using defaults");
+        Ok(Self::default())
+    }
+
+    /// The first existing `whitespace.{yml,toml,json}` in the user config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = Self::user_config_dir()?;
+        let project_name = env!("CARGO_PKG_NAME");
+        CONFIG_EXTENSIONS
+            .iter()
+            .map(|ext| config_dir.join(format!("{}.{}", project_name, ext)))
+            .find(|path| path.exists())
+    }
+
+    /// `~/.config/whitespace`, where the default config file lives.
+    pub fn user_config_dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")))
+    }
+
+    /// A JSON Schema for config files, derived from the same types they are read into, so
+    /// editors can validate and complete `whitespace.yml`.
+    pub fn json_schema() -> serde_json::Value {
+        let mut schema = schemars::schema_for!(Config).to_value();
+        // The default thread count is this machine's; the schema says how it's chosen instead
+        schema["properties"]["processing"]["default"]["threads"] = "nproc".into();
+        schema
+    }
+
+    /// Override settings from `WHITESPACE_*` environment variables, looked up with `var`. Each
+    /// setting has one, named after its key: `exclude-paths` is `WHITESPACE_EXCLUDE_PATHS` and
+    /// `processing.threads` is `WHITESPACE_PROCESSING_THREADS`. Lists are comma-separated, or
+    /// YAML flow sequences such as `[{files: ["*.md"]}]`.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        let mut value = serde_yaml::to_value(&*self).context("Failed to serialize config")?;
+        for (key, _) in SETTING_DOCS {
+            let Some(raw) = var(&env_var_name(key)) else {
+                continue;
+            };
+            let mut setting = &mut value;
+            for part in key.split('.') {
+                setting = &mut setting[part];
+            }
+            if setting.is_mapping() {
+                continue;
+            }
+            *setting = parse_env_value(setting, &raw).with_context(|| format!("Invalid {}", env_var_name(key)))?;
+            *self = serde_yaml::from_value(value.clone()).with_context(|| format!("Invalid {}", env_var_name(key)))?;
+            tracing::info!("{} set from {}", key, env_var_name(key));
+        }
+        Ok(())
+    }
+
+    /// Render this config as YAML with a comment above every setting.
+    pub fn to_commented_yaml(&self) -> Result<String> {
+        let value = serde_yaml::to_value(self).context("Failed to serialize config")?;
+        let serde_yaml::Value::Mapping(mapping) = value else {
+            eyre::bail!("Config did not serialize to a mapping");
+        };
+
+        let mut out = String::from(
+            "# whitespace.yml - generated by `whitespace config init`\n\
+             # Every setting below is the built-in default; edit or delete as needed.\n",
+        );
+
+        for (key, value) in &mapping {
+            let key = key.as_str().unwrap_or_default();
+            out.push('\n');
+            push_comment(&mut out, "", key);
+            match value {
+                serde_yaml::Value::Mapping(section) => {
+                    out.push_str(&format!("{}:\n", key));
+                    for (sub_key, sub_value) in section {
+                        let sub_key = sub_key.as_str().unwrap_or_default();
+                        push_comment(&mut out, "  ", &format!("{}.{}", key, sub_key));
+                        // The default thread count is whatever this machine has; keep the file portable
+                        let rendered = if key == "processing"
+                            && sub_key == "threads"
+                            && sub_value.as_u64() == Some(num_cpus::get() as u64)
+                        {
+                            "nproc".to_string()
+                        } else {
+                            serde_yaml::to_string(sub_value)?
+                        };
+                        let rendered = rendered.trim_end();
+                        if rendered.contains('\n') {
+                            // Nested lists and mappings go on their own lines, indented under the key
+                            out.push_str(&format!("  {}:\n", sub_key));
+                            for line in rendered.lines() {
+                                out.push_str(&format!("    {}\n", line));
+                            }
+                        } else {
+                            out.push_str(&format!("  {}: {}\n", sub_key, rendered));
+                        }
+                    }
+                }
+                _ => {
+                    let mut single = serde_yaml::Mapping::new();
+                    single.insert(key.into(), value.clone());
+                    out.push_str(&serde_yaml::to_string(&single)?);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub(crate) fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::load_extending(path.as_ref(), &mut Vec::new())
+    }
+
+    /// Load the config file at `path` and whatever it extends. `chain` holds the files whose
+    /// `extends` led here, so that a file extending itself, however indirectly, is caught.
+    fn load_extending(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Self> {
+        let content = fs::read_to_string(path).context("Failed to read config file")?;
+
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        if chain.contains(&canonical) {
+            let cycle: Vec<String> = chain
+                .iter()
+                .chain([&canonical])
+                .map(|file| file.display().to_string())
+                .collect();
+            eyre::bail!("Config files extend each other in a cycle: {}", cycle.join(" -> "));
+        }
+        chain.push(canonical);
+
+        let format = ConfigFormat::from_path(path);
+        let config: Self = match format {
+            ConfigFormat::Toml => toml::from_str(&content).context("Failed to parse TOML config file")?,
+            ConfigFormat::Json => serde_json::from_str(&content).context("Failed to parse JSON config file")?,
+            ConfigFormat::Yaml => serde_yaml::from_str(&content).context("Failed to parse config file")?,
+        };
+        let config = match &config.extends {
+            Some(name) => Self::extend(Self::base(name, path, chain)?, &content, format)?,
+            None => config,
+        };
+
+        tracing::info!("Loaded config from: {}", path.display());
+        Ok(config)
+    }
+
+    /// What the config file at `path` extends: the preset called `name`, or else the config file
+    /// `name`, relative to the directory holding `path`.
+    fn base(name: &str, path: &Path, chain: &mut Vec<PathBuf>) -> Result<Self> {
+        if let Some(preset) = Preset::from_name(name) {
+            return Ok(preset.config());
+        }
+        let base_path = path.parent().unwrap_or(Path::new("")).join(name);
+        Self::load_extending(&base_path, chain).with_context(|| {
+            format!(
+                "Failed to load {}, which {} extends",
+                base_path.display(),
+                path.display()
+            )
+        })
+    }
+
+    /// Read config `content` over the config it extends, so that it only changes the settings
+    /// it names.
+    fn extend(base: Self, content: &str, format: ConfigFormat) -> Result<Self> {
+        let mut value = serde_yaml::to_value(base).context("Failed to serialize config")?;
+        let overlay: serde_yaml::Value = match format {
+            ConfigFormat::Toml => toml::from_str(content).context("Failed to parse TOML config file")?,
+            ConfigFormat::Json => serde_json::from_str(content).context("Failed to parse JSON config file")?,
+            ConfigFormat::Yaml => serde_yaml::from_str(content).context("Failed to parse config file")?,
+        };
+        // An empty YAML file is null, and changes nothing
+        if !overlay.is_null() {
+            merge_values(&mut value, overlay);
+        }
+        serde_yaml::from_value(value).context("Failed to apply config over the one it extends")
     }
 }
 
@@ -298,7 +1673,7 @@ processing:
   max-file-size: 1000000
 "#;
         let config: Config = serde_yaml::from_str(yaml).unwrap();
-        assert_eq!(config.processing.threads, num_cpus::get());
+        assert_eq!(config.processing.threads, Threads::Count(num_cpus::get()));
         assert_eq!(config.processing.max_file_size, 1000000);
     }
 
@@ -310,7 +1685,7 @@ processing:
   max-file-size: 2000000
 "#;
         let config: Config = serde_yaml::from_str(yaml).unwrap();
-        assert_eq!(config.processing.threads, 8);
+        assert_eq!(config.processing.threads, Threads::Count(8));
         assert_eq!(config.processing.max_file_size, 2000000);
     }
 
@@ -326,6 +1701,18 @@ processing:
         assert!(error_msg.contains("invalid thread value"));
     }
 
+    #[test]
+    fn test_threads_config_auto_io() {
+        let config: Config = serde_yaml::from_str("processing:\n  threads: auto-io\n").unwrap();
+        assert_eq!(config.processing.threads, Threads::AutoIo);
+        assert!(config.to_commented_yaml().unwrap().contains("  threads: auto-io\n"));
+        let config: Config = toml::from_str("[processing]\nthreads = \"auto-io\"\n").unwrap();
+        assert_eq!(config.processing.threads, Threads::AutoIo);
+        assert_eq!(parse_threads("auto-io"), Ok(Threads::AutoIo));
+        assert_eq!(parse_threads("6"), Ok(Threads::Count(6)));
+        assert!(parse_threads("auto").is_err());
+    }
+
     #[test]
     fn test_threads_config_zero() {
         let yaml = r#"
@@ -338,6 +1725,488 @@ processing:
         assert!(error_msg.contains("threads must be greater than 0"));
     }
 
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1048576"), Ok(1048576));
+        assert_eq!(parse_size("512b"), Ok(512));
+        assert_eq!(parse_size("10MB"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("10 mib"), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("1.5GiB"), Ok(1536 * 1024 * 1024));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("99999999999999999999").is_err());
+        assert!(parse_size("20000000TB").is_err());
+    }
+
+    #[test]
+    fn test_size_config_units() {
+        let yaml = r#"
+processing:
+  max-file-size: 10MB
+  streaming-threshold: "1.5 MiB"
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.processing.max_file_size, 10 * 1024 * 1024);
+        assert_eq!(config.processing.streaming_threshold, 1536 * 1024);
+
+        let config: Config = toml::from_str("[processing]\nmax-file-size = \"2K\"\n").unwrap();
+        assert_eq!(config.processing.max_file_size, 2048);
+
+        let error = serde_yaml::from_str::<Config>("processing:\n  max-file-size: 10 parsecs\n").unwrap_err();
+        assert!(error.to_string().contains("max-file-size: invalid size"));
+    }
+
+    #[test]
+    fn test_streaming_threshold_config() {
+        let yaml = r#"
+processing:
+  streaming-threshold: 4096
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.processing.streaming_threshold, 4096);
+
+        let config: Config = serde_yaml::from_str("processing: {}").unwrap();
+        assert_eq!(config.processing.streaming_threshold, DEFAULT_STREAMING_THRESHOLD);
+        assert!(!config.processing.mmap);
+    }
+
+    #[test]
+    fn test_mmap_config() {
+        let yaml = r#"
+processing:
+  mmap: true
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.processing.mmap);
+        assert!(!config.processing.intent_log);
+
+        let config: Config = serde_yaml::from_str("processing:\n  intent-log: true\n").unwrap();
+        assert!(config.processing.intent_log);
+    }
+
+    #[test]
+    fn test_io_config_and_nfs_safe() {
+        let config = Config::default();
+        assert_eq!(config.processing.io_threads, None);
+        assert_eq!(config.processing.io_retries, 0);
+        assert!(config.processing.trust_mtime);
+
+        let yaml = "processing:\n  mmap: true\n  io-threads: 8\n  io-retries: 1\n";
+        let mut config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.processing.io_threads, Some(8));
+        config.processing.apply_nfs_safe();
+        assert!(!config.processing.mmap);
+        assert_eq!(config.processing.io_threads, Some(4));
+        assert_eq!(config.processing.io_retries, 3);
+        assert!(!config.processing.trust_mtime);
+
+        // A lower limit the config already sets is kept
+        let mut config: Config = serde_yaml::from_str("processing:\n  io-threads: 2\n").unwrap();
+        config.processing.apply_nfs_safe();
+        assert_eq!(config.processing.io_threads, Some(2));
+
+        let error = serde_yaml::from_str::<Config>("processing:\n  io-threads: 0\n").unwrap_err();
+        assert!(error.to_string().contains("io-threads must be greater than 0"));
+    }
+
+    #[test]
+    fn test_follow_symlinks_config() {
+        let config: Config = serde_yaml::from_str("follow-symlinks: true").unwrap();
+        assert!(config.follow_symlinks);
+        assert!(!Config::default().follow_symlinks);
+    }
+
+    #[test]
+    fn test_hardlinks_config() {
+        assert_eq!(Config::default().hardlinks, HardLinkPolicy::Skip);
+        let config: Config = serde_yaml::from_str("hardlinks: in-place").unwrap();
+        assert_eq!(config.hardlinks, HardLinkPolicy::InPlace);
+        assert!(serde_yaml::from_str::<Config>("hardlinks: relink").is_err());
+    }
+
+    #[test]
+    fn test_whitespace_only_files_config() {
+        assert_eq!(Config::default().whitespace_only_files, WhitespaceOnlyPolicy::Keep);
+        let config: Config = serde_yaml::from_str("whitespace-only-files: delete").unwrap();
+        assert_eq!(config.whitespace_only_files, WhitespaceOnlyPolicy::Delete);
+        assert!(serde_yaml::from_str::<Config>("whitespace-only-files: remove").is_err());
+    }
+
+    #[test]
+    fn test_preserve_xattrs_config() {
+        assert!(Config::default().preserve_xattrs);
+        let config: Config = serde_yaml::from_str("preserve-xattrs: false").unwrap();
+        assert!(!config.preserve_xattrs);
+    }
+
+    #[test]
+    fn test_rules_config() {
+        let config: Config = serde_yaml::from_str("rules:\n  indent-style: spaces\n  indent-width: 2\n").unwrap();
+        assert_eq!(config.rules.indent_style, Some(IndentStyle::Spaces));
+        assert_eq!(config.rules.indent_width, 2);
+
+        let config: Config = toml::from_str("[rules]\nindent-style = \"tabs\"\n").unwrap();
+        assert_eq!(config.rules.indent_style, Some(IndentStyle::Tabs));
+        assert_eq!(config.rules.indent_width, 4);
+
+        assert_eq!(Config::default().rules.indent_style, None);
+        assert!(serde_yaml::from_str::<Config>("rules:\n  indent-style: mixed\n").is_err());
+    }
+
+    #[test]
+    fn test_trailing_whitespace_config() {
+        let yaml = "rules:\n  trailing-whitespace: ascii\n  extra-trailing-chars: [\"\\u200B\", \"\\u00A0\"]\n";
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.rules.trailing_whitespace, TrailingWhitespace::Ascii);
+        assert_eq!(config.rules.extra_trailing_chars, vec!['\u{200B}', '\u{A0}']);
+        assert_eq!(Config::default().rules.trailing_whitespace, TrailingWhitespace::Unicode);
+
+        assert!(TrailingWhitespace::Unicode.matches('\u{3000}'));
+        assert!(!TrailingWhitespace::Ascii.matches('\u{A0}'));
+        assert!(!TrailingWhitespace::Unicode.matches('\u{200B}'));
+    }
+
+    #[test]
+    fn test_max_line_length_config() {
+        let yaml = r#"
+rules:
+  max-line-length: 100
+  overrides:
+    - files: ["*.py"]
+      max-line-length: 79
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.rules.max_line_length, Some(100));
+        assert_eq!(Config::default().rules.max_line_length, None);
+
+        let mut rules = config.rules.clone();
+        config.rules.overrides[0].apply(&mut rules);
+        assert_eq!(rules.max_line_length, Some(79));
+    }
+
+    #[test]
+    fn test_preserve_line_patterns_config() {
+        let yaml = r#"
+rules:
+  preserve-line-patterns: ["-- $", "\\\\$"]
+  overrides:
+    - types: [markdown]
+      preserve-line-patterns: []
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let patterns = &config.rules.preserve_line_patterns;
+        assert!(patterns.matches("SELECT 1; -- "));
+        assert!(patterns.matches("make all \\"));
+        assert!(!patterns.matches("-- comment  "));
+        assert!(Config::default().rules.preserve_line_patterns.is_empty());
+
+        let mut rules = config.rules.clone();
+        config.rules.overrides[0].apply(&mut rules);
+        assert!(rules.preserve_line_patterns.is_empty());
+
+        let toml_text = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml_text).unwrap().rules, config.rules);
+
+        let error = serde_yaml::from_str::<Config>("rules:\n  preserve-line-patterns: [\"(\"]\n").unwrap_err();
+        assert!(error.to_string().contains("invalid line pattern"), "{}", error);
+    }
+
+    #[test]
+    fn test_replacements_config() {
+        let yaml = r#"
+rules:
+  replacements:
+    - name: smart-quotes
+      files: ["*.md"]
+      pattern: "[“”]"
+      replace: '"'
+    - pattern: "\\s+$"
+      replace: ""
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let replacements = &config.rules.replacements;
+        assert_eq!(replacements[0].name, "smart-quotes");
+        assert_eq!(replacements[0].files, ["*.md"]);
+        assert_eq!(replacements[1].name, "\\s+$");
+        assert_eq!(
+            replacements[1].pattern.replace_all("a  ", &replacements[1].replace),
+            "a"
+        );
+
+        let toml_text = toml::to_string(&config).unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml_text).unwrap().rules, config.rules);
+
+        let error = serde_yaml::from_str::<Config>("rules:\n  replacements:\n    - pattern: \"(\"\n").unwrap_err();
+        assert!(
+            error.to_string().contains("invalid pattern in replacement"),
+            "{}",
+            error
+        );
+
+        let yaml = "rules:\n  replacements:\n    - name: split\n      pattern: \";\"\n      replace: \";\\n\"\n";
+        let error = serde_yaml::from_str::<Config>(yaml).unwrap_err();
+        assert!(
+            error.to_string().contains("replacement `split` can't put a line break"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_end_of_file_config() {
+        let yaml = r#"
+rules:
+  end-of-file: [final-newline]
+  overrides:
+    - types: [c]
+      end-of-file: [final-newline, no-continuation]
+    - files: ["*.txt"]
+      end-of-file: []
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.rules.end_of_file, [EndOfFile::FinalNewline]);
+        assert!(Config::default().rules.end_of_file.is_empty());
+
+        let mut rules = config.rules.clone();
+        config.rules.overrides[0].apply(&mut rules);
+        assert_eq!(rules.end_of_file, [EndOfFile::FinalNewline, EndOfFile::NoContinuation]);
+        config.rules.overrides[1].apply(&mut rules);
+        assert!(rules.end_of_file.is_empty());
+        assert!(serde_yaml::from_str::<Config>("rules:\n  end-of-file: [semicolon]\n").is_err());
+    }
+
+    #[test]
+    fn test_rule_overrides_config() {
+        let yaml = r#"
+rules:
+  max-consecutive-blank-lines: 1
+  overrides:
+    - files: ["*.py"]
+      max-consecutive-blank-lines: 2
+    - files: ["*.md"]
+      trim-trailing-blank-lines: true
+    - types: [shell]
+      trim-trailing-blank-lines: true
+file-types:
+  names:
+    "*.env": shell
+  interpreters:
+    nu: nushell
+"#;
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.rules.max_consecutive_blank_lines, Some(1));
+        assert_eq!(config.rules.overrides.len(), 3);
+        assert_eq!(config.rules.overrides[2].types, ["shell"]);
+        assert_eq!(config.file_types.names["*.env"], "shell");
+        assert_eq!(config.file_types.interpreters["nu"], "nushell");
+
+        let mut rules = config.rules.clone();
+        config.rules.overrides[0].apply(&mut rules);
+        assert_eq!(rules.max_consecutive_blank_lines, Some(2));
+        assert!(!rules.trim_trailing_blank_lines);
+        config.rules.overrides[1].apply(&mut rules);
+        assert_eq!(rules.max_consecutive_blank_lines, Some(2));
+        assert!(rules.trim_trailing_blank_lines);
+
+        // Serialized configs round-trip through TOML too
+        let toml_text = toml::to_string(&config).unwrap();
+        let parsed: Config = toml::from_str(&toml_text).unwrap();
+        assert_eq!(parsed.rules, config.rules);
+    }
+
+    #[test]
+    fn test_extends_preset() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let yaml = temp_dir.path().join("whitespace.yml");
+        fs::write(
+            &yaml,
+            "extends: strict\nrules:\n  line-endings: preserve\n  indent-style: null\n",
+        )
+        .unwrap();
+        let config = Config::load(Some(&yaml)).unwrap();
+        assert_eq!(config.extends.as_deref(), Some("strict"));
+        // The file's own settings win, and the preset's other settings remain
+        assert_eq!(config.rules.line_endings, LineEndings::Preserve);
+        assert!(config.rules.trim_trailing_blank_lines);
+        assert_eq!(config.exclude_paths, Config::default().exclude_paths);
+
+        let toml = temp_dir.path().join("whitespace.toml");
+        fs::write(&toml, "extends = \"git-friendly\"\n").unwrap();
+        let config = Config::load(Some(&toml)).unwrap();
+        assert_eq!(config.rules.trailing_whitespace, TrailingWhitespace::Ascii);
+    }
+
+    #[test]
+    fn test_extends_file() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let shared = temp_dir.path().join("shared");
+        let project = temp_dir.path().join("project");
+        fs::create_dir_all(&shared).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            shared.join("base.toml"),
+            "extends = \"strict\"\nexclude-files = [\"*.snap\"]\n\n[processing]\nthreads = 2\n",
+        )
+        .unwrap();
+        let yaml = project.join("whitespace.yml");
+        fs::write(
+            &yaml,
+            "extends: ../shared/base.toml\nprocessing:\n  max-file-size: 1000\n",
+        )
+        .unwrap();
+
+        // Each file changes only what it names, down a chain that ends at a preset
+        let config = Config::load(Some(&yaml)).unwrap();
+        assert_eq!(config.exclude_files, ["*.snap"]);
+        assert_eq!(config.processing.threads, Threads::Count(2));
+        assert_eq!(config.processing.max_file_size, 1000);
+        assert!(config.rules.trim_trailing_blank_lines);
+
+        fs::write(shared.join("base.toml"), "extends = \"../project/whitespace.yml\"\n").unwrap();
+        let error = format!("{:#}", Config::load(Some(&yaml)).unwrap_err());
+        assert!(error.contains("extend each other in a cycle"), "{}", error);
+
+        fs::write(&yaml, "extends: missing.yml\n").unwrap();
+        let error = format!("{:#}", Config::load(Some(&yaml)).unwrap_err());
+        assert!(error.contains("missing.yml, which"), "{}", error);
+    }
+
+    #[test]
+    fn test_toml_and_json_match_yaml() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let yaml = temp_dir.path().join("whitespace.yml");
+        let toml = temp_dir.path().join("whitespace.toml");
+        let json = temp_dir.path().join("whitespace.json");
+        fs::write(
+            &yaml,
+            "exclude-files: [\"*.snap\"]\nfollow-symlinks: true\nprocessing:\n  threads: 2\n  max-file-size: 1000\n",
+        )
+        .unwrap();
+        fs::write(
+            &toml,
+            "exclude-files = [\"*.snap\"]\nfollow-symlinks = true\n\n[processing]\nthreads = 2\nmax-file-size = 1000\n",
+        )
+        .unwrap();
+        fs::write(
+            &json,
+            r#"{"exclude-files": ["*.snap"], "follow-symlinks": true, "processing": {"threads": 2, "max-file-size": 1000}}"#,
+        )
+        .unwrap();
+
+        let from_yaml = Config::load(Some(&yaml)).unwrap();
+        for path in [&toml, &json] {
+            let config = Config::load(Some(path)).unwrap();
+            assert_eq!(config.exclude_files, from_yaml.exclude_files);
+            assert_eq!(config.follow_symlinks, from_yaml.follow_symlinks);
+            assert_eq!(config.processing.threads, Threads::Count(2));
+            assert_eq!(config.processing.max_file_size, 1000);
+        }
+    }
+
+    #[test]
+    fn test_toml_threads_nproc_and_errors() {
+        let config: Config = toml::from_str("[processing]\nthreads = \"nproc\"\n").unwrap();
+        assert_eq!(config.processing.threads, Threads::Count(num_cpus::get()));
+        assert!(toml::from_str::<Config>("[processing]\nthreads = 0\n").is_err());
+        assert!(serde_json::from_str::<Config>(r#"{"processing": {"threads": "many"}}"#).is_err());
+    }
+
+    #[test]
+    fn test_commented_yaml_round_trips() {
+        let yaml = Config::default().to_commented_yaml().unwrap();
+        let parsed: Config = serde_yaml::from_str(&yaml).unwrap();
+
+        assert_eq!(
+            serde_yaml::to_string(&parsed).unwrap(),
+            serde_yaml::to_string(&Config::default()).unwrap()
+        );
+        assert!(yaml.contains("# Processing settings\nprocessing:\n  # Files larger than"));
+        assert!(yaml.contains("  threads: nproc\n"));
+    }
+
+    #[test]
+    fn test_every_setting_is_documented() {
+        let serde_yaml::Value::Mapping(mapping) = serde_yaml::to_value(Config::default()).unwrap() else {
+            panic!("config is not a mapping");
+        };
+        let documented = |key: &str| SETTING_DOCS.iter().any(|(name, _)| *name == key);
+
+        for (key, value) in &mapping {
+            let key = key.as_str().unwrap();
+            assert!(documented(key), "{} is undocumented", key);
+            if let serde_yaml::Value::Mapping(section) = value {
+                for sub_key in section.keys() {
+                    let path = format!("{}.{}", key, sub_key.as_str().unwrap());
+                    assert!(documented(&path), "{} is undocumented", path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_schema_covers_every_setting() {
+        let schema = Config::json_schema();
+        let resolve = |node: &serde_json::Value| match node["$ref"].as_str() {
+            Some(reference) => schema["$defs"][reference.trim_start_matches("#/$defs/")].clone(),
+            None => node.clone(),
+        };
+
+        for (key, _) in SETTING_DOCS {
+            let mut node = schema.clone();
+            for part in key.split('.') {
+                node = resolve(&node)["properties"][part].clone();
+                assert!(!node.is_null(), "{} is missing from the schema", key);
+            }
+        }
+
+        let processing = resolve(&schema["properties"]["processing"]);
+        assert_eq!(processing["additionalProperties"], false);
+        let max_file_size = &processing["properties"]["max-file-size"]["oneOf"];
+        assert!(
+            max_file_size
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|option| option["type"] == "string")
+        );
+        assert_eq!(schema["properties"]["processing"]["default"]["threads"], "nproc");
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let vars = std::collections::HashMap::from([
+            ("WHITESPACE_EXCLUDE_PATHS", "vendor/**, *.gen.go"),
+            ("WHITESPACE_FOLLOW_SYMLINKS", "1"),
+            ("WHITESPACE_PROCESSING_THREADS", "3"),
+            ("WHITESPACE_PROCESSING_MAX_FILE_SIZE", "10MB"),
+            ("WHITESPACE_RULES_MAX_CONSECUTIVE_BLANK_LINES", "2"),
+            (
+                "WHITESPACE_RULES_OVERRIDES",
+                "[{files: ['*.md'], preserve-hard-breaks: true}]",
+            ),
+            ("WHITESPACE_BINARY_DETECTION", "ignored: sections are set key by key"),
+        ]);
+        let mut config = Config::default();
+        config
+            .apply_env(|name| vars.get(name).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.exclude_paths, ["vendor/**", "*.gen.go"]);
+        assert!(config.follow_symlinks);
+        assert_eq!(config.processing.threads, Threads::Count(3));
+        assert_eq!(config.processing.max_file_size, 10 * 1024 * 1024);
+        assert_eq!(config.rules.max_consecutive_blank_lines, Some(2));
+        assert_eq!(config.rules.overrides.len(), 1);
+        assert_eq!(config.rules.overrides[0].files, ["*.md"]);
+
+        let error = Config::default()
+            .apply_env(|name| (name == "WHITESPACE_SKIP_CONFLICTS").then(|| "maybe".to_string()))
+            .unwrap_err();
+        assert!(format!("{:#}", error).contains("Invalid WHITESPACE_SKIP_CONFLICTS"));
+    }
+
     #[test]
     fn test_threads_config_defaults() {
         let yaml = r#"
@@ -345,7 +2214,7 @@ processing:
   max-file-size: 5000000
 "#;
         let config: Config = serde_yaml::from_str(yaml).unwrap();
-        assert_eq!(config.processing.threads, num_cpus::get());
+        assert_eq!(config.processing.threads, Threads::Count(num_cpus::get()));
         assert_eq!(config.processing.max_file_size, 5000000);
     }
 }
@@ -358,10 +2227,74 @@ pub struct RuntimeConfig {
     pub directories: Vec<PathBuf>,
     /// Whether to perform a dry run
     pub dry_run: bool,
-    /// Whether to process recursively
-    pub recursive: bool,
+    /// Scan rewritten files again and fail any that a second pass would still change
+    pub verify: bool,
+    /// Write cleaned copies of the files into a mirrored tree here instead of rewriting them
+    pub write_to: Option<PathBuf>,
+    /// Copy each file to its name with this suffix before rewriting it
+    pub backup: Option<String>,
+    /// Put a copy of each file in the operating system's trash before rewriting it
+    pub trash: bool,
+    /// Resume the run with this id, or the latest run, where it stopped
+    pub resume: Option<String>,
+    /// Maximum directory depth to descend (None = unlimited)
+    pub max_depth: Option<usize>,
+    /// Process the files listed here (`-` for stdin) instead of walking the directories
+    pub files_from: Option<PathBuf>,
+    /// The `files_from` list is NUL-delimited rather than one path per line
+    pub null_delimited: bool,
+    /// Clean only these lines of the single file that is the one target
+    pub line_ranges: Option<LineRanges>,
+    /// Run only the rules this selects
+    pub rule_selection: Option<RuleSelection>,
+    /// Clean only lines added or changed since this git ref
+    pub diff_only: Option<String>,
+    /// Clean the files HEAD and the index change, then stage, amend or commit the cleanup
+    pub fixup: Option<FixupMode>,
     /// Number of threads to use
-    pub threads: usize,
+    pub threads: Threads,
+    /// Output format for findings
+    pub format: OutputFormat,
+    /// Order files are listed and reported in
+    pub sort: SortOrder,
+    /// Print only the paths of files with changes, one per line, instead of the usual output
+    pub files_with_matches: bool,
+    /// Print only the paths of files with changes, NUL-delimited, instead of the usual output
+    pub print0: bool,
+    /// Suppress normal output, leaving only errors
+    pub quiet: bool,
+    /// When to use colors and emoji
+    pub color: ColorChoice,
+    /// List skipped files and why they were skipped
+    pub show_skipped: bool,
+    /// Print a summary after each directory as well as for the whole run
+    pub per_directory: bool,
+    /// List changed files under a header per directory
+    pub group_by_dir: bool,
+    /// Report each file as soon as it finishes, in this order
+    pub stream: Option<StreamOrder>,
+    /// List every file with why it was included or skipped
+    pub why: bool,
+    /// Absolute directory that displayed and reported paths are made relative to
+    pub relative_to: Option<PathBuf>,
+    /// Whether to use the incremental clean-file cache
+    pub use_cache: bool,
+    /// Whether to lock each target root while rewriting files in it
+    pub use_lock: bool,
+    /// Whether to record a rewriting run's progress so it can be resumed
+    pub use_journal: bool,
+    /// Abort the run once this many files have failed (None = never)
+    pub max_errors: Option<usize>,
+    /// Process at most this many files (None = no limit)
+    pub max_files: Option<usize>,
+    /// Stop walking and processing once this much time has passed (None = no limit)
+    pub timeout: Option<Duration>,
+    /// Print a per-phase time breakdown after the run
+    pub timings: bool,
+    /// Write a run report to this file, in the format its extension names
+    pub report: Option<PathBuf>,
+    /// Console verbosity and log file destination
+    pub logging: LoggingConfig,
     /// File-based configuration (exclude patterns, etc.)
     pub file_config: Config,
 }
@@ -370,32 +2303,565 @@ impl RuntimeConfig {
     /// Create RuntimeConfig by merging CLI args with file config.
     pub fn from_cli(cli: &Cli) -> Result<Self> {
         // Load file-based config
-        let file_config = Config::load(cli.config.as_ref()).context("Failed to load configuration file")?;
+        let mut file_config = Config::load(cli.config.as_ref()).context("Failed to load configuration file")?;
+        file_config
+            .apply_env(|name| std::env::var(name).ok())
+            .context("Failed to apply environment variables")?;
+        if let Some(preset) = cli.preset {
+            preset.apply(&mut file_config);
+        }
+
+        // CLI flags that mirror file settings can only turn them on
+        if cli.follow_symlinks {
+            file_config.follow_symlinks = true;
+        }
+        if cli.force {
+            file_config.diff_detection.enabled = false;
+        }
+        if cli.allow_conflicts {
+            file_config.skip_conflicts = false;
+        }
+        if cli.include_generated {
+            file_config.generated_detection.enabled = false;
+        }
+        if let Some(max_file_size) = cli.max_file_size {
+            file_config.processing.max_file_size = max_file_size;
+        }
+        if cli.nfs_safe {
+            file_config.processing.apply_nfs_safe();
+        }
+        if let Some(io_threads) = cli.io_threads {
+            if io_threads == 0 {
+                eyre::bail!("IO threads must be greater than 0");
+            }
+            file_config.processing.io_threads = Some(io_threads);
+        }
+        exclude_backups(cli.backup.as_deref(), &mut file_config)?;
 
-        // Determine target directories
-        let directories = if cli.directories.is_empty() {
+        // Determine target directories, which subcommands may supply themselves
+        let cli_directories: &[PathBuf] = match &cli.command {
+            Some(Command::Stats { directories, .. }) => directories,
+            Some(Command::Diff { trees }) => trees,
+            Some(Command::Daemon { directory, .. }) => directory.as_slice(),
+            Some(Command::Explain { root, .. }) => root.as_slice(),
+            Some(
+                Command::Config { .. }
+                | Command::Man { .. }
+                | Command::Bench { .. }
+                | Command::PreCommit { .. }
+                | Command::Lsp
+                | Command::InstallHook { .. }
+                | Command::UninstallHook { .. },
+            )
+            | None => &cli.directories,
+        };
+        let directories = if cli_directories.is_empty() {
             vec![PathBuf::from(".")]
         } else {
-            cli.directories.clone()
+            cli_directories.to_vec()
         };
 
+        // Without --strict, bad targets are reported and skipped at run time instead
+        if cli.lines.is_some() {
+            validate_single_file(&directories)?;
+        } else if cli.strict {
+            validate_directories(&directories)?;
+        }
+
         // Determine thread count: CLI overrides file config if explicitly set
-        let threads = if cli.threads != num_cpus::get() {
+        let threads = if cli.threads != Threads::default() {
             cli.threads // User explicitly set threads via CLI
         } else {
             file_config.processing.threads // Use file config value
         };
 
-        // Validate thread count
-        if threads == 0 {
-            eyre::bail!("Thread count must be greater than 0");
+        // `-r` is sugar for the default unlimited depth
+        let max_depth = if cli.recursive { None } else { cli.max_depth };
+        // `--fail-fast` is sugar for `--max-errors 1`
+        let max_errors = if cli.fail_fast { Some(1) } else { cli.max_errors };
+        validate_limits(threads, max_depth, max_errors)?;
+        validate_budget(cli.max_files, cli.timeout.map(Duration::from_secs))?;
+        validate_report(cli.report.as_deref())?;
+        let relative_to = resolve_relative_to(cli.relative_to.as_deref())?;
+        let rule_selection = (!cli.only.is_empty() || !cli.skip.is_empty()).then(|| RuleSelection {
+            only: cli.only.clone(),
+            skip: cli.skip.clone(),
+        });
+        if let Some(selection) = &rule_selection {
+            selection.validate(&file_config)?;
         }
 
         Ok(Self {
             directories,
             dry_run: cli.dry_run,
-            recursive: cli.recursive,
+            verify: cli.verify,
+            write_to: cli.write_to.clone(),
+            backup: cli.backup.clone(),
+            trash: cli.trash,
+            resume: cli.resume.clone(),
+            max_depth,
+            files_from: cli.files_from.clone(),
+            null_delimited: cli.null,
+            line_ranges: cli.lines.clone(),
+            rule_selection,
+            diff_only: cli.diff_only.clone(),
+            fixup: cli.fixup.then_some(if cli.amend {
+                FixupMode::Amend
+            } else if cli.fixup_commit {
+                FixupMode::Commit
+            } else {
+                FixupMode::Stage
+            }),
+            threads,
+            format: cli.format,
+            sort: cli.sort,
+            files_with_matches: cli.files_with_matches,
+            print0: cli.print0,
+            quiet: cli.quiet,
+            color: cli.color,
+            show_skipped: cli.show_skipped,
+            per_directory: cli.per_directory,
+            group_by_dir: cli.group_by_dir,
+            stream: cli.stream,
+            why: cli.why,
+            relative_to,
+            // Files the cache knows to be clean would never be copied into the mirror
+            use_cache: !cli.no_cache && cli.write_to.is_none(),
+            use_lock: !cli.no_lock,
+            use_journal: !cli.no_journal,
+            max_errors,
+            max_files: cli.max_files,
+            timeout: cli.timeout.map(Duration::from_secs),
+            timings: cli.timings,
+            report: cli.report.clone(),
+            logging: LoggingConfig::from_cli(cli),
+            file_config,
+        })
+    }
+
+    /// Start building a RuntimeConfig programmatically, e.g. from library code or tests.
+    pub fn builder() -> RuntimeConfigBuilder {
+        RuntimeConfigBuilder::default()
+    }
+
+    /// Whether headings, summaries and skip lists are printed: not with `--quiet` or when only
+    /// paths are listed.
+    pub fn decorated(&self) -> bool {
+        !self.quiet && !self.files_with_matches && !self.print0
+    }
+}
+
+fn validate_directories(directories: &[PathBuf]) -> Result<()> {
+    for directory in directories {
+        if !directory.exists() {
+            eyre::bail!("Directory does not exist: {}", directory.display());
+        }
+        if !directory.is_dir() {
+            eyre::bail!("Not a directory: {}", directory.display());
+        }
+    }
+    Ok(())
+}
+
+/// `--lines` numbers the lines of one file, so that must be the only target.
+fn validate_single_file(targets: &[PathBuf]) -> Result<()> {
+    match targets {
+        [file] if file.is_file() => Ok(()),
+        [file] => eyre::bail!("--lines needs a file to clean, but {} is not one", file.display()),
+        _ => eyre::bail!(
+            "--lines applies to a single file, but {} targets were given",
+            targets.len()
+        ),
+    }
+}
+
+fn validate_limits(threads: Threads, max_depth: Option<usize>, max_errors: Option<usize>) -> Result<()> {
+    if threads == Threads::Count(0) {
+        eyre::bail!("Thread count must be greater than 0");
+    }
+    if max_depth == Some(0) {
+        eyre::bail!("Max depth must be greater than 0");
+    }
+    if max_errors == Some(0) {
+        eyre::bail!("Max errors must be greater than 0");
+    }
+    Ok(())
+}
+
+fn validate_budget(max_files: Option<usize>, timeout: Option<Duration>) -> Result<()> {
+    if max_files == Some(0) {
+        eyre::bail!("Max files must be greater than 0");
+    }
+    if timeout == Some(Duration::ZERO) {
+        eyre::bail!("Timeout must be greater than 0");
+    }
+    Ok(())
+}
+
+/// Leave the backups `--backup` made on earlier runs alone, so that they aren't cleaned and
+/// backed up in turn. The suffix must make a sibling of each file, so it can't be empty or
+/// hold a path separator.
+fn exclude_backups(backup: Option<&str>, file_config: &mut Config) -> Result<()> {
+    let Some(suffix) = backup else {
+        return Ok(());
+    };
+    if suffix.is_empty() || suffix.contains(['/', std::path::MAIN_SEPARATOR]) {
+        eyre::bail!(
+            "Invalid backup suffix '{}': it must be a non-empty file name suffix",
+            suffix
+        );
+    }
+    file_config.exclude_files.push(format!("*{}", globset::escape(suffix)));
+    Ok(())
+}
+
+/// Fail before the run, rather than after it, if the report's format can't be told from its name.
+fn validate_report(report: Option<&Path>) -> Result<()> {
+    if let Some(report) = report {
+        ReportFormat::from_path(report)?;
+    }
+    Ok(())
+}
+
+/// Resolve `--relative-to` to an absolute directory: `cwd` names the current directory, and
+/// anything else must be an existing directory (`./cwd` for one actually called `cwd`).
+fn resolve_relative_to(relative_to: Option<&Path>) -> Result<Option<PathBuf>> {
+    let Some(base) = relative_to else {
+        return Ok(None);
+    };
+    let cwd = std::env::current_dir().context("Failed to determine the current directory")?;
+    if base == Path::new("cwd") {
+        return Ok(Some(cwd));
+    }
+    if !base.is_dir() {
+        eyre::bail!("Not a directory: {}", base.display());
+    }
+    Ok(Some(crate::output::normalize_path(&cwd.join(base))))
+}
+
+/// Builder for [`RuntimeConfig`].
+///
+/// Unset values get the same defaults as the CLI, except that logging is off.
+/// Unlike [`RuntimeConfig::from_cli`], `build` requires every directory to exist.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeConfigBuilder {
+    directories: Vec<PathBuf>,
+    dry_run: bool,
+    verify: bool,
+    write_to: Option<PathBuf>,
+    backup: Option<String>,
+    trash: bool,
+    resume: Option<String>,
+    max_depth: Option<usize>,
+    files_from: Option<PathBuf>,
+    null_delimited: bool,
+    line_ranges: Option<LineRanges>,
+    rule_selection: Option<RuleSelection>,
+    diff_only: Option<String>,
+    fixup: Option<FixupMode>,
+    threads: Option<Threads>,
+    format: OutputFormat,
+    sort: SortOrder,
+    files_with_matches: bool,
+    print0: bool,
+    quiet: bool,
+    color: ColorChoice,
+    show_skipped: bool,
+    per_directory: bool,
+    group_by_dir: bool,
+    stream: Option<StreamOrder>,
+    why: bool,
+    relative_to: Option<PathBuf>,
+    no_cache: bool,
+    no_lock: bool,
+    no_journal: bool,
+    max_errors: Option<usize>,
+    max_files: Option<usize>,
+    timeout: Option<Duration>,
+    timings: bool,
+    report: Option<PathBuf>,
+    logging: Option<LoggingConfig>,
+    file_config: Option<Config>,
+}
+
+impl RuntimeConfigBuilder {
+    /// Replace the target directories (default: `.`).
+    pub fn directories<I, P>(mut self, directories: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.directories = directories.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add one target directory.
+    pub fn directory<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.directories.push(directory.into());
+        self
+    }
+
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Scan rewritten files again and fail any that a second pass would still change.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Process the files listed in `path` (`-` for stdin) instead of walking the directories.
+    pub fn files_from<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.files_from = Some(path.into());
+        self
+    }
+
+    /// Read the `files_from` list as NUL-delimited paths.
+    pub fn null_delimited(mut self, null_delimited: bool) -> Self {
+        self.null_delimited = null_delimited;
+        self
+    }
+
+    /// Clean only these lines of the single file that must then be the one target.
+    pub fn line_ranges(mut self, line_ranges: LineRanges) -> Self {
+        self.line_ranges = Some(line_ranges);
+        self
+    }
+
+    /// Run only the rules `selection` allows.
+    pub fn rule_selection(mut self, selection: RuleSelection) -> Self {
+        self.rule_selection = Some(selection);
+        self
+    }
+
+    /// Clean only lines added or changed since `git_ref`, such as `HEAD` or `origin/main`.
+    pub fn diff_only<S: Into<String>>(mut self, git_ref: S) -> Self {
+        self.diff_only = Some(git_ref.into());
+        self
+    }
+
+    /// Clean the files HEAD and the index change and stage, amend or commit the cleanup, for
+    /// [`crate::run_fixup`].
+    pub fn fixup(mut self, mode: FixupMode) -> Self {
+        self.fixup = Some(mode);
+        self
+    }
+
+    /// Write cleaned copies of the files into a mirrored tree under `dir`, leaving the
+    /// originals alone.
+    pub fn write_to<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.write_to = Some(dir.into());
+        self
+    }
+
+    /// Copy each file to its name with `suffix` appended, such as `.bak`, before rewriting it.
+    pub fn backup<S: Into<String>>(mut self, suffix: S) -> Self {
+        self.backup = Some(suffix.into());
+        self
+    }
+
+    /// Put a copy of each file in the operating system's trash before rewriting it.
+    pub fn trash(mut self, trash: bool) -> Self {
+        self.trash = trash;
+        self
+    }
+
+    /// Resume the run `id` (or [`crate::resume::LATEST`]) where it stopped, over its directories.
+    pub fn resume(mut self, id: impl Into<String>) -> Self {
+        self.resume = Some(id.into());
+        self
+    }
+
+    /// Thread count (default: `processing.threads` from the file config).
+    pub fn threads(mut self, threads: impl Into<Threads>) -> Self {
+        self.threads = Some(threads.into());
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn sort(mut self, sort: SortOrder) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Print only changed paths, one per line, instead of the usual output.
+    pub fn files_with_matches(mut self, files_with_matches: bool) -> Self {
+        self.files_with_matches = files_with_matches;
+        self
+    }
+
+    /// Print only changed paths, NUL-delimited, instead of the usual output.
+    pub fn print0(mut self, print0: bool) -> Self {
+        self.print0 = print0;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn show_skipped(mut self, show_skipped: bool) -> Self {
+        self.show_skipped = show_skipped;
+        self
+    }
+
+    pub fn per_directory(mut self, per_directory: bool) -> Self {
+        self.per_directory = per_directory;
+        self
+    }
+
+    pub fn group_by_dir(mut self, group_by_dir: bool) -> Self {
+        self.group_by_dir = group_by_dir;
+        self
+    }
+
+    /// Report each file as soon as it finishes, in `order`, instead of once its directory is done.
+    pub fn stream(mut self, order: StreamOrder) -> Self {
+        self.stream = Some(order);
+        self
+    }
+
+    /// List every file with why it was included or skipped.
+    pub fn why(mut self, why: bool) -> Self {
+        self.why = why;
+        self
+    }
+
+    /// Make displayed and reported paths relative to `directory` (`cwd` for the current one).
+    pub fn relative_to<P: Into<PathBuf>>(mut self, directory: P) -> Self {
+        self.relative_to = Some(directory.into());
+        self
+    }
+
+    pub fn use_cache(mut self, use_cache: bool) -> Self {
+        self.no_cache = !use_cache;
+        self
+    }
+
+    pub fn use_lock(mut self, use_lock: bool) -> Self {
+        self.no_lock = !use_lock;
+        self
+    }
+
+    pub fn use_journal(mut self, use_journal: bool) -> Self {
+        self.no_journal = !use_journal;
+        self
+    }
+
+    /// Abort once this many files have failed (default: never).
+    pub fn max_errors(mut self, max_errors: Option<usize>) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
+
+    /// Process at most this many files (default: no limit).
+    pub fn max_files(mut self, max_files: Option<usize>) -> Self {
+        self.max_files = max_files;
+        self
+    }
+
+    /// Stop walking and processing after this long (default: no limit).
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timings(mut self, timings: bool) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    /// Write a run report to `path` (`.json`, `.sarif` or `.html`).
+    pub fn report<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.report = Some(path.into());
+        self
+    }
+
+    pub fn logging(mut self, logging: LoggingConfig) -> Self {
+        self.logging = Some(logging);
+        self
+    }
+
+    /// File-based configuration to merge (default: [`Config::default`]).
+    pub fn config(mut self, config: Config) -> Self {
+        self.file_config = Some(config);
+        self
+    }
+
+    /// Validate and assemble the RuntimeConfig.
+    pub fn build(self) -> Result<RuntimeConfig> {
+        let mut file_config = self.file_config.unwrap_or_default();
+        exclude_backups(self.backup.as_deref(), &mut file_config)?;
+        let threads = self.threads.unwrap_or(file_config.processing.threads);
+        validate_limits(threads, self.max_depth, self.max_errors)?;
+        validate_budget(self.max_files, self.timeout)?;
+        validate_report(self.report.as_deref())?;
+        let relative_to = resolve_relative_to(self.relative_to.as_deref())?;
+        if let Some(selection) = &self.rule_selection {
+            selection.validate(&file_config)?;
+        }
+
+        let directories = if self.directories.is_empty() { vec![PathBuf::from(".")] } else { self.directories };
+        if self.line_ranges.is_some() {
+            validate_single_file(&directories)?;
+        } else {
+            validate_directories(&directories)?;
+        }
+
+        Ok(RuntimeConfig {
+            directories,
+            dry_run: self.dry_run,
+            verify: self.verify,
+            use_cache: !self.no_cache && self.write_to.is_none(),
+            write_to: self.write_to,
+            backup: self.backup,
+            trash: self.trash,
+            resume: self.resume,
+            max_depth: self.max_depth,
+            files_from: self.files_from,
+            null_delimited: self.null_delimited,
+            line_ranges: self.line_ranges,
+            rule_selection: self.rule_selection,
+            diff_only: self.diff_only,
+            fixup: self.fixup,
             threads,
+            format: self.format,
+            sort: self.sort,
+            files_with_matches: self.files_with_matches,
+            print0: self.print0,
+            quiet: self.quiet,
+            color: self.color,
+            show_skipped: self.show_skipped,
+            per_directory: self.per_directory,
+            group_by_dir: self.group_by_dir,
+            stream: self.stream,
+            why: self.why,
+            relative_to,
+            use_lock: !self.no_lock,
+            use_journal: !self.no_journal,
+            max_errors: self.max_errors,
+            max_files: self.max_files,
+            timeout: self.timeout,
+            timings: self.timings,
+            report: self.report,
+            logging: self.logging.unwrap_or_else(LoggingConfig::off),
             file_config,
         })
     }
@@ -404,15 +2870,68 @@ impl RuntimeConfig {
 #[cfg(test)]
 mod runtime_config_tests {
     use super::*;
+    use crate::logging::TraceFormat;
+    use clap::Parser;
 
     fn default_cli() -> Cli {
         Cli {
+            command: None,
             directories: vec![],
+            files_from: None,
+            null: false,
+            lines: None,
+            diff_only: None,
+            only: vec![],
+            skip: vec![],
+            fixup: false,
+            amend: false,
+            fixup_commit: false,
             config: None,
+            preset: None,
             dry_run: false,
-            verbose: false,
-            recursive: true,
-            threads: num_cpus::get(),
+            verify: false,
+            write_to: None,
+            backup: None,
+            trash: false,
+            resume: None,
+            verbose: 0,
+            quiet: false,
+            color: ColorChoice::Auto,
+            log_file: None,
+            no_log_file: false,
+            log_max_size: 10 << 20,
+            log_keep: 5,
+            trace_format: TraceFormat::Text,
+            strict: false,
+            recursive: false,
+            max_depth: None,
+            follow_symlinks: false,
+            max_file_size: None,
+            threads: Threads::default(),
+            io_threads: None,
+            nfs_safe: false,
+            no_cache: false,
+            no_lock: false,
+            no_journal: false,
+            max_errors: None,
+            fail_fast: false,
+            max_files: None,
+            timeout: None,
+            force: false,
+            allow_conflicts: false,
+            include_generated: false,
+            show_skipped: false,
+            per_directory: false,
+            group_by_dir: false,
+            stream: None,
+            why: false,
+            relative_to: None,
+            timings: false,
+            format: OutputFormat::Text,
+            sort: SortOrder::Path,
+            files_with_matches: false,
+            print0: false,
+            report: None,
         }
     }
 
@@ -433,6 +2952,27 @@ mod runtime_config_tests {
         assert_eq!(config.directories.len(), 2);
     }
 
+    #[test]
+    fn test_runtime_config_stats_directories() {
+        let cli = Cli {
+            command: Some(Command::Stats {
+                directories: vec![PathBuf::from("/tmp")],
+                top: 10,
+            }),
+            ..default_cli()
+        };
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.directories, vec![PathBuf::from("/tmp")]);
+    }
+
+    #[test]
+    fn test_runtime_config_diff_trees() {
+        let cli = Cli::try_parse_from(["whitespace", "diff", "/tmp", "/var"]).unwrap();
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.directories, vec![PathBuf::from("/tmp"), PathBuf::from("/var")]);
+        assert!(Cli::try_parse_from(["whitespace", "diff", "/tmp"]).is_err());
+    }
+
     #[test]
     fn test_runtime_config_dry_run() {
         let cli = Cli {
@@ -443,13 +2983,281 @@ mod runtime_config_tests {
         assert!(config.dry_run);
     }
 
+    #[test]
+    fn test_runtime_config_write_to() {
+        let cli = Cli::try_parse_from(["whitespace", "--write-to", "/tmp/clean"]).unwrap();
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.write_to, Some(PathBuf::from("/tmp/clean")));
+        assert!(!config.use_cache);
+
+        assert!(Cli::try_parse_from(["whitespace", "--write-to", "/tmp/clean", "--dry-run"]).is_err());
+        assert!(Cli::try_parse_from(["whitespace", "--write-to", "/tmp/clean", "--fixup"]).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_backup() {
+        let cli = Cli::try_parse_from(["whitespace", "--backup", ".bak"]).unwrap();
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.backup.as_deref(), Some(".bak"));
+        assert_eq!(config.file_config.exclude_files.last().unwrap(), "*.bak");
+
+        for suffix in ["", "/x", "~"] {
+            let result = RuntimeConfig::builder().backup(suffix).build();
+            assert_eq!(result.is_ok(), suffix == "~", "{:?}", suffix);
+        }
+        assert!(Cli::try_parse_from(["whitespace", "--backup", ".bak", "--dry-run"]).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_max_depth() {
+        let config = RuntimeConfig::from_cli(&default_cli()).unwrap();
+        assert_eq!(config.max_depth, None);
+
+        let cli = Cli {
+            max_depth: Some(2),
+            ..default_cli()
+        };
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.max_depth, Some(2));
+
+        let cli = Cli {
+            max_depth: Some(0),
+            ..default_cli()
+        };
+        assert!(RuntimeConfig::from_cli(&cli).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_max_errors() {
+        let config = RuntimeConfig::from_cli(&default_cli()).unwrap();
+        assert_eq!(config.max_errors, None);
+
+        let cli = Cli {
+            fail_fast: true,
+            ..default_cli()
+        };
+        assert_eq!(RuntimeConfig::from_cli(&cli).unwrap().max_errors, Some(1));
+
+        let cli = Cli {
+            max_errors: Some(0),
+            ..default_cli()
+        };
+        assert!(RuntimeConfig::from_cli(&cli).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_max_file_size() {
+        let cli = Cli {
+            max_file_size: Some(10 * 1024 * 1024),
+            ..default_cli()
+        };
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.file_config.processing.max_file_size, 10 * 1024 * 1024);
+
+        let cli = Cli::try_parse_from(["whitespace", "--max-file-size", "512K"]).unwrap();
+        assert_eq!(cli.max_file_size, Some(512 * 1024));
+        assert!(Cli::try_parse_from(["whitespace", "--max-file-size", "lots"]).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_io_threads() {
+        let config = RuntimeConfig::from_cli(&default_cli()).unwrap();
+        assert_eq!(config.file_config.processing.io_threads, None);
+
+        // An explicit limit wins over the one --nfs-safe picks
+        let cli = Cli::try_parse_from(["whitespace", "--io-threads", "8", "--nfs-safe", "-j", "32"]).unwrap();
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.file_config.processing.io_threads, Some(8));
+        assert_eq!(config.threads, Threads::Count(32));
+
+        let cli = Cli {
+            io_threads: Some(0),
+            ..default_cli()
+        };
+        assert!(RuntimeConfig::from_cli(&cli).is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_relative_to() {
+        let cwd = std::env::current_dir().unwrap();
+        let cli = Cli {
+            relative_to: Some(PathBuf::from("cwd")),
+            ..default_cli()
+        };
+        assert_eq!(RuntimeConfig::from_cli(&cli).unwrap().relative_to, Some(cwd.clone()));
+
+        let config = RuntimeConfig::builder().relative_to("src/..").build().unwrap();
+        assert_eq!(config.relative_to, Some(cwd));
+        assert!(RuntimeConfig::builder().relative_to("no/such/dir").build().is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_force() {
+        let config = RuntimeConfig::from_cli(&default_cli()).unwrap();
+        assert!(config.file_config.diff_detection.enabled);
+
+        let cli = Cli {
+            force: true,
+            ..default_cli()
+        };
+        assert!(
+            !RuntimeConfig::from_cli(&cli)
+                .unwrap()
+                .file_config
+                .diff_detection
+                .enabled
+        );
+    }
+
     #[test]
     fn test_runtime_config_threads_from_cli() {
         let cli = Cli {
-            threads: 4,
+            threads: Threads::Count(4),
+            ..default_cli()
+        };
+        let config = RuntimeConfig::from_cli(&cli).unwrap();
+        assert_eq!(config.threads, Threads::Count(4));
+    }
+
+    #[test]
+    fn test_runtime_config_quiet_and_color() {
+        let config = RuntimeConfig::from_cli(&default_cli()).unwrap();
+        assert!(!config.quiet);
+        assert_eq!(config.color, ColorChoice::Auto);
+
+        let cli = Cli {
+            quiet: true,
+            color: ColorChoice::Never,
             ..default_cli()
         };
         let config = RuntimeConfig::from_cli(&cli).unwrap();
-        assert_eq!(config.threads, 4);
+        assert!(config.quiet);
+        assert_eq!(config.color, ColorChoice::Never);
+        assert_eq!(config.logging.color, ColorChoice::Never);
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let config = RuntimeConfig::builder().build().unwrap();
+        assert_eq!(config.directories, vec![PathBuf::from(".")]);
+        assert!(!config.dry_run);
+        assert!(config.use_cache);
+        assert_eq!(config.threads, Config::default().processing.threads);
+        assert_eq!(config.logging, LoggingConfig::off());
+    }
+
+    #[test]
+    fn test_builder_merges_config() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file_config = Config {
+            follow_symlinks: true,
+            processing: ProcessingSettings {
+                threads: Threads::Count(3),
+                ..ProcessingSettings::default()
+            },
+            ..Config::default()
+        };
+
+        let config = RuntimeConfig::builder()
+            .directory(temp_dir.path())
+            .dry_run(true)
+            .max_depth(Some(2))
+            .use_cache(false)
+            .config(file_config)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.directories, vec![temp_dir.path().to_path_buf()]);
+        assert!(config.dry_run);
+        assert_eq!(config.max_depth, Some(2));
+        assert!(!config.use_cache);
+        assert_eq!(config.threads, Threads::Count(3));
+        assert!(config.file_config.follow_symlinks);
+        assert_eq!(
+            RuntimeConfig::builder().threads(5).build().unwrap().threads,
+            Threads::Count(5)
+        );
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("file.txt");
+        fs::write(&file, "content").unwrap();
+
+        assert!(RuntimeConfig::builder().threads(0).build().is_err());
+        assert!(RuntimeConfig::builder().max_depth(Some(0)).build().is_err());
+        assert!(
+            RuntimeConfig::builder()
+                .directory(temp_dir.path().join("missing"))
+                .build()
+                .is_err()
+        );
+        assert!(RuntimeConfig::builder().directories([&file]).build().is_err());
+    }
+
+    #[test]
+    fn test_runtime_config_strict_directories() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let missing = temp_dir.path().join("missing");
+
+        let cli = Cli {
+            directories: vec![temp_dir.path().to_path_buf(), missing.clone()],
+            ..default_cli()
+        };
+        assert!(RuntimeConfig::from_cli(&cli).is_ok());
+
+        let strict = Cli { strict: true, ..cli };
+        let err = RuntimeConfig::from_cli(&strict).unwrap_err();
+        assert!(err.to_string().contains("Directory does not exist"));
+
+        let strict_valid = Cli {
+            directories: vec![temp_dir.path().to_path_buf()],
+            ..strict
+        };
+        assert!(RuntimeConfig::from_cli(&strict_valid).is_ok());
+    }
+
+    #[test]
+    fn test_runtime_config_rule_selection() {
+        let select = |args: &[&str]| {
+            let args = ["whitespace"].iter().chain(args);
+            RuntimeConfig::from_cli(&Cli::try_parse_from(args).unwrap())
+        };
+
+        assert_eq!(select(&[]).unwrap().rule_selection, None);
+        let config = select(&["--only", "end-of-file,line-endings", "--skip", "line-endings"]).unwrap();
+        let selection = config.rule_selection.unwrap();
+        assert_eq!(selection.only, ["end-of-file", "line-endings"]);
+        assert_eq!(selection.skip, ["line-endings"]);
+        assert!(selection.allows("end-of-file"));
+        assert!(!selection.allows("line-endings"));
+        assert!(!selection.allows("trailing-whitespace"));
+
+        let err = select(&["--skip", "final-newline"]).unwrap_err();
+        assert!(
+            err.to_string().contains("Unknown rule for --skip: final-newline"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_runtime_config_line_ranges() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}\n").unwrap();
+        let lines = |targets: &[&Path]| {
+            let mut args = vec!["whitespace".as_ref(), "--lines".as_ref(), "100-200".as_ref()];
+            args.extend(targets.iter().map(|target| target.as_os_str()));
+            RuntimeConfig::from_cli(&Cli::try_parse_from(args).unwrap())
+        };
+
+        let config = lines(&[&file]).unwrap();
+        assert_eq!(config.line_ranges, Some(LineRanges::new([100..=200])));
+        let err = lines(&[temp_dir.path()]).unwrap_err();
+        assert!(err.to_string().contains("not one"), "{}", err);
+        assert!(lines(&[&file, &file]).is_err());
+        assert!(lines(&[]).is_err());
     }
 }
@@ -0,0 +1,215 @@
+use crate::config::CustomRule;
+use crate::processor::RuleLines;
+use crate::walker::compile_globs;
+use eyre::{Context, Result};
+use globset::GlobSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tracing::{debug, warn};
+
+/// [`CustomRule`]s with their `files` patterns compiled.
+#[derive(Debug, Clone)]
+pub struct CustomRules {
+    rules: Vec<(Option<GlobSet>, CustomRule)>,
+}
+
+impl CustomRules {
+    pub fn new(rules: &[CustomRule]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let globs = (!rule.files.is_empty()).then(|| compile_globs(&rule.files));
+                (globs, rule.clone())
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// The rules whose `files` match the file name, in the order they run. A rule without
+    /// `files` applies to every file.
+    pub fn for_file(&self, path: &Path) -> Vec<&CustomRule> {
+        let name = path.file_name();
+        self.rules
+            .iter()
+            .filter(|(globs, _)| match globs {
+                Some(globs) => name.is_some_and(|name| globs.is_match(name)),
+                None => true,
+            })
+            .map(|(_, rule)| rule)
+            .collect()
+    }
+}
+
+/// What custom rules made of a file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Transformed {
+    /// The content after every rule
+    pub content: String,
+    /// 1-based lines that a rule changed or reported a finding on, numbered as that rule saw them
+    pub lines: Vec<usize>,
+    /// The same lines by rule, for the rules that changed or reported any
+    pub rules: Vec<RuleLines>,
+}
+
+/// Run `content` through each of `rules` in turn. A rule's command reads the content on stdin
+/// and writes the transformed content to stdout; `WHITESPACE_PATH` names the file. Lines it
+/// prints to stderr as `LINE: message` are findings, which are logged and count as lines that
+/// need attention even when the content is left as it is.
+pub fn apply(rules: &[&CustomRule], path: &Path, content: &str) -> Result<Transformed> {
+    let mut transformed = Transformed {
+        content: content.to_string(),
+        lines: Vec::new(),
+        rules: Vec::new(),
+    };
+    for rule in rules {
+        let (output, findings) = run(rule, path, &transformed.content)?;
+        let mut lines = changed_lines(&transformed.content, &output);
+        for (line, message) in findings {
+            warn!("{}:{}: {}: {}", path.display(), line, rule.name, message);
+            lines.push(line);
+        }
+        lines.sort_unstable();
+        lines.dedup();
+        transformed.lines.extend(&lines);
+        transformed.content = output;
+        if !lines.is_empty() {
+            transformed.rules.push(RuleLines {
+                rule: rule.name.clone(),
+                lines,
+            });
+        }
+    }
+    transformed.lines.sort_unstable();
+    transformed.lines.dedup();
+    Ok(transformed)
+}
+
+/// Run one rule's command, returning what it printed and its findings.
+fn run(rule: &CustomRule, path: &Path, content: &str) -> Result<(String, Vec<(usize, String)>)> {
+    let Some((program, args)) = rule.command.split_first() else {
+        eyre::bail!("Custom rule `{}` has no command", rule.name);
+    };
+    debug!("Running custom rule {} on {}", rule.name, path.display());
+    let mut child = Command::new(program)
+        .args(args)
+        .env("WHITESPACE_PATH", path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run custom rule `{}` ({})", rule.name, program))?;
+
+    // Feed stdin from another thread, so a command that writes before it has read everything
+    // can't fill its stdout pipe and stall both sides
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let output = std::thread::scope(|scope| {
+        scope.spawn(move || stdin.write_all(content.as_bytes()));
+        child.wait_with_output()
+    })
+    .with_context(|| format!("Failed to run custom rule `{}`", rule.name))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        eyre::bail!(
+            "Custom rule `{}` failed ({}): {}",
+            rule.name,
+            output.status,
+            stderr.trim()
+        );
+    }
+    let transformed = String::from_utf8(output.stdout)
+        .with_context(|| format!("Custom rule `{}` printed invalid UTF-8", rule.name))?;
+
+    let findings = stderr
+        .lines()
+        .filter_map(|line| {
+            let (number, message) = line.split_once(':')?;
+            Some((number.trim().parse().ok()?, message.trim().to_string()))
+        })
+        .collect();
+    Ok((transformed, findings))
+}
+
+/// The 1-based lines of `before` that differ in `after`. When lines were added or removed,
+/// that is everything between the lines the two share at the start and at the end, and lines
+/// that were only inserted count against the line they were inserted before.
+fn changed_lines(before: &str, after: &str) -> Vec<usize> {
+    if before == after {
+        return Vec::new();
+    }
+    let old: Vec<&str> = before.split_inclusive('\n').collect();
+    let new: Vec<&str> = after.split_inclusive('\n').collect();
+    if old.len() == new.len() {
+        return (1..=old.len()).filter(|&line| old[line - 1] != new[line - 1]).collect();
+    }
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let first = (prefix + 1).min(old.len().max(1));
+    let last = (old.len() - suffix).max(first);
+    (first..=last).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, script: &str, files: &[&str]) -> CustomRule {
+        CustomRule {
+            name: name.to_string(),
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            files: files.iter().map(|file| file.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_changed_lines() {
+        assert_eq!(changed_lines("a\nb\nc\n", "a\nb\nc\n"), Vec::<usize>::new());
+        assert_eq!(changed_lines("a\nb\nc\n", "a\nB\nc\n"), [2]);
+        assert_eq!(changed_lines("a\nb\nc\n", "A\nb\nC\n"), [1, 3]);
+        assert_eq!(changed_lines("a\nb\nc\nd\n", "a\nc\nd\n"), [2]);
+        assert_eq!(changed_lines("a\nb\n", "a\nnew\nb\n"), [2]);
+        assert_eq!(changed_lines("a\nb\n", "a\nb\nc\n"), [2]);
+        assert_eq!(changed_lines("", "header\n"), [1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_apply() {
+        let upper = rule("upper", "tr a-z A-Z", &["*.txt"]);
+        let header = rule("header", "echo \"# $WHITESPACE_PATH\"; cat", &[]);
+        let rules = CustomRules::new(&[upper, header]);
+        assert_eq!(rules.for_file(Path::new("notes.md")).len(), 1);
+
+        // Rules run in order, each on the output of the one before
+        let path = Path::new("notes.txt");
+        let transformed = apply(&rules.for_file(path), path, "one\ntwo\n").unwrap();
+        assert_eq!(transformed.content, "# notes.txt\nONE\nTWO\n");
+        assert_eq!(transformed.lines, [1, 2]);
+        let rules: Vec<(&str, &[usize])> = transformed
+            .rules
+            .iter()
+            .map(|rule| (rule.rule.as_str(), rule.lines.as_slice()))
+            .collect();
+        assert_eq!(rules, [("upper", &[1, 2][..]), ("header", &[1][..])]);
+
+        // Findings count even when the content is unchanged
+        let findings = rule("findings", "cat; echo '2: no TODOs' >&2", &[]);
+        let transformed = apply(&[&findings], path, "a\nTODO\n").unwrap();
+        assert_eq!(transformed.content, "a\nTODO\n");
+        assert_eq!(transformed.lines, [2]);
+
+        let failing = rule("failing", "echo broken >&2; exit 3", &[]);
+        let error = apply(&[&failing], path, "a\n").unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("Custom rule `failing` failed"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,620 @@
+use crate::cache::Cache;
+use crate::config::RuntimeConfig;
+use crate::engine::{self, ParallelEngine};
+use crate::lock::RootLock;
+use crate::ports::fs::RealFs;
+use crate::ports::network::NetworkFs;
+use crate::report::file_json;
+use crate::walker::FileWalker;
+use eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+/// A request, sent as one line of JSON such as `{"command": "check", "paths": ["src"]}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Request {
+    /// Report what cleaning would change without writing anything
+    Check {
+        #[serde(default)]
+        paths: Vec<PathBuf>,
+    },
+    /// Clean files in place
+    Clean {
+        #[serde(default)]
+        paths: Vec<PathBuf>,
+    },
+    /// Check that the daemon is alive
+    Ping,
+    /// Stop accepting connections; the daemon exits once open connections close
+    Shutdown,
+}
+
+/// A long-running server for one root directory.
+///
+/// The config, the walker's compiled globs and the incremental cache are loaded once and kept
+/// in memory, so each request only pays for the files it names. Requests and replies are single
+/// lines of JSON; any number can be sent over one connection.
+pub struct Daemon {
+    root: PathBuf,
+    max_depth: Option<usize>,
+    walker: FileWalker<NetworkFs<RealFs>>,
+    engine: ParallelEngine<NetworkFs<RealFs>>,
+    cache: Option<Arc<Cache>>,
+    /// Lock the root while cleaning, as a normal run does
+    use_lock: bool,
+}
+
+impl Daemon {
+    /// Prepare to serve the first target directory of `runtime_config`.
+    pub fn new(runtime_config: &RuntimeConfig) -> Result<Self> {
+        let root = runtime_config
+            .directories
+            .first()
+            .map_or_else(|| PathBuf::from("."), PathBuf::clone);
+        // Editors send absolute paths, which must share the root's prefix to hit the cache
+        let root = std::fs::canonicalize(&root).with_context(|| format!("Cannot serve {}", root.display()))?;
+        if !root.is_dir() {
+            eyre::bail!("Not a directory: {}", root.display());
+        }
+
+        let config = &runtime_config.file_config;
+        let fs = Arc::new(crate::throttled(config, crate::real_fs(config)));
+        let file_config = Arc::new(runtime_config.file_config.clone());
+        let walker = FileWalker::new(Arc::clone(&file_config), Arc::clone(&fs));
+        // There are no files to sample yet, so `auto-io` falls back to a thread per CPU
+        let threads = engine::thread_count(runtime_config.threads, Arc::clone(&file_config), Arc::clone(&fs), &[]);
+        let mut engine =
+            ParallelEngine::new(file_config, fs, threads).context("Failed to initialize parallel engine")?;
+
+        // As in a normal run, a broken cache only costs speed
+        let cache = if runtime_config.use_cache {
+            match Cache::open(&root, &runtime_config.file_config) {
+                Ok(cache) => Some(Arc::new(cache)),
+                Err(e) => {
+                    warn!("Running without a cache: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        if let Some(cache) = &cache {
+            engine = engine.with_cache(Arc::clone(cache));
+        }
+
+        Ok(Self {
+            root,
+            max_depth: runtime_config.max_depth,
+            walker,
+            engine,
+            cache,
+            use_lock: runtime_config.use_lock,
+        })
+    }
+
+    /// Listen on the Unix domain socket at `socket` until a `shutdown` request arrives.
+    /// A socket left behind by a daemon that died is replaced; a live one is an error.
+    #[cfg(unix)]
+    pub fn serve(&self, socket: &Path) -> Result<()> {
+        use std::io::BufReader;
+        use std::os::unix::net::{UnixListener, UnixStream};
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                eyre::bail!("A daemon is already listening on {}", socket.display());
+            }
+            std::fs::remove_file(socket)
+                .with_context(|| format!("Failed to remove stale socket {}", socket.display()))?;
+        }
+        if let Some(parent) = socket.parent() {
+            std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let listener =
+            UnixListener::bind(socket).with_context(|| format!("Failed to listen on {}", socket.display()))?;
+        info!("Serving {} on {}", self.root.display(), socket.display());
+
+        let stopping = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Failed to accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let stopping = &stopping;
+                scope.spawn(move || {
+                    let input = match stream.try_clone() {
+                        Ok(input) => BufReader::new(input),
+                        Err(e) => return warn!("Failed to read from a connection: {}", e),
+                    };
+                    match self.serve_connection(input, &stream) {
+                        Ok(true) => {
+                            stopping.store(true, Ordering::Relaxed);
+                            // Wake the accept loop so it sees the flag
+                            let _ = UnixStream::connect(socket);
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("Connection failed: {:#}", e),
+                    }
+                });
+            }
+        });
+
+        let _ = std::fs::remove_file(socket);
+        info!("Daemon stopped");
+        Ok(())
+    }
+
+    /// Listen on the named pipe `socket`, such as `\\.\pipe\whitespace`, until a `shutdown`
+    /// request arrives. Another daemon already listening on it is an error.
+    #[cfg(windows)]
+    pub fn serve(&self, socket: &Path) -> Result<()> {
+        use std::io::BufReader;
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        if !socket.starts_with(r"\\.\pipe\") {
+            eyre::bail!(
+                "Cannot listen on {}: named pipes live under \\\\.\\pipe\\",
+                socket.display()
+            );
+        }
+        // Creating the first instance fails if another daemon holds the name
+        let mut pipe = pipe::create(socket, true)
+            .with_context(|| format!("A daemon may already be listening on {}", socket.display()))?;
+        info!("Serving {} on {}", self.root.display(), socket.display());
+
+        let stopping = AtomicBool::new(false);
+        std::thread::scope(|scope| {
+            loop {
+                if let Err(e) = pipe::accept(&pipe) {
+                    warn!("Failed to accept a connection: {}", e);
+                    pipe::disconnect(&pipe);
+                    continue;
+                }
+                if stopping.load(Ordering::Relaxed) {
+                    break;
+                }
+                // The next client connects to a fresh instance while this one is served
+                let next = match pipe::create(socket, false) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        warn!("Failed to listen for more connections: {:#}", e);
+                        break;
+                    }
+                };
+                let stream = std::mem::replace(&mut pipe, next);
+                let stopping = &stopping;
+                scope.spawn(move || {
+                    let input = match stream.try_clone() {
+                        Ok(input) => BufReader::new(input),
+                        Err(e) => return warn!("Failed to read from a connection: {}", e),
+                    };
+                    match self.serve_connection(input, &stream) {
+                        Ok(true) => {
+                            stopping.store(true, Ordering::Relaxed);
+                            // Wake the accept loop so it sees the flag
+                            let _ = std::fs::File::options().read(true).write(true).open(socket);
+                        }
+                        Ok(false) => {}
+                        Err(e) => warn!("Connection failed: {:#}", e),
+                    }
+                    pipe::disconnect(&stream);
+                });
+            }
+        });
+
+        info!("Daemon stopped");
+        Ok(())
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn serve(&self, socket: &Path) -> Result<()> {
+        eyre::bail!(
+            "Cannot listen on {}: the daemon needs Unix domain sockets or named pipes",
+            socket.display()
+        )
+    }
+
+    /// Answer each request line from `input` on `output` until the connection closes.
+    /// Returns true if the client asked the daemon to shut down.
+    pub fn serve_connection(&self, input: impl BufRead, mut output: impl Write) -> Result<bool> {
+        for line in input.lines() {
+            let line = line.context("Failed to read request")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (reply, shutdown) = self.handle(&line);
+            serde_json::to_writer(&mut output, &reply)?;
+            output.write_all(b"\n")?;
+            output.flush().context("Failed to write reply")?;
+            if shutdown {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The reply to one request line, and whether it asked the daemon to stop.
+    fn handle(&self, line: &str) -> (Value, bool) {
+        let request = match serde_json::from_str::<Request>(line) {
+            Ok(request) => request,
+            Err(e) => return (error(format!("Invalid request: {}", e)), false),
+        };
+        debug!("Daemon request: {:?}", request);
+
+        match request {
+            Request::Check { paths } => (self.process(&paths, true), false),
+            Request::Clean { paths } => (self.process(&paths, false), false),
+            Request::Ping => (json!({ "ok": true, "version": env!("GIT_DESCRIBE") }), false),
+            Request::Shutdown => (json!({ "ok": true }), true),
+        }
+    }
+
+    fn process(&self, paths: &[PathBuf], dry_run: bool) -> Value {
+        let start = Instant::now();
+        // Wait out any other run cleaning this tree, such as a pre-commit hook
+        let _lock = if dry_run || !self.use_lock {
+            None
+        } else {
+            match RootLock::acquire(&self.root, || info!("Waiting for the lock on {}", self.root.display())) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    warn!("Running without a lock on {}: {:#}", self.root.display(), e);
+                    None
+                }
+            }
+        };
+        // Attributes files may have changed since the last request
+        self.walker.clear_attributes();
+        self.engine.clear_attributes();
+        let results = match self
+            .collect(paths)
+            .and_then(|files| self.engine.process_files_with_results(files, dry_run))
+        {
+            Ok(results) => results,
+            Err(e) => return error(format!("{:#}", e)),
+        };
+        if let Some(cache) = &self.cache
+            && let Err(e) = cache.save()
+        {
+            warn!("Failed to save cache for {}: {}", self.root.display(), e);
+        }
+
+        let files: Vec<Value> = results
+            .file_results
+            .iter()
+            .map(|(path, result)| file_json(path, result))
+            .collect();
+        json!({
+            "ok": true,
+            "dry_run": dry_run,
+            "files": files,
+            "elapsed_ms": start.elapsed().as_secs_f64() * 1000.0,
+        })
+    }
+
+    /// The files a request names. Relative paths are resolved against the root, directories are
+    /// walked with the configured excludes, and no paths at all means the whole root. Paths that
+    /// lead outside the root, whether absolute, through `..` or through a symlink, are refused.
+    fn collect(&self, paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        if paths.is_empty() {
+            return self.walker.collect_files(&self.root, self.max_depth);
+        }
+        let mut files = Vec::new();
+        for path in paths {
+            let joined = self.root.join(path);
+            let Ok(path) = std::fs::canonicalize(&joined) else {
+                eyre::bail!("Not a file or directory: {}", joined.display());
+            };
+            if !path.starts_with(&self.root) {
+                eyre::bail!("Outside {}: {}", self.root.display(), joined.display());
+            }
+            if path.is_dir() {
+                files.extend(self.walker.collect_files(&path, self.max_depth)?);
+            } else if path.is_file() {
+                files.push(path);
+            } else {
+                eyre::bail!("Not a file or directory: {}", path.display());
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Where the daemon listens unless `--socket` says otherwise: the user's runtime directory,
+/// falling back to the temporary directory.
+#[cfg(not(windows))]
+pub fn default_socket() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("whitespace")
+        .join("daemon.sock")
+}
+
+/// Where the daemon listens unless `--socket` says otherwise: a named pipe for the user.
+#[cfg(windows)]
+pub fn default_socket() -> PathBuf {
+    let user = std::env::var("USERNAME").unwrap_or_default();
+    PathBuf::from(format!(r"\\.\pipe\whitespace-{}", user))
+}
+
+/// The Windows named pipe calls the daemon needs. Each connected instance is wrapped in a
+/// [`File`](std::fs::File), which reads and writes a pipe handle like any other.
+#[cfg(windows)]
+mod pipe {
+    use eyre::Result;
+    use std::fs::File;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::path::Path;
+    use windows_sys::Win32::Foundation::{ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, FlushFileBuffers, PIPE_ACCESS_DUPLEX,
+    };
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS,
+        PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    const BUFFER_SIZE: u32 = 64 * 1024;
+
+    /// A new instance of the pipe `name`, waiting for a client; with `first`, failing if any
+    /// instance already exists.
+    pub fn create(name: &Path, first: bool) -> Result<File> {
+        let wide: Vec<u16> = name.as_os_str().encode_wide().chain(Some(0)).collect();
+        let open_mode = PIPE_ACCESS_DUPLEX | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+        let pipe_mode = PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS;
+        // SAFETY: `wide` is a NUL-terminated UTF-16 string that outlives the call, and a null
+        // security attributes pointer asks for the default security descriptor
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide.as_ptr(),
+                open_mode,
+                pipe_mode,
+                PIPE_UNLIMITED_INSTANCES,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                std::ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error())
+                .map_err(|e| eyre::eyre!("Failed to create named pipe {}: {}", name.display(), e));
+        }
+        // SAFETY: the handle was just created, is valid and is owned by nothing else
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+
+    /// Wait for a client to connect to `pipe`.
+    pub fn accept(pipe: &File) -> std::io::Result<()> {
+        // SAFETY: `pipe` holds a valid pipe handle, opened for synchronous I/O
+        if unsafe { ConnectNamedPipe(pipe.as_raw_handle(), std::ptr::null_mut()) } != 0 {
+            return Ok(());
+        }
+        let e = std::io::Error::last_os_error();
+        // A client that connected before the call is connected all the same
+        if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED as i32) { Ok(()) } else { Err(e) }
+    }
+
+    /// Let the client read the last reply, then drop it.
+    pub fn disconnect(pipe: &File) {
+        // SAFETY: `pipe` holds a valid pipe handle
+        unsafe {
+            FlushFileBuffers(pipe.as_raw_handle());
+            DisconnectNamedPipe(pipe.as_raw_handle());
+        }
+    }
+}
+
+fn error(message: String) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::io::Cursor;
+    use tempfile::TempDir;
+
+    fn daemon(root: &Path) -> Daemon {
+        let runtime_config = RuntimeConfig::builder()
+            .directories([root])
+            .use_cache(false)
+            .config(Config::default())
+            .build()
+            .unwrap();
+        Daemon::new(&runtime_config).unwrap()
+    }
+
+    fn replies(daemon: &Daemon, requests: &str) -> (Vec<Value>, bool) {
+        let mut output = Vec::new();
+        let shutdown = daemon.serve_connection(Cursor::new(requests), &mut output).unwrap();
+        let replies = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        (replies, shutdown)
+    }
+
+    #[test]
+    fn test_check_and_clean_requests() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join("src")).unwrap();
+        std::fs::write(temp_dir.path().join("src/a.txt"), "one \ntwo\n").unwrap();
+        std::fs::write(temp_dir.path().join("b.txt"), "clean\n").unwrap();
+        let daemon = daemon(temp_dir.path());
+
+        let (replies, shutdown) = replies(
+            &daemon,
+            "{\"command\": \"check\", \"paths\": [\"src\"]}\n\n\
+             {\"command\": \"clean\"}\n\
+             {\"command\": \"check\", \"paths\": [\"missing.txt\"]}\n\
+             {\"command\": \"lint\"}\n",
+        );
+
+        assert!(!shutdown);
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["ok"], true);
+        assert_eq!(replies[0]["files"].as_array().unwrap().len(), 1);
+        assert_eq!(replies[0]["files"][0]["outcome"], "modified");
+        assert_eq!(replies[0]["files"][0]["lines"], json!([1]));
+
+        assert_eq!(replies[1]["dry_run"], false);
+        assert_eq!(replies[1]["files"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("src/a.txt")).unwrap(),
+            "one\ntwo\n"
+        );
+
+        assert_eq!(replies[2]["ok"], false);
+        assert!(replies[2]["error"].as_str().unwrap().contains("missing.txt"));
+        assert!(replies[3]["error"].as_str().unwrap().starts_with("Invalid request"));
+    }
+
+    #[test]
+    fn test_requests_see_changed_attributes() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        std::fs::write(temp_dir.path().join(".gitattributes"), "*.txt binary\n").unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "one \n").unwrap();
+        let daemon = daemon(temp_dir.path());
+        let check = "{\"command\": \"check\", \"paths\": [\"a.txt\"]}\n";
+
+        let (before, _) = replies(&daemon, check);
+        std::fs::write(temp_dir.path().join(".gitattributes"), "*.bat eol=crlf\n").unwrap();
+        let (after, _) = replies(&daemon, check);
+
+        assert_eq!(before[0]["files"][0]["outcome"], "skipped");
+        assert_eq!(after[0]["files"][0]["outcome"], "modified");
+    }
+
+    #[test]
+    fn test_paths_outside_the_root_are_refused() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("root");
+        std::fs::create_dir(&root).unwrap();
+        let outside = temp_dir.path().join("outside.txt");
+        std::fs::write(&outside, "secret  \n").unwrap();
+        let daemon = daemon(&root);
+
+        let requests = [
+            json!({ "command": "clean", "paths": ["../outside.txt"] }),
+            json!({ "command": "clean", "paths": [&outside] }),
+        ];
+        #[cfg(unix)]
+        let requests = {
+            std::os::unix::fs::symlink(&outside, root.join("link.txt")).unwrap();
+            let mut requests = requests.to_vec();
+            requests.push(json!({ "command": "clean", "paths": ["link.txt"] }));
+            requests
+        };
+        for request in requests {
+            let (replies, _) = replies(&daemon, &format!("{}\n", request));
+            assert_eq!(replies[0]["ok"], false, "{}", request);
+            assert!(
+                replies[0]["error"].as_str().unwrap().starts_with("Outside"),
+                "{}",
+                replies[0]
+            );
+        }
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "secret  \n");
+    }
+
+    #[test]
+    fn test_shutdown_ends_the_connection() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = daemon(temp_dir.path());
+
+        let (replies, shutdown) = replies(
+            &daemon,
+            "{\"command\": \"ping\"}\n{\"command\": \"shutdown\"}\n{\"command\": \"ping\"}\n",
+        );
+
+        assert!(shutdown);
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["version"], env!("GIT_DESCRIBE"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_over_socket() {
+        use std::io::BufReader;
+        use std::os::unix::net::UnixStream;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "x\t\n").unwrap();
+        let socket = temp_dir.path().join("run/daemon.sock");
+        let daemon = daemon(temp_dir.path());
+
+        std::thread::scope(|scope| {
+            let server = scope.spawn(|| daemon.serve(&socket));
+            let stream = loop {
+                if let Ok(stream) = UnixStream::connect(&socket) {
+                    break stream;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = |line: &str| {
+                writeln!(&stream, "{}", line).unwrap();
+                let mut reply = String::new();
+                reader.read_line(&mut reply).unwrap();
+                serde_json::from_str::<Value>(&reply).unwrap()
+            };
+
+            assert_eq!(request(r#"{"command": "check"}"#)["files"][0]["lines"], json!([1]));
+            assert_eq!(request(r#"{"command": "shutdown"}"#)["ok"], true);
+            server.join().unwrap().unwrap();
+        });
+        assert!(!socket.exists());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_serve_over_named_pipe() {
+        use std::io::BufReader;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "x\t\n").unwrap();
+        let pipe = PathBuf::from(format!(r"\\.\pipe\whitespace-test-{}", std::process::id()));
+        let daemon = daemon(temp_dir.path());
+
+        std::thread::scope(|scope| {
+            let server = scope.spawn(|| daemon.serve(&pipe));
+            let stream = loop {
+                if let Ok(stream) = std::fs::File::options().read(true).write(true).open(&pipe) {
+                    break stream;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = |line: &str| {
+                writeln!(&stream, "{}", line).unwrap();
+                let mut reply = String::new();
+                reader.read_line(&mut reply).unwrap();
+                serde_json::from_str::<Value>(&reply).unwrap()
+            };
+
+            assert_eq!(request(r#"{"command": "check"}"#)["files"][0]["lines"], json!([1]));
+            // A second daemon can't take over the pipe
+            assert!(daemon.serve(&pipe).is_err());
+            assert_eq!(request(r#"{"command": "shutdown"}"#)["ok"], true);
+            server.join().unwrap().unwrap();
+        });
+    }
+}
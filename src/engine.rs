@@ -1,15 +1,30 @@
-use crate::config::Config;
+use crate::cache::Cache;
+use crate::config::{Config, RuleSelection, Threads};
+use crate::git_diff::ChangedLines;
 use crate::ports::fs::FileSystem;
-use crate::processor::{ProcessingResult, WhitespaceProcessor};
+use crate::processor::{Outcome, ProcessingResult, SkipReason, WhitespaceProcessor};
+use crate::report::outcome_name;
+use crate::rules::LineRanges;
+use crate::timings::Timings;
 use eyre::Result;
-use log::{debug, info, warn};
 use rayon::prelude::*;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::{Span, debug, debug_span, info, warn};
+
+/// Called as each file finishes, with its index in the files given, from whichever thread ran it.
+pub type OnResult = dyn Fn(usize, &Path, &ProcessingResult) + Send + Sync;
 
 pub struct ParallelEngine<F: FileSystem> {
+    fs: Arc<F>,
     processor: WhitespaceProcessor<F>,
+    max_errors: Option<usize>,
+    deadline: Option<Instant>,
+    on_result: Option<Arc<OnResult>>,
 }
 
 #[derive(Debug)]
@@ -17,12 +32,24 @@ pub struct ProcessingSummary {
     pub files_processed: usize,
     pub files_modified: usize,
     pub files_with_errors: usize,
+    pub files_skipped: usize,
     pub duration: Duration,
 }
 
 #[derive(Debug)]
 pub struct ProcessingResults {
     pub file_results: Vec<(PathBuf, ProcessingResult)>,
+    /// True if the error limit was reached and the remaining files were not processed
+    pub aborted: bool,
+}
+
+impl ProcessingResults {
+    /// Files that were deliberately left alone, with the reason.
+    pub fn skipped(&self) -> impl Iterator<Item = (&Path, SkipReason)> {
+        self.file_results
+            .iter()
+            .filter_map(|(path, result)| result.skip_reason().map(|reason| (path.as_path(), reason)))
+    }
 }
 
 impl<F: FileSystem> ParallelEngine<F> {
@@ -40,9 +67,82 @@ impl<F: FileSystem> ParallelEngine<F> {
             debug!("Thread pool already initialized, using existing configuration");
         }
 
-        let processor = WhitespaceProcessor::new(Arc::clone(&config), fs);
+        let processor = WhitespaceProcessor::new(Arc::clone(&config), Arc::clone(&fs));
+
+        Ok(Self {
+            fs,
+            processor,
+            max_errors: None,
+            deadline: None,
+            on_result: None,
+        })
+    }
+
+    /// Share an incremental cache with the processor so known-clean files are skipped.
+    pub fn with_cache(mut self, cache: Arc<Cache>) -> Self {
+        self.processor = self.processor.with_cache(cache);
+        self
+    }
+
+    /// Accumulate per-phase times for `--timings`.
+    pub fn with_timings(mut self, timings: Arc<Timings>) -> Self {
+        self.processor = self.processor.with_timings(timings);
+        self
+    }
+
+    /// Clean only the lines in `line_ranges` of each file.
+    pub fn with_line_ranges(mut self, line_ranges: LineRanges) -> Self {
+        self.processor = self.processor.with_line_ranges(line_ranges);
+        self
+    }
+
+    /// Run only the rules `selection` allows.
+    pub fn with_rule_selection(mut self, selection: RuleSelection) -> Self {
+        self.processor = self.processor.with_rule_selection(selection);
+        self
+    }
+
+    /// Clean only the lines git reports as changed in each file.
+    pub fn with_changed_lines(mut self, changed_lines: Arc<ChangedLines>) -> Self {
+        self.processor = self.processor.with_changed_lines(changed_lines);
+        self
+    }
+
+    /// Write files that need no cleaning as well, as they are.
+    pub fn with_write_unchanged(mut self) -> Self {
+        self.processor = self.processor.with_write_unchanged();
+        self
+    }
+
+    /// Copy each file to its name with `suffix` appended before rewriting it.
+    pub fn with_backup(mut self, suffix: impl Into<String>) -> Self {
+        self.processor = self.processor.with_backup(suffix);
+        self
+    }
+
+    /// Put a copy of each file in the operating system's trash before rewriting it.
+    pub fn with_trash(mut self) -> Self {
+        self.processor = self.processor.with_trash();
+        self
+    }
+
+    /// Stop processing once `max_errors` files have failed (None = never stop).
+    /// Files already in flight finish; those not yet started are dropped from the results.
+    pub fn with_max_errors(mut self, max_errors: Option<usize>) -> Self {
+        self.max_errors = max_errors;
+        self
+    }
 
-        Ok(Self { processor })
+    /// Leave files not yet started once `deadline` passes, reporting them as skipped.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Hand each file's result to `on_result` as soon as it finishes, for `--stream`.
+    pub fn with_on_result(mut self, on_result: Arc<OnResult>) -> Self {
+        self.on_result = Some(on_result);
+        self
     }
 
     pub fn process_files_with_results(&self, files: Vec<PathBuf>, dry_run: bool) -> Result<ProcessingResults> {
@@ -51,48 +151,132 @@ impl<F: FileSystem> ParallelEngine<F> {
         info!("Starting parallel processing of {} files", files.len());
         debug!("Dry run mode: {}", dry_run);
 
-        // Process files in parallel
-        let file_results: Vec<(PathBuf, ProcessingResult)> = files
-            .par_iter()
-            .map(|path| {
-                let result = self.processor.process_file(path, dry_run).unwrap_or_else(|e| {
-                    warn!("Failed to process {}: {}", path.display(), e);
-                    ProcessingResult {
-                        lines_modified: vec![],
-                        had_changes: false,
-                        error: Some(format!("Processing failed: {}", e)),
-                    }
-                });
-                (path.clone(), result)
+        // Process files in parallel; once the error limit trips, tasks that haven't started bail out
+        let parent = Span::current();
+        let failures = AtomicUsize::new(0);
+        let aborted = AtomicBool::new(false);
+        let mut indexed_results: Vec<(usize, ProcessingResult)> = self
+            .largest_first(&files)
+            .into_par_iter()
+            .filter_map(|index| {
+                if aborted.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let path = &files[index];
+                let result = if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    ProcessingResult::skip(SkipReason::Limit)
+                } else {
+                    self.process_one(path, dry_run, &parent, &failures, &aborted)
+                };
+                if let Some(on_result) = &self.on_result {
+                    on_result(index, path, &result);
+                }
+                Some((index, result))
             })
             .collect();
 
+        // Report in the order the files were given, not the order they were scheduled
+        indexed_results.sort_unstable_by_key(|(index, _)| *index);
+        let file_results: Vec<(PathBuf, ProcessingResult)> = indexed_results
+            .into_iter()
+            .map(|(index, result)| (files[index].clone(), result))
+            .collect();
+        let aborted = aborted.into_inner();
+
         let duration = start_time.elapsed();
 
         // Aggregate results
-        let results: Vec<ProcessingResult> = file_results.iter().map(|(_, result)| result.clone()).collect();
-        let summary = self.aggregate_results(results, duration);
+        let summary = self.aggregate_results(file_results.iter().map(|(_, result)| result), duration);
 
         info!(
-            "Processing completed: {} files processed, {} modified, {} errors in {:?}",
-            summary.files_processed, summary.files_modified, summary.files_with_errors, summary.duration
+            "Processing completed: {} files processed, {} modified, {} skipped, {} errors in {:?}",
+            summary.files_processed,
+            summary.files_modified,
+            summary.files_skipped,
+            summary.files_with_errors,
+            summary.duration
         );
 
-        Ok(ProcessingResults { file_results })
+        Ok(ProcessingResults { file_results, aborted })
+    }
+
+    /// Forget the `.gitattributes` files read so far, so that later files read them afresh.
+    pub fn clear_attributes(&self) {
+        self.processor.clear_attributes();
+    }
+
+    /// Scan `files` again without rewriting them, for `--verify`, returning those a second pass
+    /// would still change. The cache is bypassed, since the rewrites have just recorded every
+    /// one of them as clean.
+    pub fn verify(&self, files: &[PathBuf]) -> Vec<(PathBuf, ProcessingResult)> {
+        let verifier = self.processor.without_cache();
+        files
+            .par_iter()
+            .filter_map(|path| {
+                let result = verifier.process_file(path, true).ok()?;
+                result.had_changes().then(|| (path.clone(), result))
+            })
+            .collect()
+    }
+
+    /// Process the file at `path` in a span of its own, counting it towards the error limit if it
+    /// fails and setting `aborted` once the limit is reached.
+    fn process_one(
+        &self,
+        path: &Path,
+        dry_run: bool,
+        parent: &Span,
+        failures: &AtomicUsize,
+        aborted: &AtomicBool,
+    ) -> ProcessingResult {
+        // Worker threads don't inherit the caller's span, so each file's span names it
+        let span = debug_span!(parent: parent, "file", path = %path.display(), outcome = Empty);
+        let _entered = span.enter();
+        let result = self.processor.process_file(path, dry_run).unwrap_or_else(|e| {
+            warn!("Failed to process {}: {}", path.display(), e);
+            ProcessingResult::failed(e.wrap_err("Processing failed"))
+        });
+        span.record("outcome", outcome_name(&result.outcome));
+        if result.is_failure()
+            && let Some(max_errors) = self.max_errors
+            && failures.fetch_add(1, Ordering::Relaxed) + 1 >= max_errors
+        {
+            warn!("Reached {} failed files, aborting the remaining work", max_errors);
+            aborted.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Indices into `files`, largest file first, so big files start early instead of leaving
+    /// one thread busy at the end of the run. Files whose size can't be read go last.
+    fn largest_first(&self, files: &[PathBuf]) -> Vec<usize> {
+        let sizes: Vec<u64> = files
+            .par_iter()
+            .map(|path| self.fs.metadata(path).map_or(0, |metadata| metadata.len))
+            .collect();
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        order.sort_by_key(|&index| Reverse(sizes[index]));
+        order
     }
 
-    fn aggregate_results(&self, results: Vec<ProcessingResult>, duration: Duration) -> ProcessingSummary {
+    fn aggregate_results<'a>(
+        &self,
+        results: impl Iterator<Item = &'a ProcessingResult>,
+        duration: Duration,
+    ) -> ProcessingSummary {
         let mut files_processed = 0;
         let mut files_modified = 0;
         let mut files_with_errors = 0;
+        let mut files_skipped = 0;
 
         for result in results {
             files_processed += 1;
 
-            if result.error.is_some() {
-                files_with_errors += 1;
-            } else if result.had_changes {
-                files_modified += 1;
+            match result.outcome {
+                Outcome::Failed(_) => files_with_errors += 1,
+                Outcome::Skipped(_) => files_skipped += 1,
+                Outcome::Modified { .. } => files_modified += 1,
+                Outcome::Clean => {}
             }
         }
 
@@ -100,15 +284,81 @@ impl<F: FileSystem> ParallelEngine<F> {
             files_processed,
             files_modified,
             files_with_errors,
+            files_skipped,
             duration,
         }
     }
 }
 
+/// Files read and scanned to choose a thread count for `auto-io`
+const TUNING_SAMPLE: usize = 16;
+/// The most threads `auto-io` runs per logical CPU, however slow reads are
+const MAX_THREADS_PER_CPU: usize = 8;
+
+/// The thread count `threads` asks for. For `auto-io`, a sample spread across `files` is read
+/// and then scanned, and the longer the reads take next to the scans, the more threads run, so
+/// that waits on a slow disk or a network filesystem overlap. Work that is all scanning gets a
+/// thread per physical core. With no files to sample, it is one thread per CPU.
+pub fn thread_count<F: FileSystem>(threads: Threads, config: Arc<Config>, fs: Arc<F>, files: &[PathBuf]) -> usize {
+    // The thread pool is built once per process, so the first sample decides for the run
+    static TUNED: OnceLock<usize> = OnceLock::new();
+    match threads {
+        Threads::Count(count) => count,
+        Threads::AutoIo if files.is_empty() => TUNED.get().copied().unwrap_or_else(num_cpus::get),
+        Threads::AutoIo => *TUNED.get_or_init(|| {
+            let (read, scan, sampled) = time_sample(&config, &fs, files);
+            let count = threads_for(read, scan, num_cpus::get_physical(), num_cpus::get());
+            info!(
+                "auto-io: {} sampled files took {:?} to read and {:?} to scan, using {} threads",
+                sampled, read, scan, count
+            );
+            count
+        }),
+    }
+}
+
+/// How long reading and scanning a sample of `files` took, and how many were sampled. Files
+/// above the streaming threshold are left out rather than read whole.
+fn time_sample<F: FileSystem>(config: &Arc<Config>, fs: &Arc<F>, files: &[PathBuf]) -> (Duration, Duration, usize) {
+    let processor = WhitespaceProcessor::new(Arc::clone(config), Arc::clone(fs));
+    let (mut read, mut scan, mut sampled) = (Duration::ZERO, Duration::ZERO, 0);
+    let step = (files.len() / TUNING_SAMPLE).max(1);
+    for path in files.iter().step_by(step).take(TUNING_SAMPLE) {
+        let start = Instant::now();
+        let content = match fs.metadata(path) {
+            Ok(metadata) if metadata.len <= config.processing.streaming_threshold => fs.read(path),
+            _ => continue,
+        };
+        read += start.elapsed();
+        let Ok(content) = content else {
+            continue;
+        };
+        let start = Instant::now();
+        if !processor.is_binary_content(&content) {
+            processor.scan_content(&String::from_utf8_lossy(&content));
+        }
+        scan += start.elapsed();
+        sampled += 1;
+    }
+    (read, scan, sampled)
+}
+
+/// Threads for work that spends `read` waiting for every `scan` of computing: enough that
+/// each physical core keeps computing while the others wait, up to [`MAX_THREADS_PER_CPU`].
+fn threads_for(read: Duration, scan: Duration, physical: usize, logical: usize) -> usize {
+    let max = logical.max(1) * MAX_THREADS_PER_CPU;
+    if scan.is_zero() {
+        return if read.is_zero() { logical.max(1) } else { max };
+    }
+    let waiting = read.as_secs_f64() / scan.as_secs_f64();
+    let count = (physical.max(1) as f64 * (1.0 + waiting)).round() as usize;
+    count.clamp(physical.max(1), max)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ports::fs::RealFs;
+    use crate::ports::fs::{MemFs, RealFs};
     use std::fs;
     use tempfile::TempDir;
 
@@ -129,7 +379,7 @@ mod tests {
         }
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let engine = ParallelEngine::new(config, real_fs, 2).unwrap();
 
         let results = engine.process_files_with_results(files.clone(), false).unwrap();
@@ -137,7 +387,7 @@ mod tests {
         let files_modified = results
             .file_results
             .iter()
-            .filter(|(_, result)| result.had_changes && result.error.is_none())
+            .filter(|(_, result)| result.had_changes())
             .count();
 
         assert_eq!(results.file_results.len(), 3);
@@ -161,7 +411,7 @@ mod tests {
         fs::write(&test_file, original_content).unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let engine = ParallelEngine::new(config, real_fs, 1).unwrap();
 
         let results = engine
@@ -171,7 +421,7 @@ mod tests {
         let files_modified = results
             .file_results
             .iter()
-            .filter(|(_, result)| result.had_changes && result.error.is_none())
+            .filter(|(_, result)| result.had_changes())
             .count();
 
         assert_eq!(results.file_results.len(), 1);
@@ -191,7 +441,7 @@ mod tests {
         fs::write(&binary_file, b"binary\0content").unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let engine = ParallelEngine::new(config, real_fs, 1).unwrap();
 
         let results = engine.process_files_with_results(vec![binary_file], false).unwrap();
@@ -199,10 +449,132 @@ mod tests {
         let files_modified = results
             .file_results
             .iter()
-            .filter(|(_, result)| result.had_changes && result.error.is_none())
+            .filter(|(_, result)| result.had_changes())
             .count();
 
         assert_eq!(results.file_results.len(), 1);
         assert_eq!(files_modified, 0);
     }
+
+    #[test]
+    fn test_max_errors_aborts_remaining_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let files: Vec<PathBuf> = (0..500)
+            .map(|i| temp_dir.path().join(format!("missing{}.txt", i)))
+            .collect();
+
+        let engine = ParallelEngine::new(create_test_config(), Arc::new(RealFs::default()), 1)
+            .unwrap()
+            .with_max_errors(Some(3));
+        let results = engine.process_files_with_results(files.clone(), false).unwrap();
+
+        assert!(results.aborted);
+        assert!(results.file_results.len() < files.len());
+        assert!(results.file_results.iter().all(|(_, result)| result.is_failure()));
+
+        let engine = ParallelEngine::new(create_test_config(), Arc::new(RealFs::default()), 1).unwrap();
+        let results = engine.process_files_with_results(files.clone(), false).unwrap();
+        assert!(!results.aborted);
+        assert_eq!(results.file_results.len(), files.len());
+    }
+
+    #[test]
+    fn test_parallel_processing_with_memfs() {
+        let files: Vec<PathBuf> = (0..200).map(|i| PathBuf::from(format!("/mem/file{}.txt", i))).collect();
+        let memfs = files.iter().fold(MemFs::new(), |memfs, path| {
+            memfs.with_file(path, b"dirty  \nclean\n\t\n")
+        });
+        let memfs = Arc::new(memfs);
+
+        let engine = ParallelEngine::new(create_test_config(), Arc::clone(&memfs), 4).unwrap();
+        let results = engine.process_files_with_results(files.clone(), false).unwrap();
+
+        assert_eq!(results.file_results.len(), files.len());
+        assert!(results.file_results.iter().all(|(_, result)| result.had_changes()));
+        for file in &files {
+            assert_eq!(memfs.get_content(file).unwrap(), b"dirty\nclean\n\n");
+        }
+    }
+
+    #[test]
+    fn test_largest_files_scheduled_first() {
+        let memfs = MemFs::new()
+            .with_file("/mem/small.txt", b"a \n")
+            .with_file("/mem/large.txt", "b \n".repeat(100).as_bytes())
+            .with_file("/mem/medium.txt", "c \n".repeat(10).as_bytes());
+        let files: Vec<PathBuf> = [
+            "/mem/small.txt",
+            "/mem/large.txt",
+            "/mem/missing.txt",
+            "/mem/medium.txt",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        let engine = ParallelEngine::new(create_test_config(), Arc::new(memfs), 2).unwrap();
+
+        assert_eq!(engine.largest_first(&files), vec![1, 3, 0, 2]);
+
+        // Results still come back in the order the files were given
+        let results = engine.process_files_with_results(files.clone(), true).unwrap();
+        let paths: Vec<PathBuf> = results.file_results.into_iter().map(|(path, _)| path).collect();
+        assert_eq!(paths, files);
+    }
+
+    #[test]
+    fn test_threads_for() {
+        let ms = Duration::from_millis;
+        // All scanning: a thread per physical core, not per hyperthread
+        assert_eq!(threads_for(ms(0), ms(10), 4, 8), 4);
+        // Reads three times as long as scans: four threads per core
+        assert_eq!(threads_for(ms(30), ms(10), 4, 8), 16);
+        // Reads that dwarf scans stop at the cap
+        assert_eq!(threads_for(ms(1000), ms(1), 4, 8), 8 * MAX_THREADS_PER_CPU);
+        assert_eq!(threads_for(ms(5), ms(0), 4, 8), 8 * MAX_THREADS_PER_CPU);
+        assert_eq!(threads_for(ms(0), ms(0), 4, 8), 8);
+    }
+
+    #[test]
+    fn test_thread_count() {
+        let files: Vec<PathBuf> = (0..40).map(|i| PathBuf::from(format!("/mem/file{}.txt", i))).collect();
+        let memfs = files.iter().fold(MemFs::new(), |memfs, path| {
+            memfs.with_file(path, "dirty  \n".repeat(100).as_bytes())
+        });
+        let memfs = Arc::new(memfs);
+        assert_eq!(
+            thread_count(Threads::Count(3), create_test_config(), Arc::clone(&memfs), &files),
+            3
+        );
+
+        let (_, _, sampled) = time_sample(&create_test_config(), &memfs, &files);
+        assert_eq!(sampled, TUNING_SAMPLE);
+        let count = thread_count(Threads::AutoIo, create_test_config(), Arc::clone(&memfs), &files);
+        assert!((num_cpus::get_physical()..=num_cpus::get() * MAX_THREADS_PER_CPU).contains(&count));
+        // Later runs in the process keep the first choice, as the thread pool does
+        assert_eq!(thread_count(Threads::AutoIo, create_test_config(), memfs, &[]), count);
+    }
+
+    #[test]
+    fn test_on_result_sees_every_file() {
+        let files: Vec<PathBuf> = (0..50).map(|i| PathBuf::from(format!("/mem/file{}.txt", i))).collect();
+        let memfs = files
+            .iter()
+            .fold(MemFs::new(), |memfs, path| memfs.with_file(path, b"dirty  \n"));
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_result = {
+            let seen = Arc::clone(&seen);
+            Arc::new(move |index: usize, path: &Path, result: &ProcessingResult| {
+                assert!(result.had_changes());
+                seen.lock().unwrap().push((index, path.to_path_buf()));
+            })
+        };
+        let engine = ParallelEngine::new(create_test_config(), Arc::new(memfs), 4)
+            .unwrap()
+            .with_on_result(on_result);
+        engine.process_files_with_results(files.clone(), true).unwrap();
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(seen, files.into_iter().enumerate().collect::<Vec<_>>());
+    }
 }
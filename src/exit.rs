@@ -0,0 +1,59 @@
+use std::process::ExitCode;
+
+/// Process exit status. The numeric codes are a stable contract for scripts and CI.
+///
+/// When several conditions apply the most severe wins, in declaration order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitStatus {
+    /// Nothing to do, or all changes applied
+    #[default]
+    Success,
+    /// Trailing whitespace found in dry-run (check) mode
+    Findings,
+    /// Some files or directories could not be processed
+    Errors,
+    /// Invalid command line or configuration
+    Usage,
+}
+
+impl ExitStatus {
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Success => 0,
+            Self::Findings => 1,
+            Self::Errors => 2,
+            Self::Usage => 3,
+        }
+    }
+
+    /// The more severe of the two statuses.
+    pub fn combine(self, other: Self) -> Self {
+        self.max(other)
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_stable() {
+        assert_eq!(ExitStatus::Success.code(), 0);
+        assert_eq!(ExitStatus::Findings.code(), 1);
+        assert_eq!(ExitStatus::Errors.code(), 2);
+        assert_eq!(ExitStatus::Usage.code(), 3);
+    }
+
+    #[test]
+    fn test_combine_keeps_most_severe() {
+        assert_eq!(ExitStatus::Success.combine(ExitStatus::Findings), ExitStatus::Findings);
+        assert_eq!(ExitStatus::Errors.combine(ExitStatus::Findings), ExitStatus::Errors);
+        assert_eq!(ExitStatus::default().combine(ExitStatus::Success), ExitStatus::Success);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::config::{Config, HardLinkPolicy, ReadOnlyPolicy};
+use crate::custom::CustomRules;
+use crate::filetypes::{Detection, FileTypes};
+use crate::gitattributes::{AttrValue, GitAttributes};
+use crate::ports::fs::FileSystem;
+use crate::processor::{Outcome, ProcessingResult, SkipReason, WhitespaceProcessor};
+use crate::rules::RuleSet;
+use crate::walker::{self, FileWalker};
+use eyre::Result;
+use std::io::BufRead;
+use std::path::Path;
+use std::sync::Arc;
+
+/// One decision on a file's way through the walk and the processor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The setting or check applied
+    pub check: &'static str,
+    /// What it found
+    pub finding: String,
+}
+
+/// Every decision a run would make about one file, in order, and where they lead.
+#[derive(Debug, Default)]
+pub struct Explanation {
+    pub steps: Vec<Step>,
+    /// The file's fate, as `--why` would put it
+    pub verdict: String,
+}
+
+impl Explanation {
+    fn step(&mut self, check: &'static str, finding: impl Into<String>) {
+        self.steps.push(Step {
+            check,
+            finding: finding.into(),
+        });
+    }
+}
+
+/// Follow `path` through the checks a run over `root` would make, recording each decision.
+/// `path` must be spelled as the walk would reach it, i.e. starting with `root`. Nothing is
+/// written: the file is processed as in a dry run.
+pub fn explain<F: FileSystem>(config: Arc<Config>, fs: Arc<F>, root: &Path, path: &Path) -> Result<Explanation> {
+    if !fs.is_file(path) {
+        eyre::bail!("Not a file: {}", path.display());
+    }
+    let walker = FileWalker::new(Arc::clone(&config), Arc::clone(&fs));
+    let mut explanation = Explanation::default();
+
+    // The walk never enters an excluded directory, so the files in it are never even seen
+    let dirs: Vec<&Path> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(root) && *dir != root)
+        .collect();
+    for dir in dirs.into_iter().rev() {
+        if let Some(rule) = walker.rule_for(root, dir)? {
+            explanation.step("directory", format!("{} {}", dir.display(), rule));
+            explanation.verdict = format!(
+                "{}: inside {}, which is never entered",
+                rule.skip_reason(),
+                dir.display()
+            );
+            return Ok(explanation);
+        }
+    }
+
+    if !config.follow_symlinks && fs.is_symlink(path) {
+        explanation.step("follow-symlinks", "a symlink, and symlinks aren't followed");
+        explanation.verdict = "skipped: symlink".to_string();
+        return Ok(explanation);
+    }
+
+    for (check, rule) in walker.checks(root, path)? {
+        let finding = match &rule {
+            Some(rule) => rule.to_string(),
+            None if check == "processing.max-file-size" => format!(
+                "{} bytes, within {} bytes",
+                fs.metadata(path)?.len,
+                config.processing.max_file_size
+            ),
+            None if check == "special-file" => "a regular file".to_string(),
+            None if check == "sparse-file" => "not sparse".to_string(),
+            None => "no match".to_string(),
+        };
+        explanation.step(check, finding);
+        if let Some(rule) = rule {
+            explanation.verdict = format!("{}: {}", rule.skip_reason(), rule);
+            return Ok(explanation);
+        }
+    }
+
+    // Content checks, in the order the processor makes them; the first to skip the file ends it
+    let result = WhitespaceProcessor::new(Arc::clone(&config), Arc::clone(&fs)).process_file(path, true)?;
+    let skipped = result.skip_reason();
+    let name = path.file_name().unwrap_or_default();
+    let binary = match walker::first_match(&config.binary_detection.text_files, name) {
+        Some(pattern) => format!("not checked: matches text-files pattern `{}`", pattern),
+        None if skipped == Some(SkipReason::Binary) => "content looks binary".to_string(),
+        None => "content looks like text".to_string(),
+    };
+    let content_checks = [
+        ("binary-detection", SkipReason::Binary, binary),
+        (
+            "encoding",
+            SkipReason::InvalidEncoding,
+            if skipped == Some(SkipReason::InvalidEncoding) {
+                "not valid UTF-8"
+            } else {
+                "valid UTF-8"
+            }
+            .to_string(),
+        ),
+        (
+            "diff-detection",
+            SkipReason::Diff,
+            match (skipped, FileTypes::new(&config.file_types).by_name(path)) {
+                _ if !config.diff_detection.enabled => "off".to_string(),
+                (Some(SkipReason::Diff), _) => "content looks like a patch".to_string(),
+                (_, Some(file_type)) => format!("not checked: `{}` files aren't patches", file_type),
+                _ => "not a patch".to_string(),
+            },
+        ),
+        (
+            "generated-detection",
+            SkipReason::Generated,
+            match skipped {
+                _ if !config.generated_detection.enabled => "off",
+                Some(SkipReason::Generated) => "a generated-code marker in its first lines",
+                _ => "no generated-code marker",
+            }
+            .to_string(),
+        ),
+        (
+            "skip-conflicts",
+            SkipReason::Conflict,
+            match skipped {
+                _ if !config.skip_conflicts => "off",
+                Some(SkipReason::Conflict) => "unresolved merge conflict markers",
+                _ => "no conflict markers",
+            }
+            .to_string(),
+        ),
+    ];
+    for (check, skip_reason, finding) in content_checks {
+        explanation.step(check, finding);
+        if skipped == Some(skip_reason) {
+            explanation.verdict = reason(&walker, Some(root), path, &result);
+            return Ok(explanation);
+        }
+    }
+
+    if fs.metadata(path)?.readonly {
+        let finding = match (skipped, config.readonly) {
+            (Some(SkipReason::ReadOnly), _) => "read-only, with trailing whitespace the policy leaves alone",
+            (_, ReadOnlyPolicy::Force) => "read-only, and the policy writes such files anyway",
+            _ => "read-only, but there is nothing to clean",
+        };
+        explanation.step("readonly", finding);
+        if skipped == Some(SkipReason::ReadOnly) {
+            explanation.verdict = reason(&walker, Some(root), path, &result);
+            return Ok(explanation);
+        }
+    }
+
+    let links = fs.metadata(path)?.links;
+    if links > 1 {
+        let finding = match (skipped, config.hardlinks) {
+            (Some(SkipReason::HardLinked), _) => {
+                format!("{} links, with trailing whitespace the policy leaves alone", links)
+            }
+            (_, HardLinkPolicy::Break) => format!("{} links, and the policy cleans only this one", links),
+            (_, HardLinkPolicy::InPlace) => format!("{} links, and the policy cleans them all", links),
+            _ => format!("{} links, but there is nothing to clean", links),
+        };
+        explanation.step("hardlinks", finding);
+        if skipped == Some(SkipReason::HardLinked) {
+            explanation.verdict = reason(&walker, Some(root), path, &result);
+            return Ok(explanation);
+        }
+    }
+
+    let rule_set = RuleSet::new(&config.rules).with_file_types(FileTypes::new(&config.file_types));
+    let first_line = fs
+        .reader(path)?
+        .lines()
+        .next()
+        .and_then(|line| line.ok())
+        .unwrap_or_default();
+    let (file_type, finding) = match rule_set.file_types().detect(path, Some(&first_line)) {
+        Some((file_type, Detection::Name(pattern))) => (
+            Some(file_type),
+            format!("`{}`, by name pattern `{}`", file_type, pattern),
+        ),
+        Some((file_type, Detection::Shebang(program))) => (
+            Some(file_type),
+            format!("`{}`, by the `{}` shebang", file_type, program),
+        ),
+        None => (None, "no type recognized".to_string()),
+    };
+    explanation.step("file-types", finding);
+    let overrides: Vec<String> = rule_set
+        .overrides_for(path, file_type)
+        .map(|rule_override| {
+            if rule_override.files.is_empty() {
+                format!("`{}` type", rule_override.types.join("`, `"))
+            } else {
+                format!("`{}`", rule_override.files.join("`, `"))
+            }
+        })
+        .collect();
+    let rules = if overrides.is_empty() {
+        "base rules, no override matches".to_string()
+    } else {
+        format!("base rules with the {} override", overrides.join(" and "))
+    };
+    explanation.step("rules", rules);
+    if let Some(syntax) = rule_set.for_script(path, &first_line).literal_syntax {
+        explanation.step(
+            "preserve-string-literals",
+            format!("{:?} literals are left alone", syntax),
+        );
+    }
+    if config.gitattributes
+        && let Some(eol) = GitAttributes::new().get(fs.as_ref(), path, "eol")
+        && let AttrValue::Value(value) = &eol.value
+        && (value == "lf" || value == "crlf")
+    {
+        explanation.step(
+            "gitattributes",
+            format!("`eol={}` set by `{}` in {}", value, eol.pattern, eol.file.display()),
+        );
+    }
+    let custom_rules = CustomRules::new(&config.custom_rules);
+    let custom_rules: Vec<String> = custom_rules
+        .for_file(path)
+        .iter()
+        .map(|rule| format!("`{}`", rule.name))
+        .collect();
+    if !custom_rules.is_empty() {
+        explanation.step(
+            "custom-rules",
+            format!("{} run after the built-in rules", custom_rules.join(", ")),
+        );
+    }
+
+    explanation.verdict = reason(&walker, Some(root), path, &result);
+    if let Outcome::Modified { lines, .. } = &result.outcome {
+        explanation.verdict.push_str(&crate::format_line_numbers(lines));
+    }
+    Ok(explanation)
+}
+
+/// Why a file ended the run with `result`, in a few words, for `--why`. `root` is the
+/// directory that was walked to find the file, if one was, so that the walk's decisions can
+/// name the pattern that made them.
+pub fn reason<F: FileSystem>(
+    walker: &FileWalker<F>,
+    root: Option<&Path>,
+    path: &Path,
+    result: &ProcessingResult,
+) -> String {
+    match &result.outcome {
+        Outcome::Modified { lines, .. } => format!(
+            "included: trailing whitespace on {} {}",
+            lines.len(),
+            if lines.len() == 1 { "line" } else { "lines" }
+        ),
+        Outcome::Clean => "included: already clean".to_string(),
+        Outcome::Failed(error) => format!("included, but failed: {:#}", error),
+        Outcome::Skipped(skip_reason) => {
+            let rule = root.and_then(|root| walker.rule_for(root, path).ok().flatten());
+            match rule {
+                Some(rule) => format!("{}: {}", skip_reason, rule),
+                None => format!("{}: {}", skip_reason, detail(*skip_reason)),
+            }
+        }
+    }
+}
+
+/// What a skip reason means when no configured pattern accounts for it: the file's content
+/// decided, or the walk's rules weren't involved.
+fn detail(skip_reason: SkipReason) -> &'static str {
+    match skip_reason {
+        SkipReason::Binary => "content looks binary",
+        SkipReason::TooLarge => "over max-file-size",
+        SkipReason::InvalidEncoding => "content is not valid UTF-8",
+        SkipReason::Excluded => "matches an exclude pattern",
+        SkipReason::Diff => "content looks like a patch",
+        SkipReason::Conflict => "has unresolved merge conflict markers",
+        SkipReason::Generated => "has a generated-code marker in its first lines",
+        SkipReason::Vendored => "inside a vendored directory",
+        SkipReason::ReadOnly => "the readonly policy leaves read-only files that need cleaning alone",
+        SkipReason::HardLinked => "the hardlinks policy leaves hard-linked files that need cleaning alone",
+        SkipReason::Special => "a FIFO, socket or device node, not a regular file",
+        SkipReason::Sparse => "mostly holes, which rewriting would fill in",
+        SkipReason::Limit => "not reached before --max-files or --timeout stopped the run",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::ports::fs::MemFs;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_reason() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/app.min.js", b"content")
+                .with_file("/repo/blob", b"\0\0"),
+        );
+        let walker = FileWalker::new(Arc::new(Config::default()), fs);
+        let root = Some(Path::new("/repo"));
+        let reason = |path: &str, result: ProcessingResult| reason(&walker, root, Path::new(path), &result);
+
+        assert_eq!(
+            reason("/repo/a.rs", ProcessingResult::from_lines(vec![1, 4], 3)),
+            "included: trailing whitespace on 2 lines"
+        );
+        assert_eq!(
+            reason("/repo/b.rs", ProcessingResult::clean()),
+            "included: already clean"
+        );
+        assert_eq!(
+            reason("/repo/app.min.js", ProcessingResult::skip(SkipReason::Excluded)),
+            "excluded: matches exclude-files pattern `*.min.js`"
+        );
+        assert_eq!(
+            reason("/repo/blob", ProcessingResult::skip(SkipReason::Binary)),
+            "binary: content looks binary"
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_dir("/repo/.git")
+                .with_file("/repo/.gitattributes", b"*.bat eol=crlf\n*.fixture binary\n")
+                .with_file("/repo/README.md", b"# Title  \nline \n")
+                .with_file("/repo/run.bat", b"echo  \r\n")
+                .with_file("/repo/data.fixture", b"data  \n")
+                .with_file("/repo/blob", b"\0\0")
+                .with_file("/repo/scripts/deploy", b"#!/bin/sh\necho \n")
+                .with_file("/repo/api.pb.go", b"package api \n")
+                .with_file("/repo/node_modules/pkg/index.js", b"content"),
+        );
+        let config = Arc::new(Config::default());
+        let explain = |path: &str| {
+            explain(
+                Arc::clone(&config),
+                Arc::clone(&fs),
+                Path::new("/repo"),
+                Path::new(path),
+            )
+        };
+
+        let explanation = explain("/repo/README.md").unwrap();
+        let checks: Vec<&str> = explanation.steps.iter().map(|step| step.check).collect();
+        assert_eq!(
+            checks,
+            [
+                ".whitespaceignore",
+                "exclude-paths",
+                "exclude-files",
+                "diff-detection.files",
+                "exclude-binary-extensions",
+                ".gitattributes",
+                "generated-detection.files",
+                "special-file",
+                "processing.max-file-size",
+                "sparse-file",
+                "binary-detection",
+                "encoding",
+                "diff-detection",
+                "generated-detection",
+                "skip-conflicts",
+                "file-types",
+                "rules"
+            ]
+        );
+        assert_eq!(
+            explanation.steps[12].finding,
+            "not checked: `markdown` files aren't patches"
+        );
+        assert_eq!(explanation.steps[15].finding, "`markdown`, by name pattern `*.md`");
+        assert_eq!(
+            explanation.steps.last().unwrap().finding,
+            "base rules with the `*.md`, `*.markdown` override"
+        );
+        assert_eq!(explanation.verdict, "included: trailing whitespace on 1 line (2)");
+
+        let explanation = explain("/repo/scripts/deploy").unwrap();
+        assert_eq!(explanation.steps[15].finding, "`shell`, by the `sh` shebang");
+
+        let explanation = explain("/repo/run.bat").unwrap();
+        assert_eq!(
+            explanation.steps.last().unwrap().finding,
+            "`eol=crlf` set by `*.bat` in /repo/.gitattributes"
+        );
+        assert_eq!(
+            explain("/repo/data.fixture").unwrap().verdict,
+            "binary: -text set by `*.fixture` in /repo/.gitattributes"
+        );
+
+        let explanation = explain("/repo/blob").unwrap();
+        assert_eq!(explanation.steps.last().unwrap().finding, "content looks binary");
+        assert_eq!(explanation.verdict, "binary: content looks binary");
+
+        let explanation = explain("/repo/api.pb.go").unwrap();
+        assert_eq!(
+            explanation.verdict,
+            "generated: matches generated-detection.files pattern `*.pb.go`"
+        );
+
+        let explanation = explain("/repo/node_modules/pkg/index.js").unwrap();
+        assert_eq!(explanation.steps.len(), 1);
+        assert_eq!(
+            explanation.verdict,
+            "excluded: inside /repo/node_modules, which is never entered"
+        );
+
+        assert!(explain("/repo/missing.txt").is_err());
+    }
+}
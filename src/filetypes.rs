@@ -0,0 +1,224 @@
+use crate::config::FileTypeSettings;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+/// File types recognized by name, each with the file-name patterns that give it. Patterns are
+/// matched case-insensitively against the file name.
+const NAMES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cmake", &["CMakeLists.txt", "*.cmake"]),
+    ("cpp", &["*.cc", "*.cpp", "*.cxx", "*.hh", "*.hpp"]),
+    ("css", &["*.css", "*.scss"]),
+    ("csv", &["*.csv"]),
+    ("dockerfile", &["Dockerfile", "*.dockerfile", "Containerfile"]),
+    ("go", &["*.go"]),
+    ("groovy", &["*.groovy", "*.gradle", "Jenkinsfile"]),
+    ("html", &["*.html", "*.htm"]),
+    ("java", &["*.java"]),
+    ("javascript", &["*.js", "*.mjs", "*.cjs", "*.jsx"]),
+    ("json", &["*.json"]),
+    ("just", &["justfile", ".justfile"]),
+    ("make", &["Makefile", "GNUmakefile", "*.mk", "*.mak"]),
+    ("markdown", &["*.md", "*.markdown"]),
+    ("perl", &["*.pl", "*.pm"]),
+    ("python", &["*.py", "*.pyi", "*.pyw", "SConstruct", "SConscript"]),
+    ("ruby", &["*.rb", "*.gemspec", "Gemfile", "Rakefile", "Vagrantfile"]),
+    ("rust", &["*.rs"]),
+    (
+        "shell",
+        &[
+            "*.sh",
+            "*.bash",
+            "*.zsh",
+            "*.ksh",
+            ".bashrc",
+            ".bash_profile",
+            ".bash_aliases",
+            ".bash_logout",
+            ".profile",
+            ".zshrc",
+            ".zshenv",
+            ".zprofile",
+            ".kshrc",
+            "PKGBUILD",
+        ],
+    ),
+    ("toml", &["*.toml"]),
+    ("tsv", &["*.tsv", "*.tab"]),
+    ("typescript", &["*.ts", "*.mts", "*.cts", "*.tsx"]),
+    ("yaml", &["*.yml", "*.yaml"]),
+];
+
+/// File types recognized by the program a `#!` line runs, each with its programs. Version
+/// suffixes don't matter: `python3.12` runs `python`.
+const INTERPRETERS: &[(&str, &[&str])] = &[
+    ("awk", &["awk", "gawk", "mawk"]),
+    ("fish", &["fish"]),
+    ("javascript", &["node", "nodejs", "deno", "bun"]),
+    ("lua", &["lua", "luajit"]),
+    ("make", &["make"]),
+    ("perl", &["perl"]),
+    ("php", &["php"]),
+    ("python", &["python", "pypy"]),
+    ("r", &["Rscript"]),
+    ("ruby", &["ruby"]),
+    ("shell", &["sh", "bash", "dash", "ash", "ksh", "mksh", "zsh"]),
+    ("tcl", &["tclsh", "wish"]),
+];
+
+/// How a file's type was recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Detection<'a> {
+    /// By a file-name pattern
+    Name(&'a str),
+    /// By the program its `#!` line runs
+    Shebang(&'a str),
+}
+
+/// Recognizes a file's type, such as `shell` or `make`, so that rule overrides can apply to
+/// files whose extension says nothing, like `Makefile`, `.bashrc` or a `deploy` script. The
+/// name decides first; failing that, the shebang on the first line does. The mappings in the
+/// `file-types` setting come before the built-in ones.
+#[derive(Debug, Clone)]
+pub struct FileTypes {
+    /// In the order they are tried
+    names: Vec<(GlobMatcher, String)>,
+    interpreters: HashMap<String, String>,
+}
+
+impl Default for FileTypes {
+    fn default() -> Self {
+        Self::new(&FileTypeSettings::default())
+    }
+}
+
+impl FileTypes {
+    pub fn new(settings: &FileTypeSettings) -> Self {
+        let user_names = settings
+            .names
+            .iter()
+            .map(|(pattern, file_type)| (pattern.as_str(), file_type.as_str()));
+        let builtin_names = NAMES
+            .iter()
+            .flat_map(|(file_type, patterns)| patterns.iter().map(move |pattern| (*pattern, *file_type)));
+        let mut names = Vec::new();
+        for (pattern, file_type) in user_names.chain(builtin_names) {
+            match GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .case_insensitive(true)
+                .build()
+            {
+                Ok(glob) => names.push((glob.compile_matcher(), file_type.to_string())),
+                Err(e) => warn!("Ignoring invalid file-types pattern '{}': {}", pattern, e),
+            }
+        }
+
+        let mut interpreters: HashMap<String, String> = INTERPRETERS
+            .iter()
+            .flat_map(|(file_type, programs)| {
+                programs
+                    .iter()
+                    .map(|program| (program.to_string(), file_type.to_string()))
+            })
+            .collect();
+        interpreters.extend(settings.interpreters.clone());
+        Self { names, interpreters }
+    }
+
+    /// The type of the file at `path`, and how it was recognized, from its name or else the
+    /// shebang on its `first_line`, if it has one.
+    pub fn detect<'a>(&'a self, path: &Path, first_line: Option<&'a str>) -> Option<(&'a str, Detection<'a>)> {
+        let by_name = path.file_name().and_then(|name| {
+            let (matcher, file_type) = self.names.iter().find(|(matcher, _)| matcher.is_match(name))?;
+            Some((file_type.as_str(), Detection::Name(matcher.glob().glob())))
+        });
+        by_name.or_else(|| {
+            let program = interpreter(first_line?)?;
+            let file_type = self.interpreters.get(program).or_else(|| {
+                self.interpreters
+                    .get(program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.'))
+            })?;
+            Some((file_type.as_str(), Detection::Shebang(program)))
+        })
+    }
+
+    /// The type of the file at `path`, from its name alone.
+    pub fn by_name(&self, path: &Path) -> Option<&str> {
+        self.detect(path, None).map(|(file_type, _)| file_type)
+    }
+}
+
+/// The program a `#!` line runs: `sh` for `#!/bin/sh`, and `python3` for
+/// `#!/usr/bin/env -S python3 -u`.
+fn interpreter(first_line: &str) -> Option<&str> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let program = words.next()?.rsplit('/').next()?;
+    if program != "env" {
+        return Some(program);
+    }
+    // env's own options and variable assignments come before the program
+    words.find(|word| !word.starts_with('-') && !word.contains('='))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_detect() {
+        let file_types = FileTypes::default();
+        let detect = |path: &str, first_line: Option<&'static str>| {
+            file_types
+                .detect(Path::new(path), first_line)
+                .map(|(file_type, _)| file_type)
+        };
+        assert_eq!(detect("src/main.rs", None), Some("rust"));
+        assert_eq!(detect("SETUP.PY", None), Some("python"));
+        assert_eq!(detect("build/Makefile", None), Some("make"));
+        assert_eq!(detect("/home/me/.bashrc", None), Some("shell"));
+        assert_eq!(detect("notes.txt", None), None);
+
+        // The name decides before the shebang
+        assert_eq!(detect("tool.rb", Some("#!/bin/sh")), Some("ruby"));
+        assert_eq!(detect("deploy", Some("#!/bin/bash -e")), Some("shell"));
+        assert_eq!(detect("deploy", Some("#!/usr/bin/env python3.12")), Some("python"));
+        assert_eq!(
+            detect("deploy", Some("#!/usr/bin/env -S PYTHONUTF8=1 ruby -w")),
+            Some("ruby")
+        );
+        assert_eq!(detect("deploy", Some("#!/usr/bin/env")), None);
+        assert_eq!(detect("deploy", Some("# not a shebang")), None);
+        assert_eq!(
+            file_types.detect(Path::new("deploy"), Some("#!/usr/bin/env node")),
+            Some(("javascript", Detection::Shebang("node")))
+        );
+        assert_eq!(
+            file_types.detect(Path::new(".zshrc"), None),
+            Some(("shell", Detection::Name(".zshrc")))
+        );
+    }
+
+    #[test]
+    fn test_configured_mappings_come_first() {
+        let settings = FileTypeSettings {
+            names: BTreeMap::from([
+                ("*.tpl".to_string(), "html".to_string()),
+                ("*.h".to_string(), "cpp".to_string()),
+            ]),
+            interpreters: BTreeMap::from([("nu".to_string(), "nushell".to_string())]),
+        };
+        let file_types = FileTypes::new(&settings);
+        assert_eq!(file_types.by_name(Path::new("page.tpl")), Some("html"));
+        assert_eq!(file_types.by_name(Path::new("vec.h")), Some("cpp"));
+        assert_eq!(file_types.by_name(Path::new("vec.c")), Some("c"));
+        assert_eq!(
+            file_types
+                .detect(Path::new("script"), Some("#!/usr/bin/env nu"))
+                .map(|(file_type, _)| file_type),
+            Some("nushell")
+        );
+    }
+}
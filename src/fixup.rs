@@ -0,0 +1,183 @@
+use crate::git_diff::{git, toplevel};
+use eyre::{Result, eyre};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// What `--fixup` does with the cleanup once it is staged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FixupMode {
+    /// Leave the cleanup staged for a commit of its own
+    #[default]
+    Stage,
+    /// Fold the cleanup into HEAD with `git commit --amend`
+    Amend,
+    /// Commit the cleanup with `git commit --fixup=HEAD`, ready for `rebase --autosquash`
+    Commit,
+}
+
+/// The files `--fixup` cleans in a git work tree: those HEAD changed, plus those staged when
+/// the cleanup is only staged.
+#[derive(Debug)]
+pub struct Fixup {
+    top: PathBuf,
+    mode: FixupMode,
+    files: Vec<PathBuf>,
+}
+
+impl Fixup {
+    /// Find the files to clean in the work tree containing `dir`. This refuses when staging or
+    /// committing the cleanup would sweep in changes that aren't whitespace: unstaged edits to
+    /// those files, or, when committing, anything already staged.
+    pub fn plan(dir: &Path, mode: FixupMode) -> Result<Self> {
+        let top = toplevel(dir)?;
+        let has_head = git(&top, &["rev-parse", "--verify", "-q", "HEAD"]).is_ok();
+        if !has_head && mode != FixupMode::Stage {
+            eyre::bail!("There is no HEAD commit to fix up");
+        }
+
+        let staged = names(&top, &["diff", "--cached", "--name-only", "--no-renames", "-z"])?;
+        if mode != FixupMode::Stage && !staged.is_empty() {
+            eyre::bail!(
+                "The index has staged changes that would be committed with the cleanup: {}",
+                list(&staged)
+            );
+        }
+
+        let mut files = staged;
+        if has_head {
+            files.extend(names(
+                &top,
+                &[
+                    "diff-tree",
+                    "--no-commit-id",
+                    "--name-only",
+                    "--no-renames",
+                    "-r",
+                    "--root",
+                    "-z",
+                    "HEAD",
+                ],
+            )?);
+        }
+        // Deleted files have nothing left to clean
+        files.retain(|file| top.join(file).is_file());
+
+        let unstaged = names(&top, &["diff", "--name-only", "--no-renames", "-z"])?;
+        let mixed: BTreeSet<_> = files.intersection(&unstaged).cloned().collect();
+        if !mixed.is_empty() {
+            eyre::bail!(
+                "Files have unstaged changes that would be staged with the cleanup: {}",
+                list(&mixed)
+            );
+        }
+
+        Ok(Self {
+            top,
+            mode,
+            files: files.into_iter().collect(),
+        })
+    }
+
+    /// The canonical paths of the files to clean, sorted.
+    pub fn files(&self) -> Vec<PathBuf> {
+        self.files.iter().map(|file| self.top.join(file)).collect()
+    }
+
+    /// Stage the `cleaned` files, given as returned by [`Fixup::files`], then amend or commit as
+    /// the mode says. Returns what was done, for the user.
+    pub fn apply(&self, cleaned: &[PathBuf]) -> Result<String> {
+        let mut add = vec!["add", "--"];
+        let relative: Vec<_> = cleaned
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.top).ok()?.to_str())
+            .collect();
+        add.extend(&relative);
+        git(&self.top, &add).map_err(|e| eyre!("Failed to stage the cleanup: {}", e))?;
+
+        let count = match cleaned.len() {
+            1 => "1 file".to_string(),
+            n => format!("{} files", n),
+        };
+        match self.mode {
+            FixupMode::Stage => Ok(format!("Staged the cleanup of {}", count)),
+            FixupMode::Amend => {
+                git(&self.top, &["commit", "--amend", "--no-edit", "--no-verify", "-q"])
+                    .map_err(|e| eyre!("Failed to amend HEAD: {}", e))?;
+                Ok(format!("Amended HEAD with the cleanup of {}", count))
+            }
+            FixupMode::Commit => {
+                git(&self.top, &["commit", "--fixup=HEAD", "--no-verify", "-q"])
+                    .map_err(|e| eyre!("Failed to commit the fixup: {}", e))?;
+                Ok(format!("Committed the cleanup of {} as a fixup of HEAD", count))
+            }
+        }
+    }
+}
+
+/// The NUL-delimited paths git printed for `args`.
+fn names(top: &Path, args: &[&str]) -> Result<BTreeSet<PathBuf>> {
+    let output = git(top, args).map_err(|e| eyre!("Failed to list changed files: {}", e))?;
+    Ok(output
+        .split('\0')
+        .filter(|name| !name.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+fn list(files: &BTreeSet<PathBuf>) -> String {
+    files
+        .iter()
+        .map(|file| file.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fixup() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path();
+        let git = |args: &[&str]| Command::new("git").args(args).current_dir(repo).output();
+        if !git(&["init", "-q"]).is_ok_and(|output| output.status.success()) {
+            return;
+        }
+        // Amending commits as whoever the repository says, so say someone
+        git(&["config", "user.name", "test"]).unwrap();
+        git(&["config", "user.email", "test@example.com"]).unwrap();
+        fs::write(repo.join("old.txt"), "old  \n").unwrap();
+        git(&["add", "."]).unwrap();
+        assert!(git(&["commit", "-qm", "initial"]).unwrap().status.success());
+        fs::write(repo.join("feature.txt"), "feature  \n").unwrap();
+        fs::write(repo.join("staged.txt"), "staged  \n").unwrap();
+        git(&["add", "feature.txt"]).unwrap();
+        assert!(git(&["commit", "-qm", "feature"]).unwrap().status.success());
+        git(&["add", "staged.txt"]).unwrap();
+
+        let top = repo.canonicalize().unwrap();
+        let staging = Fixup::plan(repo, FixupMode::Stage).unwrap();
+        assert_eq!(staging.files(), [top.join("feature.txt"), top.join("staged.txt")]);
+        let err = Fixup::plan(repo, FixupMode::Amend).unwrap_err();
+        assert!(err.to_string().contains("staged.txt"), "{}", err);
+
+        git(&["reset", "-q", "staged.txt"]).unwrap();
+        fs::write(repo.join("feature.txt"), "edited\n").unwrap();
+        let err = Fixup::plan(repo, FixupMode::Amend).unwrap_err();
+        assert!(err.to_string().contains("unstaged"), "{}", err);
+
+        fs::write(repo.join("feature.txt"), "feature  \n").unwrap();
+        let amend = Fixup::plan(repo, FixupMode::Amend).unwrap();
+        assert_eq!(amend.files(), [top.join("feature.txt")]);
+        fs::write(repo.join("feature.txt"), "feature\n").unwrap();
+        assert!(amend.apply(&amend.files()).unwrap().starts_with("Amended HEAD"));
+        let head = git(&["show", "HEAD:feature.txt"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&head.stdout), "feature\n");
+        let log = git(&["log", "--format=%s"]).unwrap();
+        assert_eq!(String::from_utf8_lossy(&log.stdout), "feature\ninitial\n");
+    }
+}
@@ -0,0 +1,202 @@
+use crate::rules::LineRanges;
+use eyre::{Context, Result, eyre};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The lines of each file in a git work tree that were added or changed since a ref, for
+/// `--diff-only`. Files that are new to git count as changed throughout.
+#[derive(Debug, Default)]
+pub struct ChangedLines {
+    /// Keyed by canonical path
+    files: HashMap<PathBuf, LineRanges>,
+}
+
+impl ChangedLines {
+    /// The lines changed in the work tree containing `dir` since `git_ref`, staged or not.
+    pub fn since(dir: &Path, git_ref: &str) -> Result<Self> {
+        let top = toplevel(dir)?;
+        let diff = git(
+            &top,
+            &[
+                "-c",
+                "core.quotePath=false",
+                "diff",
+                "--no-color",
+                "--no-ext-diff",
+                "--no-prefix",
+                "--unified=0",
+                git_ref,
+                "--",
+            ],
+        )
+        .map_err(|e| eyre!("Failed to diff against {}: {}", git_ref, e))?;
+        let untracked = git(&top, &["ls-files", "--others", "--exclude-standard", "-z"])
+            .map_err(|e| eyre!("Failed to list untracked files: {}", e))?;
+
+        let mut files = parse_diff(&diff);
+        for path in untracked.split('\0').filter(|path| !path.is_empty()) {
+            files.insert(PathBuf::from(path), LineRanges::new([1..=usize::MAX]));
+        }
+        Ok(Self::new(
+            files.into_iter().map(|(path, ranges)| (top.join(path), ranges)),
+        ))
+    }
+
+    /// Changed lines given directly, such as from an editor's own diff, keyed by canonical path.
+    pub fn new(files: impl IntoIterator<Item = (PathBuf, LineRanges)>) -> Self {
+        Self {
+            files: files.into_iter().collect(),
+        }
+    }
+
+    /// The changed lines of the file at canonical `path`, or None if it hasn't changed.
+    pub fn for_file(&self, path: &Path) -> Option<&LineRanges> {
+        self.files.get(path)
+    }
+}
+
+/// The canonical top directory of the git work tree containing `dir`, which the paths git
+/// prints are relative to.
+pub(crate) fn toplevel(dir: &Path) -> Result<PathBuf> {
+    let top = git(dir, &["rev-parse", "--show-toplevel"]).map_err(|e| eyre!("Not in a git repository: {}", e))?;
+    let top = PathBuf::from(top.trim_end_matches(['\n', '\r']));
+    Ok(top.canonicalize().unwrap_or(top))
+}
+
+/// Run git in `dir`, returning what it printed. A failure's message is git's own complaint.
+pub(crate) fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        eyre::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    String::from_utf8(output.stdout).context("git printed invalid UTF-8")
+}
+
+/// The new-side line ranges of each file in a `git diff --no-prefix --unified=0`, by path.
+/// Hunks that only remove lines add none.
+fn parse_diff(diff: &str) -> HashMap<PathBuf, LineRanges> {
+    let mut files: HashMap<PathBuf, Vec<_>> = HashMap::new();
+    let mut current = None;
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            current = (path != "/dev/null").then(|| PathBuf::from(unquote(path)));
+        } else if let Some(hunk) = line.strip_prefix("@@ ")
+            && let Some(path) = &current
+            && let Some(added) = hunk.split(' ').find_map(|range| range.strip_prefix('+'))
+        {
+            let (start, count) = added.split_once(',').unwrap_or((added, "1"));
+            if let (Ok(start), Ok(count)) = (start.parse::<usize>(), count.parse::<usize>())
+                && count > 0
+            {
+                files.entry(path.clone()).or_default().push(start..=start + count - 1);
+            }
+        }
+    }
+    files
+        .into_iter()
+        .map(|(path, ranges)| (path, LineRanges::new(ranges)))
+        .collect()
+}
+
+/// A path as git prints it, with the quotes and escapes git adds to unusual names removed.
+fn unquote(path: &str) -> String {
+    let Some(quoted) = path.strip_prefix('"').and_then(|path| path.strip_suffix('"')) else {
+        return path.to_string();
+    };
+    let mut unquoted = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some(escaped) => escaped,
+                None => break,
+            },
+            c => c,
+        };
+        unquoted.push(c);
+    }
+    unquoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_diff() {
+        let diff = "\
+diff --git src/main.rs src/main.rs
+--- src/main.rs
++++ src/main.rs
+@@ -3 +3 @@ fn main() {
+-old
++new
+@@ -10,0 +11,3 @@ fn other() {
++a
++b
++c
+@@ -20,2 +22,0 @@
+-gone
+-gone
+diff --git old.txt old.txt
+--- old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-bye
+diff --git \"tab\\there.txt\" \"tab\\there.txt\"
+--- /dev/null
++++ \"tab\\there.txt\"
+@@ -0,0 +1,2 @@
++hello
++world
+";
+        let files = parse_diff(diff);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[Path::new("src/main.rs")], LineRanges::new([3..=3, 11..=13]));
+        assert_eq!(files[Path::new("tab\there.txt")], LineRanges::new([1..=2]));
+    }
+
+    #[test]
+    fn test_changed_lines_since() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path();
+        let git = |args: &[&str]| Command::new("git").args(args).current_dir(repo).output();
+        if !git(&["init", "-q"]).is_ok_and(|output| output.status.success()) {
+            return;
+        }
+        fs::write(repo.join("old.txt"), "one\ntwo\nthree\n").unwrap();
+        git(&["add", "."]).unwrap();
+        let commit = git(&[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "-qm",
+            "initial",
+        ])
+        .unwrap();
+        assert!(commit.status.success());
+
+        fs::write(repo.join("old.txt"), "one\nTWO\nthree\nfour\n").unwrap();
+        fs::write(repo.join("new.txt"), "new\n").unwrap();
+        let changed = ChangedLines::since(repo, "HEAD").unwrap();
+        let repo = repo.canonicalize().unwrap();
+        assert_eq!(
+            changed.for_file(&repo.join("old.txt")),
+            Some(&LineRanges::new([2..=2, 4..=4]))
+        );
+        assert!(changed.for_file(&repo.join("new.txt")).unwrap().contains(1));
+
+        assert!(ChangedLines::since(&repo, "no-such-ref").is_err());
+    }
+}
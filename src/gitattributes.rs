@@ -0,0 +1,318 @@
+use crate::ports::fs::FileSystem;
+use globset::{GlobBuilder, GlobMatcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tracing::{debug, warn};
+
+/// Name of git's per-directory attributes file.
+pub const ATTRIBUTES_FILE_NAME: &str = ".gitattributes";
+
+/// An attribute's state for a path, as a `.gitattributes` line gives it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+}
+
+impl AttrValue {
+    /// True for `name` and `name=true`, the spellings linguist accepts for its attributes.
+    pub fn is_true(&self) -> bool {
+        matches!(self, Self::Set) || matches!(self, Self::Value(value) if value == "true")
+    }
+}
+
+/// The `.gitattributes` line that decided an attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrMatch {
+    pub file: PathBuf,
+    pub pattern: String,
+    pub value: AttrValue,
+}
+
+#[derive(Debug)]
+struct AttrRule {
+    pattern: String,
+    matcher: GlobMatcher,
+    /// Patterns without a slash match the file name at any depth
+    basename: bool,
+    /// `None` is `!name`, which returns the attribute to unspecified
+    attrs: Vec<(String, Option<AttrValue>)>,
+}
+
+#[derive(Debug)]
+struct AttrFile {
+    path: PathBuf,
+    /// The canonical directory the file is in, which its patterns are relative to
+    dir: PathBuf,
+    rules: Vec<AttrRule>,
+}
+
+/// The attributes files that apply in a directory, nearest first.
+#[derive(Debug, Default)]
+struct DirAttrs {
+    /// Canonical
+    dir: PathBuf,
+    /// Whether the directory is inside a git work tree; outside one no attributes apply
+    in_repo: bool,
+    files: Vec<Arc<AttrFile>>,
+}
+
+/// The attributes git's `.gitattributes` files give paths: those of each file's own directory
+/// and every directory above it, up to the top of its repository. Files are read once per
+/// directory, as paths in it are first asked about, and shared between threads until
+/// [`clear`](Self::clear) forgets them, so that a long-lived owner sees files that change.
+///
+/// As in git, files deeper in the tree take precedence, and within a file later lines do.
+#[derive(Debug, Default)]
+pub struct GitAttributes {
+    /// Keyed by directory, both as asked about and canonical
+    dirs: RwLock<HashMap<PathBuf, Arc<DirAttrs>>>,
+}
+
+impl GitAttributes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forget every attributes file read so far, so that later lookups read them afresh.
+    pub fn clear(&self) {
+        self.dirs.write().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+
+    /// The state of attribute `name` for the file at `path`, and the line that gave it, or
+    /// None if it is unspecified.
+    pub fn get<F: FileSystem>(&self, fs: &F, path: &Path, name: &str) -> Option<AttrMatch> {
+        let dir = match path.parent()? {
+            dir if dir.as_os_str().is_empty() => Path::new("."),
+            dir => dir,
+        };
+        let dir_attrs = self.dir_attrs(fs, dir);
+        let path = dir_attrs.dir.join(path.file_name()?);
+        for file in &dir_attrs.files {
+            let Ok(relative) = path.strip_prefix(&file.dir) else {
+                continue;
+            };
+            let file_name = relative.file_name().unwrap_or_default();
+            for rule in file.rules.iter().rev() {
+                let Some((_, value)) = rule.attrs.iter().rev().find(|(attr, _)| attr == name) else {
+                    continue;
+                };
+                let matched = if rule.basename {
+                    rule.matcher.is_match(file_name)
+                } else {
+                    rule.matcher.is_match(relative)
+                };
+                if matched {
+                    return value.clone().map(|value| AttrMatch {
+                        file: file.path.clone(),
+                        pattern: rule.pattern.clone(),
+                        value,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn dir_attrs<F: FileSystem>(&self, fs: &F, dir: &Path) -> Arc<DirAttrs> {
+        if let Some(dir_attrs) = self.cached(dir) {
+            return dir_attrs;
+        }
+        let canonical = fs.canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        let dir_attrs = self.canonical_dir_attrs(fs, &canonical);
+        self.remember(dir, &dir_attrs);
+        dir_attrs
+    }
+
+    fn canonical_dir_attrs<F: FileSystem>(&self, fs: &F, dir: &Path) -> Arc<DirAttrs> {
+        if let Some(dir_attrs) = self.cached(dir) {
+            return dir_attrs;
+        }
+        // The top of the work tree holds `.git`; attributes files above it don't apply, and
+        // neither do any outside a work tree, as git ignores them there
+        let top = fs.exists(&dir.join(".git"));
+        let parent = if top { None } else { dir.parent().map(|parent| self.canonical_dir_attrs(fs, parent)) };
+        let in_repo = top || parent.as_ref().is_some_and(|parent| parent.in_repo);
+        let mut files = Vec::new();
+        if in_repo {
+            files.extend(load(dir, fs).map(Arc::new));
+            files.extend(parent.iter().flat_map(|parent| parent.files.iter().cloned()));
+        }
+        let dir_attrs = Arc::new(DirAttrs {
+            dir: dir.to_path_buf(),
+            in_repo,
+            files,
+        });
+        self.remember(dir, &dir_attrs);
+        dir_attrs
+    }
+
+    fn cached(&self, dir: &Path) -> Option<Arc<DirAttrs>> {
+        let dirs = self.dirs.read().unwrap_or_else(|e| e.into_inner());
+        dirs.get(dir).cloned()
+    }
+
+    fn remember(&self, dir: &Path, dir_attrs: &Arc<DirAttrs>) {
+        let mut dirs = self.dirs.write().unwrap_or_else(|e| e.into_inner());
+        dirs.insert(dir.to_path_buf(), Arc::clone(dir_attrs));
+    }
+}
+
+fn load<F: FileSystem>(dir: &Path, fs: &F) -> Option<AttrFile> {
+    let path = dir.join(ATTRIBUTES_FILE_NAME);
+    if !fs.is_file(&path) {
+        return None;
+    }
+    let content = match fs.read(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+        Err(e) => {
+            warn!("Failed to read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let rules: Vec<AttrRule> = content.lines().filter_map(|line| parse_line(line, &path)).collect();
+    debug!("Loaded {} attribute lines from {}", rules.len(), path.display());
+    Some(AttrFile {
+        path,
+        dir: dir.to_path_buf(),
+        rules,
+    })
+}
+
+fn parse_line(line: &str, path: &Path) -> Option<AttrRule> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split_whitespace();
+    let pattern = fields.next()?;
+    // Macro definitions aren't patterns, and patterns for directories never apply to files
+    if pattern.starts_with("[attr]") || pattern.ends_with('/') {
+        return None;
+    }
+    let mut attrs = Vec::new();
+    for field in fields {
+        if let Some(name) = field.strip_prefix('-') {
+            attrs.push((name.to_string(), Some(AttrValue::Unset)));
+        } else if let Some(name) = field.strip_prefix('!') {
+            attrs.push((name.to_string(), None));
+        } else if let Some((name, value)) = field.split_once('=') {
+            attrs.push((name.to_string(), Some(AttrValue::Value(value.to_string()))));
+        } else {
+            attrs.push((field.to_string(), Some(AttrValue::Set)));
+            // Git's one built-in macro
+            if field == "binary" {
+                for name in ["diff", "merge", "text"] {
+                    attrs.push((name.to_string(), Some(AttrValue::Unset)));
+                }
+            }
+        }
+    }
+
+    let anchored = pattern.strip_prefix('/').unwrap_or(pattern);
+    let glob = match GlobBuilder::new(anchored).literal_separator(true).build() {
+        Ok(glob) => glob,
+        Err(e) => {
+            warn!("Invalid pattern in {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    Some(AttrRule {
+        pattern: pattern.to_string(),
+        matcher: glob.compile_matcher(),
+        basename: !pattern.contains('/'),
+        attrs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+
+    #[test]
+    fn test_layered_attributes() {
+        let fs = MemFs::new()
+            .with_dir("/repo/.git")
+            .with_file("/.gitattributes", b"* linguist-generated\n")
+            .with_file(
+                "/repo/.gitattributes",
+                b"# generated code\n*.pb.go linguist-generated\n/docs/** linguist-documentation=true\n*.png binary\n",
+            )
+            .with_file(
+                "/repo/src/.gitattributes",
+                b"api.pb.go -linguist-generated\nold.pb.go !linguist-generated\n*.bat eol=crlf\n",
+            )
+            .with_dir("/repo/src/deep")
+            .with_dir("/repo/docs");
+        let attributes = GitAttributes::new();
+        let get = |path: &str, name: &str| attributes.get(&fs, Path::new(path), name);
+
+        let generated = get("/repo/src/deep/x.pb.go", "linguist-generated").unwrap();
+        assert_eq!(generated.value, AttrValue::Set);
+        assert_eq!(generated.file, PathBuf::from("/repo/.gitattributes"));
+        assert_eq!(generated.pattern, "*.pb.go");
+        assert_eq!(
+            get("/repo/src/api.pb.go", "linguist-generated").map(|m| m.value),
+            Some(AttrValue::Unset)
+        );
+        assert_eq!(get("/repo/src/old.pb.go", "linguist-generated"), None);
+        // Attributes files above the top of the repository don't apply
+        assert_eq!(get("/repo/src/main.go", "linguist-generated"), None);
+
+        // Anchored patterns are relative to their file's directory
+        assert!(
+            get("/repo/docs/a.md", "linguist-documentation")
+                .unwrap()
+                .value
+                .is_true()
+        );
+        assert_eq!(get("/repo/src/docs/a.md", "linguist-documentation"), None);
+
+        assert_eq!(
+            get("/repo/src/deep/run.bat", "eol").map(|m| m.value),
+            Some(AttrValue::Value("crlf".to_string()))
+        );
+        assert_eq!(get("/repo/logo.png", "text").map(|m| m.value), Some(AttrValue::Unset));
+        assert_eq!(get("/repo/logo.png", "binary").map(|m| m.value), Some(AttrValue::Set));
+    }
+
+    #[test]
+    fn test_no_attributes_outside_a_repository() {
+        let fs = MemFs::new()
+            .with_file(
+                "/.gitattributes",
+                b"*.txt binary
+",
+            )
+            .with_file(
+                "/tmp/work/.gitattributes",
+                b"*.txt -text
+",
+            );
+        let attributes = GitAttributes::new();
+        assert_eq!(attributes.get(&fs, Path::new("/tmp/work/a.txt"), "text"), None);
+    }
+
+    #[test]
+    fn test_clear_reads_files_afresh() {
+        let fs = MemFs::new().with_dir("/repo/.git").with_file(
+            "/repo/.gitattributes",
+            b"*.txt -text
+",
+        );
+        let attributes = GitAttributes::new();
+        let text = || attributes.get(&fs, Path::new("/repo/a.txt"), "text").map(|m| m.value);
+        assert_eq!(text(), Some(AttrValue::Unset));
+
+        fs.write(Path::new("/repo/.gitattributes"), b"*.txt text\n").unwrap();
+        assert_eq!(text(), Some(AttrValue::Unset));
+        attributes.clear();
+        assert_eq!(text(), Some(AttrValue::Set));
+    }
+}
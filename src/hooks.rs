@@ -0,0 +1,175 @@
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// First line after the shebang of every hook we write, so we never remove someone else's.
+const MARKER: &str = "# Installed by `whitespace install-hook`; remove with `whitespace uninstall-hook`.";
+
+/// Git hooks `install-hook` can manage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HookType {
+    /// Clean staged files, stopping the commit if any changed
+    #[default]
+    PreCommit,
+    /// Refuse to push while the work tree has trailing whitespace
+    PrePush,
+}
+
+impl HookType {
+    /// The hook's file name in the hooks directory.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::PreCommit => "pre-commit",
+            Self::PrePush => "pre-push",
+        }
+    }
+
+    fn script(self) -> String {
+        let body = match self {
+            Self::PreCommit => {
+                "# Clean trailing whitespace from staged files. If anything changed, the commit stops\n\
+                 # so the fixes can be reviewed and staged.\n\
+                 git diff --cached --name-only --diff-filter=ACMR -z | xargs -0 whitespace pre-commit\n"
+            }
+            Self::PrePush => {
+                "# Refuse to push while any file in the work tree has trailing whitespace.\n\
+                 whitespace --dry-run \"$(git rev-parse --show-toplevel)\" || {\n    \
+                     echo \"Trailing whitespace found; run 'whitespace' to fix it before pushing.\" >&2\n    \
+                     exit 1\n\
+                 }\n"
+            }
+        };
+        format!("#!/bin/sh\n{}\n{}", MARKER, body)
+    }
+}
+
+/// The hooks directory git uses for the repository containing `repo`. This honors
+/// `core.hooksPath` and linked worktrees, unlike assuming `.git/hooks`.
+pub fn hooks_dir(repo: &Path) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .current_dir(repo)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        eyre::bail!(
+            "Not in a git repository: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let hooks = String::from_utf8(output.stdout).context("git printed a non-UTF-8 hooks path")?;
+    // Relative paths are relative to `repo`; joining leaves absolute ones unchanged
+    Ok(repo.join(hooks.trim_end_matches(['\n', '\r'])))
+}
+
+/// Write `hook` into `hooks_dir`, refusing to replace a hook we didn't write unless `force`.
+/// Returns the hook's path.
+pub fn install(hooks_dir: &Path, hook: HookType, force: bool) -> Result<PathBuf> {
+    let path = hooks_dir.join(hook.file_name());
+    if !force && path.exists() && !is_ours(&path) {
+        eyre::bail!(
+            "{} already exists and was not installed by whitespace (use --force to replace it)",
+            path.display()
+        );
+    }
+
+    fs::create_dir_all(hooks_dir).with_context(|| format!("Failed to create {}", hooks_dir.display()))?;
+    fs::write(&path, hook.script()).with_context(|| format!("Failed to write {}", path.display()))?;
+    make_executable(&path)?;
+    Ok(path)
+}
+
+/// Remove `hook` from `hooks_dir` if we installed it. Returns the removed hook's path.
+pub fn uninstall(hooks_dir: &Path, hook: HookType) -> Result<PathBuf> {
+    let path = hooks_dir.join(hook.file_name());
+    if !path.exists() {
+        eyre::bail!("No {} hook is installed", hook.file_name());
+    }
+    if !is_ours(&path) {
+        eyre::bail!("{} was not installed by whitespace; leaving it alone", path.display());
+    }
+    fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+    Ok(path)
+}
+
+fn is_ours(path: &Path) -> bool {
+    fs::read_to_string(path).is_ok_and(|script| script.lines().nth(1) == Some(MARKER))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {} executable", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let temp_dir = TempDir::new().unwrap();
+        let hooks = temp_dir.path().join("hooks");
+
+        let path = install(&hooks, HookType::PreCommit, false).unwrap();
+        assert_eq!(path, hooks.join("pre-commit"));
+        let script = fs::read_to_string(&path).unwrap();
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("xargs -0 whitespace pre-commit"));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+        }
+
+        // Reinstalling our own hook is fine
+        install(&hooks, HookType::PreCommit, false).unwrap();
+        assert_eq!(uninstall(&hooks, HookType::PreCommit).unwrap(), path);
+        assert!(!path.exists());
+        assert!(uninstall(&hooks, HookType::PreCommit).is_err());
+    }
+
+    #[test]
+    fn test_foreign_hooks_are_protected() {
+        let temp_dir = TempDir::new().unwrap();
+        let hooks = temp_dir.path();
+        fs::write(hooks.join("pre-push"), "#!/bin/sh\nmake test\n").unwrap();
+
+        assert!(install(hooks, HookType::PrePush, false).is_err());
+        assert!(uninstall(hooks, HookType::PrePush).is_err());
+        assert_eq!(
+            fs::read_to_string(hooks.join("pre-push")).unwrap(),
+            "#!/bin/sh\nmake test\n"
+        );
+
+        install(hooks, HookType::PrePush, true).unwrap();
+        assert!(
+            fs::read_to_string(hooks.join("pre-push"))
+                .unwrap()
+                .contains("whitespace --dry-run")
+        );
+    }
+
+    #[test]
+    fn test_hooks_dir_honors_hooks_path() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path();
+        let git = |args: &[&str]| Command::new("git").args(args).current_dir(repo).output();
+        if !git(&["init", "-q"]).is_ok_and(|output| output.status.success()) {
+            return;
+        }
+
+        assert_eq!(hooks_dir(repo).unwrap(), repo.join(".git/hooks"));
+        git(&["config", "core.hooksPath", "githooks"]).unwrap();
+        assert_eq!(hooks_dir(repo).unwrap(), repo.join("githooks"));
+    }
+}
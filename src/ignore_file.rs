@@ -0,0 +1,131 @@
+use crate::ports::fs::FileSystem;
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+/// Name of the per-directory ignore file, using gitignore syntax.
+pub const IGNORE_FILE_NAME: &str = ".whitespaceignore";
+
+/// Layered `.whitespaceignore` matchers discovered while walking a tree.
+///
+/// Each directory may contribute its own matcher. Deeper files take precedence,
+/// so a subdirectory can re-include (`!pattern`) something a parent excluded.
+#[derive(Debug, Default)]
+pub struct IgnoreStack {
+    matchers: HashMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load `dir/.whitespaceignore` if present. Call once per directory as the walk enters it.
+    pub fn enter_dir<F: FileSystem>(&mut self, dir: &Path, fs: &F) {
+        let ignore_path = dir.join(IGNORE_FILE_NAME);
+        if !fs.is_file(&ignore_path) {
+            return;
+        }
+
+        let content = match fs.read(&ignore_path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+            Err(e) => {
+                warn!("Failed to read {}: {}", ignore_path.display(), e);
+                return;
+            }
+        };
+
+        let mut builder = GitignoreBuilder::new(dir);
+        for line in content.lines() {
+            if let Err(e) = builder.add_line(Some(ignore_path.clone()), line) {
+                warn!("Invalid pattern in {}: {}", ignore_path.display(), e);
+            }
+        }
+
+        match builder.build() {
+            Ok(matcher) => {
+                debug!(
+                    "Loaded {} patterns from {}",
+                    matcher.num_ignores(),
+                    ignore_path.display()
+                );
+                self.matchers.insert(dir.to_path_buf(), matcher);
+            }
+            Err(e) => warn!("Failed to build ignore rules from {}: {}", ignore_path.display(), e),
+        }
+    }
+
+    /// True if the nearest ignore file with an opinion on `path` ignores it.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.matched(path, is_dir).is_ignore()
+    }
+
+    /// The ignore file and pattern that ignore `path`, if it is ignored.
+    pub fn ignore_rule(&self, path: &Path, is_dir: bool) -> Option<(PathBuf, String)> {
+        match self.matched(path, is_dir) {
+            Match::Ignore(glob) => {
+                let file = glob.from().unwrap_or(Path::new(IGNORE_FILE_NAME));
+                Some((file.to_path_buf(), glob.original().to_string()))
+            }
+            _ => None,
+        }
+    }
+
+    fn matched(&self, path: &Path, is_dir: bool) -> Match<&Glob> {
+        if self.matchers.is_empty() {
+            return Match::None;
+        }
+
+        for dir in path.ancestors().skip(1) {
+            if let Some(matcher) = self.matchers.get(dir) {
+                match matcher.matched(path, is_dir) {
+                    Match::None => {}
+                    decision => return decision,
+                }
+            }
+        }
+
+        Match::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+
+    #[test]
+    fn test_layered_ignore_files() {
+        let fs = MemFs::new()
+            .with_file("root/.whitespaceignore", b"*.snap\ngenerated/\n")
+            .with_file("root/sub/.whitespaceignore", b"!keep.snap\n");
+
+        let mut stack = IgnoreStack::new();
+        stack.enter_dir(Path::new("root"), &fs);
+        stack.enter_dir(Path::new("root/sub"), &fs);
+
+        assert!(stack.is_ignored(Path::new("root/a.snap"), false));
+        assert!(stack.is_ignored(Path::new("root/sub/other.snap"), false));
+        assert!(!stack.is_ignored(Path::new("root/sub/keep.snap"), false));
+        assert!(stack.is_ignored(Path::new("root/generated"), true));
+        assert!(!stack.is_ignored(Path::new("root/generated"), false));
+        assert!(!stack.is_ignored(Path::new("root/main.rs"), false));
+
+        assert_eq!(
+            stack.ignore_rule(Path::new("root/sub/other.snap"), false),
+            Some((PathBuf::from("root/.whitespaceignore"), "*.snap".to_string()))
+        );
+        assert_eq!(stack.ignore_rule(Path::new("root/sub/keep.snap"), false), None);
+    }
+
+    #[test]
+    fn test_no_ignore_files() {
+        let fs = MemFs::new();
+        let mut stack = IgnoreStack::new();
+        stack.enter_dir(Path::new("root"), &fs);
+
+        assert!(!stack.is_ignored(Path::new("root/a.txt"), false));
+    }
+}
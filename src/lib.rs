@@ -1,22 +1,72 @@
+pub mod bench;
+pub mod cache;
 pub mod cli;
+pub mod compare;
 pub mod config;
+pub mod custom;
+pub mod daemon;
 pub mod engine;
+pub mod exit;
+pub mod explain;
+pub mod filetypes;
+pub mod fixup;
+pub mod git_diff;
+pub mod gitattributes;
+pub mod hooks;
+pub mod ignore_file;
+pub mod literals;
+pub mod lock;
+pub mod logging;
+pub mod lsp;
+pub mod metrics;
+pub mod output;
 pub mod ports;
+pub mod preset;
 pub mod processor;
+pub mod report;
+pub mod resume;
+pub mod rules;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timings;
+pub mod transform;
+pub mod validate;
 pub mod walker;
 
-pub use cli::Cli;
-pub use config::{Config, RuntimeConfig};
+pub use bench::BenchOptions;
+pub use cache::Cache;
+pub use cli::{Cli, Command, ConfigCommand};
+pub use config::{Config, RuntimeConfig, RuntimeConfigBuilder};
 pub use engine::{ParallelEngine, ProcessingResults, ProcessingSummary};
-pub use ports::fs::{FileSystem, FsMetadata, MemFs, RealFs};
-pub use processor::{ProcessingResult, WhitespaceProcessor};
+pub use exit::ExitStatus;
+pub use fixup::FixupMode;
+pub use logging::LoggingConfig;
+pub use output::{ColorChoice, OutputFormat, SortOrder, StreamOrder};
+pub use ports::fs::{FileContents, FileSystem, FsMetadata, MemFs, RealFs, WriteFn};
+pub use preset::Preset;
+pub use processor::{Outcome, ProcessingResult, SkipReason, WhitespaceProcessor};
+pub use stats::StatsReport;
 pub use walker::FileWalker;
 
+use clap::CommandFactory;
 use colored::*;
 use eyre::{Context, Result};
-use log::info;
+use fixup::Fixup;
+use git_diff::ChangedLines;
+use lock::RootLock;
+use ports::intent::{IntentLog, IntentLogFs, Recovery};
+use ports::mirror::MirrorFs;
+use ports::network::NetworkFs;
+use resume::RunJournal;
+use std::collections::HashMap;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use timings::{Phase, Timings};
+use tracing::field::Empty;
+use tracing::{info, info_span, warn};
 
 /// Format line numbers into compressed ranges (e.g., "1-3,5,7-10")
 pub fn format_line_numbers(lines: &[usize]) -> String {
@@ -55,150 +105,1384 @@ pub fn format_line_numbers(lines: &[usize]) -> String {
 /// Display processing results to the console.
 /// Returns the number of files with changes.
 pub fn display_results(file_results: &[(PathBuf, ProcessingResult)], is_dry_run: bool) -> usize {
+    let files_with_changes = display_changed_files(file_results);
+    let (lines_with_changes, bytes_removed) = change_totals(file_results);
+    display_summary(files_with_changes, lines_with_changes, bytes_removed, is_dry_run);
+    files_with_changes
+}
+
+/// Lines changed and bytes removed across `file_results`, or that would be in a dry run.
+fn change_totals(file_results: &[(PathBuf, ProcessingResult)]) -> (usize, usize) {
+    file_results.iter().fold((0, 0), |(lines, bytes), (_, result)| {
+        (lines + result.lines_modified().len(), bytes + result.bytes_removed())
+    })
+}
+
+/// List files with changes and their line ranges, without a summary.
+/// Returns the number of files with changes.
+pub fn display_changed_files(file_results: &[(PathBuf, ProcessingResult)]) -> usize {
     let mut files_with_changes = 0;
 
     for (file_path, result) in file_results {
-        if result.had_changes && result.error.is_none() {
-            let line_info = format_line_numbers(&result.lines_modified);
+        if let Outcome::Modified { lines, .. } = &result.outcome {
+            let line_info = format_line_numbers(lines);
             println!("{}{}", file_path.display().to_string().blue(), line_info.dimmed());
             files_with_changes += 1;
         }
+        for notice in output::notice_lines(file_path, result) {
+            println!("{}", notice);
+        }
     }
 
-    // Display summary with colors and icons
+    files_with_changes
+}
+
+/// Print the one-line verdict: nothing found, files cleaned, or files that would be. A dry run
+/// also estimates its impact, so the size of the change is known before applying it.
+fn display_summary(files_with_changes: usize, lines_with_changes: usize, bytes_removed: usize, is_dry_run: bool) {
+    // Colors and icons are both dropped for plain output
     if files_with_changes == 0 {
-        println!("{}", "✅ No trailing whitespace found".green().bold());
+        println!(
+            "{}",
+            format!("{}No trailing whitespace found", output::icon("✅"))
+                .green()
+                .bold()
+        );
     } else if is_dry_run {
         println!(
-            "\n{} {} {}",
-            "📋".cyan(),
+            "\n{}{} {} {}",
+            output::icon("📋").cyan(),
             format!("{}", files_with_changes).cyan().bold(),
-            "files NOT cleaned".yellow()
+            "files NOT cleaned".yellow(),
+            format!("({})", dry_run_impact(lines_with_changes, bytes_removed)).dimmed()
         );
     } else {
         println!(
-            "\n{} {} {}",
-            "🧹".green(),
+            "\n{}{} {}",
+            output::icon("🧹").green(),
             format!("{}", files_with_changes).cyan().bold(),
             "files cleaned".green().bold()
         );
     }
+}
 
-    files_with_changes
+/// Describe what a dry run would change, e.g. "12 lines affected, 48 bytes would be removed".
+fn dry_run_impact(lines_with_changes: usize, bytes_removed: usize) -> String {
+    format!(
+        "{} {} affected, {} {} would be removed",
+        lines_with_changes,
+        if lines_with_changes == 1 { "line" } else { "lines" },
+        bytes_removed,
+        if bytes_removed == 1 { "byte" } else { "bytes" }
+    )
+}
+
+/// Describe what a whole run covered, e.g. "Scanned 12 files in 2 directories, 1 failed (35.2ms)".
+/// Without a directory count the files came from a `--files-from` list.
+fn run_totals_line(files_scanned: usize, directories: Option<usize>, files_failed: usize, elapsed: Duration) -> String {
+    let files = if files_scanned == 1 { "file" } else { "files" };
+    let mut line = match directories {
+        Some(1) => format!("Scanned {} {} in 1 directory", files_scanned, files),
+        Some(directories) => format!("Scanned {} {} in {} directories", files_scanned, files, directories),
+        None => format!("Scanned {} listed {}", files_scanned, files),
+    };
+    if files_failed > 0 {
+        line.push_str(&format!(", {} failed", files_failed));
+    }
+    line.push_str(&format!(" ({:.1}ms)", elapsed.as_secs_f64() * 1000.0));
+    line
 }
 
-/// Process a single directory and return (files_with_changes, files_modified).
+/// Describe the generated and vendored code a run left alone, e.g. "Left alone: 3 generated,
+/// 1 vendored", or None if there was none.
+fn generated_line(generated: usize, vendored: usize) -> Option<String> {
+    let counts: Vec<String> = [(generated, "generated"), (vendored, "vendored")]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, kind)| format!("{} {}", count, kind))
+        .collect();
+    (!counts.is_empty()).then(|| format!("Left alone: {} (--include-generated cleans them)", counts.join(", ")))
+}
+
+/// Per-directory counts returned by [`process_directory`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DirectorySummary {
+    /// Files handed to the processor, i.e. not skipped by the walk
+    pub files_scanned: usize,
+    /// Files with trailing whitespace (cleaned, or that would be in a dry run)
+    pub files_with_changes: usize,
+    /// Lines changed or removed in those files
+    pub lines_with_changes: usize,
+    /// Bytes removed from those files
+    pub bytes_removed: usize,
+    /// Files actually rewritten
+    pub files_modified: usize,
+    /// Files that could not be read, processed or written
+    pub files_failed: usize,
+    /// True if the error limit was reached before every file was processed
+    pub aborted: bool,
+    /// True if `--max-files` or `--timeout` stopped the walk or processing early
+    pub limit_reached: bool,
+    /// Files skipped as generated code
+    pub generated_skipped: usize,
+    /// Files and directories skipped as vendored code; a directory counts once
+    pub vendored_skipped: usize,
+}
+
+/// Process a single directory and summarize what happened.
 pub fn process_directory<F: FileSystem>(
     target_dir: &Path,
     runtime_config: &RuntimeConfig,
     fs: Arc<F>,
-) -> Result<(usize, usize)> {
+) -> Result<DirectorySummary> {
+    let run = RunState {
+        max_errors: runtime_config.max_errors,
+        max_files: runtime_config.max_files,
+        deadline: runtime_config.timeout.map(|timeout| Instant::now() + timeout),
+        timings: None,
+        journal: None,
+    };
+    let (summary, _) = process_directory_in_run(target_dir, runtime_config, fs, &run)?;
+    Ok(summary)
+}
+
+/// Where the files given to [`process_files_in_run`] came from.
+#[derive(Debug, Clone, Copy)]
+enum Source<'a> {
+    /// Found by walking this target directory
+    Walk(&'a Path),
+    /// Listed in this file (`-` for stdin) by `--files-from`
+    List(&'a Path),
+    /// This one file, named with `--lines`
+    File(&'a Path),
+}
+
+impl<'a> Source<'a> {
+    /// The directory or list, for headings and messages.
+    fn path(self) -> &'a Path {
+        match self {
+            Self::Walk(path) | Self::List(path) | Self::File(path) => path,
+        }
+    }
+
+    /// The directory walked, whose exclude patterns decided what was found.
+    fn root(self) -> Option<&'a Path> {
+        match self {
+            Self::Walk(root) => Some(root),
+            Self::List(_) | Self::File(_) => None,
+        }
+    }
+}
+
+/// State [`run`] shares across directories.
+struct RunState {
+    /// Failed files still allowed before aborting
+    max_errors: Option<usize>,
+    /// Files still allowed to be processed, from `--max-files`
+    max_files: Option<usize>,
+    /// When `--timeout` runs out
+    deadline: Option<Instant>,
+    /// Per-phase times, when `--timings` is on
+    timings: Option<Arc<Timings>>,
+    /// The journal a resumable run records its progress in
+    journal: Option<Arc<RunJournal>>,
+}
+
+fn process_directory_in_run<F: FileSystem>(
+    target_dir: &Path,
+    runtime_config: &RuntimeConfig,
+    fs: Arc<F>,
+    run: &RunState,
+) -> Result<(DirectorySummary, Vec<(PathBuf, ProcessingResult)>)> {
+    let span = info_span!("directory", path = %target_dir.display(), files = Empty);
+    let _entered = span.enter();
     info!("Processing directory: {}", target_dir.display());
 
     let file_config = Arc::new(runtime_config.file_config.clone());
 
     // Initialize file walker
-    let walker = FileWalker::new(Arc::clone(&file_config), Arc::clone(&fs));
+    let walker = FileWalker::new(Arc::clone(&file_config), Arc::clone(&fs)).with_limits(run.max_files, run.deadline);
 
-    // Collect files
-    let files = walker
-        .collect_files(target_dir, runtime_config.recursive)
-        .with_context(|| format!("Failed to collect files from {}", target_dir.display()))?;
+    // Collect files, remembering what was skipped along the way, unless a resumed run has them
+    let walked = run.journal.as_ref().and_then(|journal| journal.walked(target_dir));
+    let walk = if let Some(files) = walked {
+        walker::WalkResult {
+            files,
+            ..walker::WalkResult::default()
+        }
+    } else {
+        let walk_start = Instant::now();
+        let walk = walker
+            .walk(target_dir, runtime_config.max_depth)
+            .with_context(|| format!("Failed to collect files from {}", target_dir.display()))?;
+        if let Some(timings) = &run.timings {
+            timings.record(Phase::Walk, walk_start.elapsed());
+        }
+        // A walk cut short by a limit is no list to resume from
+        if let Some(journal) = &run.journal
+            && !walk.stopped
+            && let Err(e) = journal.record_pending(target_dir, &walk.files)
+        {
+            warn!("{:#}", e);
+        }
+        walk
+    };
+    // A resumed run leaves the files it already did alone
+    let files = match &run.journal {
+        Some(journal) => journal.not_done(walk.files),
+        None => walk.files,
+    };
+    span.record("files", files.len());
+    let show_skipped =
+        runtime_config.show_skipped && runtime_config.format == OutputFormat::Text && runtime_config.decorated();
 
     if files.is_empty() {
-        return Ok((0, 0));
+        let mut skipped: Vec<_> = walk
+            .skipped
+            .into_iter()
+            .map(|(path, reason)| (path, ProcessingResult::skip(reason)))
+            .collect();
+        let reasons = arrange_results(&mut skipped, Some(target_dir), runtime_config, &fs);
+        if let Some(reasons) = &reasons {
+            output::display_why(&skipped, reasons);
+        }
+        if show_skipped {
+            output::display_skipped(
+                &skipped
+                    .iter()
+                    .filter_map(|(path, result)| Some((path.as_path(), result.skip_reason()?)))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        let summary = DirectorySummary {
+            limit_reached: walk.stopped,
+            generated_skipped: count_skipped(&skipped, SkipReason::Generated),
+            vendored_skipped: count_skipped(&skipped, SkipReason::Vendored),
+            ..DirectorySummary::default()
+        };
+        return Ok((summary, skipped));
     }
 
     info!("Found {} files to process in {}", files.len(), target_dir.display());
 
+    // Open the incremental cache; a broken cache only costs speed, never correctness
+    let cache = if runtime_config.use_cache {
+        match Cache::open(target_dir, &file_config) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(e) => {
+                warn!("Cache unavailable for {}: {}", target_dir.display(), e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let skipped = walk
+        .skipped
+        .into_iter()
+        .map(|(path, reason)| (path, ProcessingResult::skip(reason)))
+        .collect();
+    let (mut summary, file_results) =
+        process_files_in_run(Source::Walk(target_dir), files, skipped, cache, runtime_config, fs, run)?;
+    summary.limit_reached |= walk.stopped;
+    Ok((summary, file_results))
+}
+
+/// Process exactly the files listed at `source` (`-` for stdin), without walking anything.
+/// Listed paths that aren't files are reported and count as failures.
+fn process_file_list_in_run<F: FileSystem>(
+    source: &Path,
+    runtime_config: &RuntimeConfig,
+    fs: Arc<F>,
+    run: &RunState,
+) -> Result<(DirectorySummary, Vec<(PathBuf, ProcessingResult)>)> {
+    let _entered = info_span!("file_list", source = %source.display()).entered();
+    let list = if source == Path::new("-") {
+        let mut list = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut list)
+            .map(|_| list)
+            .context("Failed to read the file list from stdin")
+    } else {
+        std::fs::read(source).with_context(|| format!("Failed to read the file list {}", source.display()))
+    }?;
+    let paths = walker::parse_file_list(&list, runtime_config.null_delimited);
+    info!("Read {} paths from {}", paths.len(), source.display());
+
+    let (files, not_files): (Vec<PathBuf>, Vec<PathBuf>) = paths.into_iter().partition(|path| fs.is_file(path));
+    let not_files = not_files
+        .into_iter()
+        .map(|path| {
+            eprintln!(
+                "{}{} {}",
+                output::icon("❌").red(),
+                "Not a file:".red(),
+                path.display().to_string().yellow()
+            );
+            let error = eyre::eyre!("Not a file: {}", path.display());
+            (path, ProcessingResult::failed(error))
+        })
+        .collect();
+    process_files_in_run(Source::List(source), files, not_files, None, runtime_config, fs, run)
+}
+
+/// Process `files`, display what changed, and summarize the results together with `known`:
+/// results the caller already has, such as files the walk skipped.
+fn process_files_in_run<F: FileSystem>(
+    source: Source,
+    mut files: Vec<PathBuf>,
+    mut known: Vec<(PathBuf, ProcessingResult)>,
+    cache: Option<Arc<Cache>>,
+    runtime_config: &RuntimeConfig,
+    fs: Arc<F>,
+    run: &RunState,
+) -> Result<(DirectorySummary, Vec<(PathBuf, ProcessingResult)>)> {
+    // Files beyond the --max-files budget are reported rather than processed
+    if let Some(max_files) = run.max_files
+        && files.len() > max_files
+    {
+        let over = files.split_off(max_files);
+        known.extend(
+            over.into_iter()
+                .map(|path| (path, ProcessingResult::skip(SkipReason::Limit))),
+        );
+    }
+    let files_given = files.len();
+    let label = source.path();
+    let file_config = Arc::new(runtime_config.file_config.clone());
+    let show_skipped =
+        runtime_config.show_skipped && runtime_config.format == OutputFormat::Text && runtime_config.decorated();
+
     // Initialize engine
-    let engine =
-        ParallelEngine::new(file_config, fs, runtime_config.threads).context("Failed to initialize parallel engine")?;
+    let threads = engine::thread_count(
+        runtime_config.threads,
+        Arc::clone(&file_config),
+        Arc::clone(&fs),
+        &files,
+    );
+    let mut engine = ParallelEngine::new(file_config, Arc::clone(&fs), threads)
+        .context("Failed to initialize parallel engine")?
+        .with_max_errors(run.max_errors)
+        .with_deadline(run.deadline);
+    if let Some(cache) = &cache {
+        engine = engine.with_cache(Arc::clone(cache));
+    }
+    if let Some(timings) = &run.timings {
+        engine = engine.with_timings(Arc::clone(timings));
+    }
+    if let Some(line_ranges) = &runtime_config.line_ranges {
+        engine = engine.with_line_ranges(line_ranges.clone());
+    }
+    if let Some(selection) = &runtime_config.rule_selection {
+        engine = engine.with_rule_selection(selection.clone());
+    }
+    if runtime_config.write_to.is_some() {
+        engine = engine.with_write_unchanged();
+    }
+    if let Some(suffix) = &runtime_config.backup {
+        engine = engine.with_backup(suffix.as_str());
+    }
+    if runtime_config.trash {
+        engine = engine.with_trash();
+    }
+    if let Some(git_ref) = &runtime_config.diff_only {
+        // Listed files are named relative to the current directory, and so in its repository
+        let dir = source.root().unwrap_or(Path::new("."));
+        let changed_lines = ChangedLines::since(dir, git_ref)?;
+        engine = engine.with_changed_lines(Arc::new(changed_lines));
+    }
+    // JUnit is one document for the whole run, so there is nothing to stream
+    let printer = runtime_config
+        .stream
+        .filter(|_| runtime_config.format != OutputFormat::Junit)
+        .map(|order| {
+            Arc::new(output::StreamPrinter::new(
+                std::io::stdout(),
+                order,
+                stream_render(runtime_config),
+            ))
+        });
+    // Files that failed or weren't reached are left for a resumed run to try
+    let journal = run.journal.clone().filter(|_| matches!(source, Source::Walk(_)));
+    if printer.is_some() || journal.is_some() {
+        let printer = printer.clone();
+        engine = engine.with_on_result(Arc::new(move |index: usize, path: &Path, result: &ProcessingResult| {
+            if let Some(printer) = &printer {
+                printer.report(index, path, result);
+            }
+            if let Some(journal) = &journal
+                && !result.is_failure()
+                && result.skip_reason() != Some(SkipReason::Limit)
+                && let Err(e) = journal.record_done(path)
+            {
+                warn!("{:#}", e);
+            }
+        }));
+    }
+    if printer.is_some() && runtime_config.per_directory && runtime_config.format == OutputFormat::Text {
+        println!("\n{}{}", output::icon("📁"), label.display().to_string().bold());
+    }
 
     // Process files and collect results for display
-    let results = engine
+    let mut results = engine
         .process_files_with_results(files, runtime_config.dry_run)
-        .with_context(|| format!("Failed to process files in {}", target_dir.display()))?;
+        .with_context(|| format!("Failed to process files in {}", label.display()))?;
+    if let Some(printer) = &printer {
+        printer.finish();
+    }
+    if runtime_config.verify {
+        verify_results(&engine, &mut results.file_results);
+    }
+
+    if let Some(cache) = &cache
+        && let Err(e) = cache.save()
+    {
+        warn!("Failed to save cache for {}: {}", label.display(), e);
+    }
+    // Files the deadline kept from being started weren't scanned
+    let files_scanned = files_given - count_limited(&results);
+    results.file_results.extend(known);
+    let reasons = arrange_results(&mut results.file_results, source.root(), runtime_config, &fs);
+    let limit_reached = count_limited(&results) > 0;
 
     // Display results to console for this directory
-    let files_with_changes = display_results(&results.file_results, runtime_config.dry_run);
+    let streamed = printer.is_some();
+    let files_with_changes = match runtime_config.format {
+        _ if runtime_config.quiet => output::count_changed_files(&results.file_results),
+        // Files were listed as they finished; only a per-directory summary is left to print
+        _ if streamed
+            && (runtime_config.print0
+                || runtime_config.files_with_matches
+                || runtime_config.format != OutputFormat::Text) =>
+        {
+            output::count_changed_files(&results.file_results)
+        }
+        _ if runtime_config.print0 => {
+            output::write_changed_paths(&mut std::io::stdout().lock(), &results.file_results, b'\0')?
+        }
+        _ if runtime_config.files_with_matches => {
+            output::write_changed_paths(&mut std::io::stdout().lock(), &results.file_results, b'\n')?
+        }
+        OutputFormat::Text => {
+            if runtime_config.per_directory && !streamed {
+                println!("\n{}{}", output::icon("📁"), label.display().to_string().bold());
+            }
+            let files_with_changes = if streamed {
+                output::count_changed_files(&results.file_results)
+            } else if runtime_config.group_by_dir {
+                // Relative paths are already relative to what --relative-to named
+                let root = if runtime_config.relative_to.is_some() { Path::new("") } else { label };
+                output::display_grouped(&results.file_results, root)
+            } else {
+                display_changed_files(&results.file_results)
+            };
+            if runtime_config.per_directory {
+                let (lines_with_changes, bytes_removed) = change_totals(&results.file_results);
+                display_summary(
+                    files_with_changes,
+                    lines_with_changes,
+                    bytes_removed,
+                    runtime_config.dry_run,
+                );
+            }
+            files_with_changes
+        }
+        OutputFormat::Github => output::display_github_annotations(&results.file_results),
+        // JUnit is a single document for the whole run, printed once every directory is done
+        OutputFormat::Junit => output::count_changed_files(&results.file_results),
+    };
+    if let Some(reasons) = &reasons {
+        output::display_why(&results.file_results, reasons);
+    }
+    if show_skipped {
+        output::display_skipped(&results.skipped().collect::<Vec<_>>());
+    }
     let actual_files_modified = if runtime_config.dry_run { 0 } else { files_with_changes };
+    let (lines_with_changes, bytes_removed) = change_totals(&results.file_results);
+    let files_failed = results
+        .file_results
+        .iter()
+        .filter(|(_, result)| result.is_failure())
+        .count();
+
+    let summary = DirectorySummary {
+        files_scanned,
+        files_with_changes,
+        lines_with_changes,
+        bytes_removed,
+        files_modified: actual_files_modified,
+        files_failed,
+        aborted: results.aborted,
+        limit_reached,
+        generated_skipped: count_skipped(&results.file_results, SkipReason::Generated),
+        vendored_skipped: count_skipped(&results.file_results, SkipReason::Vendored),
+    };
+    Ok((summary, results.file_results))
+}
+
+/// Scan the files a run rewrote again, for `--verify`, and fail any that a second pass would
+/// still change. Cleaning should leave nothing for another pass to do; when a combination of
+/// rules doesn't, such as a line ending conversion that leaves trailing whitespace behind, the
+/// run says so loudly rather than passing.
+fn verify_results<F: FileSystem>(engine: &ParallelEngine<F>, file_results: &mut [(PathBuf, ProcessingResult)]) {
+    let rewritten: Vec<PathBuf> = file_results
+        .iter()
+        .filter(|(_, result)| result.had_changes())
+        .map(|(path, _)| path.clone())
+        .collect();
+    let unstable: HashMap<PathBuf, ProcessingResult> = engine.verify(&rewritten).into_iter().collect();
+    info!(
+        "Verified {} rewritten files, {} not clean",
+        rewritten.len(),
+        unstable.len()
+    );
+    for (path, result) in file_results.iter_mut() {
+        let Some(again) = unstable.get(path) else {
+            continue;
+        };
+        let lines = format_line_numbers(again.lines_modified());
+        eprintln!(
+            "{}{} {}{}",
+            output::icon("❌").red(),
+            "Not clean after cleaning:".red().bold(),
+            path.display().to_string().yellow(),
+            lines
+        );
+        *result = ProcessingResult::failed(eyre::eyre!(
+            "Verification failed: a second pass would still change it{}",
+            lines
+        ));
+    }
+}
+
+/// What `--stream` writes for a file with changes, as the run's output would list it.
+fn stream_render(runtime_config: &RuntimeConfig) -> impl Fn(&Path, &ProcessingResult) -> Option<Vec<u8>> + use<> {
+    let relative_to = runtime_config.relative_to.clone();
+    let cwd = std::env::current_dir().ok();
+    let terminator = if runtime_config.print0 {
+        Some(b'\0')
+    } else {
+        runtime_config.files_with_matches.then_some(b'\n')
+    };
+    let format = runtime_config.format;
+    move |path, result| {
+        let notices = terminator.is_none() && format == OutputFormat::Text && !result.notices.is_empty();
+        if !result.had_changes() && !notices {
+            return None;
+        }
+        let path = match (&relative_to, &cwd) {
+            (Some(base), Some(cwd)) => output::relative_path(path, base, cwd),
+            _ => path.to_path_buf(),
+        };
+        if let Some(terminator) = terminator {
+            let mut bytes = path.into_os_string().into_encoded_bytes();
+            bytes.push(terminator);
+            return Some(bytes);
+        }
+        let lines = result.lines_modified();
+        let mut rendered = match format {
+            OutputFormat::Github => output::github_annotations(&path, lines).join("\n") + "\n",
+            _ if !result.had_changes() => String::new(),
+            _ => format!(
+                "{}{}\n",
+                path.display().to_string().blue(),
+                format_line_numbers(lines).dimmed()
+            ),
+        };
+        if notices {
+            for notice in output::notice_lines(&path, result) {
+                rendered.push_str(&notice);
+                rendered.push('\n');
+            }
+        }
+        Some(rendered.into_bytes())
+    }
+}
+
+/// The journal of a run that rewrites the files of walked targets: reopened for `--resume`,
+/// otherwise started afresh, unless it can't be, which like a broken cache is no reason to stop.
+fn open_journal(runtime_config: &RuntimeConfig, targets: &[PathBuf]) -> Result<Option<Arc<RunJournal>>> {
+    if let Some(id) = &runtime_config.resume {
+        return RunJournal::resume(id).map(|journal| Some(Arc::new(journal)));
+    }
+    if runtime_config.dry_run
+        || !runtime_config.use_journal
+        || runtime_config.files_from.is_some()
+        || runtime_config.line_ranges.is_some()
+    {
+        return Ok(None);
+    }
+    match RunJournal::start(targets) {
+        Ok(journal) => Ok(Some(Arc::new(journal))),
+        Err(e) => {
+            warn!("Running without a way to resume: {:#}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// Take the advisory lock on `root` for a run that rewrites files, waiting (and saying so) if
+/// another run holds it. None for dry runs, `--write-to`, `--no-lock`, or if the lock can't be
+/// taken, which like a broken cache is no reason to stop.
+fn lock_target(root: &Path, runtime_config: &RuntimeConfig) -> Option<RootLock> {
+    if runtime_config.dry_run || runtime_config.write_to.is_some() || !runtime_config.use_lock {
+        return None;
+    }
+    let on_wait = || {
+        if !runtime_config.quiet {
+            eprintln!(
+                "{}{} {}",
+                output::icon("🔒").yellow(),
+                "Waiting for another whitespace run on".yellow(),
+                root.display().to_string().yellow()
+            );
+        }
+    };
+    match RootLock::acquire(root, on_wait) {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            warn!("Running without a lock on {}: {:#}", root.display(), e);
+            None
+        }
+    }
+}
+
+/// Put results in `--sort` order and make their paths relative to `--relative-to`, if given,
+/// before anything shows or reports them. With `--why`, returns the reason for each result,
+/// found in between while the paths are still the ones walked; `root` is the directory walked.
+fn arrange_results<F: FileSystem>(
+    file_results: &mut [(PathBuf, ProcessingResult)],
+    root: Option<&Path>,
+    runtime_config: &RuntimeConfig,
+    fs: &Arc<F>,
+) -> Option<Vec<String>> {
+    output::sort_results(file_results, runtime_config.sort, |path| {
+        fs.metadata(path).map_or(0, |metadata| metadata.len)
+    });
+
+    let reasons = (runtime_config.why && runtime_config.format == OutputFormat::Text && runtime_config.decorated())
+        .then(|| {
+            let walker = FileWalker::new(Arc::new(runtime_config.file_config.clone()), Arc::clone(fs));
+            file_results
+                .iter()
+                .map(|(path, result)| explain::reason(&walker, root, path, result))
+                .collect()
+        });
+
+    if let Some(base) = &runtime_config.relative_to
+        && let Ok(cwd) = std::env::current_dir()
+    {
+        for (path, _) in file_results.iter_mut() {
+            *path = output::relative_path(path, base, &cwd);
+        }
+    }
+    reasons
+}
+
+fn count_skipped(file_results: &[(PathBuf, ProcessingResult)], skip_reason: SkipReason) -> usize {
+    file_results
+        .iter()
+        .filter(|(_, result)| result.skip_reason() == Some(skip_reason))
+        .count()
+}
+
+fn count_limited(results: &ProcessingResults) -> usize {
+    results
+        .skipped()
+        .filter(|(_, reason)| *reason == SkipReason::Limit)
+        .count()
+}
+
+/// Main application entry point. Returns the exit status the run warrants: findings in a
+/// dry run, or any directory or file that could not be processed, are not a clean success.
+pub fn run(runtime_config: &RuntimeConfig) -> Result<ExitStatus> {
+    let recovered = recover_rewrites(runtime_config);
+    let config = &runtime_config.file_config;
+    let status = match &runtime_config.write_to {
+        Some(out) => run_with(
+            runtime_config,
+            Arc::new(throttled(config, MirrorFs::new(Arc::new(RealFs::default()), out))),
+        ),
+        None if config.processing.intent_log && !runtime_config.dry_run => {
+            let fs = IntentLogFs::new(real_fs(config), IntentLog::start()?);
+            run_with(runtime_config, Arc::new(throttled(config, fs)))
+        }
+        None => run_with(runtime_config, Arc::new(throttled(config, real_fs(config)))),
+    }?;
+    Ok(status.combine(recovered))
+}
+
+/// The real filesystem, writing files as the config asks.
+pub(crate) fn real_fs(config: &Config) -> RealFs {
+    RealFs::default().with_preserve_xattrs(config.preserve_xattrs)
+}
 
-    Ok((files_with_changes, actual_files_modified))
+/// `fs` reading and writing at most `processing.io-threads` files at once, however many threads
+/// process them, and retrying transient errors as `processing.io-retries` allows.
+pub(crate) fn throttled<F: FileSystem>(config: &Config, fs: F) -> NetworkFs<F> {
+    NetworkFs::with_settings(Arc::new(fs), &config.processing)
 }
 
-/// Main application entry point. Returns Ok(()) on success.
-pub fn run(runtime_config: &RuntimeConfig) -> Result<()> {
+/// Report the rewrites a crashed run with an intent log left unfinished, and unless this is a
+/// dry run finish or undo them. A file neither can put right is an error.
+fn recover_rewrites(runtime_config: &RuntimeConfig) -> ExitStatus {
+    let repair = !runtime_config.dry_run;
+    let recoveries = match ports::intent::recover(repair) {
+        Ok(recoveries) => recoveries,
+        Err(e) => {
+            warn!("Failed to recover interrupted rewrites: {:#}", e);
+            return ExitStatus::Success;
+        }
+    };
+    let mut status = ExitStatus::Success;
+    for recovery in recoveries {
+        let (message, path) = match recovery {
+            Recovery::Finished(path) if repair => ("Finished an interrupted rewrite of", path),
+            Recovery::Finished(path) => ("Interrupted rewrite, to finish:", path),
+            Recovery::Undone(path) if repair => ("Undid an interrupted rewrite of", path),
+            Recovery::Undone(path) => ("Interrupted rewrite, to undo:", path),
+            Recovery::Damaged(path) => {
+                eprintln!(
+                    "{}{} {}",
+                    output::icon("❌").red(),
+                    "Damaged by an interrupted rewrite, restore it:".red(),
+                    path.display().to_string().yellow()
+                );
+                status = ExitStatus::Errors;
+                continue;
+            }
+        };
+        eprintln!("{}{} {}", output::icon("🩹").yellow(), message.yellow(), path.display());
+    }
+    status
+}
+
+/// [`run`], reading and writing files through `fs`.
+fn run_with<F: FileSystem>(runtime_config: &RuntimeConfig, fs: Arc<F>) -> Result<ExitStatus> {
     info!("Starting whitespace removal application");
 
-    let fs = Arc::new(RealFs);
+    let start_time = Instant::now();
+    runtime_config.color.apply();
+    // Reports include the phase breakdown, so timings are also gathered for them
+    let timings = (runtime_config.timings || runtime_config.report.is_some()).then(|| Arc::new(Timings::default()));
+    // Every file's result, kept only for outputs that cover the whole run
+    let keep_results = runtime_config.report.is_some() || runtime_config.format == OutputFormat::Junit;
+    let mut run_results = Vec::new();
 
     info!("Target directories: {:?}", runtime_config.directories);
-    info!("Recursive: {}", runtime_config.recursive);
+    info!("Max depth: {:?}", runtime_config.max_depth);
     info!("Dry run: {}", runtime_config.dry_run);
     info!("Threads: {}", runtime_config.threads);
+    info!("IO threads: {:?}", runtime_config.file_config.processing.io_threads);
 
+    let mut total_files_scanned = 0;
     let mut total_files_with_changes = 0;
+    let mut total_lines_with_changes = 0;
+    let mut total_bytes_removed = 0;
     let mut total_files_modified = 0;
+    let mut total_files_failed = 0;
+    let mut total_generated_skipped = 0;
+    let mut total_vendored_skipped = 0;
     let mut processed_dirs = 0;
+    let mut failed_dirs = 0;
+    let mut limit_reached = false;
+    let deadline = runtime_config.timeout.map(|timeout| start_time + timeout);
 
-    // Process each directory
-    for target_dir in &runtime_config.directories {
-        if !target_dir.exists() {
+    // Process each directory, once even if several targets lead to it; a file list replaces them
+    let mut targets = match &runtime_config.files_from {
+        Some(source) => vec![source.clone()],
+        None => walker::dedupe_targets(&runtime_config.directories, fs.as_ref()),
+    };
+    let journal = open_journal(runtime_config, &targets)?;
+    if let Some(journal) = &journal
+        && runtime_config.resume.is_some()
+    {
+        targets = journal.targets().to_vec();
+    }
+    let mut stopped = false;
+    let is_list = runtime_config.files_from.is_some();
+    let is_file = runtime_config.line_ranges.is_some();
+    for target in &targets {
+        if !is_list && !target.exists() {
             eprintln!(
-                "{} {} {}",
-                "❌".red(),
+                "{}{} {}",
+                output::icon("❌").red(),
                 "Directory does not exist:".red(),
-                target_dir.display().to_string().yellow()
+                target.display().to_string().yellow()
             );
+            failed_dirs += 1;
             continue;
         }
 
-        if !target_dir.is_dir() {
+        if !is_list && !is_file && !target.is_dir() {
             eprintln!(
-                "{} {} {}",
-                "❌".red(),
+                "{}{} {}",
+                output::icon("❌").red(),
                 "Not a directory:".red(),
-                target_dir.display().to_string().yellow()
+                target.display().to_string().yellow()
             );
+            failed_dirs += 1;
             continue;
         }
 
-        match process_directory(target_dir, runtime_config, Arc::clone(&fs)) {
-            Ok((files_with_changes, files_modified)) => {
-                total_files_with_changes += files_with_changes;
-                total_files_modified += files_modified;
+        // A file list or single file is named relative to the current directory, so that is what's locked
+        let _lock = lock_target(if is_list || is_file { Path::new(".") } else { target }, runtime_config);
+        let run = RunState {
+            // `--verify` fails files after the engine has counted, so a target can overshoot
+            max_errors: runtime_config
+                .max_errors
+                .map(|max| max.saturating_sub(total_files_failed)),
+            max_files: runtime_config
+                .max_files
+                .map(|max| max.saturating_sub(total_files_scanned)),
+            deadline,
+            timings: timings.clone(),
+            journal: journal.clone(),
+        };
+        let processed = if is_list {
+            process_file_list_in_run(target, runtime_config, Arc::clone(&fs), &run)
+        } else if is_file {
+            let files = vec![target.clone()];
+            process_files_in_run(
+                Source::File(target),
+                files,
+                Vec::new(),
+                None,
+                runtime_config,
+                Arc::clone(&fs),
+                &run,
+            )
+        } else {
+            process_directory_in_run(target, runtime_config, Arc::clone(&fs), &run)
+        };
+        match processed {
+            Ok((summary, file_results)) => {
+                if keep_results {
+                    run_results.extend(file_results);
+                }
+                total_files_scanned += summary.files_scanned;
+                total_files_with_changes += summary.files_with_changes;
+                total_lines_with_changes += summary.lines_with_changes;
+                total_bytes_removed += summary.bytes_removed;
+                total_files_modified += summary.files_modified;
+                total_files_failed += summary.files_failed;
+                total_generated_skipped += summary.generated_skipped;
+                total_vendored_skipped += summary.vendored_skipped;
                 processed_dirs += 1;
+                let out_of_errors = runtime_config.max_errors.is_some_and(|max| total_files_failed >= max);
+                if summary.aborted || out_of_errors {
+                    eprintln!(
+                        "{}{}",
+                        output::icon("🛑").red(),
+                        format!("Aborted after {} failed files", total_files_failed).red()
+                    );
+                    stopped = true;
+                    break;
+                }
+                if summary.limit_reached {
+                    limit_reached = true;
+                    let limit = match (runtime_config.timeout, deadline) {
+                        (Some(timeout), Some(deadline)) if Instant::now() >= deadline => {
+                            format!("--timeout {}s", timeout.as_secs())
+                        }
+                        _ => format!("--max-files {}", runtime_config.max_files.unwrap_or_default()),
+                    };
+                    eprintln!(
+                        "{}{}",
+                        output::icon("⏱️").yellow(),
+                        format!("Stopped early: {} reached, remaining files were not processed", limit).yellow()
+                    );
+                    stopped = true;
+                    break;
+                }
             }
             Err(e) => {
+                failed_dirs += 1;
                 eprintln!(
-                    "{} {} {}: {}",
-                    "⚠️".yellow(),
+                    "{}{} {}: {}",
+                    output::icon("⚠️").yellow(),
                     "Error processing".red(),
-                    target_dir.display().to_string().yellow(),
+                    target.display().to_string().yellow(),
                     e
                 );
             }
         }
     }
 
+    if let Some(journal) = journal {
+        if stopped {
+            eprintln!(
+                "{}{}",
+                output::icon("⏯️").yellow(),
+                format!("Resume with --resume {}", journal.id()).yellow()
+            );
+        } else if let Some(Err(e)) = Arc::into_inner(journal).map(RunJournal::finish) {
+            warn!("{:#}", e);
+        }
+    }
+
+    // The report is written even if nothing could be processed, so CI always gets its artifact
+    let mut report_failed = false;
+    if let Some(path) = &runtime_config.report {
+        let report = report::RunReport {
+            dry_run: runtime_config.dry_run,
+            directories: &runtime_config.directories,
+            config: &runtime_config.file_config,
+            file_results: &run_results,
+            relative_to: runtime_config.relative_to.as_deref(),
+            fs: fs.as_ref(),
+            elapsed: start_time.elapsed(),
+            timings: timings.as_deref(),
+        };
+        match report::write_report(path, &report) {
+            Ok(()) => info!("Wrote report to {}", path.display()),
+            Err(e) => {
+                report_failed = true;
+                eprintln!("{}{} {:#}", output::icon("❌").red(), "Report not written:".red(), e);
+            }
+        }
+    }
+
+    let mut metrics = metrics::RunMetrics {
+        files_scanned: total_files_scanned,
+        files_with_changes: total_files_with_changes,
+        files_modified: total_files_modified,
+        files_failed: total_files_failed,
+        lines_with_changes: total_lines_with_changes,
+        bytes_removed: total_bytes_removed,
+        duration: start_time.elapsed(),
+        status: ExitStatus::Errors,
+    };
     if processed_dirs == 0 {
-        println!("{}", "No valid directories found to process".yellow());
-        return Ok(());
+        eprintln!("{}", "No valid directories found to process".yellow());
+        export_metrics(runtime_config, &metrics);
+        return Ok(ExitStatus::Errors);
     }
 
     // Log summary information
     info!("Processing completed:");
     info!("  Directories processed: {}", processed_dirs);
+    info!("  Directories failed: {}", failed_dirs);
     info!("  Files with changes: {}", total_files_with_changes);
+    info!("  Lines with changes: {}", total_lines_with_changes);
+    info!("  Bytes removed: {}", total_bytes_removed);
     info!("  Files modified: {}", total_files_modified);
+    info!("  Files failed: {}", total_files_failed);
+
+    if runtime_config.format == OutputFormat::Junit && !runtime_config.quiet {
+        print!("{}", output::junit_xml(&run_results, start_time.elapsed()));
+    }
+    if runtime_config.format == OutputFormat::Text && runtime_config.decorated() {
+        if runtime_config.per_directory {
+            println!("\n{}", "Total".bold());
+        }
+        display_summary(
+            total_files_with_changes,
+            total_lines_with_changes,
+            total_bytes_removed,
+            runtime_config.dry_run,
+        );
+        println!(
+            "{}",
+            run_totals_line(
+                total_files_scanned,
+                (runtime_config.files_from.is_none() && !is_file).then_some(processed_dirs),
+                total_files_failed,
+                start_time.elapsed()
+            )
+            .dimmed()
+        );
+        if let Some(line) = generated_line(total_generated_skipped, total_vendored_skipped) {
+            println!("{}", line.dimmed());
+        }
+    }
+
+    if let Some(timings) = timings.as_deref().filter(|_| runtime_config.timings) {
+        timings::display_timings(timings, start_time.elapsed());
+    }
+
+    let mut status = ExitStatus::Success;
+    if runtime_config.dry_run && total_files_with_changes > 0 {
+        status = status.combine(ExitStatus::Findings);
+    }
+    if failed_dirs > 0 || total_files_failed > 0 || report_failed || limit_reached {
+        status = status.combine(ExitStatus::Errors);
+    }
+    metrics.status = status;
+    metrics.duration = start_time.elapsed();
+    export_metrics(runtime_config, &metrics);
+    Ok(status)
+}
+
+/// Export a run's counters to the sinks the `metrics` settings configure. A sink that can't be
+/// reached is reported, but like a broken cache is no reason to fail the run.
+fn export_metrics(runtime_config: &RuntimeConfig, metrics: &metrics::RunMetrics) {
+    if let Err(e) = metrics::export(&runtime_config.file_config.metrics, metrics) {
+        warn!("Metrics not exported: {:#}", e);
+        eprintln!(
+            "{}{} {:#}",
+            output::icon("⚠️").yellow(),
+            "Metrics not exported:".yellow(),
+            e
+        );
+    }
+}
+
+/// Clean exactly `files`, as the `pre-commit` framework expects of a hook: nothing is walked,
+/// and the status is [`ExitStatus::Findings`] (exit 1) if any file was modified, so the commit
+/// stops and the fixes can be reviewed and staged.
+pub fn run_pre_commit(runtime_config: &RuntimeConfig, files: &[PathBuf]) -> Result<ExitStatus> {
+    info!("Pre-commit run on {} files", files.len());
+    runtime_config.color.apply();
+
+    let fs = Arc::new(throttled(
+        &runtime_config.file_config,
+        real_fs(&runtime_config.file_config),
+    ));
+    let (files, not_files): (Vec<PathBuf>, Vec<PathBuf>) = files.iter().cloned().partition(|file| fs.is_file(file));
+    for path in &not_files {
+        eprintln!(
+            "{}{} {}",
+            output::icon("❌").red(),
+            "Not a file:".red(),
+            path.display().to_string().yellow()
+        );
+    }
+
+    let file_config = Arc::new(runtime_config.file_config.clone());
+    let threads = engine::thread_count(
+        runtime_config.threads,
+        Arc::clone(&file_config),
+        Arc::clone(&fs),
+        &files,
+    );
+    let engine =
+        ParallelEngine::new(file_config, Arc::clone(&fs), threads).context("Failed to initialize parallel engine")?;
+    // Git runs hooks from the top of the work tree, which is what a daemon there locks too
+    let _lock = lock_target(Path::new("."), runtime_config);
+    let mut results = engine.process_files_with_results(files, false)?;
+    arrange_results(&mut results.file_results, None, runtime_config, &fs);
+
+    let files_modified = if runtime_config.quiet {
+        output::count_changed_files(&results.file_results)
+    } else {
+        display_changed_files(&results.file_results)
+    };
+    for (path, result) in &results.file_results {
+        if let Some(error) = result.error() {
+            eprintln!("{}{}: {:#}", output::icon("⚠️").yellow(), path.display(), error);
+        }
+    }
+
+    let mut status = ExitStatus::Success;
+    if files_modified > 0 {
+        status = status.combine(ExitStatus::Findings);
+    }
+    if !not_files.is_empty() || results.file_results.iter().any(|(_, result)| result.is_failure()) {
+        status = status.combine(ExitStatus::Errors);
+    }
+    Ok(status)
+}
+
+/// Clean the files HEAD and the index change, then stage, amend or commit the cleanup as
+/// `mode` says, so whitespace fixes stay out of feature commits.
+pub fn run_fixup(runtime_config: &RuntimeConfig, mode: FixupMode) -> Result<ExitStatus> {
+    runtime_config.color.apply();
+    let fixup = Fixup::plan(Path::new("."), mode)?;
+    let files = fixup.files();
+    info!("Fixup of {} files", files.len());
+
+    let fs = Arc::new(throttled(
+        &runtime_config.file_config,
+        real_fs(&runtime_config.file_config),
+    ));
+    let file_config = Arc::new(runtime_config.file_config.clone());
+    let threads = engine::thread_count(
+        runtime_config.threads,
+        Arc::clone(&file_config),
+        Arc::clone(&fs),
+        &files,
+    );
+    let mut engine =
+        ParallelEngine::new(file_config, Arc::clone(&fs), threads).context("Failed to initialize parallel engine")?;
+    if let Some(git_ref) = &runtime_config.diff_only {
+        engine = engine.with_changed_lines(Arc::new(ChangedLines::since(Path::new("."), git_ref)?));
+    }
+    if let Some(selection) = &runtime_config.rule_selection {
+        engine = engine.with_rule_selection(selection.clone());
+    }
+    let _lock = lock_target(Path::new("."), runtime_config);
+    let mut results = engine.process_files_with_results(files, runtime_config.dry_run)?;
+    let cleaned: Vec<PathBuf> = results
+        .file_results
+        .iter()
+        .filter(|(_, result)| result.had_changes())
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    // Git named the files from the top of the work tree; show them from here
+    if let Ok(cwd) = std::env::current_dir().and_then(|cwd| cwd.canonicalize()) {
+        for (path, _) in &mut results.file_results {
+            *path = output::relative_path(path, &cwd, &cwd);
+        }
+    }
+    arrange_results(&mut results.file_results, None, runtime_config, &fs);
+    if !runtime_config.quiet {
+        display_changed_files(&results.file_results);
+    }
+    let failed = results.file_results.iter().any(|(_, result)| result.is_failure());
+    for (path, result) in &results.file_results {
+        if let Some(error) = result.error() {
+            eprintln!("{}{}: {:#}", output::icon("⚠️").yellow(), path.display(), error);
+        }
+    }
+
+    let mut status = ExitStatus::Success;
+    if runtime_config.dry_run {
+        if !cleaned.is_empty() {
+            status = status.combine(ExitStatus::Findings);
+        }
+    } else if !cleaned.is_empty() {
+        let done = fixup.apply(&cleaned)?;
+        if !runtime_config.quiet {
+            println!("{}{}", output::icon("✅").green(), done.green());
+        }
+    } else if !runtime_config.quiet {
+        println!("{}{}", output::icon("✅").green(), "Nothing to clean up".green());
+    }
+    if failed {
+        status = status.combine(ExitStatus::Errors);
+    }
+    Ok(status)
+}
+
+/// Serve the Language Server Protocol on stdin and stdout until the client exits.
+pub fn run_lsp(runtime_config: &RuntimeConfig) -> Result<()> {
+    info!("Starting language server");
+    let server = lsp::LanguageServer::new(&runtime_config.file_config);
+    server.serve(std::io::stdin().lock(), std::io::stdout().lock())
+}
 
+/// Serve check and clean requests for the first target directory until asked to shut down.
+pub fn run_daemon(runtime_config: &RuntimeConfig, socket: Option<&Path>) -> Result<()> {
+    let socket = socket.map_or_else(daemon::default_socket, Path::to_path_buf);
+    let daemon = daemon::Daemon::new(runtime_config)?;
+    if !runtime_config.quiet {
+        eprintln!("Listening on {}", socket.display());
+    }
+    daemon.serve(&socket)
+}
+
+/// Show each decision a run over the first target directory would make about `path`.
+pub fn run_explain(runtime_config: &RuntimeConfig, path: &Path) -> Result<()> {
+    runtime_config.color.apply();
+    let root = runtime_config
+        .directories
+        .first()
+        .map_or(Path::new("."), PathBuf::as_path);
+
+    // Spell the path the way the walk would reach it, below the root as given
+    let absolute = |path: &Path| {
+        std::path::absolute(path)
+            .map(|path| output::normalize_path(&path))
+            .with_context(|| format!("Failed to resolve {}", path.display()))
+    };
+    let walked = match absolute(path)?.strip_prefix(absolute(root)?) {
+        Ok(relative) => root.join(relative),
+        Err(_) => eyre::bail!("{} is not under {}", path.display(), root.display()),
+    };
+
+    let explanation = explain::explain(
+        Arc::new(runtime_config.file_config.clone()),
+        Arc::new(RealFs::default()),
+        root,
+        &walked,
+    )?;
+    output::display_explanation(path, &explanation);
+    Ok(())
+}
+
+/// Scan every target directory without modifying anything and print aggregated metrics.
+pub fn run_stats(runtime_config: &RuntimeConfig, top: usize) -> Result<()> {
+    info!("Collecting stats for: {:?}", runtime_config.directories);
+    runtime_config.color.apply();
+
+    let fs = Arc::new(RealFs::default());
+    let file_config = Arc::new(runtime_config.file_config.clone());
+    let walker = FileWalker::new(Arc::clone(&file_config), Arc::clone(&fs));
+
+    let mut files = Vec::new();
+    for target_dir in &walker::dedupe_targets(&runtime_config.directories, fs.as_ref()) {
+        if !target_dir.is_dir() {
+            eprintln!(
+                "{}{} {}",
+                output::icon("❌").red(),
+                "Not a directory:".red(),
+                target_dir.display().to_string().yellow()
+            );
+            continue;
+        }
+        let collected = walker
+            .collect_files(target_dir, runtime_config.max_depth)
+            .with_context(|| format!("Failed to collect files from {}", target_dir.display()))?;
+        files.extend(collected);
+    }
+
+    let report = stats::collect_stats(&files, file_config, fs);
+    stats::display_stats(&report, top);
+
+    Ok(())
+}
+
+/// Compare the two target trees and list the files that differ, and whether only in whitespace.
+/// The status is [`ExitStatus::Findings`] (exit 1) if anything else differs.
+pub fn run_diff(runtime_config: &RuntimeConfig) -> Result<ExitStatus> {
+    runtime_config.color.apply();
+    let [left, right] = runtime_config.directories.as_slice() else {
+        eyre::bail!("diff compares exactly two directories");
+    };
+    for tree in [left, right] {
+        if !tree.is_dir() {
+            eyre::bail!("Not a directory: {}", tree.display());
+        }
+    }
+    info!("Comparing {} with {}", left.display(), right.display());
+
+    let diff = compare::compare_trees(
+        left,
+        right,
+        runtime_config.max_depth,
+        Arc::new(runtime_config.file_config.clone()),
+        Arc::new(RealFs::default()),
+    )?;
+    if !runtime_config.quiet {
+        compare::display_tree_diff(left, right, &diff);
+    }
+    Ok(diff.status())
+}
+
+/// Benchmark walking and processing a generated tree and print the throughput per thread count.
+pub fn run_bench(runtime_config: &RuntimeConfig, options: &bench::BenchOptions) -> Result<()> {
+    info!("Benchmarking with {:?}", options);
+    runtime_config.color.apply();
+
+    let report = bench::run(options, &runtime_config.file_config.rules)?;
+    bench::display_bench(&report);
+    Ok(())
+}
+
+/// Validate a config file: `path`, or the default config location when None.
+/// Fails if the file has errors; warnings are only printed.
+pub fn run_config_validate(path: Option<&Path>) -> Result<()> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => Config::default_path().ok_or_else(|| eyre::eyre!("No config file found to validate"))?,
+    };
+    info!("Validating config: {}", path.display());
+
+    let diagnostics = validate::validate_file(&path)?;
+    let errors = validate::display_diagnostics(&path, &diagnostics);
+    if errors > 0 {
+        eyre::bail!("{} has {} error(s)", path.display(), errors);
+    }
+    Ok(())
+}
+
+/// Render the man page for the whole CLI.
+pub fn render_man_page() -> Result<Vec<u8>> {
+    let mut page = Vec::new();
+    clap_mangen::Man::new(Cli::command())
+        .render(&mut page)
+        .context("Failed to render man page")?;
+    Ok(page)
+}
+
+/// Print the man page, or write it and one page per subcommand into `out_dir`.
+pub fn run_man(out_dir: Option<&Path>) -> Result<()> {
+    match out_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+            clap_mangen::generate_to(Cli::command(), dir)
+                .with_context(|| format!("Failed to write man pages to {}", dir.display()))?;
+            info!("Wrote man pages to {}", dir.display());
+        }
+        None => {
+            std::io::stdout()
+                .write_all(&render_man_page()?)
+                .context("Failed to write man page")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a fully commented default `whitespace.yml`, to the user config directory or, with
+/// `project`, to the current directory. Refuses to overwrite an existing file unless `force`.
+pub fn run_config_init(project: bool, force: bool) -> Result<()> {
+    let dir = if project {
+        PathBuf::from(".")
+    } else {
+        Config::user_config_dir().ok_or_else(|| eyre::eyre!("Could not determine config directory"))?
+    };
+    let path = dir.join(format!("{}.yml", env!("CARGO_PKG_NAME")));
+
+    if path.exists() && !force {
+        eyre::bail!("{} already exists (use --force to overwrite)", path.display());
+    }
+
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(&path, Config::default().to_commented_yaml()?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    info!("Wrote default config to {}", path.display());
+    println!("{}{}", output::icon("📝"), format!("Wrote {}", path.display()).green());
+    if project {
+        println!("Use it with: whitespace --config {}", path.display());
+    }
+    Ok(())
+}
+
+/// Print the JSON Schema for config files.
+pub fn run_config_schema() -> Result<()> {
+    let schema = serde_json::to_string_pretty(&Config::json_schema()).context("Failed to serialize schema")?;
+    writeln!(std::io::stdout(), "{}", schema).context("Failed to write schema")
+}
+
+/// Install a git hook for the repository containing the current directory.
+pub fn run_install_hook(hook: hooks::HookType, force: bool) -> Result<()> {
+    let path = hooks::install(&hooks::hooks_dir(Path::new("."))?, hook, force)?;
+    info!("Installed {} hook at {}", hook.file_name(), path.display());
+    println!(
+        "{}{}",
+        output::icon("🪝"),
+        format!("Installed {}", path.display()).green()
+    );
+    Ok(())
+}
+
+/// Remove a git hook installed by [`run_install_hook`].
+pub fn run_uninstall_hook(hook: hooks::HookType) -> Result<()> {
+    let path = hooks::uninstall(&hooks::hooks_dir(Path::new("."))?, hook)?;
+    info!("Removed {} hook at {}", hook.file_name(), path.display());
+    println!(
+        "{}{}",
+        output::icon("🗑️"),
+        format!("Removed {}", path.display()).green()
+    );
+    Ok(())
+}
+
+/// Print the effective file configuration, including CLI overrides, as YAML.
+pub fn run_config_show(runtime_config: &RuntimeConfig) -> Result<()> {
+    let yaml = serde_yaml::to_string(&runtime_config.file_config).context("Failed to serialize configuration")?;
+    print!("{}", yaml);
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::hash_content;
 
     #[test]
     fn test_format_line_numbers_empty() {
@@ -220,6 +1504,279 @@ mod tests {
         assert_eq!(format_line_numbers(&[1, 2, 3, 5, 7, 8, 9]), " (1-3,5,7-9)");
     }
 
+    #[test]
+    fn test_run_totals_line() {
+        assert_eq!(
+            run_totals_line(1, Some(1), 0, Duration::from_micros(1_250)),
+            "Scanned 1 file in 1 directory (1.2ms)"
+        );
+        assert_eq!(
+            run_totals_line(12, Some(2), 3, Duration::from_millis(40)),
+            "Scanned 12 files in 2 directories, 3 failed (40.0ms)"
+        );
+        assert_eq!(
+            run_totals_line(3, None, 0, Duration::from_millis(2)),
+            "Scanned 3 listed files (2.0ms)"
+        );
+    }
+
+    #[test]
+    fn test_generated_line() {
+        assert_eq!(generated_line(0, 0), None);
+        assert_eq!(
+            generated_line(3, 1).as_deref(),
+            Some("Left alone: 3 generated, 1 vendored (--include-generated cleans them)")
+        );
+        assert_eq!(
+            generated_line(0, 2).as_deref(),
+            Some("Left alone: 2 vendored (--include-generated cleans them)")
+        );
+    }
+
+    #[test]
+    fn test_dry_run_impact() {
+        assert_eq!(dry_run_impact(1, 1), "1 line affected, 1 byte would be removed");
+        assert_eq!(dry_run_impact(12, 48), "12 lines affected, 48 bytes would be removed");
+    }
+
+    fn run_dry(directories: &[&Path]) -> ExitStatus {
+        let runtime_config = RuntimeConfig {
+            directories: directories.iter().map(|d| d.to_path_buf()).collect(),
+            dry_run: true,
+            quiet: true,
+            use_cache: false,
+            ..RuntimeConfig::builder().build().unwrap()
+        };
+        run(&runtime_config).unwrap()
+    }
+
+    #[test]
+    fn test_run_exit_status() {
+        let clean = tempfile::TempDir::new().unwrap();
+        std::fs::write(clean.path().join("clean.txt"), "clean\n").unwrap();
+        let dirty = tempfile::TempDir::new().unwrap();
+        std::fs::write(dirty.path().join("dirty.txt"), "dirty  \n").unwrap();
+        let missing = clean.path().join("missing");
+
+        assert_eq!(run_dry(&[clean.path()]), ExitStatus::Success);
+        assert_eq!(run_dry(&[clean.path(), dirty.path()]), ExitStatus::Findings);
+        assert_eq!(run_dry(&[dirty.path(), &missing]), ExitStatus::Errors);
+        assert_eq!(run_dry(&[&missing]), ExitStatus::Errors);
+    }
+
+    #[test]
+    fn test_run_files_from() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let listed = temp_dir.path().join("listed file.txt");
+        let unlisted = temp_dir.path().join("unlisted.txt");
+        std::fs::write(&listed, "dirty  \n").unwrap();
+        std::fs::write(&unlisted, "dirty  \n").unwrap();
+        let list = temp_dir.path().join("files.lst");
+        std::fs::write(&list, format!("{}\0", listed.display())).unwrap();
+        let runtime_config = RuntimeConfig::builder()
+            .files_from(&list)
+            .null_delimited(true)
+            .quiet(true)
+            .use_cache(false)
+            .build()
+            .unwrap();
+
+        assert_eq!(run(&runtime_config).unwrap(), ExitStatus::Success);
+        assert_eq!(std::fs::read_to_string(&listed).unwrap(), "dirty\n");
+        assert_eq!(std::fs::read_to_string(&unlisted).unwrap(), "dirty  \n");
+
+        // Listed paths that aren't files are errors
+        std::fs::write(&list, format!("{}\n", temp_dir.path().display())).unwrap();
+        let runtime_config = RuntimeConfig {
+            null_delimited: false,
+            ..runtime_config
+        };
+        assert_eq!(run(&runtime_config).unwrap(), ExitStatus::Errors);
+    }
+
+    #[test]
+    fn test_verify_results() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let clean = temp_dir.path().join("clean.txt");
+        let dirty = temp_dir.path().join("dirty.txt");
+        std::fs::write(&clean, "clean\n").unwrap();
+        std::fs::write(&dirty, "ok\ndirty  \n").unwrap();
+
+        // As if a rule left the second file dirty after rewriting it
+        let mut file_results = vec![
+            (clean.clone(), ProcessingResult::from_lines(vec![1], 2)),
+            (dirty.clone(), ProcessingResult::from_lines(vec![2], 2)),
+        ];
+        let engine = ParallelEngine::new(Arc::new(Config::default()), Arc::new(RealFs::default()), 1).unwrap();
+        verify_results(&engine, &mut file_results);
+
+        assert!(file_results[0].1.had_changes());
+        let error = file_results[1].1.error().unwrap().to_string();
+        assert_eq!(error, "Verification failed: a second pass would still change it (2)");
+        assert_eq!(std::fs::read_to_string(&dirty).unwrap(), "ok\ndirty  \n");
+
+        // A rewrite records the file as clean, which mustn't let the second pass skip it
+        let config = Config::default();
+        let cache = Arc::new(Cache::load_from(temp_dir.path(), &temp_dir.path().join("cache.json"), &config).unwrap());
+        let content = std::fs::read(&dirty).unwrap();
+        cache.record_clean(
+            &dirty,
+            &RealFs::default().metadata(&dirty).unwrap(),
+            hash_content(&content),
+        );
+        let engine = ParallelEngine::new(Arc::new(config), Arc::new(RealFs::default()), 1)
+            .unwrap()
+            .with_cache(cache);
+        let mut file_results = vec![(dirty.clone(), ProcessingResult::from_lines(vec![2], 2))];
+        verify_results(&engine, &mut file_results);
+        assert!(file_results[0].1.is_failure());
+    }
+
+    #[test]
+    fn test_run_max_files() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            std::fs::write(temp_dir.path().join(name), "dirty  \n").unwrap();
+        }
+        let runtime_config = RuntimeConfig::builder()
+            .directories([temp_dir.path()])
+            .max_files(Some(2))
+            .quiet(true)
+            .use_cache(false)
+            .use_journal(false)
+            .build()
+            .unwrap();
+
+        // Stopping early leaves the run incomplete, which is an error
+        assert_eq!(run(&runtime_config).unwrap(), ExitStatus::Errors);
+        let cleaned = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .filter(|name| std::fs::read_to_string(temp_dir.path().join(name)).unwrap() == "dirty\n")
+            .count();
+        assert_eq!(cleaned, 2);
+
+        // Files beyond the budget are reported as skipped
+        let run = RunState {
+            max_errors: None,
+            max_files: Some(1),
+            deadline: None,
+            timings: None,
+            journal: None,
+        };
+        let (summary, results) = process_files_in_run(
+            Source::Walk(temp_dir.path()),
+            vec![temp_dir.path().join("a.txt"), temp_dir.path().join("c.txt")],
+            Vec::new(),
+            None,
+            &runtime_config,
+            Arc::new(RealFs::default()),
+            &run,
+        )
+        .unwrap();
+        assert!(summary.limit_reached);
+        assert_eq!(summary.files_scanned, 1);
+        assert_eq!(results[1].1.skip_reason(), Some(SkipReason::Limit));
+    }
+
+    #[test]
+    fn test_run_verify_failures_use_up_max_errors() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let targets = [temp_dir.path().join("one"), temp_dir.path().join("two")];
+        for target in &targets {
+            std::fs::create_dir(target).unwrap();
+            for name in ["a.txt", "b.txt"] {
+                std::fs::write(target.join(name), "a\n").unwrap();
+            }
+        }
+        // A replacement that never settles, so every rewritten file fails verification
+        let config: Config =
+            serde_yaml::from_str("rules:\n  replacements:\n    - pattern: a\n      replace: aa\n").unwrap();
+        let runtime_config = RuntimeConfig::builder()
+            .directories(targets.clone())
+            .config(config)
+            .verify(true)
+            .max_errors(Some(1))
+            .quiet(true)
+            .use_cache(false)
+            .use_journal(false)
+            .use_lock(false)
+            .build()
+            .unwrap();
+
+        assert_eq!(run(&runtime_config).unwrap(), ExitStatus::Errors);
+        assert_eq!(std::fs::read_to_string(targets[0].join("a.txt")).unwrap(), "aa\n");
+        // The first target used up the budget, so the second was never started
+        for name in ["a.txt", "b.txt"] {
+            assert_eq!(std::fs::read_to_string(targets[1].join(name)).unwrap(), "a\n");
+        }
+    }
+
+    #[test]
+    fn test_run_pre_commit() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dirty = temp_dir.path().join("dirty.txt");
+        let clean = temp_dir.path().join("clean.txt");
+        let untouched = temp_dir.path().join("not-passed.txt");
+        std::fs::write(&dirty, "dirty  \n").unwrap();
+        std::fs::write(&clean, "clean\n").unwrap();
+        std::fs::write(&untouched, "untouched  \n").unwrap();
+        let runtime_config = RuntimeConfig {
+            quiet: true,
+            ..RuntimeConfig::builder().build().unwrap()
+        };
+
+        let files = [dirty.clone(), clean.clone()];
+        assert_eq!(run_pre_commit(&runtime_config, &files).unwrap(), ExitStatus::Findings);
+        assert_eq!(std::fs::read_to_string(&dirty).unwrap(), "dirty\n");
+        assert_eq!(std::fs::read_to_string(&untouched).unwrap(), "untouched  \n");
+
+        assert_eq!(run_pre_commit(&runtime_config, &files).unwrap(), ExitStatus::Success);
+        assert_eq!(run_pre_commit(&runtime_config, &[]).unwrap(), ExitStatus::Success);
+        let directory = [temp_dir.path().to_path_buf()];
+        assert_eq!(run_pre_commit(&runtime_config, &directory).unwrap(), ExitStatus::Errors);
+    }
+
+    #[test]
+    fn test_process_directory_with_memfs() {
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/dirty.txt", b"dirty  \n")
+                .with_file("/mem/src/also_dirty.rs", b"fn main() {}\t\n")
+                .with_file("/mem/clean.txt", b"clean\n")
+                .with_file("/mem/target/out.txt", b"excluded  \n"),
+        );
+        let runtime_config = RuntimeConfig {
+            quiet: true,
+            use_cache: false,
+            ..RuntimeConfig::builder().build().unwrap()
+        };
+
+        let summary = process_directory(Path::new("/mem"), &runtime_config, Arc::clone(&memfs)).unwrap();
+
+        assert_eq!(summary.files_with_changes, 2);
+        assert_eq!(summary.lines_with_changes, 2);
+        assert_eq!(summary.bytes_removed, 3);
+        assert_eq!(summary.files_modified, 2);
+        assert_eq!(memfs.get_content(Path::new("/mem/dirty.txt")).unwrap(), b"dirty\n");
+        assert_eq!(
+            memfs.get_content(Path::new("/mem/src/also_dirty.rs")).unwrap(),
+            b"fn main() {}\n"
+        );
+        assert_eq!(
+            memfs.get_content(Path::new("/mem/target/out.txt")).unwrap(),
+            b"excluded  \n"
+        );
+        assert!(!Path::new("/mem").exists());
+    }
+
+    #[test]
+    fn test_render_man_page() {
+        let page = String::from_utf8(render_man_page().unwrap()).unwrap();
+        assert!(page.contains(".TH whitespace"));
+        assert!(page.contains("dry\\-run"));
+        assert!(!page.contains("\\fBman\\fR"));
+    }
+
     #[test]
     fn test_format_line_numbers_separate() {
         assert_eq!(format_line_numbers(&[1, 3, 5]), " (1,3,5)");
@@ -0,0 +1,378 @@
+/// Languages whose multi-line string literals can be followed, so that trailing whitespace
+/// inside them, which is part of the string's value, is left alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// `"..."` and raw `r#"..."#` strings, which may span lines
+    Rust,
+    /// Triple-quoted `"""..."""` and `'''...'''` strings
+    Python,
+    /// Block scalars (`key: |`, `- >-`), YAML's heredocs
+    Yaml,
+    /// Quoted `"..."` fields of comma-separated values, which may span lines
+    Csv,
+    /// Quoted fields of tab-separated values, where a trailing tab ends an empty last field
+    Tsv,
+}
+
+impl Syntax {
+    /// The syntax of a file of type `file_type`, as [`FileTypes`](crate::filetypes::FileTypes)
+    /// recognizes it.
+    pub fn for_type(file_type: &str) -> Option<Self> {
+        match file_type {
+            "rust" => Some(Self::Rust),
+            "python" => Some(Self::Python),
+            "yaml" => Some(Self::Yaml),
+            "csv" => Some(Self::Csv),
+            "tsv" => Some(Self::Tsv),
+            _ => None,
+        }
+    }
+
+    /// True for data files, where trailing whitespace in a quoted field is a value rather than
+    /// code, so keeping it is worth a warning.
+    pub fn is_tabular(self) -> bool {
+        matches!(self, Self::Csv | Self::Tsv)
+    }
+}
+
+/// Where a line starts and ends relative to the string literals around it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineSpan {
+    /// The line begins inside a literal opened on an earlier line, so its indentation is content
+    pub starts_inside: bool,
+    /// The line ends inside a literal, so its trailing whitespace is content
+    pub ends_inside: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Code,
+    /// A Rust block comment, nested this deep
+    BlockComment(usize),
+    /// A string delimited by this quote that continues onto the next line
+    Quoted(u8),
+    /// A Rust raw string closed by `"` and this many `#`
+    RawString(usize),
+    /// A Python string closed by three of this quote
+    TripleQuoted(u8),
+    /// A YAML block scalar whose content lines are indented at least this much
+    BlockScalar(usize),
+}
+
+/// A lightweight tokenizer that follows a file's string literals line by line. It knows only
+/// enough of each language to find where literals begin and end: comments, quotes, escapes and,
+/// for Rust, character literals and lifetimes.
+#[derive(Debug)]
+pub struct LiteralScanner {
+    syntax: Syntax,
+    state: State,
+}
+
+impl LiteralScanner {
+    pub fn new(syntax: Syntax) -> Self {
+        Self {
+            syntax,
+            state: State::Code,
+        }
+    }
+
+    /// Scan the next line (without its line ending).
+    pub fn scan(&mut self, line: &str) -> LineSpan {
+        let starts_inside = self.in_literal();
+        match self.syntax {
+            Syntax::Rust => self.scan_rust(line.as_bytes()),
+            Syntax::Python => self.scan_python(line.as_bytes()),
+            Syntax::Csv => self.scan_fields(line.as_bytes(), b','),
+            Syntax::Tsv => self.scan_fields(line.as_bytes(), b'\t'),
+            // Whether a line belongs to a block scalar depends on its own indentation
+            Syntax::Yaml => return self.scan_yaml(line),
+        }
+        LineSpan {
+            starts_inside,
+            ends_inside: self.in_literal(),
+        }
+    }
+
+    fn in_literal(&self) -> bool {
+        matches!(
+            self.state,
+            State::Quoted(_) | State::RawString(_) | State::TripleQuoted(_) | State::BlockScalar(_)
+        )
+    }
+
+    fn scan_rust(&mut self, line: &[u8]) {
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            match self.state {
+                State::BlockComment(depth) if rest.starts_with(b"*/") => {
+                    self.state = if depth == 1 { State::Code } else { State::BlockComment(depth - 1) };
+                    i += 2;
+                }
+                State::BlockComment(depth) if rest.starts_with(b"/*") => {
+                    self.state = State::BlockComment(depth + 1);
+                    i += 2;
+                }
+                State::Quoted(_) if rest[0] == b'\\' => i += 2,
+                State::Quoted(quote) if rest[0] == quote => {
+                    self.state = State::Code;
+                    i += 1;
+                }
+                State::RawString(hashes) if rest[0] == b'"' && closes_raw(&rest[1..], hashes) => {
+                    self.state = State::Code;
+                    i += 1 + hashes;
+                }
+                State::Code if rest.starts_with(b"//") => return,
+                State::Code if rest.starts_with(b"/*") => {
+                    self.state = State::BlockComment(1);
+                    i += 2;
+                }
+                State::Code if rest[0] == b'"' => {
+                    self.state = State::Quoted(b'"');
+                    i += 1;
+                }
+                State::Code if rest[0] == b'\'' => i += char_literal_len(rest).unwrap_or(1),
+                State::Code if is_ident_start(rest[0]) && (i == 0 || !is_ident(line[i - 1])) => {
+                    let word = rest.iter().take_while(|&&b| is_ident(b)).count();
+                    i += word;
+                    // `r"..."`, `br#"..."#` and `cr"..."` are raw; `r#ident` is not
+                    if matches!(&rest[..word], b"r" | b"br" | b"cr") {
+                        let hashes = line[i..].iter().take_while(|&&b| b == b'#').count();
+                        if line.get(i + hashes) == Some(&b'"') {
+                            self.state = State::RawString(hashes);
+                            i += hashes + 1;
+                        }
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    fn scan_python(&mut self, line: &[u8]) {
+        let mut i = 0;
+        while i < line.len() {
+            let rest = &line[i..];
+            match self.state {
+                State::Quoted(_) | State::TripleQuoted(_) if rest[0] == b'\\' => i += 2,
+                State::Quoted(quote) if rest[0] == quote => {
+                    self.state = State::Code;
+                    i += 1;
+                }
+                State::TripleQuoted(quote) if rest.starts_with(&[quote; 3]) => {
+                    self.state = State::Code;
+                    i += 3;
+                }
+                State::Code if rest[0] == b'#' => return,
+                State::Code if rest[0] == b'"' || rest[0] == b'\'' => {
+                    if rest.starts_with(&[rest[0]; 3]) {
+                        self.state = State::TripleQuoted(rest[0]);
+                        i += 3;
+                    } else {
+                        self.state = State::Quoted(rest[0]);
+                        i += 1;
+                    }
+                }
+                _ => i += 1,
+            }
+        }
+
+        // A single-quoted string only continues past a backslash that escapes the line ending
+        if matches!(self.state, State::Quoted(_)) && i == line.len() {
+            self.state = State::Code;
+        }
+    }
+
+    /// A quote opens a field only at its start, optionally after spaces; inside, `""` is an
+    /// escaped quote and a lone `"` closes the field.
+    fn scan_fields(&mut self, line: &[u8], delimiter: u8) {
+        let mut field_start = true;
+        let mut i = 0;
+        while i < line.len() {
+            match self.state {
+                State::Quoted(_) if line[i] == b'"' && line.get(i + 1) == Some(&b'"') => i += 2,
+                State::Quoted(_) if line[i] == b'"' => {
+                    self.state = State::Code;
+                    field_start = false;
+                    i += 1;
+                }
+                State::Code if line[i] == b'"' && field_start => {
+                    self.state = State::Quoted(b'"');
+                    i += 1;
+                }
+                State::Code => {
+                    if line[i] == delimiter {
+                        field_start = true;
+                    } else if line[i] != b' ' {
+                        field_start = false;
+                    }
+                    i += 1;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    /// Block scalar content is every following line that is blank or indented past the key
+    /// or `-` that introduced it. Quoted scalars fold away their trailing whitespace, so
+    /// trimming inside them is harmless and they aren't tracked.
+    fn scan_yaml(&mut self, line: &str) -> LineSpan {
+        let indent = line.len() - line.trim_start_matches(' ').len();
+        if let State::BlockScalar(content_indent) = self.state {
+            if line.trim().is_empty() || indent >= content_indent {
+                return LineSpan {
+                    starts_inside: true,
+                    ends_inside: true,
+                };
+            }
+            self.state = State::Code;
+        }
+
+        if let Some(content_indent) = block_scalar_indent(line) {
+            self.state = State::BlockScalar(content_indent);
+        }
+        LineSpan::default()
+    }
+}
+
+/// True if `rest` starts with the `hashes` `#` that close a raw string after its `"`.
+fn closes_raw(rest: &[u8], hashes: usize) -> bool {
+    rest.len() >= hashes && rest[..hashes].iter().all(|&b| b == b'#')
+}
+
+/// The length of the Rust character literal at the start of `rest`, or None for a lifetime.
+fn char_literal_len(rest: &[u8]) -> Option<usize> {
+    if rest.get(1) == Some(&b'\\') {
+        // Escapes such as '\'' and '\u{1F600}' end at the next quote after the escaped character
+        let close = rest.iter().skip(3).position(|&b| b == b'\'')?;
+        return Some(close + 4);
+    }
+    let char_len = match *rest.get(1)? {
+        b if b < 0x80 => 1,
+        b if b >= 0xF0 => 4,
+        b if b >= 0xE0 => 3,
+        _ => 2,
+    };
+    (rest.get(1 + char_len) == Some(&b'\'')).then_some(char_len + 2)
+}
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// If `line` opens a YAML block scalar, the least indentation its content lines have: one
+/// more than the column of the key (`key: |`) or sequence dash (`- |`) that owns it.
+fn block_scalar_indent(line: &str) -> Option<usize> {
+    if line.trim_start().starts_with('#') {
+        return None;
+    }
+    let code = line.split(" #").next().unwrap_or(line).trim_end();
+    let (head, indicator) = code.rsplit_once(' ')?;
+    let mut modifiers = indicator.bytes();
+    if !matches!(modifiers.next(), Some(b'|' | b'>'))
+        || indicator.len() > 3
+        || !modifiers.all(|b| b == b'-' || b == b'+' || b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    // Tags and anchors may sit between the key and the indicator
+    let owner = head.split_whitespace().rfind(|token| !token.starts_with(['!', '&']))?;
+
+    // Step past any `- ` sequence entries to find where the key starts
+    let mut column = line.len() - line.trim_start_matches(' ').len();
+    let mut dash = None;
+    while let Some(rest) = line[column..].strip_prefix("- ") {
+        dash = Some(column);
+        column = line.len() - rest.trim_start_matches(' ').len();
+    }
+
+    if owner.ends_with(':') {
+        Some(column + 1)
+    } else if owner == "-" {
+        dash.map(|dash| dash + 1)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filetypes::FileTypes;
+    use std::path::Path;
+
+    /// Whether each line ends inside a literal.
+    fn ends_inside(syntax: Syntax, source: &str) -> Vec<bool> {
+        let mut scanner = LiteralScanner::new(syntax);
+        source.lines().map(|line| scanner.scan(line).ends_inside).collect()
+    }
+
+    #[test]
+    fn test_rust_literals() {
+        let source = "let a = \"one  \nt\\\"wo  \n\";  \n\
+                      let b = r#\"raw \" still  \n\"#;\n\
+                      let c = '\"'; // \"  \n\
+                      fn f<'a>(x: &'a str) -> char { '\\'' }\n\
+                      /* \"  \n */ let d = b\"  \nx\";\n\
+                      let r#type = 1;  ";
+        assert_eq!(
+            ends_inside(Syntax::Rust, source),
+            vec![true, true, false, true, false, false, false, false, true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_python_literals() {
+        let source = "doc = \"\"\"first  \nsecond \\\"\"\"  \n\"\"\"\nx = 'it''s'  # '''  \n\
+                      y = r'''a\\'''  \nb'''  \ns = 'unterminated  \nz = 1  ";
+        assert_eq!(
+            ends_inside(Syntax::Python, source),
+            vec![true, true, false, false, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_yaml_block_scalars() {
+        let source = "script: |  \n  echo one  \n\n  echo two\nname: x  \n\
+                      steps:\n  - run: >-\n      long  \n  - name: y\n  - |\n    text  \n\
+                      url: \"a |\"  \nnote: x # |\n# key: |\n  not: content  ";
+        let mut scanner = LiteralScanner::new(Syntax::Yaml);
+        let spans: Vec<bool> = source.lines().map(|line| scanner.scan(line).starts_inside).collect();
+        assert_eq!(
+            spans,
+            vec![
+                false, true, true, true, false, false, false, true, false, false, true, false, false, false, false
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quoted_fields() {
+        let source = "id,note  \n1,\"two  \nlines  \",x  \n2, \"a \"\"quoted\"\"  \n\"\n3,say \"hi  \n4,\"\"  ";
+        assert_eq!(
+            ends_inside(Syntax::Csv, source),
+            vec![false, true, false, true, false, false, false]
+        );
+        assert_eq!(
+            ends_inside(Syntax::Tsv, "a\t\"b  \nc\"\t\nd,\"e  "),
+            vec![true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_syntax_for_type() {
+        let file_types = FileTypes::default();
+        let syntax = |path: &str| file_types.by_name(Path::new(path)).and_then(Syntax::for_type);
+        assert_eq!(syntax("src/main.rs"), Some(Syntax::Rust));
+        assert_eq!(syntax("setup.PY"), Some(Syntax::Python));
+        assert_eq!(syntax(".github/ci.yml"), Some(Syntax::Yaml));
+        assert_eq!(syntax("data/people.csv"), Some(Syntax::Csv));
+        assert_eq!(syntax("data/people.tsv"), Some(Syntax::Tsv));
+        assert_eq!(syntax("Makefile"), None);
+    }
+}
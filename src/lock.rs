@@ -0,0 +1,107 @@
+use crate::cache::ContentHasher;
+use eyre::{Context, Result};
+use std::fs::{self, File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// An advisory lock on a target root, held until it is dropped, so that runs which would
+/// rewrite the same files (a pre-commit hook and the daemon, say) take turns instead of racing.
+///
+/// The lock is an OS file lock on a file outside the tree, keyed by the root's canonical path.
+/// It is released when the process exits, however it exits, so a crashed run never leaves a
+/// stale lock behind.
+#[derive(Debug)]
+pub struct RootLock {
+    _file: File,
+}
+
+impl RootLock {
+    /// Lock `root` in the default lock directory, calling `on_wait` and then blocking if
+    /// another run holds it.
+    pub fn acquire(root: &Path, on_wait: impl FnOnce()) -> Result<Self> {
+        Self::acquire_in(&default_lock_dir(), root, on_wait)
+    }
+
+    /// Lock `root` using a lock file in `lock_dir`.
+    pub fn acquire_in(lock_dir: &Path, root: &Path, on_wait: impl FnOnce()) -> Result<Self> {
+        fs::create_dir_all(lock_dir).with_context(|| format!("Failed to create {}", lock_dir.display()))?;
+        let path = lock_path(lock_dir, root);
+        let mut file = File::options()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open lock file {}", path.display()))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                on_wait();
+                file.lock()
+                    .with_context(|| format!("Failed to lock {}", path.display()))?;
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()));
+            }
+        }
+        debug!("Locked {} with {}", root.display(), path.display());
+
+        // The holder's pid, for anyone wondering what a waiting run is waiting on
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { _file: file })
+    }
+}
+
+/// Where lock files live: the user's runtime directory, falling back to the temp directory.
+pub fn default_lock_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("whitespace")
+        .join("locks")
+}
+
+fn lock_path(lock_dir: &Path, root: &Path) -> PathBuf {
+    let canonical_root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+    let mut hasher = ContentHasher::new();
+    hasher.update(canonical_root.to_string_lossy().as_bytes());
+    lock_dir.join(format!("{:016x}.lock", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_second_lock_waits_for_the_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let locks = temp_dir.path().join("locks");
+        let root = temp_dir.path().join("tree");
+        fs::create_dir(&root).unwrap();
+
+        let first = RootLock::acquire_in(&locks, &root, || panic!("nothing holds the lock yet")).unwrap();
+        let (waiting, waited) = mpsc::channel();
+        let (locked, acquired) = mpsc::channel();
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = RootLock::acquire_in(&locks, &root, || waiting.send(()).unwrap()).unwrap();
+                locked.send(()).unwrap();
+            });
+
+            waited.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert!(acquired.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(first);
+            acquired.recv_timeout(Duration::from_secs(5)).unwrap();
+        });
+
+        // Other roots, including the same root spelled differently, are keyed by canonical path
+        let other = temp_dir.path().join("other");
+        fs::create_dir(&other).unwrap();
+        let _root = RootLock::acquire_in(&locks, &root.join("."), || panic!("the lock was released")).unwrap();
+        let _other = RootLock::acquire_in(&locks, &other, || panic!("a different root")).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use crate::cli::Cli;
+use crate::output::ColorChoice;
+use clap::ValueEnum;
+use eyre::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::{EnvFilter, Targets};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{Layer, Registry};
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors and observability tooling
+    Json,
+}
+
+/// When the log file is rotated and how many rotated files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRotation {
+    /// Rotate once the log file would grow past this many bytes (0 = never)
+    pub max_size: u64,
+    /// Rotated files kept besides the current one, `whitespace.log.1` being the newest
+    pub keep: usize,
+}
+
+impl Default for LogRotation {
+    /// Keep 5 files of 10MB.
+    fn default() -> Self {
+        Self {
+            max_size: 10 << 20,
+            keep: 5,
+        }
+    }
+}
+
+/// Where log records go and how much of them reaches the console.
+///
+/// The binary builds this from `-v`/`-q`/`--log-file`/`--no-log-file`/`--trace-format` and
+/// the `--log-max-size`/`--log-keep` rotation flags.
+/// Library users can pass [`LoggingConfig::off`] (or simply never call [`init`]) to keep the
+/// crate silent, or install a `tracing` subscriber of their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggingConfig {
+    /// Maximum level echoed to stderr
+    pub console_level: LevelFilter,
+    /// Log file to append to (None = no file logging); its level comes from `RUST_LOG`
+    pub log_file: Option<PathBuf>,
+    /// When the log file is rotated
+    pub rotation: LogRotation,
+    /// When to color console log lines
+    pub color: ColorChoice,
+    /// How log lines are written, to the console and the log file alike
+    pub format: TraceFormat,
+}
+
+impl LoggingConfig {
+    /// No console output and no log file.
+    pub fn off() -> Self {
+        Self {
+            console_level: LevelFilter::OFF,
+            log_file: None,
+            rotation: LogRotation::default(),
+            color: ColorChoice::Auto,
+            format: TraceFormat::Text,
+        }
+    }
+
+    /// Map the verbosity and log file flags: warnings by default, `-v` info, `-vv` debug,
+    /// `-vvv` trace and `-q` errors only.
+    pub fn from_cli(cli: &Cli) -> Self {
+        let console_level = if cli.quiet {
+            LevelFilter::ERROR
+        } else {
+            match cli.verbose {
+                0 => LevelFilter::WARN,
+                1 => LevelFilter::INFO,
+                2 => LevelFilter::DEBUG,
+                _ => LevelFilter::TRACE,
+            }
+        };
+
+        let log_file = if cli.no_log_file {
+            None
+        } else {
+            Some(cli.log_file.clone().unwrap_or_else(default_log_file))
+        };
+
+        Self {
+            console_level,
+            log_file,
+            rotation: LogRotation {
+                max_size: cli.log_max_size,
+                keep: cli.log_keep,
+            },
+            color: cli.color,
+            format: cli.trace_format,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            console_level: LevelFilter::WARN,
+            log_file: Some(default_log_file()),
+            rotation: LogRotation::default(),
+            color: ColorChoice::Auto,
+            format: TraceFormat::Text,
+        }
+    }
+}
+
+/// `~/.local/share/whitespace/logs/whitespace.log`, or `./whitespace.log` without a data dir.
+pub fn default_log_file() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("whitespace")
+        .join("logs")
+        .join("whitespace.log")
+}
+
+/// Install the global subscriber described by `config`. Does nothing if both outputs are
+/// disabled.
+///
+/// The library itself only emits `tracing` spans and events: a span per directory and per
+/// file, whose close is logged with its `time.busy` and `time.idle`. Library users who want
+/// them install their own subscriber instead of calling this. Records from dependencies that
+/// use the `log` crate are forwarded to the subscriber too.
+pub fn init(config: &LoggingConfig) -> Result<()> {
+    let Some(layers) = build_layers(config)? else {
+        return Ok(());
+    };
+
+    tracing_subscriber::registry()
+        .with(layers)
+        .try_init()
+        .context("Failed to install logger")?;
+
+    if let Some(log_file) = &config.log_file {
+        info!("Logging initialized, writing to: {}", log_file.display());
+    }
+    Ok(())
+}
+
+type BoxedLayer = Box<dyn Layer<Registry> + Send + Sync>;
+
+fn build_layers(config: &LoggingConfig) -> Result<Option<Vec<BoxedLayer>>> {
+    let mut layers = Vec::new();
+
+    if let Some(log_file) = &config.log_file {
+        if let Some(log_dir) = log_file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(log_dir).context("Failed to create log directory")?;
+        }
+
+        let target = Arc::new(
+            RotatingFile::open(log_file, config.rotation)
+                .with_context(|| format!("Failed to open log file {}", log_file.display()))?,
+        );
+
+        // Check for RUST_LOG environment variable, default to INFO
+        let filter = EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy();
+
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(target)
+            .with_ansi(false)
+            .with_span_events(FmtSpan::CLOSE);
+        layers.push(match config.format {
+            TraceFormat::Text => layer.with_filter(filter).boxed(),
+            TraceFormat::Json => layer.json().with_filter(filter).boxed(),
+        });
+    }
+
+    if config.console_level != LevelFilter::OFF {
+        // Dependencies such as globset are chatty at debug level; only raise our own verbosity
+        let filter = Targets::new()
+            .with_default(config.console_level.min(LevelFilter::WARN))
+            .with_target(env!("CARGO_CRATE_NAME"), config.console_level);
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_ansi(config.color.styles_stderr())
+            .without_time()
+            .with_target(false);
+        layers.push(match config.format {
+            TraceFormat::Text => layer.with_filter(filter).boxed(),
+            TraceFormat::Json => layer.json().with_filter(filter).boxed(),
+        });
+    }
+
+    if layers.is_empty() { Ok(None) } else { Ok(Some(layers)) }
+}
+
+/// A log file that, once it would grow past its maximum size, is renamed to `<name>.1`, the
+/// older rotated files each moving up a number and the one past `keep` being deleted.
+struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    /// The open file and its size so far
+    current: Mutex<(File, u64)>,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, rotation: LogRotation) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            current: Mutex::new((file, size)),
+        })
+    }
+
+    /// `<name>.<n>`
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> io::Result<File> {
+        // Another run sharing the log may have rotated it already, so missing files are fine
+        let ignore_missing = |result: io::Result<()>| match result {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        };
+        if self.rotation.keep == 0 {
+            ignore_missing(fs::remove_file(&self.path))?;
+        } else {
+            // Renaming onto an existing file fails on Windows, so the oldest goes first
+            ignore_missing(fs::remove_file(self.rotated(self.rotation.keep)))?;
+            for n in (1..self.rotation.keep).rev() {
+                ignore_missing(fs::rename(self.rotated(n), self.rotated(n + 1)))?;
+            }
+            ignore_missing(fs::rename(&self.path, self.rotated(1)))?;
+        }
+        open_append(&self.path)
+    }
+}
+
+impl Write for &RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap_or_else(|e| e.into_inner());
+        let (file, size) = &mut *current;
+        if self.rotation.max_size > 0 && *size > 0 && *size + buf.len() as u64 > self.rotation.max_size {
+            *file = self.rotate()?;
+            *size = 0;
+        }
+        let written = file.write(buf)?;
+        *size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.lock().unwrap_or_else(|e| e.into_inner()).0.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_from_cli_verbosity() {
+        let level = |args: &[&str]| {
+            let cli = Cli::try_parse_from(std::iter::once("whitespace").chain(args.iter().copied())).unwrap();
+            LoggingConfig::from_cli(&cli).console_level
+        };
+
+        assert_eq!(level(&[]), LevelFilter::WARN);
+        assert_eq!(level(&["-v"]), LevelFilter::INFO);
+        assert_eq!(level(&["-vv"]), LevelFilter::DEBUG);
+        assert_eq!(level(&["-vvvv"]), LevelFilter::TRACE);
+        assert_eq!(level(&["-q"]), LevelFilter::ERROR);
+        assert!(Cli::try_parse_from(["whitespace", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_from_cli_log_file() {
+        let cli = Cli::try_parse_from(["whitespace", "--log-file", "/tmp/ws.log"]).unwrap();
+        assert_eq!(
+            LoggingConfig::from_cli(&cli).log_file,
+            Some(PathBuf::from("/tmp/ws.log"))
+        );
+
+        let cli = Cli::try_parse_from(["whitespace", "--no-log-file"]).unwrap();
+        assert_eq!(LoggingConfig::from_cli(&cli).log_file, None);
+        let cli = Cli::try_parse_from(["whitespace", "--no-log"]).unwrap();
+        assert_eq!(LoggingConfig::from_cli(&cli).log_file, None);
+
+        let cli = Cli::try_parse_from(["whitespace"]).unwrap();
+        assert_eq!(LoggingConfig::from_cli(&cli).log_file, Some(default_log_file()));
+        assert_eq!(LoggingConfig::from_cli(&cli).rotation, LogRotation::default());
+
+        let cli = Cli::try_parse_from(["whitespace", "--log-max-size", "1MB", "--log-keep", "2"]).unwrap();
+        assert_eq!(
+            LoggingConfig::from_cli(&cli).rotation,
+            LogRotation {
+                max_size: 1 << 20,
+                keep: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("ws.log");
+        let rotated = |n: usize| temp_dir.path().join(format!("ws.log.{}", n));
+        fs::write(&path, "old\n").unwrap();
+
+        let log = RotatingFile::open(&path, LogRotation { max_size: 8, keep: 2 }).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            (&log).write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "four\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "three\n");
+        assert_eq!(fs::read_to_string(rotated(2)).unwrap(), "two\n");
+        assert!(!rotated(3).exists());
+
+        // Without rotated files kept, the log starts over
+        let log = RotatingFile::open(&path, LogRotation { max_size: 8, keep: 0 }).unwrap();
+        (&log).write_all(b"five and six\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "five and six\n");
+        assert_eq!(fs::read_to_string(rotated(1)).unwrap(), "three\n");
+    }
+
+    #[test]
+    fn test_from_cli_trace_format() {
+        let cli = Cli::try_parse_from(["whitespace", "--trace-format", "json"]).unwrap();
+        assert_eq!(LoggingConfig::from_cli(&cli).format, TraceFormat::Json);
+
+        let cli = Cli::try_parse_from(["whitespace"]).unwrap();
+        assert_eq!(LoggingConfig::from_cli(&cli).format, TraceFormat::Text);
+    }
+
+    #[test]
+    fn test_build_layers() {
+        assert!(build_layers(&LoggingConfig::off()).unwrap().is_none());
+
+        let temp_dir = TempDir::new().unwrap();
+        let log_file = temp_dir.path().join("nested").join("ws.log");
+        let layers = build_layers(&LoggingConfig {
+            console_level: LevelFilter::DEBUG,
+            log_file: Some(log_file.clone()),
+            rotation: LogRotation::default(),
+            color: ColorChoice::Never,
+            format: TraceFormat::Json,
+        })
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(layers.len(), 2);
+        assert!(log_file.exists());
+    }
+
+    #[test]
+    fn test_file_layer_logs_span_timings() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_file = temp_dir.path().join("ws.log");
+        let layers = build_layers(&LoggingConfig {
+            log_file: Some(log_file.clone()),
+            format: TraceFormat::Json,
+            ..LoggingConfig::off()
+        })
+        .unwrap()
+        .unwrap();
+
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layers), || {
+            let _span = tracing::info_span!("file", path = "src/main.rs").entered();
+            info!("cleaned");
+        });
+
+        let log = fs::read_to_string(&log_file).unwrap();
+        let lines: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["fields"]["message"], "cleaned");
+        assert_eq!(lines[0]["span"]["path"], "src/main.rs");
+        assert_eq!(lines[1]["fields"]["message"], "close");
+        assert!(lines[1]["fields"]["time.busy"].is_string());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::config::{Config, Rules};
+use crate::filetypes::FileTypes;
+use crate::processor::{process_lines, scan_lines};
+use crate::rules::{RuleSet, trim_line};
+use eyre::{Context, Result};
+use serde_json::{Value, json};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use tracing::{debug, warn};
+
+/// JSON-RPC error code for requests the server doesn't implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// A minimal Language Server Protocol server over stdio-style streams.
+///
+/// Open documents are tracked in full (`TextDocumentSyncKind.Full`). Trailing whitespace
+/// diagnostics are published when a document is opened or saved, and `textDocument/formatting`
+/// and a `source.fixAll` code action both clean the whole document with the configured rules.
+pub struct LanguageServer {
+    rules: RuleSet,
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl LanguageServer {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            rules: RuleSet::new(&config.rules).with_file_types(FileTypes::new(&config.file_types)),
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /// Serve requests from `input` until the client sends `exit` or closes the stream.
+    pub fn serve(mut self, mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            debug!("LSP message: {}", method);
+            if method == "exit" {
+                break;
+            }
+            for reply in self.handle(method, &message) {
+                write_message(&mut output, &reply)?;
+            }
+        }
+        if !self.shutdown {
+            warn!("LSP client exited without a shutdown request");
+        }
+        Ok(())
+    }
+
+    /// Messages to send in response to one from the client.
+    fn handle(&mut self, method: &str, message: &Value) -> Vec<Value> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default().to_string();
+        let respond = |result: Value| vec![json!({ "jsonrpc": "2.0", "id": message["id"], "result": result })];
+
+        match method {
+            "initialize" => respond(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": { "includeText": true } },
+                    "documentFormattingProvider": true,
+                    "codeActionProvider": { "codeActionKinds": ["source.fixAll"] },
+                },
+                "serverInfo": { "name": "whitespace", "version": env!("GIT_DESCRIBE") },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                respond(Value::Null)
+            }
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+                vec![self.diagnostics(&uri)]
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole document
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.documents.insert(uri, text.to_string());
+                }
+                Vec::new()
+            }
+            "textDocument/didSave" => {
+                if let Some(text) = params["text"].as_str() {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                vec![self.diagnostics(&uri)]
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                vec![publish(&uri, Vec::new())]
+            }
+            "textDocument/formatting" => respond(json!(self.fix_all(&uri).into_iter().collect::<Vec<_>>())),
+            "textDocument/codeAction" => {
+                let actions: Vec<Value> = self
+                    .fix_all(&uri)
+                    .into_iter()
+                    .map(|edit| {
+                        json!({
+                            "title": "Remove trailing whitespace",
+                            "kind": "source.fixAll",
+                            "edit": { "changes": { uri.as_str(): [edit] } },
+                        })
+                    })
+                    .collect();
+                respond(json!(actions))
+            }
+            _ if message.get("id").is_some() => vec![json!({
+                "jsonrpc": "2.0",
+                "id": message["id"],
+                "error": { "code": METHOD_NOT_FOUND, "message": format!("Unsupported method: {}", method) },
+            })],
+            // Other notifications, such as `initialized`, need no reply
+            _ => Vec::new(),
+        }
+    }
+
+    /// The rules for the document at `uri`, which may be a script known only by its shebang.
+    fn rules_for(&self, uri: &str, text: &str) -> Cow<'_, Rules> {
+        let first_line = text.lines().next().unwrap_or_default();
+        self.rules.for_script(&uri_to_path(uri), first_line)
+    }
+
+    /// A `publishDiagnostics` notification with one warning per line the rules would change.
+    fn diagnostics(&self, uri: &str) -> Value {
+        let Some(text) = self.documents.get(uri) else {
+            return publish(uri, Vec::new());
+        };
+        let rules = self.rules_for(uri, text);
+        let lines: Vec<&str> = text.split('\n').collect();
+
+        let diagnostics = scan_lines(text, &rules)
+            .lines
+            .into_iter()
+            .filter_map(|line_num| {
+                let line = lines.get(line_num - 1)?;
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                let end = utf16_len(line);
+                // Lines changed for other reasons, such as indentation, are marked whole
+                let (start, message) = match utf16_len(trim_line(line, &rules)) {
+                    start if start < end => (start, "Trailing whitespace"),
+                    _ => (0, "Whitespace to clean"),
+                };
+                Some(json!({
+                    "range": range(line_num - 1, start, line_num - 1, end),
+                    "severity": 2,
+                    "source": "whitespace",
+                    "message": message,
+                }))
+            })
+            .collect();
+        publish(uri, diagnostics)
+    }
+
+    /// A single edit replacing the whole document with its cleaned text, or none if it's clean.
+    fn fix_all(&self, uri: &str) -> Option<Value> {
+        let text = self.documents.get(uri)?;
+        let rules = self.rules_for(uri, text);
+        let (cleaned, modified, _) = process_lines(text, &rules);
+        if modified.is_empty() {
+            return None;
+        }
+
+        // The end of the document: after its last newline, as many UTF-16 units as remain
+        let last_line = text.rsplit('\n').next().unwrap_or_default();
+        let end_line = text.matches('\n').count();
+        Some(json!({
+            "range": range(0, 0, end_line, utf16_len(last_line)),
+            "newText": cleaned,
+        }))
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+fn range(start_line: usize, start_character: usize, end_line: usize, end_character: usize) -> Value {
+    json!({
+        "start": { "line": start_line, "character": start_character },
+        "end": { "line": end_line, "character": end_character },
+    })
+}
+
+/// LSP positions count UTF-16 code units by default.
+fn utf16_len(text: &str) -> usize {
+    text.chars().map(char::len_utf16).sum()
+}
+
+/// The path in a `file://` URI, used to pick per-file rules. Other schemes keep the whole URI,
+/// whose last segment still serves as the file name.
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+/// Read one `Content-Length`-framed JSON-RPC message, or None at the end of the stream.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).context("Failed to read LSP header")? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = Some(value.trim().parse::<usize>().context("Invalid Content-Length")?);
+        }
+    }
+
+    let length = content_length.ok_or_else(|| eyre::eyre!("LSP message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).context("Failed to read LSP message")?;
+    serde_json::from_slice(&body).map(Some).context("Invalid LSP message")
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_vec(message)?;
+    write!(output, "Content-Length: {}\r\n\r\n", body.len())?;
+    output.write_all(&body)?;
+    output.flush().context("Failed to write LSP message")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(messages: &[Value]) -> Vec<u8> {
+        let mut input = Vec::new();
+        for message in messages {
+            write_message(&mut input, message).unwrap();
+        }
+        input
+    }
+
+    fn replies(input: Vec<u8>) -> Vec<Value> {
+        let mut output = Vec::new();
+        LanguageServer::new(&Config::default())
+            .serve(Cursor::new(input), &mut output)
+            .unwrap();
+        let mut output = Cursor::new(output);
+        std::iter::from_fn(|| read_message(&mut output).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_diagnostics_and_formatting() {
+        let uri = "file:///tmp/My%20Notes/a.txt";
+        let input = frame(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "languageId": "plaintext", "version": 1, "text": "é  \r\nok\n\t" } },
+            }),
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "textDocument/formatting", "params": { "textDocument": { "uri": uri } } }),
+            json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {} }),
+            json!({ "jsonrpc": "2.0", "id": 4, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        let replies = replies(input);
+        assert_eq!(replies.len(), 5);
+        assert_eq!(replies[0]["result"]["capabilities"]["documentFormattingProvider"], true);
+
+        let diagnostics = &replies[1]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 2);
+        assert_eq!(diagnostics[0]["range"], range(0, 1, 0, 3));
+        assert_eq!(diagnostics[1]["range"], range(2, 0, 2, 1));
+
+        assert_eq!(replies[2]["id"], 2);
+        assert_eq!(replies[2]["result"][0]["newText"], "é\nok\n");
+        assert_eq!(replies[2]["result"][0]["range"], range(0, 0, 2, 1));
+        assert_eq!(replies[3]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(replies[4]["result"], Value::Null);
+    }
+
+    #[test]
+    fn test_save_republishes_and_clean_documents_have_no_fix() {
+        let uri = "file:///a.rs";
+        let input = frame(&[
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": { "textDocument": { "uri": uri, "text": "fn main() {} \n" } },
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": { "textDocument": { "uri": uri }, "contentChanges": [{ "text": "fn main() {}\n" }] },
+            }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didSave", "params": { "textDocument": { "uri": uri } } }),
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/codeAction", "params": { "textDocument": { "uri": uri } } }),
+        ]);
+
+        let replies = replies(input);
+        assert_eq!(replies[0]["params"]["diagnostics"].as_array().unwrap().len(), 1);
+        assert_eq!(replies[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(replies[2]["result"], json!([]));
+    }
+
+    #[test]
+    fn test_uri_to_path() {
+        assert_eq!(
+            uri_to_path("file:///tmp/My%20Notes/a.md"),
+            PathBuf::from("/tmp/My Notes/a.md")
+        );
+        assert_eq!(uri_to_path("untitled:Untitled-1"), PathBuf::from("untitled:Untitled-1"));
+        assert_eq!(uri_to_path("file:///50%"), PathBuf::from("/50%"));
+    }
+}
@@ -1,49 +1,32 @@
 use clap::Parser;
+use clap::error::ErrorKind;
 use eyre::{Context, Result};
-use log::info;
-use std::env;
-use std::fs;
-use std::path::PathBuf;
-
-use whitespace::{Cli, RuntimeConfig};
-
-fn setup_logging() -> Result<()> {
-    // Create log directory
-    let log_dir = dirs::data_local_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("whitespace")
-        .join("logs");
-
-    fs::create_dir_all(&log_dir).context("Failed to create log directory")?;
-
-    let log_file = log_dir.join("whitespace.log");
-
-    // Setup env_logger with file output
-    let target = Box::new(
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&log_file)
-            .context("Failed to open log file")?,
-    );
-
-    // Check for RUST_LOG environment variable, default to INFO
-    let log_level = env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
+use std::process::ExitCode;
+use tracing::info;
 
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&log_level))
-        .target(env_logger::Target::Pipe(target))
-        .init();
+use whitespace::{BenchOptions, Cli, Command, ConfigCommand, ExitStatus, LoggingConfig, RuntimeConfig};
 
-    info!("Logging initialized, writing to: {}", log_file.display());
-    Ok(())
+/// Print an error with its context chain and map it to an exit code.
+fn fail(error: eyre::Report, status: ExitStatus) -> ExitCode {
+    eprintln!("Error: {:?}", error);
+    status.into()
 }
 
-fn main() -> Result<()> {
-    // Setup logging first
-    setup_logging().context("Failed to setup logging")?;
+fn main() -> ExitCode {
+    // Parse CLI arguments; usage errors get our own exit code rather than clap's
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) if matches!(e.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            return ExitStatus::Usage.into();
+        }
+    };
 
-    // Parse CLI arguments
-    let cli = Cli::parse();
+    // Setup logging before anything else can log
+    if let Err(e) = whitespace::logging::init(&LoggingConfig::from_cli(&cli)).context("Failed to setup logging") {
+        return fail(e, ExitStatus::Usage);
+    }
 
     info!(
         "Starting with config from: {:?}",
@@ -53,11 +36,96 @@ fn main() -> Result<()> {
             .unwrap_or_else(|| "defaults".to_string())
     );
 
+    // Man pages and validating or generating configs have to work even when the config fails to load
+    let early = match &cli.command {
+        Some(Command::Man { out_dir }) => {
+            Some(whitespace::run_man(out_dir.as_deref()).map_err(|e| (e, ExitStatus::Errors)))
+        }
+        Some(Command::Config {
+            action: ConfigCommand::Validate { path },
+        }) => Some(
+            whitespace::run_config_validate(path.as_deref().or(cli.config.as_deref()))
+                .map_err(|e| (e, ExitStatus::Usage)),
+        ),
+        Some(Command::Config {
+            action: ConfigCommand::Schema,
+        }) => Some(whitespace::run_config_schema().map_err(|e| (e, ExitStatus::Errors))),
+        Some(Command::Config {
+            action: ConfigCommand::Init { project, force },
+        }) => Some(whitespace::run_config_init(*project, *force).map_err(|e| (e, ExitStatus::Errors))),
+        Some(Command::InstallHook { hook, force }) => {
+            Some(whitespace::run_install_hook(*hook, *force).map_err(|e| (e, ExitStatus::Errors)))
+        }
+        Some(Command::UninstallHook { hook }) => {
+            Some(whitespace::run_uninstall_hook(*hook).map_err(|e| (e, ExitStatus::Errors)))
+        }
+        _ => None,
+    };
+    if let Some(result) = early {
+        return match result {
+            Ok(()) => ExitStatus::Success.into(),
+            Err((e, status)) => fail(e, status),
+        };
+    }
+
     // Build validated runtime configuration
-    let runtime_config = RuntimeConfig::from_cli(&cli).context("Failed to build runtime configuration")?;
+    let runtime_config = match RuntimeConfig::from_cli(&cli).context("Failed to build runtime configuration") {
+        Ok(runtime_config) => runtime_config,
+        Err(e) => return fail(e, ExitStatus::Usage),
+    };
 
-    // Run the main application logic
-    whitespace::run(&runtime_config).context("Application failed")?;
+    // Run the requested subcommand, or the main application logic
+    let result: Result<ExitStatus> = match &cli.command {
+        Some(Command::Stats { top, .. }) => whitespace::run_stats(&runtime_config, *top)
+            .map(|()| ExitStatus::Success)
+            .context("Stats failed"),
+        Some(Command::Diff { .. }) => whitespace::run_diff(&runtime_config).context("Diff failed"),
+        Some(Command::Config { .. }) => whitespace::run_config_show(&runtime_config).map(|()| ExitStatus::Success),
+        Some(Command::Explain { path, .. }) => whitespace::run_explain(&runtime_config, path)
+            .map(|()| ExitStatus::Success)
+            .context("Explain failed"),
+        Some(Command::Bench {
+            files,
+            min_size,
+            max_size,
+            dirty_percent,
+            thread_counts,
+            runs,
+            seed,
+        }) => {
+            let options = BenchOptions {
+                files: *files,
+                min_size: *min_size,
+                max_size: *max_size,
+                dirty_percent: *dirty_percent,
+                thread_counts: thread_counts.clone(),
+                runs: *runs,
+                seed: *seed,
+            };
+            whitespace::run_bench(&runtime_config, &options)
+                .map(|()| ExitStatus::Success)
+                .context("Benchmark failed")
+        }
+        Some(Command::Lsp) => whitespace::run_lsp(&runtime_config)
+            .context("Language server failed")
+            .map(|()| ExitStatus::Success),
+        Some(Command::Daemon { socket, .. }) => whitespace::run_daemon(&runtime_config, socket.as_deref())
+            .context("Daemon failed")
+            .map(|()| ExitStatus::Success),
+        Some(Command::PreCommit { files }) => {
+            whitespace::run_pre_commit(&runtime_config, files).context("Pre-commit run failed")
+        }
+        Some(Command::Man { .. } | Command::InstallHook { .. } | Command::UninstallHook { .. }) => {
+            unreachable!("handled before loading config")
+        }
+        None => match runtime_config.fixup {
+            Some(mode) => whitespace::run_fixup(&runtime_config, mode).context("Fixup failed"),
+            None => whitespace::run(&runtime_config).context("Application failed"),
+        },
+    };
 
-    Ok(())
+    match result {
+        Ok(status) => status.into(),
+        Err(e) => fail(e, ExitStatus::Errors),
+    }
 }
@@ -0,0 +1,191 @@
+use crate::config::MetricsSettings;
+use crate::exit::ExitStatus;
+use eyre::{Context, Result};
+use std::fmt::Write as _;
+use std::net::UdpSocket;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The counters of one run, exported by [`export`] for dashboards of scheduled runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunMetrics {
+    pub files_scanned: usize,
+    /// Files with trailing whitespace, cleaned or, in a dry run, not
+    pub files_with_changes: usize,
+    pub files_modified: usize,
+    pub files_failed: usize,
+    pub lines_with_changes: usize,
+    pub bytes_removed: usize,
+    pub duration: Duration,
+    pub status: ExitStatus,
+}
+
+impl RunMetrics {
+    /// Each counter's name, help text and value.
+    fn counters(&self) -> [(&'static str, &'static str, usize); 6] {
+        [
+            ("files_scanned", "Files scanned", self.files_scanned),
+            (
+                "files_with_changes",
+                "Files with trailing whitespace, cleaned or, in a dry run, not",
+                self.files_with_changes,
+            ),
+            ("files_modified", "Files rewritten", self.files_modified),
+            ("files_failed", "Files that could not be processed", self.files_failed),
+            (
+                "lines_with_changes",
+                "Lines cleaned or, in a dry run, needing it",
+                self.lines_with_changes,
+            ),
+            ("bytes_removed", "Bytes removed", self.bytes_removed),
+        ]
+    }
+}
+
+/// Export `metrics` to every sink `settings` configures. Every sink is tried, even after one
+/// fails; the error names those that failed.
+pub fn export(settings: &MetricsSettings, metrics: &RunMetrics) -> Result<()> {
+    let mut failures = Vec::new();
+    if let Some(path) = &settings.prometheus_textfile {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        if let Err(e) = write_textfile(path, &prometheus_text(&settings.prefix, metrics, timestamp)) {
+            failures.push(format!("{:#}", e));
+        }
+    }
+    if let Some(address) = &settings.statsd
+        && let Err(e) = send_statsd(address, &statsd_lines(&settings.prefix, metrics))
+    {
+        failures.push(format!("{:#}", e));
+    }
+    if failures.is_empty() { Ok(()) } else { eyre::bail!(failures.join("; ")) }
+}
+
+/// The run in the Prometheus text exposition format, as gauges of the last run, for the node
+/// exporter's textfile collector.
+fn prometheus_text(prefix: &str, metrics: &RunMetrics, timestamp: Duration) -> String {
+    // Prometheus names allow neither the dots nor the dashes of statsd ones
+    let prefix = prefix.replace(['.', '-'], "_");
+    let mut text = String::new();
+    let mut gauge = |name: &str, help: &str, value: String| {
+        let name = format!("{}_last_run_{}", prefix, name);
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        let _ = writeln!(text, "{} {}", name, value);
+    };
+    for (name, help, value) in metrics.counters() {
+        gauge(name, help, value.to_string());
+    }
+    gauge(
+        "duration_seconds",
+        "How long the run took",
+        format!("{:.3}", metrics.duration.as_secs_f64()),
+    );
+    gauge(
+        "exit_status",
+        "The run's exit status: 0 clean, 1 findings, 2 errors",
+        metrics.status.code().to_string(),
+    );
+    gauge(
+        "timestamp_seconds",
+        "When the run finished, in seconds since the Unix epoch",
+        timestamp.as_secs().to_string(),
+    );
+    text
+}
+
+/// The run as statsd counters, with its duration as a timer.
+fn statsd_lines(prefix: &str, metrics: &RunMetrics) -> String {
+    let mut lines = String::new();
+    for (name, _, value) in metrics.counters() {
+        let _ = writeln!(lines, "{}.{}:{}|c", prefix, name, value);
+    }
+    let _ = writeln!(lines, "{}.runs.exit_{}:1|c", prefix, metrics.status.code());
+    let _ = writeln!(lines, "{}.duration:{}|ms", prefix, metrics.duration.as_millis());
+    lines
+}
+
+/// Write a textfile whole, by renaming it into place, so the collector never reads half of it.
+fn write_textfile(path: &Path, text: &str) -> Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    std::fs::write(&temp_path, text)
+        .and_then(|()| std::fs::rename(&temp_path, path))
+        .with_context(|| format!("Failed to write metrics to {}", path.display()))
+}
+
+/// Send `lines` to the statsd server at `address`, such as `127.0.0.1:8125`, in one datagram.
+fn send_statsd(address: &str, lines: &str) -> Result<()> {
+    let bind = if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" };
+    UdpSocket::bind(bind)
+        .and_then(|socket| socket.send_to(lines.as_bytes(), address))
+        .with_context(|| format!("Failed to send metrics to statsd at {}", address))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn metrics() -> RunMetrics {
+        RunMetrics {
+            files_scanned: 12,
+            files_with_changes: 3,
+            files_modified: 3,
+            files_failed: 1,
+            lines_with_changes: 7,
+            bytes_removed: 20,
+            duration: Duration::from_millis(1500),
+            status: ExitStatus::Errors,
+        }
+    }
+
+    #[test]
+    fn test_prometheus_text() {
+        let text = prometheus_text("whitespace", &metrics(), Duration::from_secs(1_700_000_000));
+        assert!(text.contains(
+            "# HELP whitespace_last_run_files_scanned Files scanned\n\
+             # TYPE whitespace_last_run_files_scanned gauge\n\
+             whitespace_last_run_files_scanned 12\n"
+        ));
+        assert!(text.contains("whitespace_last_run_files_failed 1\n"));
+        assert!(text.contains("whitespace_last_run_duration_seconds 1.500\n"));
+        assert!(text.contains("whitespace_last_run_exit_status 2\n"));
+        assert!(text.contains("whitespace_last_run_timestamp_seconds 1700000000\n"));
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        let lines = statsd_lines("ci.whitespace", &metrics());
+        assert!(lines.starts_with("ci.whitespace.files_scanned:12|c\n"));
+        assert!(lines.contains("ci.whitespace.runs.exit_2:1|c\n"));
+        assert!(lines.ends_with("ci.whitespace.duration:1500|ms\n"));
+    }
+
+    #[test]
+    fn test_export() {
+        let temp_dir = TempDir::new().unwrap();
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let settings = MetricsSettings {
+            prometheus_textfile: Some(temp_dir.path().join("whitespace.prom")),
+            statsd: Some(server.local_addr().unwrap().to_string()),
+            ..MetricsSettings::default()
+        };
+        export(&settings, &metrics()).unwrap();
+
+        let text = std::fs::read_to_string(temp_dir.path().join("whitespace.prom")).unwrap();
+        assert!(text.contains("whitespace_last_run_files_modified 3\n"));
+        let mut datagram = [0; 1024];
+        let len = server.recv(&mut datagram).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&datagram[..len]).unwrap(),
+            statsd_lines("whitespace", &metrics())
+        );
+
+        let settings = MetricsSettings {
+            prometheus_textfile: Some(temp_dir.path().join("missing").join("whitespace.prom")),
+            ..MetricsSettings::default()
+        };
+        assert!(export(&settings, &metrics()).is_err());
+    }
+}
@@ -0,0 +1,676 @@
+use crate::explain::Explanation;
+use crate::processor::{Outcome, ProcessingResult, SkipReason};
+use clap::ValueEnum;
+use colored::*;
+use eyre::{Context, Result};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::io::{IsTerminal, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Console output format for findings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Colored, human-readable output with line ranges and a summary
+    #[default]
+    Text,
+    /// GitHub Actions workflow commands (`::warning file=...,line=...::`)
+    Github,
+    /// JUnit XML with one test case per file, failing where trailing whitespace was found
+    Junit,
+}
+
+/// Order files are listed and reported in. Files are processed in parallel, so without a
+/// fixed order two runs over the same tree could list them differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By path
+    #[default]
+    Path,
+    /// Most changed lines first, then by path
+    Changes,
+    /// Largest file first, then by path
+    Size,
+}
+
+/// The order `--stream` reports files in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum StreamOrder {
+    /// As each file finishes
+    #[default]
+    Finished,
+    /// In the order the files were found, each as soon as every file before it has finished
+    Ordered,
+}
+
+/// When to use colors and emoji in console output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolve against the environment and apply it to all `colored` output.
+    /// Returns whether styled output is enabled.
+    pub fn apply(self) -> bool {
+        let styled = should_style(self, std::env::var_os("NO_COLOR"), std::io::stdout().is_terminal());
+        colored::control::set_override(styled);
+        styled
+    }
+
+    /// Whether output to stderr, such as log lines, is styled.
+    pub fn styles_stderr(self) -> bool {
+        should_style(self, std::env::var_os("NO_COLOR"), std::io::stderr().is_terminal())
+    }
+}
+
+fn should_style(choice: ColorChoice, no_color: Option<OsString>, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // https://no-color.org: any non-empty value disables color
+        ColorChoice::Auto => no_color.is_none_or(|value| value.is_empty()) && is_terminal,
+    }
+}
+
+/// `"<icon> "` when styled output is enabled, nothing when output is plain.
+pub fn icon(icon: &str) -> String {
+    if colored::control::SHOULD_COLORIZE.should_colorize() {
+        format!("{} ", icon)
+    } else {
+        String::new()
+    }
+}
+
+/// Number of files that had changes applied (or would have, in a dry run).
+pub fn count_changed_files(file_results: &[(PathBuf, ProcessingResult)]) -> usize {
+    file_results.iter().filter(|(_, result)| result.had_changes()).count()
+}
+
+/// Write the path of each file with changes followed by `terminator`: a newline for `-l`, or
+/// NUL for `--print0` and `xargs -0`. Paths are written as raw bytes, so names that aren't
+/// valid UTF-8 survive.
+/// Returns the number of files with changes.
+pub fn write_changed_paths(
+    output: &mut impl Write,
+    file_results: &[(PathBuf, ProcessingResult)],
+    terminator: u8,
+) -> Result<usize> {
+    let mut files_with_changes = 0;
+    for (path, _) in file_results.iter().filter(|(_, result)| result.had_changes()) {
+        output.write_all(path.as_os_str().as_encoded_bytes())?;
+        output.write_all(&[terminator])?;
+        files_with_changes += 1;
+    }
+    output.flush().context("Failed to write paths")?;
+    Ok(files_with_changes)
+}
+
+/// List files with changes under a header for each directory, with paths relative to `root`
+/// and each directory's file and line counts, so thousands of findings across a large tree
+/// stay readable.
+/// Returns the number of files with changes.
+pub fn display_grouped(file_results: &[(PathBuf, ProcessingResult)], root: &Path) -> usize {
+    let groups = group_by_dir(file_results, root);
+    for (dir, files) in &groups {
+        let lines: usize = files.iter().map(|(_, lines)| lines.len()).sum();
+        println!(
+            "\n{}{} {}",
+            icon("📂"),
+            dir.display().to_string().bold(),
+            format!(
+                "({} {}, {} {})",
+                files.len(),
+                if files.len() == 1 { "file" } else { "files" },
+                lines,
+                if lines == 1 { "line" } else { "lines" }
+            )
+            .dimmed()
+        );
+        for (name, lines) in files {
+            println!(
+                "  {}{}",
+                name.display().to_string().blue(),
+                crate::format_line_numbers(lines).dimmed()
+            );
+        }
+    }
+    groups.values().map(Vec::len).sum()
+}
+
+/// A line for each rule that reported lines of the file at `path` without changing them, such
+/// as `src/lib.rs (12,40) max-line-length`.
+pub fn notice_lines(path: &Path, result: &ProcessingResult) -> Vec<String> {
+    result
+        .notices
+        .iter()
+        .map(|notice| {
+            format!(
+                "{}{} {}",
+                path.display().to_string().blue(),
+                crate::format_line_numbers(&notice.lines).dimmed(),
+                notice.rule.yellow()
+            )
+        })
+        .collect()
+}
+
+/// Files with changes keyed by their directory relative to `root` (`.` for `root` itself), each
+/// with its file name and changed lines. Directories and the files in each are sorted.
+fn group_by_dir<'a>(
+    file_results: &'a [(PathBuf, ProcessingResult)],
+    root: &Path,
+) -> BTreeMap<PathBuf, Vec<(&'a Path, &'a [usize])>> {
+    let mut groups: BTreeMap<PathBuf, Vec<(&Path, &[usize])>> = BTreeMap::new();
+    for (path, result) in file_results.iter().filter(|(_, result)| result.had_changes()) {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let dir = match relative.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let name = relative.file_name().map_or(relative, Path::new);
+        groups.entry(dir).or_default().push((name, result.lines_modified()));
+    }
+    for files in groups.values_mut() {
+        files.sort();
+    }
+    groups
+}
+
+/// Sort results into `order`; `size` gives a file's size in bytes for [`SortOrder::Size`].
+pub fn sort_results(file_results: &mut [(PathBuf, ProcessingResult)], order: SortOrder, size: impl Fn(&Path) -> u64) {
+    match order {
+        SortOrder::Path => file_results.sort_by(|(a, _), (b, _)| a.cmp(b)),
+        SortOrder::Changes => file_results.sort_by(|(a, a_result), (b, b_result)| {
+            let changes = |result: &ProcessingResult| result.lines_modified().len();
+            changes(b_result).cmp(&changes(a_result)).then_with(|| a.cmp(b))
+        }),
+        SortOrder::Size => file_results.sort_by_cached_key(|(path, _)| (Reverse(size(path)), path.clone())),
+    }
+}
+
+/// `path` (relative to `cwd` unless absolute) as seen from the absolute directory `base`,
+/// e.g. `../src/lib.rs`. Only the paths' text is compared; symlinks aren't resolved.
+pub fn relative_path(path: &Path, base: &Path, cwd: &Path) -> PathBuf {
+    let path = normalize_path(&cwd.join(path));
+    let base = normalize_path(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Nothing in common, not even a root: on another drive, say
+    if common == 0 {
+        return path;
+    }
+
+    let mut relative: PathBuf = base.components().skip(common).map(|_| Component::ParentDir).collect();
+    relative.extend(path.components().skip(common));
+    if relative.as_os_str().is_empty() { PathBuf::from(".") } else { relative }
+}
+
+/// Resolve the `.` and `..` components of `path` lexically.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+                normalized.pop();
+            }
+            // `..` at the root stays at the root; a leading `..` of a relative path is kept
+            Component::ParentDir if normalized.has_root() => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// List every file with why it was included or skipped, as given by `reasons`.
+pub fn display_why(file_results: &[(PathBuf, ProcessingResult)], reasons: &[String]) {
+    if file_results.is_empty() {
+        return;
+    }
+    println!("\n{}{}", icon("🔎"), "Why".bold());
+    for ((path, _), reason) in file_results.iter().zip(reasons) {
+        println!("  {} {}", path.display().to_string().blue(), reason.dimmed());
+    }
+}
+
+/// Show the decisions made about `path`, one per line, and where they led.
+pub fn display_explanation(path: &Path, explanation: &Explanation) {
+    println!("{}{}", icon("🔎"), path.display().to_string().bold());
+    let width = explanation.steps.iter().map(|step| step.check.len()).max().unwrap_or(0);
+    for step in &explanation.steps {
+        println!("  {:width$}  {}", step.check, step.finding.dimmed());
+    }
+    println!("{} {}", "=>".bold(), explanation.verdict);
+}
+
+/// List skipped files, sorted by path, each with the reason it was skipped.
+pub fn display_skipped(skipped: &[(&Path, SkipReason)]) {
+    if skipped.is_empty() {
+        return;
+    }
+
+    println!("\n{}{}", icon("⏭️"), "Skipped".yellow().bold());
+    for line in skipped_lines(skipped) {
+        println!("  {}", line.dimmed());
+    }
+}
+
+fn skipped_lines(skipped: &[(&Path, SkipReason)]) -> Vec<String> {
+    let mut skipped = skipped.to_vec();
+    skipped.sort();
+    skipped
+        .iter()
+        .map(|(path, reason)| format!("{} ({})", path.display(), reason))
+        .collect()
+}
+
+/// Print one GitHub Actions `::warning` workflow command per modified line.
+/// Returns the number of files with changes.
+pub fn display_github_annotations(file_results: &[(PathBuf, ProcessingResult)]) -> usize {
+    let mut files_with_changes = 0;
+
+    for (file_path, result) in file_results {
+        if result.had_changes() {
+            for annotation in github_annotations(file_path, result.lines_modified()) {
+                println!("{}", annotation);
+            }
+            files_with_changes += 1;
+        }
+    }
+
+    files_with_changes
+}
+
+/// Writes each file's findings as soon as they are known, for `--stream`, rather than once its
+/// whole directory is done, so a long run shows progress and an interrupted one what it found.
+/// `render` gives the bytes written for a file, if any.
+pub struct StreamPrinter<W, R> {
+    order: StreamOrder,
+    render: R,
+    state: Mutex<StreamState<W>>,
+}
+
+struct StreamState<W> {
+    output: W,
+    /// The index of the next file in order
+    next: usize,
+    /// Files rendered in order while one before them is still running
+    waiting: BTreeMap<usize, Option<Vec<u8>>>,
+}
+
+impl<W, R> StreamPrinter<W, R>
+where
+    W: Write,
+    R: Fn(&Path, &ProcessingResult) -> Option<Vec<u8>>,
+{
+    pub fn new(output: W, order: StreamOrder, render: R) -> Self {
+        Self {
+            order,
+            render,
+            state: Mutex::new(StreamState {
+                output,
+                next: 0,
+                waiting: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Report the file at `index` in the order the files were given.
+    pub fn report(&self, index: usize, path: &Path, result: &ProcessingResult) {
+        let rendered = (self.render)(path, result);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *state;
+        match self.order {
+            StreamOrder::Finished => {
+                if let Some(bytes) = rendered {
+                    write_streamed(&mut state.output, &bytes);
+                }
+            }
+            StreamOrder::Ordered => {
+                state.waiting.insert(index, rendered);
+                while let Some(rendered) = state.waiting.remove(&state.next) {
+                    state.next += 1;
+                    if let Some(bytes) = rendered {
+                        write_streamed(&mut state.output, &bytes);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the files still waiting on earlier ones that never finished, because the run was
+    /// aborted.
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *state;
+        for bytes in std::mem::take(&mut state.waiting).into_values().flatten() {
+            write_streamed(&mut state.output, &bytes);
+        }
+    }
+}
+
+/// Write and flush at once, so each file shows up as it finishes. A failed write, such as to a
+/// closed pipe, can't stop the workers, and the summary after the run reports the same files.
+fn write_streamed(output: &mut impl Write, bytes: &[u8]) {
+    let _ = output.write_all(bytes).and_then(|()| output.flush());
+}
+
+/// Build the workflow command lines for a single file's findings.
+pub fn github_annotations(file_path: &Path, lines: &[usize]) -> Vec<String> {
+    let file = escape_property(&display_path(file_path));
+    lines
+        .iter()
+        .map(|line| format!("::warning file={},line={}::trailing whitespace", file, line))
+        .collect()
+}
+
+/// One JUnit XML document for a whole run: each file is a test case that fails if it had
+/// trailing whitespace and errors if it couldn't be processed. Files skipped for their content
+/// are reported as skipped; excluded paths were never candidates and are left out.
+pub fn junit_xml(file_results: &[(PathBuf, ProcessingResult)], elapsed: Duration) -> String {
+    let mut cases = Vec::new();
+    let (mut failures, mut errors, mut skipped) = (0, 0, 0);
+    for (path, result) in file_results {
+        let name = escape_xml(&display_path(path));
+        let body = match &result.outcome {
+            Outcome::Clean => None,
+            Outcome::Modified { lines, .. } => {
+                failures += 1;
+                Some(format!(
+                    "<failure type=\"trailing-whitespace\" message=\"Trailing whitespace on {} line(s)\">Lines {}</failure>",
+                    lines.len(),
+                    crate::format_line_numbers(lines).trim_matches([' ', '(', ')'])
+                ))
+            }
+            Outcome::Failed(error) => {
+                errors += 1;
+                Some(format!("<error message=\"{}\"/>", escape_xml(&format!("{:#}", error))))
+            }
+            Outcome::Skipped(SkipReason::Excluded) => continue,
+            Outcome::Skipped(reason) => {
+                skipped += 1;
+                Some(format!("<skipped message=\"{}\"/>", reason))
+            }
+        };
+        cases.push(match body {
+            Some(body) => format!(
+                "    <testcase classname=\"whitespace\" name=\"{}\">{}</testcase>",
+                name, body
+            ),
+            None => format!("    <testcase classname=\"whitespace\" name=\"{}\"/>", name),
+        });
+    }
+
+    let counts = format!(
+        "tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{:.3}\"",
+        cases.len(),
+        failures,
+        errors,
+        skipped,
+        elapsed.as_secs_f64()
+    );
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<testsuites name=\"whitespace\" {}>\n", counts));
+    xml.push_str(&format!("  <testsuite name=\"whitespace\" {}>\n", counts));
+    for case in cases {
+        xml.push_str(&case);
+        xml.push('\n');
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// `value` with the characters XML and HTML give meaning to replaced by their entities.
+pub(crate) fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// GitHub (and SARIF consumers) resolve paths relative to the workspace, so drop a leading `./`.
+pub(crate) fn display_path(path: &Path) -> String {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    path.to_string_lossy().to_string()
+}
+
+/// Escape a workflow command property value per the Actions toolkit rules.
+fn escape_property(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+        .replace(':', "%3A")
+        .replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_annotations_per_line() {
+        let annotations = github_annotations(Path::new("./src/main.rs"), &[3, 7]);
+        assert_eq!(
+            annotations,
+            vec![
+                "::warning file=src/main.rs,line=3::trailing whitespace",
+                "::warning file=src/main.rs,line=7::trailing whitespace",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_github_annotations_escape_path() {
+        let annotations = github_annotations(Path::new("dir,with:odd%chars.txt"), &[1]);
+        assert_eq!(
+            annotations,
+            vec!["::warning file=dir%2Cwith%3Aodd%25chars.txt,line=1::trailing whitespace"]
+        );
+    }
+
+    #[test]
+    fn test_should_style() {
+        assert!(should_style(ColorChoice::Always, Some("1".into()), false));
+        assert!(!should_style(ColorChoice::Never, None, true));
+        assert!(should_style(ColorChoice::Auto, None, true));
+        assert!(should_style(ColorChoice::Auto, Some("".into()), true));
+        assert!(!should_style(ColorChoice::Auto, Some("1".into()), true));
+        assert!(!should_style(ColorChoice::Auto, None, false));
+    }
+
+    #[test]
+    fn test_skipped_lines_sorted_with_reason() {
+        let skipped = [
+            (Path::new("src/logo.png"), SkipReason::Binary),
+            (Path::new("src/huge.log"), SkipReason::TooLarge),
+            (Path::new("target"), SkipReason::Excluded),
+            (Path::new("src/latin1.txt"), SkipReason::InvalidEncoding),
+        ];
+        assert_eq!(
+            skipped_lines(&skipped),
+            vec![
+                "src/huge.log (too large)",
+                "src/latin1.txt (invalid encoding)",
+                "src/logo.png (binary)",
+                "target (excluded)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_group_by_dir() {
+        let file_results = vec![
+            (
+                PathBuf::from("repo/src/lib.rs"),
+                ProcessingResult::from_lines(vec![3], 1),
+            ),
+            (
+                PathBuf::from("repo/README.md"),
+                ProcessingResult::from_lines(vec![1, 2], 2),
+            ),
+            (PathBuf::from("repo/src/clean.rs"), ProcessingResult::clean()),
+            (
+                PathBuf::from("repo/src/bin/main.rs"),
+                ProcessingResult::from_lines(vec![9], 4),
+            ),
+            (
+                PathBuf::from("repo/src/cli.rs"),
+                ProcessingResult::from_lines(vec![5], 1),
+            ),
+        ];
+        let groups = group_by_dir(&file_results, Path::new("repo"));
+        let summary: Vec<(&Path, Vec<&Path>)> = groups
+            .iter()
+            .map(|(dir, files)| (dir.as_path(), files.iter().map(|(name, _)| *name).collect()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Path::new("."), vec![Path::new("README.md")]),
+                (Path::new("src"), vec![Path::new("cli.rs"), Path::new("lib.rs")]),
+                (Path::new("src/bin"), vec![Path::new("main.rs")]),
+            ]
+        );
+        assert_eq!(groups[Path::new(".")][0].1, &[1, 2]);
+    }
+
+    #[test]
+    fn test_sort_results() {
+        let mut file_results = vec![
+            (PathBuf::from("src/b.rs"), ProcessingResult::from_lines(vec![1], 1)),
+            (PathBuf::from("src/a.rs"), ProcessingResult::clean()),
+            (PathBuf::from("README.md"), ProcessingResult::from_lines(vec![1, 2], 2)),
+            (PathBuf::from("src/c.rs"), ProcessingResult::from_lines(vec![4], 1)),
+        ];
+        let size = |path: &Path| if path.ends_with("a.rs") { 100 } else { 10 };
+        let order = |file_results: &[(PathBuf, ProcessingResult)]| -> Vec<String> {
+            file_results
+                .iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect()
+        };
+
+        sort_results(&mut file_results, SortOrder::Path, size);
+        assert_eq!(order(&file_results), ["README.md", "src/a.rs", "src/b.rs", "src/c.rs"]);
+        sort_results(&mut file_results, SortOrder::Changes, size);
+        assert_eq!(order(&file_results), ["README.md", "src/b.rs", "src/c.rs", "src/a.rs"]);
+        sort_results(&mut file_results, SortOrder::Size, size);
+        assert_eq!(order(&file_results), ["src/a.rs", "README.md", "src/b.rs", "src/c.rs"]);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let cwd = Path::new("/work/repo");
+        let relative = |path: &str, base: &str| relative_path(Path::new(path), Path::new(base), cwd);
+        assert_eq!(relative("./src/lib.rs", "/work/repo"), Path::new("src/lib.rs"));
+        assert_eq!(relative("src/lib.rs", "/work/repo/src"), Path::new("lib.rs"));
+        assert_eq!(
+            relative("src/lib.rs", "/work/repo/docs/api"),
+            Path::new("../../src/lib.rs")
+        );
+        assert_eq!(relative("../other/a.txt", "/work/repo"), Path::new("../other/a.txt"));
+        assert_eq!(relative("/etc/hosts", "/work/repo"), Path::new("../../etc/hosts"));
+        assert_eq!(relative("src/../src", "/work/repo/src"), Path::new("."));
+
+        assert_eq!(normalize_path(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize_path(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(normalize_path(Path::new("../a/..")), Path::new(".."));
+    }
+
+    #[test]
+    fn test_write_changed_paths() {
+        let file_results = vec![
+            (PathBuf::from("a b.txt"), ProcessingResult::from_lines(vec![1], 2)),
+            (PathBuf::from("clean.txt"), ProcessingResult::clean()),
+            (PathBuf::from("new\nline.txt"), ProcessingResult::from_lines(vec![2], 2)),
+        ];
+        let mut output = Vec::new();
+        assert_eq!(write_changed_paths(&mut output, &file_results, b'\0').unwrap(), 2);
+        assert_eq!(output, b"a b.txt\0new\nline.txt\0");
+
+        let mut output = Vec::new();
+        write_changed_paths(&mut output, &file_results[..2], b'\n').unwrap();
+        assert_eq!(output, b"a b.txt\n");
+    }
+
+    #[test]
+    fn test_stream_printer() {
+        let render = |path: &Path, result: &ProcessingResult| {
+            result
+                .had_changes()
+                .then(|| format!("{}\n", path.display()).into_bytes())
+        };
+        let dirty = ProcessingResult::from_lines(vec![1], 2);
+        let clean = ProcessingResult::clean();
+
+        let printer = StreamPrinter::new(Vec::new(), StreamOrder::Finished, render);
+        printer.report(2, Path::new("c"), &dirty);
+        printer.report(0, Path::new("a"), &dirty);
+        printer.report(1, Path::new("b"), &clean);
+        printer.finish();
+        assert_eq!(printer.state.into_inner().unwrap().output, b"c\na\n");
+
+        // Ordered output waits for earlier files, clean or not, and flushes the rest at the end
+        let printer = StreamPrinter::new(Vec::new(), StreamOrder::Ordered, render);
+        printer.report(2, Path::new("c"), &dirty);
+        printer.report(4, Path::new("e"), &dirty);
+        printer.report(1, Path::new("b"), &clean);
+        assert!(printer.state.lock().unwrap().output.is_empty());
+        printer.report(0, Path::new("a"), &dirty);
+        assert_eq!(printer.state.lock().unwrap().output, b"a\nc\n");
+        printer.finish();
+        assert_eq!(printer.state.into_inner().unwrap().output, b"a\nc\ne\n");
+    }
+
+    #[test]
+    fn test_junit_xml() {
+        let file_results = vec![
+            (
+                PathBuf::from("./src/a&b.rs"),
+                ProcessingResult::from_lines(vec![1, 2, 4], 6),
+            ),
+            (PathBuf::from("./src/clean.rs"), ProcessingResult::clean()),
+            (PathBuf::from("./logo.png"), ProcessingResult::skip(SkipReason::Binary)),
+            (PathBuf::from("./target"), ProcessingResult::skip(SkipReason::Excluded)),
+            (
+                PathBuf::from("./locked.txt"),
+                ProcessingResult::failed(eyre::eyre!("Permission \"denied\"")),
+            ),
+        ];
+
+        let xml = junit_xml(&file_results, Duration::from_millis(1500));
+        let lines: Vec<&str> = xml.lines().collect();
+
+        assert_eq!(lines[0], "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+        assert_eq!(
+            lines[2],
+            "  <testsuite name=\"whitespace\" tests=\"4\" failures=\"1\" errors=\"1\" skipped=\"1\" time=\"1.500\">"
+        );
+        assert_eq!(
+            lines[3],
+            "    <testcase classname=\"whitespace\" name=\"src/a&amp;b.rs\"><failure type=\"trailing-whitespace\" \
+             message=\"Trailing whitespace on 3 line(s)\">Lines 1-2,4</failure></testcase>"
+        );
+        assert_eq!(
+            lines[4],
+            "    <testcase classname=\"whitespace\" name=\"src/clean.rs\"/>"
+        );
+        assert!(lines[5].contains("<skipped message=\"binary\"/>"));
+        assert!(lines[6].contains("<error message=\"Permission &quot;denied&quot;\"/>"));
+        assert_eq!(lines.len(), 9);
+    }
+}
@@ -1,26 +1,109 @@
 use eyre::{Context, Result};
-use std::cell::RefCell;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::Metadata;
+use std::io::{BufRead, BufReader, BufWriter, Cursor, Seek, Write};
+use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
+
+/// Callback that produces the new contents of a file into the supplied writer.
+pub type WriteFn<'a> = dyn FnMut(&mut dyn Write) -> Result<()> + 'a;
 
 /// Trait for filesystem operations, enabling dependency injection for testing.
 pub trait FileSystem: Send + Sync {
     fn read(&self, path: &Path) -> Result<Vec<u8>>;
+    /// Read a file, memory-mapping it where the backend supports it.
+    fn read_mapped(&self, path: &Path) -> Result<FileContents> {
+        self.read(path).map(FileContents::Owned)
+    }
     fn write(&self, path: &Path, content: &[u8]) -> Result<()>;
+    /// Open a buffered reader so large files can be consumed without loading them fully.
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>>;
+    /// Replace a file's contents with whatever `fill` writes, without buffering it all in memory.
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()>;
+    /// Like [`write_with`](Self::write_with), but into the file itself rather than a new one
+    /// put in its place, so that every hard link to it sees the new contents.
+    fn write_in_place(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        self.write_with(path, fill)
+    }
     fn metadata(&self, path: &Path) -> Result<FsMetadata>;
+    /// Mark a file read-only, or give its owner write permission back.
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()>;
+    /// Give the file at `to` the permissions of the file at `from`.
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()>;
+    /// Delete a file.
+    fn remove_file(&self, path: &Path) -> Result<()>;
+    /// Create a directory and any missing parents. Fails if a file is in the way.
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+    /// Put a copy of a file in the operating system's trash, as deleted from `path`, so that it
+    /// can be restored from there. The file itself stays where it is.
+    fn trash_copy(&self, path: &Path) -> Result<()>;
+    /// List the entries directly inside a directory, sorted by path.
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+    /// Resolve a path to its absolute form with every symlink followed.
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
     fn is_dir(&self, path: &Path) -> bool;
     fn is_file(&self, path: &Path) -> bool;
     fn is_symlink(&self, path: &Path) -> bool;
     fn exists(&self, path: &Path) -> bool;
 }
 
+/// File bytes that are either owned on the heap or memory-mapped.
+#[derive(Debug)]
+pub enum FileContents {
+    Owned(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+impl Deref for FileContents {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileContents::Owned(bytes) => bytes,
+            FileContents::Mapped(map) => map,
+        }
+    }
+}
+
 /// Simplified metadata struct for our needs.
 #[derive(Debug, Clone)]
 pub struct FsMetadata {
     pub len: u64,
     pub is_file: bool,
     pub is_dir: bool,
+    /// Last modification time, if the backend tracks one
+    pub modified: Option<SystemTime>,
+    /// (device, inode) pair identifying the underlying file, where the platform provides one
+    pub file_id: Option<(u64, u64)>,
+    /// No one may write to the file
+    pub readonly: bool,
+    /// Hard links to the file, itself included
+    pub links: u64,
+    /// Neither a regular file nor a directory: a FIFO, socket or device node, which reading
+    /// could block on or never finish
+    pub special: bool,
+    /// Bytes the file takes up on disk, where the platform says
+    pub allocated: Option<u64>,
+}
+
+impl FsMetadata {
+    /// Files at least this long are checked for sparseness; smaller ones can't balloon much.
+    const SPARSE_MIN_LEN: u64 = 1024 * 1024;
+    /// A file is sparse when it is this many times longer than the space it takes up. That is
+    /// more than compression saves on text, so compressed filesystems don't count.
+    const SPARSE_RATIO: u64 = 16;
+
+    /// Mostly holes, which rewriting would fill in: a sparse file of a few megabytes on disk
+    /// could come back gigabytes long.
+    pub fn is_sparse(&self) -> bool {
+        self.len >= Self::SPARSE_MIN_LEN
+            && self
+                .allocated
+                .is_some_and(|allocated| allocated.saturating_mul(Self::SPARSE_RATIO) < self.len)
+    }
 }
 
 impl From<Metadata> for FsMetadata {
@@ -29,102 +112,588 @@ impl From<Metadata> for FsMetadata {
             len: m.len(),
             is_file: m.is_file(),
             is_dir: m.is_dir(),
+            modified: m.modified().ok(),
+            file_id: file_id(&m),
+            readonly: m.permissions().readonly(),
+            links: links(&m),
+            special: !m.is_file() && !m.is_dir(),
+            allocated: allocated(&m),
         }
     }
 }
 
+#[cfg(unix)]
+fn file_id(m: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_m: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn links(m: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    m.nlink()
+}
+
+#[cfg(not(unix))]
+fn links(_m: &Metadata) -> u64 {
+    1
+}
+
+#[cfg(unix)]
+fn allocated(m: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    // Counted in 512-byte blocks whatever the filesystem's block size
+    Some(m.blocks().saturating_mul(512))
+}
+
+#[cfg(not(unix))]
+fn allocated(_m: &Metadata) -> Option<u64> {
+    None
+}
+
+/// Clear every write bit, or restore just the owner's, so that other users never gain write
+/// access (which `Permissions::set_readonly(false)` would grant on Unix).
+#[cfg(unix)]
+fn set_readonly_permissions(permissions: &mut std::fs::Permissions, readonly: bool) {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = permissions.mode();
+    permissions.set_mode(if readonly { mode & !0o222 } else { mode | 0o200 });
+}
+
+#[cfg(not(unix))]
+fn set_readonly_permissions(permissions: &mut std::fs::Permissions, readonly: bool) {
+    permissions.set_readonly(readonly);
+}
+
 /// Real filesystem implementation.
-#[derive(Debug, Clone, Default)]
-pub struct RealFs;
+#[derive(Debug, Clone, Copy)]
+pub struct RealFs {
+    preserve_xattrs: bool,
+}
+
+impl Default for RealFs {
+    fn default() -> Self {
+        Self { preserve_xattrs: true }
+    }
+}
+
+impl RealFs {
+    /// Whether a rewritten file keeps the original's extended attributes (on Unix, where they
+    /// exist): SELinux labels, macOS quarantine flags and resource forks, and the like.
+    pub fn with_preserve_xattrs(mut self, preserve: bool) -> Self {
+        self.preserve_xattrs = preserve;
+        self
+    }
+}
 
 impl FileSystem for RealFs {
     fn read(&self, path: &Path) -> Result<Vec<u8>> {
-        std::fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))
+        std::fs::read(long_path(path)).with_context(|| format!("Failed to read file: {}", path.display()))
+    }
+
+    fn read_mapped(&self, path: &Path) -> Result<FileContents> {
+        let file =
+            std::fs::File::open(long_path(path)).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        // SAFETY: the mapping is only read, and is dropped before the file is rewritten.
+        // Concurrent modification by another process could still change the bytes under us,
+        // which at worst yields a stale scan; the rewrite re-reads nothing from the map.
+        let map = unsafe { memmap2::Mmap::map(&file) }
+            .with_context(|| format!("Failed to memory-map file: {}", path.display()))?;
+        Ok(FileContents::Mapped(map))
     }
 
+    /// Written through a temp file like [`write_with`](Self::write_with), so a crash never
+    /// leaves the file truncated.
     fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
-        std::fs::write(path, content).with_context(|| format!("Failed to write file: {}", path.display()))
+        self.write_with(path, &mut |writer| {
+            writer
+                .write_all(content)
+                .with_context(|| format!("Failed to write file: {}", path.display()))
+        })
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        let file =
+            std::fs::File::open(long_path(path)).with_context(|| format!("Failed to open file: {}", path.display()))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        // Renaming over a symlink would replace the link itself, so write to its target
+        let path = &*resolve_symlink(path)?;
+        let path = &*long_path(path);
+
+        // Write to a sibling temp file and rename over the original so a failure
+        // midway never leaves a truncated file behind. A new file gets default permissions,
+        // and the temp file is new, so it gets the original's extended attributes by copying.
+        let temp_path = temp_sibling(path);
+        let result = (|| -> Result<()> {
+            let permissions = std::fs::metadata(path).ok().map(|metadata| metadata.permissions());
+            let file = create_temp(path, &temp_path)?;
+            let mut writer = BufWriter::new(file);
+            fill(&mut writer)?;
+            writer
+                .flush()
+                .with_context(|| format!("Failed to write temp file: {}", temp_path.display()))?;
+            drop(writer);
+            if self.preserve_xattrs && permissions.is_some() {
+                copy_xattrs(path, &temp_path)?;
+            }
+            if let Some(permissions) = permissions {
+                std::fs::set_permissions(&temp_path, permissions)
+                    .with_context(|| format!("Failed to set permissions: {}", temp_path.display()))?;
+            }
+            std::fs::rename(&temp_path, path).with_context(|| format!("Failed to replace file: {}", path.display()))
+        })();
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn write_in_place(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        // `fill` may read the file as it writes, so the new contents go to a temp file first
+        // and are copied over the original once complete. The temp file goes beside a
+        // symlink's target, where an intent log expects it
+        let path = &*resolve_symlink(path)?;
+        let path = &*long_path(path);
+        let temp_path = temp_sibling(path);
+        let written = (|| -> Result<std::fs::File> {
+            let file = create_temp(path, &temp_path)?;
+            let mut writer = BufWriter::new(file);
+            fill(&mut writer)?;
+            let mut file = writer
+                .into_inner()
+                .map_err(|e| e.into_error())
+                .with_context(|| format!("Failed to write temp file: {}", temp_path.display()))?;
+            file.rewind()
+                .with_context(|| format!("Failed to read temp file: {}", temp_path.display()))?;
+            Ok(file)
+        })();
+        let mut temp = match written {
+            Ok(temp) => temp,
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        };
+
+        // Unlike a rename, the copy can be cut short, so the complete contents are kept until
+        // it is done
+        std::fs::File::options()
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .and_then(|mut file| std::io::copy(&mut temp, &mut file))
+            .with_context(|| {
+                format!(
+                    "Failed to write {}; its new contents are in {}",
+                    path.display(),
+                    temp_path.display()
+                )
+            })?;
+        drop(temp);
+        let _ = std::fs::remove_file(&temp_path);
+        Ok(())
     }
 
     fn metadata(&self, path: &Path) -> Result<FsMetadata> {
-        std::fs::metadata(path)
+        std::fs::metadata(long_path(path))
             .map(FsMetadata::from)
             .with_context(|| format!("Failed to read metadata: {}", path.display()))
     }
 
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+        let mut permissions = std::fs::metadata(long_path(path))
+            .with_context(|| format!("Failed to read metadata: {}", path.display()))?
+            .permissions();
+        set_readonly_permissions(&mut permissions, readonly);
+        std::fs::set_permissions(long_path(path), permissions)
+            .with_context(|| format!("Failed to set permissions: {}", path.display()))
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+        let permissions = std::fs::metadata(long_path(from))
+            .with_context(|| format!("Failed to read metadata: {}", from.display()))?
+            .permissions();
+        std::fs::set_permissions(long_path(to), permissions)
+            .with_context(|| format!("Failed to set permissions: {}", to.display()))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(long_path(path)).with_context(|| format!("Failed to delete file: {}", path.display()))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(long_path(path))
+            .with_context(|| format!("Failed to create directory: {}", path.display()))
+    }
+
+    fn trash_copy(&self, path: &Path) -> Result<()> {
+        // Trashing a symlink would trash the link, so trash its target
+        let path = &*resolve_symlink(path)?;
+
+        // The original goes to the trash, so that the trash knows where it came from, and an
+        // exact copy, permissions and all, takes its place
+        let temp_path = temp_sibling(path);
+        std::fs::copy(long_path(path), &temp_path)
+            .with_context(|| format!("Failed to copy file: {}", path.display()))?;
+        if let Err(e) = trash::delete(path) {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(e).with_context(|| format!("Failed to move file to the trash: {}", path.display()));
+        }
+        std::fs::rename(&temp_path, long_path(path)).with_context(|| {
+            format!(
+                "Failed to put back {} after trashing it; its copy is {}",
+                path.display(),
+                temp_path.display()
+            )
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        // Entries are joined onto `path` so they never carry a long-path prefix
+        let mut entries = std::fs::read_dir(long_path(path))
+            .with_context(|| format!("Failed to read directory: {}", path.display()))?
+            .map(|entry| entry.map(|entry| path.join(entry.file_name())))
+            .collect::<std::io::Result<Vec<_>>>()
+            .with_context(|| format!("Failed to read directory: {}", path.display()))?;
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        std::fs::canonicalize(long_path(path)).with_context(|| format!("Failed to resolve path: {}", path.display()))
+    }
+
     fn is_dir(&self, path: &Path) -> bool {
-        path.is_dir()
+        long_path(path).is_dir()
     }
 
     fn is_file(&self, path: &Path) -> bool {
-        path.is_file()
+        long_path(path).is_file()
     }
 
     fn is_symlink(&self, path: &Path) -> bool {
-        path.is_symlink()
+        long_path(path).is_symlink()
     }
 
     fn exists(&self, path: &Path) -> bool {
-        path.exists()
+        long_path(path).exists()
+    }
+}
+
+/// Give `to` each extended attribute of `from`. A filesystem without them has none to copy; an
+/// attribute `to` can't take, such as a label only root may set, is left behind with a warning
+/// rather than failing the rewrite.
+#[cfg(unix)]
+fn copy_xattrs(from: &Path, to: &Path) -> Result<()> {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(e) if e.kind() == std::io::ErrorKind::Unsupported => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to list extended attributes: {}", from.display())),
+    };
+    for name in names {
+        let value = xattr::get(from, &name)
+            .with_context(|| format!("Failed to read extended attributes: {}", from.display()))?;
+        if let Some(value) = value
+            && let Err(e) = xattr::set(to, &name, &value)
+        {
+            tracing::warn!(
+                "Failed to keep extended attribute {} of {}: {}",
+                name.to_string_lossy(),
+                from.display(),
+                e
+            );
+        }
     }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_from: &Path, _to: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Windows paths longer than `MAX_PATH` only work with the `\\?\` prefix, which in turn only
+/// accepts absolute paths with `\` separators and no `.` or `..` components.
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::path::Component;
+    const MAX_PATH: usize = 260;
+
+    let Some(text) = path.to_str() else {
+        return Cow::Borrowed(path);
+    };
+    let relative = !path.is_absolute()
+        || path
+            .components()
+            .any(|component| matches!(component, Component::CurDir | Component::ParentDir));
+    if text.len() < MAX_PATH || relative || text.starts_with(r"\\?\") {
+        return Cow::Borrowed(path);
+    }
+
+    let text = text.replace('/', r"\");
+    let prefixed = match text.strip_prefix(r"\\") {
+        Some(unc) => format!(r"\\?\UNC\{}", unc),
+        None => format!(r"\\?\{}", text),
+    };
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Create `temp_path` to hold the new contents of `path`. Its name is predictable, so a file
+/// or link already there is never opened through: one a crashed run left is removed first, and
+/// anything else is an error. On Unix it starts out with `path`'s mode, so the contents are
+/// never readable by anyone the original keeps out.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn create_temp(path: &Path, temp_path: &Path) -> Result<std::fs::File> {
+    let mut options = std::fs::File::options();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    if let Ok(metadata) = std::fs::metadata(path) {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(metadata.permissions().mode() & 0o777);
+    }
+    let file = match options.open(temp_path) {
+        Err(e)
+            if e.kind() == std::io::ErrorKind::AlreadyExists
+                && std::fs::symlink_metadata(temp_path).is_ok_and(|metadata| metadata.file_type().is_file()) =>
+        {
+            tracing::debug!("Removing stale temp file: {}", temp_path.display());
+            std::fs::remove_file(temp_path).and_then(|()| options.open(temp_path))
+        }
+        result => result,
+    };
+    file.with_context(|| format!("Failed to create temp file: {}", temp_path.display()))
+}
+
+/// The target of `path` if it is a symlink, else `path` itself.
+fn resolve_symlink(path: &Path) -> Result<Cow<'_, Path>> {
+    if !path.is_symlink() {
+        return Ok(Cow::Borrowed(path));
+    }
+    std::fs::canonicalize(long_path(path))
+        .map(Cow::Owned)
+        .with_context(|| format!("Failed to resolve symlink: {}", path.display()))
+}
+
+/// Temp file path next to `path`, so the final rename stays on the same filesystem.
+pub(crate) fn temp_sibling(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.whitespace.tmp", name))
 }
 
 /// In-memory filesystem for testing.
+///
+/// Adding a file also adds its parent directories, so a tree can be built from file paths alone.
+/// A single lock guards the whole tree, so it is safe to share with the parallel engine.
 #[derive(Debug, Default)]
 pub struct MemFs {
-    files: RefCell<HashMap<PathBuf, Vec<u8>>>,
+    tree: RwLock<MemTree>,
+}
+
+#[derive(Debug, Default)]
+struct MemTree {
+    files: HashMap<PathBuf, Vec<u8>>,
+    dirs: BTreeSet<PathBuf>,
+    readonly: HashSet<PathBuf>,
+    trash: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemTree {
+    fn insert_file(&mut self, path: PathBuf, content: Vec<u8>) {
+        if let Some(parent) = path.parent() {
+            self.dirs.extend(
+                parent
+                    .ancestors()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .map(Path::to_path_buf),
+            );
+        }
+        self.files.insert(path, content);
+    }
 }
 
 impl MemFs {
     pub fn new() -> Self {
-        Self {
-            files: RefCell::new(HashMap::new()),
-        }
+        Self::default()
     }
 
     pub fn with_file<P: Into<PathBuf>>(self, path: P, content: &[u8]) -> Self {
-        self.files.borrow_mut().insert(path.into(), content.to_vec());
+        self.tree_mut().insert_file(path.into(), content.to_vec());
+        self
+    }
+
+    /// Add an (empty) directory and its parents.
+    pub fn with_dir<P: AsRef<Path>>(self, path: P) -> Self {
+        self.create_dir_all(path.as_ref())
+            .expect("directory path collides with a file");
         self
     }
 
     pub fn get_content(&self, path: &Path) -> Option<Vec<u8>> {
-        self.files.borrow().get(path).cloned()
+        self.tree().files.get(path).cloned()
+    }
+
+    /// The content last trashed from `path`.
+    pub fn get_trashed(&self, path: &Path) -> Option<Vec<u8>> {
+        self.tree().trash.get(path).cloned()
+    }
+
+    // A panic while holding the lock can't leave the maps half-updated, so poisoning is ignored
+    fn tree(&self) -> RwLockReadGuard<'_, MemTree> {
+        self.tree.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn tree_mut(&self) -> RwLockWriteGuard<'_, MemTree> {
+        self.tree.write().unwrap_or_else(|e| e.into_inner())
     }
 }
 
 impl FileSystem for MemFs {
     fn read(&self, path: &Path) -> Result<Vec<u8>> {
-        self.files
-            .borrow()
-            .get(path)
-            .cloned()
+        self.get_content(path)
             .ok_or_else(|| eyre::eyre!("File not found: {}", path.display()))
     }
 
     fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
-        self.files.borrow_mut().insert(path.to_path_buf(), content.to_vec());
+        let mut tree = self.tree_mut();
+        if tree.dirs.contains(path) {
+            eyre::bail!("Is a directory: {}", path.display());
+        }
+        if tree.readonly.contains(path) {
+            eyre::bail!("Permission denied: {}", path.display());
+        }
+        tree.insert_file(path.to_path_buf(), content.to_vec());
         Ok(())
     }
 
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        let content = self.read(path)?;
+        Ok(Box::new(Cursor::new(content)))
+    }
+
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        let mut content = Vec::new();
+        fill(&mut content)?;
+        self.write(path, &content)
+    }
+
     fn metadata(&self, path: &Path) -> Result<FsMetadata> {
-        let files = self.files.borrow();
-        if let Some(content) = files.get(path) {
+        let tree = self.tree();
+        if let Some(content) = tree.files.get(path) {
             Ok(FsMetadata {
                 len: content.len() as u64,
                 is_file: true,
                 is_dir: false,
+                modified: None,
+                file_id: None,
+                readonly: tree.readonly.contains(path),
+                links: 1,
+                special: false,
+                allocated: None,
+            })
+        } else if tree.dirs.contains(path) {
+            Ok(FsMetadata {
+                len: 0,
+                is_file: false,
+                is_dir: true,
+                modified: None,
+                file_id: None,
+                readonly: false,
+                links: 1,
+                special: false,
+                allocated: None,
             })
         } else {
             Err(eyre::eyre!("File not found: {}", path.display()))
         }
     }
 
-    fn is_dir(&self, _path: &Path) -> bool {
-        false
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+        let mut tree = self.tree_mut();
+        if !tree.files.contains_key(path) {
+            eyre::bail!("File not found: {}", path.display());
+        }
+        if readonly {
+            tree.readonly.insert(path.to_path_buf());
+        } else {
+            tree.readonly.remove(path);
+        }
+        Ok(())
+    }
+
+    /// Only read-only-ness is tracked, so that is all that is copied.
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+        let readonly = self.metadata(from)?.readonly;
+        self.set_readonly(to, readonly)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut tree = self.tree_mut();
+        if tree.files.remove(path).is_none() {
+            eyre::bail!("File not found: {}", path.display());
+        }
+        tree.readonly.remove(path);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut tree = self.tree_mut();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            if tree.files.contains_key(dir) {
+                eyre::bail!("Not a directory: {}", dir.display());
+            }
+            tree.dirs.insert(dir.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn trash_copy(&self, path: &Path) -> Result<()> {
+        let content = self.read(path)?;
+        self.tree_mut().trash.insert(path.to_path_buf(), content);
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let tree = self.tree();
+        if !tree.dirs.contains(path) {
+            eyre::bail!("Directory not found: {}", path.display());
+        }
+        let is_child = |entry: &&PathBuf| entry.parent() == Some(path);
+        let mut entries: Vec<PathBuf> = tree.dirs.iter().filter(is_child).cloned().collect();
+        entries.extend(tree.files.keys().filter(is_child).cloned());
+        entries.sort();
+        Ok(entries)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        if !self.exists(path) {
+            eyre::bail!("File not found: {}", path.display());
+        }
+        Ok(path.to_path_buf())
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.tree().dirs.contains(path)
     }
 
     fn is_file(&self, path: &Path) -> bool {
-        self.files.borrow().contains_key(path)
+        self.tree().files.contains_key(path)
     }
 
     fn is_symlink(&self, _path: &Path) -> bool {
@@ -132,13 +701,10 @@ impl FileSystem for MemFs {
     }
 
     fn exists(&self, path: &Path) -> bool {
-        self.files.borrow().contains_key(path)
+        self.is_file(path) || self.is_dir(path)
     }
 }
 
-// MemFs is not Sync due to RefCell, but we can make it work for single-threaded tests
-unsafe impl Sync for MemFs {}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,9 +737,205 @@ mod tests {
         assert!(fs.read(Path::new("missing.txt")).is_err());
     }
 
+    #[test]
+    fn test_memfs_directories() {
+        let fs = MemFs::new()
+            .with_file("/project/src/main.rs", b"fn main() {}\n")
+            .with_file("/project/README.md", b"readme\n")
+            .with_dir("/project/empty");
+
+        assert!(fs.is_dir(Path::new("/project")));
+        assert!(fs.is_dir(Path::new("/project/src")));
+        assert!(fs.metadata(Path::new("/project/src")).unwrap().is_dir);
+        assert!(fs.exists(Path::new("/project/empty")));
+        assert!(!fs.is_file(Path::new("/project/src")));
+
+        assert_eq!(
+            fs.read_dir(Path::new("/project")).unwrap(),
+            vec![
+                PathBuf::from("/project/README.md"),
+                PathBuf::from("/project/empty"),
+                PathBuf::from("/project/src"),
+            ]
+        );
+        assert!(fs.read_dir(Path::new("/project/empty")).unwrap().is_empty());
+        assert!(fs.read_dir(Path::new("/project/README.md")).is_err());
+
+        assert!(fs.create_dir_all(Path::new("/project/README.md/nested")).is_err());
+        assert!(fs.write(Path::new("/project/src"), b"oops").is_err());
+    }
+
+    #[test]
+    fn test_realfs_read_dir_sorted() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("b.txt"), "b").unwrap();
+        std::fs::write(temp_dir.path().join("a.txt"), "a").unwrap();
+        std::fs::create_dir(temp_dir.path().join("c")).unwrap();
+
+        assert_eq!(
+            RealFs::default().read_dir(temp_dir.path()).unwrap(),
+            vec![
+                temp_dir.path().join("a.txt"),
+                temp_dir.path().join("b.txt"),
+                temp_dir.path().join("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_memfs_reader_and_write_with() {
+        let fs = MemFs::new().with_file("test.txt", b"line1\nline2\n");
+
+        let lines: Vec<String> = fs
+            .reader(Path::new("test.txt"))
+            .unwrap()
+            .lines()
+            .map(|l| l.unwrap())
+            .collect();
+        assert_eq!(lines, vec!["line1", "line2"]);
+
+        fs.write_with(Path::new("test.txt"), &mut |w| Ok(w.write_all(b"rewritten")?))
+            .unwrap();
+        assert_eq!(fs.get_content(Path::new("test.txt")).unwrap(), b"rewritten");
+    }
+
+    #[test]
+    fn test_realfs_write_with_replaces_content() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.txt");
+        std::fs::write(&path, "old content").unwrap();
+
+        RealFs::default()
+            .write_with(&path, &mut |w| Ok(w.write_all(b"new content")?))
+            .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new content");
+        assert!(!temp_sibling(&path).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_realfs_write_replaces_file() {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.txt");
+        std::fs::write(&path, "old content").unwrap();
+        let inode = std::fs::metadata(&path).unwrap().ino();
+
+        // A new file renamed into place, never the old one truncated
+        RealFs::default().write(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_ne!(std::fs::metadata(&path).unwrap().ino(), inode);
+        assert!(!temp_sibling(&path).exists());
+    }
+
+    #[test]
+    fn test_realfs_write_with_failure_keeps_original() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.txt");
+        std::fs::write(&path, "original").unwrap();
+
+        let result = RealFs::default().write_with(&path, &mut |w| {
+            w.write_all(b"partial")?;
+            Err(eyre::eyre!("boom"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+        assert!(!temp_sibling(&path).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_realfs_temp_file_is_new_and_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("secret.txt");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        // A link planted at the temp name is an error, never written through
+        let victim = temp_dir.path().join("victim.txt");
+        std::fs::write(&victim, "untouched").unwrap();
+        std::os::unix::fs::symlink(&victim, temp_sibling(&path)).unwrap();
+        assert!(RealFs::default().write(&path, b"new").is_err());
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "untouched");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+
+        // A stale temp file is replaced, and the new one is private while it's written
+        std::fs::write(temp_sibling(&path), "stale").unwrap();
+        RealFs::default()
+            .write_with(&path, &mut |w| {
+                let mode = std::fs::metadata(temp_sibling(&path))?.permissions().mode();
+                assert_eq!(mode & 0o777, 0o600);
+                w.write_all(b"new")?;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_realfs_write_with_through_symlink() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let target = temp_dir.path().join("target.txt");
+        let link = temp_dir.path().join("link.txt");
+        std::fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        RealFs::default()
+            .write_with(&link, &mut |w| Ok(w.write_all(b"new")?))
+            .unwrap();
+
+        assert!(link.is_symlink());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "new");
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_realfs_write_with_preserves_xattrs() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.txt");
+        std::fs::write(&path, "old").unwrap();
+        if xattr::set(&path, "user.whitespace.test", b"kept").is_err() {
+            // The temp directory's filesystem has no user attributes to keep
+            return;
+        }
+
+        RealFs::default()
+            .write_with(&path, &mut |w| Ok(w.write_all(b"new")?))
+            .unwrap();
+        assert_eq!(
+            xattr::get(&path, "user.whitespace.test").unwrap(),
+            Some(b"kept".to_vec())
+        );
+
+        RealFs::default()
+            .with_preserve_xattrs(false)
+            .write_with(&path, &mut |w| Ok(w.write_all(b"newer")?))
+            .unwrap();
+        assert_eq!(xattr::get(&path, "user.whitespace.test").unwrap(), None);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "newer");
+    }
+
+    #[test]
+    fn test_realfs_read_mapped() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.txt");
+        std::fs::write(&path, "mapped content").unwrap();
+
+        let contents = RealFs::default().read_mapped(&path).unwrap();
+        assert!(matches!(contents, FileContents::Mapped(_)));
+        assert_eq!(&*contents, b"mapped content");
+    }
+
     #[test]
     fn test_realfs_exists() {
-        let fs = RealFs;
+        let fs = RealFs::default();
         assert!(fs.exists(Path::new("Cargo.toml")));
         assert!(!fs.exists(Path::new("nonexistent-file-12345.txt")));
     }
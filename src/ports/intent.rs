@@ -0,0 +1,567 @@
+use crate::cache::ContentHasher;
+use crate::ports::fs::{FileContents, FileSystem, FsMetadata, RealFs, WriteFn, temp_sibling};
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, TryLockError};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// A write-ahead log of the rewrites a run is making, for `processing.intent-log`.
+///
+/// Files are rewritten by writing a temp file beside them and renaming it over the original,
+/// or, for rewrites in place, copying it into the original. Where rename isn't atomic, as on
+/// some network mounts, a crash can stop a rewrite halfway; anywhere, it leaves the temp file
+/// behind. So before each rewrite the log records the file and how it is written, once the new
+/// content is written it records its length and hash, and once the rename or copy is done it
+/// records that. Each entry is synced to disk before the rewrite goes on, and the next
+/// run reads the logs of crashed runs with [`recover`] to finish or undo what they left.
+///
+/// The log is locked while its run is alive, so recovery never touches a run still going, and
+/// removed when the run ends with no rewrite in flight.
+#[derive(Debug)]
+pub struct IntentLog {
+    path: PathBuf,
+    state: Mutex<LogState>,
+}
+
+#[derive(Debug)]
+struct LogState {
+    file: File,
+    /// Rewrites begun and not yet done
+    in_flight: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Entry {
+    /// `path` is about to be rewritten through the temp file `temp`, copied into the file
+    /// rather than renamed over it if `in_place`
+    Begin {
+        path: PathBuf,
+        temp: PathBuf,
+        #[serde(default)]
+        in_place: bool,
+    },
+    /// The temp file holds all of the new content: `len` bytes that hash to `hash`
+    Written { path: PathBuf, len: u64, hash: u64 },
+    /// The rewrite replaced the file, or failed and cleaned up after itself
+    Done { path: PathBuf },
+}
+
+/// What [`recover`] found of a rewrite a crashed run left unfinished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// The new content was never completely written, so the file is as it was; its partial
+    /// temp file is removed
+    Undone(PathBuf),
+    /// The new content was completely written but hadn't replaced the file; it now does
+    Finished(PathBuf),
+    /// Neither the file nor its new content is intact, and it needs restoring from elsewhere
+    Damaged(PathBuf),
+}
+
+impl IntentLog {
+    /// Start a log for this run in the default log directory.
+    pub fn start() -> Result<Self> {
+        Self::start_in(&default_log_dir()?)
+    }
+
+    /// Start a log for this run in `dir`.
+    pub fn start_in(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let path = dir.join(format!("{}-{}.jsonl", nanos, std::process::id()));
+        let file = File::options()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create intent log {}", path.display()))?;
+        file.lock()
+            .with_context(|| format!("Failed to lock intent log {}", path.display()))?;
+        debug!("Logging rewrites in {}", path.display());
+        Ok(Self {
+            path,
+            state: Mutex::new(LogState { file, in_flight: 0 }),
+        })
+    }
+
+    /// Record that `path` is about to be rewritten, in place or not.
+    fn begin(&self, path: &Path, in_place: bool) -> Result<()> {
+        let temp = temp_sibling(path);
+        self.append(
+            &Entry::Begin {
+                path: path.to_path_buf(),
+                temp,
+                in_place,
+            },
+            1,
+        )
+    }
+
+    /// Record that the new content of `path` is completely written.
+    fn written(&self, path: &Path, len: u64, hash: u64) -> Result<()> {
+        let path = path.to_path_buf();
+        self.append(&Entry::Written { path, len, hash }, 0)
+    }
+
+    /// Record that the rewrite of `path` is over, one way or the other.
+    fn done(&self, path: &Path) -> Result<()> {
+        self.append(
+            &Entry::Done {
+                path: path.to_path_buf(),
+            },
+            -1,
+        )
+    }
+
+    fn append(&self, entry: &Entry, in_flight: isize) -> Result<()> {
+        let mut line = serde_json::to_vec(entry).context("Failed to serialize intent log entry")?;
+        line.push(b'\n');
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_flight = state.in_flight.saturating_add_signed(in_flight);
+        // The entry must be on disk before the rewrite it announces goes ahead
+        state
+            .file
+            .write_all(&line)
+            .and_then(|()| state.file.sync_data())
+            .with_context(|| format!("Failed to write intent log {}", self.path.display()))
+    }
+}
+
+impl Drop for IntentLog {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap_or_else(|e| e.into_inner());
+        if state.in_flight > 0 {
+            warn!("Keeping intent log {} for the next run to recover", self.path.display());
+        } else if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove intent log {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The real filesystem, with every rewrite recorded in an [`IntentLog`] first.
+#[derive(Debug)]
+pub struct IntentLogFs {
+    fs: RealFs,
+    log: IntentLog,
+}
+
+impl IntentLogFs {
+    pub fn new(fs: RealFs, log: IntentLog) -> Self {
+        Self { fs, log }
+    }
+}
+
+impl FileSystem for IntentLogFs {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.fs.read(path)
+    }
+
+    fn read_mapped(&self, path: &Path) -> Result<FileContents> {
+        self.fs.read_mapped(path)
+    }
+
+    /// Written through a temp file like [`write_with`](Self::write_with), so that it is logged.
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.write_with(path, &mut |writer| Ok(writer.write_all(content)?))
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        self.fs.reader(path)
+    }
+
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        self.logged(path, false, fill, |path, fill| self.fs.write_with(path, fill))
+    }
+
+    /// Logged like [`write_with`](Self::write_with): a crash while the new contents are copied
+    /// in leaves them complete in the temp file, which recovery copies into the file.
+    fn write_in_place(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        self.logged(path, true, fill, |path, fill| self.fs.write_in_place(path, fill))
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+        self.fs.metadata(path)
+    }
+
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+        self.fs.set_readonly(path, readonly)
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+        self.fs.copy_permissions(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.fs.remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn trash_copy(&self, path: &Path) -> Result<()> {
+        self.fs.trash_copy(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.fs.read_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.fs.canonicalize(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.fs.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.fs.is_file(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.fs.is_symlink(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.fs.exists(path)
+    }
+}
+
+impl IntentLogFs {
+    /// Run `write` on `path` and `fill`, logging the rewrite, in place or not, around it.
+    fn logged(
+        &self,
+        path: &Path,
+        in_place: bool,
+        fill: &mut WriteFn,
+        write: impl FnOnce(&Path, &mut WriteFn) -> Result<()>,
+    ) -> Result<()> {
+        // Logged by absolute path, through any symlink, as that is where the temp file goes
+        // and where the next run, from wherever it starts, has to look
+        let target = match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(_) => std::path::absolute(path).with_context(|| format!("Failed to resolve {}", path.display()))?,
+        };
+        self.log.begin(&target, in_place)?;
+        let result = write(path, &mut |writer| {
+            let mut hashing = HashingWriter {
+                inner: writer,
+                hasher: ContentHasher::new(),
+                len: 0,
+            };
+            fill(&mut hashing)?;
+            self.log.written(&target, hashing.len, hashing.hasher.finish())
+        });
+        // A failed rewrite removes its temp file, leaving the original as it was, unless it
+        // failed while copying it in: then the next run has to put the temp file in its place
+        if result.is_ok() || !temp_sibling(&target).exists() {
+            self.log.done(&target)?;
+        }
+        result
+    }
+}
+
+/// Counts and hashes what passes through it on the way to `inner`.
+struct HashingWriter<'w> {
+    inner: &'w mut dyn Write,
+    hasher: ContentHasher,
+    len: u64,
+}
+
+impl Write for HashingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Where intent logs live: `~/.local/share/whitespace/intents`, beside the run journals.
+pub fn default_log_dir() -> Result<PathBuf> {
+    Ok(dirs::data_local_dir()
+        .ok_or_else(|| eyre::eyre!("Could not determine local data directory"))?
+        .join("whitespace")
+        .join("intents"))
+}
+
+/// Find the rewrites crashed runs left unfinished in the default log directory, and with
+/// `repair` finish or undo them. See [`recover_in`].
+pub fn recover(repair: bool) -> Result<Vec<Recovery>> {
+    recover_in(&default_log_dir()?, repair)
+}
+
+/// Find the rewrites crashed runs left unfinished in the logs in `dir`, and with `repair`
+/// finish those whose new content was completely written, remove the partial temp files of
+/// the others, and remove the logs. Logs of runs still going are left alone.
+pub fn recover_in(dir: &Path, repair: bool) -> Result<Vec<Recovery>> {
+    let mut logs = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == "jsonl"))
+            .collect::<Vec<_>>(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    logs.sort();
+
+    let mut recoveries = Vec::new();
+    for log in logs {
+        let file = File::open(&log).with_context(|| format!("Failed to open intent log {}", log.display()))?;
+        match file.try_lock_shared() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => continue,
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock intent log {}", log.display()));
+            }
+        }
+        let content =
+            fs::read_to_string(&log).with_context(|| format!("Failed to read intent log {}", log.display()))?;
+        for (path, rewrite) in unfinished(&content) {
+            if let Some(recovery) = recover_rewrite(path, rewrite, repair)? {
+                recoveries.push(recovery);
+            }
+        }
+        if repair {
+            drop(file);
+            fs::remove_file(&log).with_context(|| format!("Failed to remove intent log {}", log.display()))?;
+        }
+    }
+    Ok(recoveries)
+}
+
+/// A rewrite a log began but never recorded done.
+#[derive(Debug, Default)]
+struct Rewrite {
+    temp: PathBuf,
+    in_place: bool,
+    written: Option<(u64, u64)>,
+}
+
+/// The rewrites in a log's `content` that were begun and not done, in the order they began.
+fn unfinished(content: &str) -> Vec<(PathBuf, Rewrite)> {
+    let mut order = Vec::new();
+    let mut rewrites = HashMap::new();
+    for line in content.lines() {
+        // A crash can leave the last line half written; its rewrite hadn't started
+        let Ok(entry) = serde_json::from_str::<Entry>(line) else {
+            continue;
+        };
+        match entry {
+            Entry::Begin { path, temp, in_place } => {
+                order.push(path.clone());
+                let rewrite = Rewrite {
+                    temp,
+                    in_place,
+                    written: None,
+                };
+                rewrites.insert(path, rewrite);
+            }
+            Entry::Written { path, len, hash } => {
+                if let Some(rewrite) = rewrites.get_mut(&path) {
+                    rewrite.written = Some((len, hash));
+                }
+            }
+            Entry::Done { path } => {
+                rewrites.remove(&path);
+            }
+        }
+    }
+    order
+        .into_iter()
+        .filter_map(|path| rewrites.remove(&path).map(|rewrite| (path, rewrite)))
+        .collect()
+}
+
+/// What became of one unfinished rewrite, finishing or undoing it with `repair`. None if there
+/// is nothing to recover: the rewrite never got going, or got all the way.
+fn recover_rewrite(path: PathBuf, rewrite: Rewrite, repair: bool) -> Result<Option<Recovery>> {
+    let intact = |file: &Path| {
+        fs::read(file)
+            .ok()
+            .map(|content| (content.len() as u64, hash(&content)))
+    };
+    let temp = intact(&rewrite.temp);
+    match (rewrite.written, temp) {
+        // Complete new content that never replaced the file
+        (Some(written), Some(temp)) if temp == written => {
+            if repair {
+                finish(&path, &rewrite)?;
+            }
+            Ok(Some(Recovery::Finished(path)))
+        }
+        // A partial temp file, so the rename never happened
+        (_, Some(_)) => {
+            if repair {
+                fs::remove_file(&rewrite.temp)
+                    .with_context(|| format!("Failed to remove {}", rewrite.temp.display()))?;
+            }
+            Ok(Some(Recovery::Undone(path)))
+        }
+        (None, None) => Ok(None),
+        // The temp file is gone, so the rename happened: did all of it?
+        (Some(written), None) if intact(&path) == Some(written) => Ok(None),
+        (Some(_), None) => Ok(Some(Recovery::Damaged(path))),
+    }
+}
+
+/// Put the complete new content of `rewrite` in `path`'s place the way the rewrite would have:
+/// rewrites in place copy it into the file, so that its hard links and ownership stay as they
+/// were, and the others rename the temp file over it.
+fn finish(path: &Path, rewrite: &Rewrite) -> Result<()> {
+    if !rewrite.in_place {
+        return fs::rename(&rewrite.temp, path).with_context(|| format!("Failed to replace file: {}", path.display()));
+    }
+    // The temp file is kept until the copy is done, so a crash now leaves it to the next run
+    let mut temp = File::open(&rewrite.temp).with_context(|| format!("Failed to open {}", rewrite.temp.display()))?;
+    File::options()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .and_then(|mut file| std::io::copy(&mut temp, &mut file))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    drop(temp);
+    fs::remove_file(&rewrite.temp).with_context(|| format!("Failed to remove {}", rewrite.temp.display()))
+}
+
+fn hash(content: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::new();
+    hasher.update(content);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_completed_rewrites_leave_no_log() {
+        let logs = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.txt");
+        fs::write(&path, "a  \n").unwrap();
+
+        let fs = IntentLogFs::new(RealFs::default(), IntentLog::start_in(logs.path()).unwrap());
+        fs.write(&path, b"a\n").unwrap();
+        assert!(fs.write(&dir.path().join("missing/b.txt"), b"b\n").is_err());
+        drop(fs);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n");
+        assert_eq!(fs::read_dir(logs.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_recover_interrupted_rewrites() {
+        let logs = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let file = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let written = |log: &IntentLog, path: &Path, content: &str| {
+            log.written(path, content.len() as u64, hash(content.as_bytes()))
+                .unwrap();
+        };
+
+        // As crashes at each step would leave them
+        let log = IntentLog::start_in(logs.path()).unwrap();
+        let partial = file("partial.txt", "old  \n");
+        log.begin(&partial, false).unwrap();
+        fs::write(temp_sibling(&partial), "ne").unwrap();
+        let unrenamed = file("unrenamed.txt", "old  \n");
+        log.begin(&unrenamed, false).unwrap();
+        fs::write(temp_sibling(&unrenamed), "new\n").unwrap();
+        written(&log, &unrenamed, "new\n");
+        let renamed = file("renamed.txt", "new\n");
+        log.begin(&renamed, false).unwrap();
+        written(&log, &renamed, "new\n");
+        let torn = file("torn.txt", "ne");
+        log.begin(&torn, false).unwrap();
+        written(&log, &torn, "new\n");
+        let done = file("done.txt", "new\n");
+        log.begin(&done, false).unwrap();
+        log.done(&done).unwrap();
+        drop(log);
+        // A run still going is left alone
+        let running = IntentLog::start_in(logs.path()).unwrap();
+        running.begin(&file("running.txt", "old  \n"), false).unwrap();
+
+        let expected = vec![
+            Recovery::Undone(partial.clone()),
+            Recovery::Finished(unrenamed.clone()),
+            Recovery::Damaged(torn.clone()),
+        ];
+        assert_eq!(recover_in(logs.path(), false).unwrap(), expected);
+        assert!(temp_sibling(&partial).exists());
+        assert_eq!(recover_in(logs.path(), true).unwrap(), expected);
+        assert!(!temp_sibling(&partial).exists());
+        assert_eq!(fs::read_to_string(&partial).unwrap(), "old  \n");
+        assert!(!temp_sibling(&unrenamed).exists());
+        assert_eq!(fs::read_to_string(&unrenamed).unwrap(), "new\n");
+        assert_eq!(fs::read_dir(logs.path()).unwrap().count(), 1);
+        assert!(recover_in(logs.path(), true).unwrap().is_empty());
+        drop(running);
+    }
+
+    #[test]
+    fn test_recover_in_place_rewrite_keeps_hard_links() {
+        let logs = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("a.txt");
+        let link = dir.path().join("b.txt");
+        fs::write(&path, "old  \n").unwrap();
+        fs::hard_link(&path, &link).unwrap();
+
+        let log = IntentLog::start_in(logs.path()).unwrap();
+        log.begin(&path, true).unwrap();
+        fs::write(temp_sibling(&path), "new\n").unwrap();
+        log.written(&path, 4, hash(b"new\n")).unwrap();
+        drop(log);
+
+        assert_eq!(
+            recover_in(logs.path(), true).unwrap(),
+            [Recovery::Finished(path.clone())]
+        );
+        assert!(!temp_sibling(&path).exists());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+        assert_eq!(fs::read_to_string(&link).unwrap(), "new\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_in_place_temp_file_is_where_the_log_says() {
+        let logs = TempDir::new().unwrap();
+        let dir = TempDir::new().unwrap();
+        let target = dir.path().join("target/a.txt");
+        fs::create_dir(dir.path().join("target")).unwrap();
+        fs::write(&target, "a  \n").unwrap();
+        let link = dir.path().join("link.txt");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let fs = IntentLogFs::new(RealFs::default(), IntentLog::start_in(logs.path()).unwrap());
+        fs.write_in_place(&link, &mut |writer| {
+            assert!(temp_sibling(&target.canonicalize()?).exists());
+            Ok(writer.write_all(b"a\n")?)
+        })
+        .unwrap();
+        drop(fs);
+
+        assert!(link.is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "a\n");
+    }
+}
@@ -0,0 +1,224 @@
+use crate::ports::fs::{FileContents, FileSystem, FsMetadata, WriteFn};
+use eyre::Result;
+use std::io::BufRead;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// A filesystem that reads through to `inner` but writes each file to the same place in a
+/// mirrored tree under `out`, for `--write-to`: the originals are never touched. Each copy
+/// gets its original's permissions, so scripts stay executable.
+///
+/// A file's place in the mirror is its path relative to the current directory, so
+/// `./src/main.rs` is written to `out/src/main.rs`. Files outside the current directory keep
+/// their whole absolute path, so `/tmp/x.txt` is written to `out/tmp/x.txt`.
+#[derive(Debug)]
+pub struct MirrorFs<F> {
+    inner: Arc<F>,
+    out: PathBuf,
+    cwd: PathBuf,
+}
+
+impl<F: FileSystem> MirrorFs<F> {
+    pub fn new(inner: Arc<F>, out: impl Into<PathBuf>) -> Self {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        Self {
+            inner,
+            out: out.into(),
+            cwd: normalize(&cwd),
+        }
+    }
+
+    /// Where the file at `path` is written in the mirror.
+    pub fn target(&self, path: &Path) -> PathBuf {
+        let absolute = normalize(&self.cwd.join(path));
+        let path = absolute.strip_prefix(&self.cwd).unwrap_or(&absolute);
+        let mut target = self.out.clone();
+        target.extend(
+            path.components()
+                .filter(|component| matches!(component, Component::Normal(_))),
+        );
+        target
+    }
+
+    /// Create the directory the mirror copy of `path` goes in, returning where that copy goes.
+    fn prepare(&self, path: &Path) -> Result<PathBuf> {
+        let target = self.target(path);
+        if let Some(parent) = target.parent() {
+            self.inner.create_dir_all(parent)?;
+        }
+        Ok(target)
+    }
+}
+
+/// `path` with `.` and `..` resolved by their names alone, not by following any links.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+impl<F: FileSystem> FileSystem for MirrorFs<F> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.inner.read(path)
+    }
+
+    fn read_mapped(&self, path: &Path) -> Result<FileContents> {
+        self.inner.read_mapped(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        let target = self.prepare(path)?;
+        self.inner.write(&target, content)?;
+        self.inner.copy_permissions(path, &target)
+    }
+
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        self.inner.reader(path)
+    }
+
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        let target = self.prepare(path)?;
+        self.inner.write_with(&target, fill)?;
+        self.inner.copy_permissions(path, &target)
+    }
+
+    /// The original's metadata, except that it is never read-only or linked: its copy is
+    /// written anew.
+    fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+        let metadata = self.inner.metadata(path)?;
+        Ok(FsMetadata {
+            readonly: false,
+            links: 1,
+            ..metadata
+        })
+    }
+
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+        self.inner.set_readonly(&self.target(path), readonly)
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_permissions(from, &self.target(to))
+    }
+
+    /// Deletes the copy in the mirror, if one was written; the original stays.
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let target = self.target(path);
+        if !self.inner.is_file(&target) {
+            return Ok(());
+        }
+        self.inner.remove_file(&target)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.inner.create_dir_all(&self.target(path))
+    }
+
+    /// Copies in the mirror are new, so there is nothing to trash.
+    fn trash_copy(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.inner.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.inner.is_file(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.inner.is_symlink(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::ports::fs::MemFs;
+    use crate::processor::WhitespaceProcessor;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_mirror_writes_beside_the_originals() {
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/src/main.rs", b"fn main() {}  \n")
+                .with_file("/repo/README.md", b"clean\n"),
+        );
+        let mirror = Arc::new(MirrorFs::new(Arc::clone(&memfs), "/out"));
+        assert_eq!(
+            mirror.target(Path::new("./src/../src/main.rs")),
+            PathBuf::from("/out/src/main.rs")
+        );
+        assert_eq!(
+            mirror.target(Path::new("src/main.rs")),
+            PathBuf::from("/out/src/main.rs")
+        );
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(
+            mirror.target(&cwd.join("src/main.rs")),
+            PathBuf::from("/out/src/main.rs")
+        );
+        assert_eq!(
+            mirror.target(Path::new("/repo/src/main.rs")),
+            PathBuf::from("/out/repo/src/main.rs")
+        );
+
+        let processor =
+            WhitespaceProcessor::new(Arc::new(Config::default()), Arc::clone(&mirror)).with_write_unchanged();
+        for path in ["/repo/src/main.rs", "/repo/README.md"] {
+            processor.process_file(Path::new(path), false).unwrap();
+        }
+        assert_eq!(
+            memfs.get_content(Path::new("/repo/src/main.rs")).unwrap(),
+            b"fn main() {}  \n"
+        );
+        assert_eq!(
+            memfs.get_content(Path::new("/out/repo/src/main.rs")).unwrap(),
+            b"fn main() {}\n"
+        );
+        assert_eq!(memfs.get_content(Path::new("/out/repo/README.md")).unwrap(), b"clean\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mirror_copies_keep_permissions() {
+        use crate::ports::fs::RealFs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let script = temp_dir.path().join("deploy.sh");
+        std::fs::write(&script, "#!/bin/sh\necho  \n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let out = temp_dir.path().join("out");
+        let mirror = Arc::new(MirrorFs::new(Arc::new(RealFs::default()), &out));
+
+        let processor = WhitespaceProcessor::new(Arc::new(Config::default()), Arc::clone(&mirror));
+        processor.process_file(&script, false).unwrap();
+        let copy = mirror.target(&script);
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "#!/bin/sh\necho\n");
+        assert_eq!(std::fs::metadata(&copy).unwrap().permissions().mode() & 0o777, 0o750);
+    }
+}
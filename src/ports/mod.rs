@@ -1 +1,4 @@
 pub mod fs;
+pub mod intent;
+pub mod mirror;
+pub mod network;
@@ -0,0 +1,384 @@
+use crate::config::ProcessingSettings;
+use crate::ports::fs::{FileContents, FileSystem, FsMetadata, WriteFn};
+use eyre::Result;
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+/// Wait before the first retry of a failed operation, doubled before each one after it.
+const BACKOFF: Duration = Duration::from_millis(100);
+
+/// `EIO`, which has no `io::ErrorKind` of its own and is the same number on every Unix.
+#[cfg(unix)]
+const EIO: i32 = 5;
+
+thread_local! {
+    /// Permits the current thread holds, so that an operation started while it holds one, such
+    /// as writing a file while streaming from a reader, never waits on a permit of its own.
+    static HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A filesystem that reads and writes through `inner` the way a network filesystem needs
+/// them: at most `io-threads` files at once, however many threads process them, and with
+/// operations that fail with a transient error (EIO, ESTALE) tried again after a backoff.
+///
+/// Only operations that can be repeated from the start are retried. Writing with a callback is
+/// not, since the callback may already have consumed what it writes.
+#[derive(Debug)]
+pub struct NetworkFs<F> {
+    inner: Arc<F>,
+    permits: Option<Permits>,
+    retries: u32,
+}
+
+impl<F: FileSystem> NetworkFs<F> {
+    pub fn new(inner: Arc<F>, io_threads: Option<usize>, retries: u32) -> Self {
+        Self {
+            inner,
+            permits: io_threads.map(Permits::new),
+            retries,
+        }
+    }
+
+    /// Wrap `inner` with the limit and retries `processing` asks for. Without either, every
+    /// operation passes straight through.
+    pub fn with_settings(inner: Arc<F>, processing: &ProcessingSettings) -> Self {
+        Self::new(inner, processing.io_threads, processing.io_retries)
+    }
+
+    /// Hold a permit for as long as the returned guard lives.
+    fn permit(&self) -> Permit<'_> {
+        Permit::acquire(self.permits.as_ref())
+    }
+
+    /// Run `op` holding a permit, trying it again while it fails with a transient error and
+    /// retries are left. The permit is given up during each backoff.
+    fn retried<T>(&self, path: &Path, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            let result = {
+                let _permit = self.permit();
+                op()
+            };
+            match result {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    let delay = BACKOFF * 2u32.pow(attempt);
+                    warn!(
+                        "Retrying {} in {:?} after a transient error: {:#}",
+                        path.display(),
+                        delay,
+                        e
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// True if `error` was caused by an I/O error that going away and trying again may cure: a
+/// stale NFS file handle, or EIO from a server that stopped answering for a while.
+pub fn is_transient(error: &eyre::Report) -> bool {
+    error
+        .chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::StaleNetworkFileHandle || is_eio(e))
+}
+
+#[cfg(unix)]
+fn is_eio(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(EIO)
+}
+
+#[cfg(not(unix))]
+fn is_eio(_error: &io::Error) -> bool {
+    false
+}
+
+/// A counting semaphore limiting how many files are read or written at once.
+#[derive(Debug)]
+struct Permits {
+    free: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Permits {
+    fn new(count: usize) -> Self {
+        Self {
+            free: Mutex::new(count.max(1)),
+            freed: Condvar::new(),
+        }
+    }
+}
+
+/// A permit held from [`Permits`], or none if there is no limit or this thread already holds
+/// one.
+struct Permit<'a> {
+    from: Option<&'a Permits>,
+}
+
+impl<'a> Permit<'a> {
+    fn acquire(permits: Option<&'a Permits>) -> Self {
+        let held = HELD.with(|held| held.replace(held.get() + 1));
+        let from = permits.filter(|_| held == 0);
+        if let Some(permits) = from {
+            let mut free = permits.free.lock().unwrap_or_else(|e| e.into_inner());
+            while *free == 0 {
+                free = permits.freed.wait(free).unwrap_or_else(|e| e.into_inner());
+            }
+            *free -= 1;
+        }
+        Self { from }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        HELD.with(|held| held.set(held.get() - 1));
+        if let Some(permits) = self.from {
+            *permits.free.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+            permits.freed.notify_one();
+        }
+    }
+}
+
+/// A reader that holds its permit until it is dropped, since every read from it is I/O.
+struct PermitReader<'a> {
+    inner: Box<dyn BufRead + 'a>,
+    _permit: Permit<'a>,
+}
+
+impl Read for PermitReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl BufRead for PermitReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.inner.consume(amount)
+    }
+}
+
+impl<F: FileSystem> FileSystem for NetworkFs<F> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.retried(path, || self.inner.read(path))
+    }
+
+    fn read_mapped(&self, path: &Path) -> Result<FileContents> {
+        self.retried(path, || self.inner.read_mapped(path))
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.retried(path, || self.inner.write(path, content))
+    }
+
+    /// Opening the reader is retried; reading from it is not, since it can't start over.
+    fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+        let permit = self.permit();
+        let inner = self.retried(path, || self.inner.reader(path))?;
+        Ok(Box::new(PermitReader { inner, _permit: permit }))
+    }
+
+    fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        let _permit = self.permit();
+        self.inner.write_with(path, fill)
+    }
+
+    fn write_in_place(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        let _permit = self.permit();
+        self.inner.write_in_place(path, fill)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+        self.retried(path, || self.inner.metadata(path))
+    }
+
+    fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+        self.retried(path, || self.inner.set_readonly(path, readonly))
+    }
+
+    fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+        self.retried(to, || self.inner.copy_permissions(from, to))
+    }
+
+    /// Not retried, since a delete that went through before the error would fail as not found.
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let _permit = self.permit();
+        self.inner.remove_file(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.retried(path, || self.inner.create_dir_all(path))
+    }
+
+    /// Not retried, so that a copy that was trashed before the error isn't trashed twice.
+    fn trash_copy(&self, path: &Path) -> Result<()> {
+        let _permit = self.permit();
+        self.inner.trash_copy(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.retried(path, || self.inner.read_dir(path))
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.retried(path, || self.inner.canonicalize(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.inner.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.inner.is_file(path)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        self.inner.is_symlink(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Fails the first `failures` reads with `kind`, and counts the reads in flight at once.
+    struct FlakyFs {
+        inner: MemFs,
+        kind: io::ErrorKind,
+        failures: AtomicUsize,
+        reads: AtomicUsize,
+        in_flight: AtomicUsize,
+        most_in_flight: AtomicUsize,
+    }
+
+    impl FlakyFs {
+        fn new(failures: usize, kind: io::ErrorKind) -> Self {
+            Self {
+                inner: MemFs::new().with_file("/share/a.txt", b"a  \n"),
+                kind,
+                failures: AtomicUsize::new(failures),
+                reads: AtomicUsize::new(0),
+                in_flight: AtomicUsize::new(0),
+                most_in_flight: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl FileSystem for FlakyFs {
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(5));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+                .is_ok()
+            {
+                return Err(eyre::Report::new(io::Error::from(self.kind)).wrap_err("Failed to read file"));
+            }
+            self.inner.read(path)
+        }
+        fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+            self.inner.write(path, content)
+        }
+        fn reader(&self, path: &Path) -> Result<Box<dyn BufRead + '_>> {
+            self.inner.reader(path)
+        }
+        fn write_with(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+            self.inner.write_with(path, fill)
+        }
+        fn metadata(&self, path: &Path) -> Result<FsMetadata> {
+            self.inner.metadata(path)
+        }
+        fn set_readonly(&self, path: &Path, readonly: bool) -> Result<()> {
+            self.inner.set_readonly(path, readonly)
+        }
+        fn copy_permissions(&self, from: &Path, to: &Path) -> Result<()> {
+            self.inner.copy_permissions(from, to)
+        }
+        fn remove_file(&self, path: &Path) -> Result<()> {
+            self.inner.remove_file(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> Result<()> {
+            self.inner.create_dir_all(path)
+        }
+        fn trash_copy(&self, path: &Path) -> Result<()> {
+            self.inner.trash_copy(path)
+        }
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+            self.inner.read_dir(path)
+        }
+        fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+            self.inner.canonicalize(path)
+        }
+        fn is_dir(&self, path: &Path) -> bool {
+            self.inner.is_dir(path)
+        }
+        fn is_file(&self, path: &Path) -> bool {
+            self.inner.is_file(path)
+        }
+        fn is_symlink(&self, path: &Path) -> bool {
+            self.inner.is_symlink(path)
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.inner.exists(path)
+        }
+    }
+
+    #[test]
+    fn test_transient_errors_are_retried() {
+        let path = Path::new("/share/a.txt");
+        let flaky = Arc::new(FlakyFs::new(1, io::ErrorKind::StaleNetworkFileHandle));
+        let fs = NetworkFs::new(Arc::clone(&flaky), None, 1);
+        assert_eq!(fs.read(path).unwrap(), b"a  \n");
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 2);
+
+        // Retries run out
+        let flaky = Arc::new(FlakyFs::new(2, io::ErrorKind::StaleNetworkFileHandle));
+        let fs = NetworkFs::new(Arc::clone(&flaky), None, 1);
+        assert!(fs.read(path).is_err());
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 2);
+
+        // Errors that won't go away aren't retried
+        let flaky = Arc::new(FlakyFs::new(1, io::ErrorKind::PermissionDenied));
+        let fs = NetworkFs::new(Arc::clone(&flaky), None, 3);
+        assert!(fs.read(path).is_err());
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_io_threads_limit_concurrent_reads() {
+        let flaky = Arc::new(FlakyFs::new(0, io::ErrorKind::Other));
+        let fs = NetworkFs::new(Arc::clone(&flaky), Some(2), 0);
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    // A reader holds a permit, and reads while it is open don't wait for another
+                    let _reader = fs.reader(Path::new("/share/a.txt")).unwrap();
+                    fs.read(Path::new("/share/a.txt")).unwrap();
+                });
+            }
+        });
+        assert_eq!(flaky.reads.load(Ordering::SeqCst), 8);
+        assert!(flaky.most_in_flight.load(Ordering::SeqCst) <= 2);
+    }
+}
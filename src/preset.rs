@@ -0,0 +1,97 @@
+use crate::config::{Config, LineEndings, RuleOverride, TrailingWhitespace};
+use clap::ValueEnum;
+
+/// Built-in bundles of rules, chosen with `--preset` or `extends:` in a config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// What `git diff --check` complains about: ASCII trailing whitespace and blank lines at
+    /// the end of a file. Line endings are left to git's own `core.autocrlf`.
+    GitFriendly,
+    /// Trailing whitespace of any kind, exactly one final newline, and LF line endings
+    Strict,
+    /// Leave whitespace that means something alone: Markdown hard line breaks and everything
+    /// inside multi-line string literals
+    MarkdownSafe,
+}
+
+impl Preset {
+    /// The preset named `name`, as `--preset` spells it.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::from_str(name, false).ok()
+    }
+
+    /// The names of every preset, for error messages.
+    pub fn names() -> Vec<String> {
+        Self::value_variants()
+            .iter()
+            .filter_map(|preset| preset.to_possible_value())
+            .map(|value| value.get_name().to_string())
+            .collect()
+    }
+
+    /// The built-in defaults with this preset applied, which a config file extending it starts from.
+    pub fn config(self) -> Config {
+        let mut config = Config::default();
+        self.apply(&mut config);
+        config
+    }
+
+    /// Change the settings this preset bundles, leaving the rest of `config` as it is.
+    pub fn apply(self, config: &mut Config) {
+        let rules = &mut config.rules;
+        match self {
+            Self::GitFriendly => {
+                rules.trailing_whitespace = TrailingWhitespace::Ascii;
+                rules.trim_trailing_blank_lines = true;
+                rules.line_endings = LineEndings::Preserve;
+            }
+            Self::Strict => {
+                rules.trailing_whitespace = TrailingWhitespace::Unicode;
+                rules.trim_trailing_blank_lines = true;
+                rules.line_endings = LineEndings::Lf;
+            }
+            Self::MarkdownSafe => {
+                rules.preserve_string_literals = true;
+                let markdown = ["*.md", "*.markdown"];
+                if !rules
+                    .overrides
+                    .iter()
+                    .any(|rule_override| rule_override.files.iter().any(|file| markdown.contains(&file.as_str())))
+                {
+                    rules.overrides.push(RuleOverride {
+                        files: markdown.iter().map(|file| file.to_string()).collect(),
+                        preserve_hard_breaks: Some(true),
+                        ..RuleOverride::default()
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(Preset::names(), ["git-friendly", "strict", "markdown-safe"]);
+        assert_eq!(Preset::from_name("strict"), Some(Preset::Strict));
+        assert_eq!(Preset::from_name("lenient"), None);
+
+        let strict = Preset::Strict.config();
+        assert_eq!(strict.rules.line_endings, LineEndings::Lf);
+        assert!(strict.rules.trim_trailing_blank_lines);
+        assert_eq!(strict.exclude_paths, Config::default().exclude_paths);
+
+        // Markdown-safe restores the hard break override a config may have dropped
+        let mut config = Config::default();
+        config.rules.overrides.clear();
+        Preset::MarkdownSafe.apply(&mut config);
+        assert!(config.rules.preserve_string_literals);
+        assert_eq!(config.rules.overrides[0].preserve_hard_breaks, Some(true));
+        let mut config = Config::default();
+        Preset::MarkdownSafe.apply(&mut config);
+        assert_eq!(config.rules.overrides, Config::default().rules.overrides);
+    }
+}
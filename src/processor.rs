@@ -1,154 +1,1255 @@
-use crate::config::Config;
-use crate::ports::fs::FileSystem;
+use crate::cache::{Cache, ContentHasher, hash_content};
+use crate::config::{Config, HardLinkPolicy, LineEndings, ReadOnlyPolicy, RuleSelection, Rules, WhitespaceOnlyPolicy};
+use crate::custom::{self, CustomRules};
+use crate::filetypes::FileTypes;
+use crate::git_diff::ChangedLines;
+use crate::gitattributes::{AttrMatch, AttrValue, GitAttributes};
+use crate::ports::fs::{FileContents, FileSystem, FsMetadata, WriteFn};
+use crate::rules::{CleanedLine, LineEnding, LineRanges, RuleSet};
+use crate::timings::{Phase, Timings};
+use crate::transform::{KEPT_IN_LITERAL, Pipeline};
+use crate::walker::compile_globs;
 use eyre::Result;
-use log::{debug, warn};
-use std::path::Path;
+use globset::GlobSet;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::convert::Infallible;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use tracing::{debug, warn};
 
 pub struct WhitespaceProcessor<F: FileSystem> {
     config: Arc<Config>,
     fs: Arc<F>,
+    rules: RuleSet,
+    custom_rules: CustomRules,
+    text_files: GlobSet,
+    cache: Option<Arc<Cache>>,
+    timings: Option<Arc<Timings>>,
+    changed_lines: Option<Arc<ChangedLines>>,
+    attributes: Arc<GitAttributes>,
+    write_unchanged: bool,
+    backup: Option<String>,
+    trash: bool,
 }
 
-#[derive(Debug, Clone)]
+/// Why a file was deliberately left alone. Skips are expected and are not errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SkipReason {
+    /// Binary extension or binary content
+    Binary,
+    /// Larger than `max-file-size`
+    TooLarge,
+    /// Not valid UTF-8
+    InvalidEncoding,
+    /// Matched an exclude pattern or `.whitespaceignore`
+    Excluded,
+    /// A patch or diff, whose trailing whitespace is meaningful
+    Diff,
+    /// Contains unresolved merge conflict markers
+    Conflict,
+    /// Generated code, by name, header or `linguist-generated`
+    Generated,
+    /// Third-party code, by directory or `linguist-vendored`
+    Vendored,
+    /// Read-only, and the `readonly` policy leaves such files alone
+    ReadOnly,
+    /// Has other hard links, and the `hardlinks` policy leaves such files alone
+    HardLinked,
+    /// A FIFO, socket or device node rather than a regular file
+    Special,
+    /// Mostly holes, which rewriting would fill in
+    Sparse,
+    /// Not reached before `--max-files` or `--timeout` stopped the run
+    Limit,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            Self::Binary => "binary",
+            Self::TooLarge => "too large",
+            Self::InvalidEncoding => "invalid encoding",
+            Self::Excluded => "excluded",
+            Self::Diff => "diff",
+            Self::Conflict => "merge conflict",
+            Self::Generated => "generated",
+            Self::Vendored => "vendored",
+            Self::ReadOnly => "read-only",
+            Self::HardLinked => "hard-linked",
+            Self::Special => "special file",
+            Self::Sparse => "sparse",
+            Self::Limit => "limit reached",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// What happened to a single file.
+#[derive(Debug)]
+pub enum Outcome {
+    /// The lines (1-based) that were cleaned or removed, or in a dry run would be, how many
+    /// bytes the file lost by it, and which rules changed which of the lines
+    Modified {
+        lines: Vec<usize>,
+        bytes_removed: usize,
+        rules: Vec<RuleLines>,
+    },
+    /// No trailing whitespace found
+    Clean,
+    /// Deliberately left alone; not an error
+    Skipped(SkipReason),
+    /// Reading, processing or writing the file failed
+    Failed(eyre::Report),
+}
+
+/// The lines of a file one rule changed, or reported a finding on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleLines {
+    /// The rule's name: a setting such as `trailing-whitespace`, or a custom rule's `name`
+    pub rule: String,
+    /// 1-based, in order
+    pub lines: Vec<usize>,
+}
+
+#[derive(Debug)]
 pub struct ProcessingResult {
-    pub lines_modified: Vec<usize>,
-    pub had_changes: bool,
-    pub error: Option<String>,
+    pub outcome: Outcome,
+    /// Lines rules reported without changing, such as ones over `max-line-length`, by rule.
+    /// They leave the outcome alone: a file with nothing else to clean is still clean.
+    pub notices: Vec<RuleLines>,
+}
+
+impl ProcessingResult {
+    /// Modified if any lines had trailing whitespace, otherwise clean.
+    pub fn from_lines(lines: Vec<usize>, bytes_removed: usize) -> Self {
+        let outcome = if lines.is_empty() {
+            Outcome::Clean
+        } else {
+            Outcome::Modified {
+                lines,
+                bytes_removed,
+                rules: Vec::new(),
+            }
+        };
+        Self::with_outcome(outcome)
+    }
+
+    fn with_outcome(outcome: Outcome) -> Self {
+        Self {
+            outcome,
+            notices: Vec::new(),
+        }
+    }
+
+    /// Attribute the modified lines to the rules that changed them.
+    pub fn with_rules(mut self, rules: Vec<RuleLines>) -> Self {
+        if let Outcome::Modified { rules: attributed, .. } = &mut self.outcome {
+            *attributed = rules;
+        }
+        self
+    }
+
+    /// Record the lines rules reported without changing them.
+    pub fn with_notices(mut self, notices: Vec<RuleLines>) -> Self {
+        self.notices = notices;
+        self
+    }
+
+    pub fn clean() -> Self {
+        Self::with_outcome(Outcome::Clean)
+    }
+
+    /// Result for a file that was left alone for `reason`.
+    pub fn skip(reason: SkipReason) -> Self {
+        Self::with_outcome(Outcome::Skipped(reason))
+    }
+
+    pub fn failed(error: eyre::Report) -> Self {
+        Self::with_outcome(Outcome::Failed(error))
+    }
+
+    /// Lines with trailing whitespace; empty unless the outcome is [`Outcome::Modified`].
+    pub fn lines_modified(&self) -> &[usize] {
+        match &self.outcome {
+            Outcome::Modified { lines, .. } => lines,
+            _ => &[],
+        }
+    }
+
+    /// The modified lines by the rule that changed them; empty unless the outcome is
+    /// [`Outcome::Modified`]. A line two rules changed is listed under both.
+    pub fn rules(&self) -> &[RuleLines] {
+        match &self.outcome {
+            Outcome::Modified { rules, .. } => rules,
+            _ => &[],
+        }
+    }
+
+    /// Bytes removed from the file or, in a dry run, that would be.
+    pub fn bytes_removed(&self) -> usize {
+        match self.outcome {
+            Outcome::Modified { bytes_removed, .. } => bytes_removed,
+            _ => 0,
+        }
+    }
+
+    /// True if the file had trailing whitespace (and, unless this was a dry run, was cleaned).
+    pub fn had_changes(&self) -> bool {
+        matches!(self.outcome, Outcome::Modified { .. })
+    }
+
+    pub fn skip_reason(&self) -> Option<SkipReason> {
+        match self.outcome {
+            Outcome::Skipped(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn error(&self) -> Option<&eyre::Report> {
+        match &self.outcome {
+            Outcome::Failed(error) => Some(error),
+            _ => None,
+        }
+    }
+
+    /// True if the file was deliberately left alone rather than failing.
+    pub fn is_skipped(&self) -> bool {
+        self.skip_reason().is_some()
+    }
+
+    /// True if reading, processing or writing the file failed.
+    pub fn is_failure(&self) -> bool {
+        self.error().is_some()
+    }
+}
+
+/// Skip a file that is in the middle of a merge, warning since it likely needs attention.
+fn conflict(path: &Path) -> ProcessingResult {
+    warn!("Skipping file with merge conflict markers: {}", path.display());
+    ProcessingResult::skip(SkipReason::Conflict)
+}
+
+/// Warn that trailing whitespace was left inside quoted CSV or TSV fields, where it may be data
+/// or a mistake, rather than trimmed.
+fn warn_kept_in_fields(path: &Path, changes: &LineChanges) {
+    if let Some(kept) = changes.notices.iter().find(|notice| notice.rule == KEPT_IN_LITERAL) {
+        warn!(
+            "Kept trailing whitespace inside quoted fields: {}{}",
+            path.display(),
+            crate::format_line_numbers(&kept.lines)
+        );
+    }
+}
+
+/// Log a read or write failure and record it with `context`.
+fn failure(path: &Path, context: &'static str, error: eyre::Report) -> ProcessingResult {
+    warn!("{}: {}: {}", context, path.display(), error);
+    ProcessingResult::failed(error.wrap_err(context))
 }
 
 impl<F: FileSystem> WhitespaceProcessor<F> {
     pub fn new(config: Arc<Config>, fs: Arc<F>) -> Self {
-        Self { config, fs }
+        let rules = RuleSet::new(&config.rules).with_file_types(FileTypes::new(&config.file_types));
+        let custom_rules = CustomRules::new(&config.custom_rules);
+        let text_files = compile_globs(&config.binary_detection.text_files);
+        Self {
+            config,
+            fs,
+            rules,
+            custom_rules,
+            text_files,
+            cache: None,
+            timings: None,
+            changed_lines: None,
+            attributes: Arc::new(GitAttributes::new()),
+            write_unchanged: false,
+            backup: None,
+            trash: false,
+        }
+    }
+
+    /// Skip files the cache knows to be clean, and record newly clean files in it.
+    pub fn with_cache(mut self, cache: Arc<Cache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Accumulate read, process and write times into `timings`.
+    pub fn with_timings(mut self, timings: Arc<Timings>) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    /// Forget the `.gitattributes` files read so far, so that later files read them afresh.
+    pub fn clear_attributes(&self) {
+        self.attributes.clear();
+    }
+
+    /// This processor without its cache, so that every file is read and scanned however it was
+    /// recorded, as `--verify` needs of files this run has just marked clean.
+    pub fn without_cache(&self) -> Self {
+        Self {
+            config: Arc::clone(&self.config),
+            fs: Arc::clone(&self.fs),
+            rules: self.rules.clone(),
+            custom_rules: self.custom_rules.clone(),
+            text_files: self.text_files.clone(),
+            cache: None,
+            timings: self.timings.clone(),
+            changed_lines: self.changed_lines.clone(),
+            attributes: Arc::clone(&self.attributes),
+            write_unchanged: self.write_unchanged,
+            backup: self.backup.clone(),
+            trash: self.trash,
+        }
+    }
+
+    /// Clean only the lines in `line_ranges`, leaving every other line byte for byte as it is.
+    /// Custom rules, which rewrite whole files, don't run.
+    pub fn with_line_ranges(mut self, line_ranges: LineRanges) -> Self {
+        let mut rules = self.rules.base().clone();
+        rules.line_ranges = Some(line_ranges);
+        self.rules = RuleSet::new(&rules).with_file_types(FileTypes::new(&self.config.file_types));
+        self
+    }
+
+    /// Run only the rules, built-in or custom, that `selection` allows.
+    pub fn with_rule_selection(mut self, selection: RuleSelection) -> Self {
+        let mut rules = self.rules.base().clone();
+        rules.selection = Some(selection);
+        self.rules = RuleSet::new(&rules).with_file_types(FileTypes::new(&self.config.file_types));
+        self
+    }
+
+    /// Clean only the lines git reports as changed in each file, leaving files it reports no
+    /// changes in alone.
+    pub fn with_changed_lines(mut self, changed_lines: Arc<ChangedLines>) -> Self {
+        self.changed_lines = Some(changed_lines);
+        self
+    }
+
+    /// Write files that need no cleaning as well, as they are. For a filesystem that writes
+    /// elsewhere than it reads, so that the copies it writes are complete.
+    pub fn with_write_unchanged(mut self) -> Self {
+        self.write_unchanged = true;
+        self
+    }
+
+    /// Copy each file to its name with `suffix` appended before rewriting it, replacing any
+    /// earlier copy.
+    pub fn with_backup(mut self, suffix: impl Into<String>) -> Self {
+        self.backup = Some(suffix.into());
+        self
+    }
+
+    /// Put a copy of each file in the operating system's trash before rewriting it, so that
+    /// the original can be restored from there.
+    pub fn with_trash(mut self) -> Self {
+        self.trash = true;
+        self
+    }
+
+    /// The rules for `path`, a script if it has a shebang `first_line`, with the line endings
+    /// its `eol` attribute asks for, limited to its changed lines when only those are cleaned.
+    fn rules_for(&self, path: &Path, first_line: Option<&str>) -> Cow<'_, Rules> {
+        let mut rules = match first_line {
+            Some(first_line) => self.rules.for_script(path, first_line),
+            None => self.rules.for_file(path),
+        };
+        if let Some(line_endings) = self.attribute(path, "eol").and_then(|eol| match eol.value {
+            AttrValue::Value(value) if value == "lf" => Some(LineEndings::Lf),
+            AttrValue::Value(value) if value == "crlf" => Some(LineEndings::Crlf),
+            _ => None,
+        }) {
+            rules.to_mut().line_endings = line_endings;
+        }
+        if let Some(changed_lines) = &self.changed_lines {
+            let line_ranges = self
+                .fs
+                .canonicalize(path)
+                .ok()
+                .and_then(|path| changed_lines.for_file(&path).cloned());
+            rules.to_mut().line_ranges = Some(line_ranges.unwrap_or_else(|| LineRanges::new([])));
+        }
+        rules
+    }
+
+    /// The `.gitattributes` line deciding attribute `name` for `path`, when they are honored.
+    fn attribute(&self, path: &Path, name: &str) -> Option<AttrMatch> {
+        if !self.config.gitattributes {
+            return None;
+        }
+        self.attributes.get(self.fs.as_ref(), path, name)
+    }
+
+    fn timed<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        match &self.timings {
+            Some(timings) => timings.time(phase, f),
+            None => f(),
+        }
     }
 
     pub fn process_file(&self, path: &Path, dry_run: bool) -> Result<ProcessingResult> {
         debug!("Processing file: {}", path.display());
 
+        // `binary` and `-text` tell git not to treat a file as text, so neither do we
+        if let Some(text) = self.attribute(path, "text")
+            && text.value == AttrValue::Unset
+        {
+            debug!(
+                "Skipping file marked `-text` in {}: {}",
+                text.file.display(),
+                path.display()
+            );
+            return Ok(ProcessingResult::skip(SkipReason::Binary));
+        }
+
+        let metadata = self.fs.metadata(path).ok();
+        // Files the walk never saw, as in a `--files-from` list, get the same checks it makes
+        if let Some(skip_reason) = metadata.as_ref().and_then(special_or_sparse) {
+            debug!("Skipping file ({}): {}", skip_reason, path.display());
+            return Ok(ProcessingResult::skip(skip_reason));
+        }
+        let readonly = metadata.as_ref().is_some_and(|metadata| metadata.readonly);
+        let hard_linked = metadata.as_ref().is_some_and(|metadata| metadata.links > 1);
+
+        // Unchanged files that were clean last time don't need to be read at all
+        if let (Some(cache), Some(metadata)) = (&self.cache, &metadata)
+            && cache.is_fresh(path, metadata)
+        {
+            debug!("Skipping cached clean file: {}", path.display());
+            return Ok(ProcessingResult::clean());
+        }
+
+        let rules = self.rules_for(path, None);
+        if rules.line_ranges.as_ref().is_some_and(LineRanges::is_empty) {
+            debug!("Skipping file without changed lines: {}", path.display());
+            return Ok(self
+                .copy_unchanged(path, dry_run)
+                .unwrap_or_else(ProcessingResult::clean));
+        }
+
+        // Very large files are memory-mapped when enabled, otherwise streamed
+        let is_large = metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.len > self.config.processing.streaming_threshold);
+        if is_large && !self.config.processing.mmap {
+            return self.process_file_streaming(path, rules, metadata, dry_run);
+        }
+
         // Read file content
-        let content = match self.fs.read(path) {
-            Ok(bytes) => bytes,
+        let read_result = self.timed(Phase::Read, || {
+            if is_large {
+                self.fs.read_mapped(path)
+            } else {
+                self.fs.read(path).map(FileContents::Owned)
+            }
+        });
+        let content = match read_result {
+            Ok(contents) => contents,
             Err(e) => {
-                let error_msg = format!("Failed to read file: {}", e);
-                warn!("{}: {}", error_msg, path.display());
-                return Ok(ProcessingResult {
-                    lines_modified: vec![],
-                    had_changes: false,
-                    error: Some(error_msg),
-                });
+                return Ok(failure(path, "Failed to read file", e));
             }
         };
 
+        // A touched-but-unchanged file still matches its cached content hash
+        let content_hash = self.cache.as_ref().map(|_| hash_content(&content));
+        if let (Some(cache), Some(hash)) = (&self.cache, content_hash)
+            && cache.matches_content(path, content.len() as u64, hash)
+        {
+            debug!("Skipping cached clean content: {}", path.display());
+            self.remember_clean(path, metadata, hash);
+            return Ok(ProcessingResult::clean());
+        }
+
         // Check if file is binary
-        if self.is_binary_content(&content) {
+        if !self.is_text_file(path) && self.timed(Phase::Process, || self.is_binary_content(&content)) {
             debug!("Skipping binary file: {}", path.display());
-            return Ok(ProcessingResult {
-                lines_modified: vec![],
-                had_changes: false,
-                error: Some("Binary file detected".to_string()),
-            });
+            return Ok(ProcessingResult::skip(SkipReason::Binary));
         }
 
-        // Convert to string
-        let content_str = match String::from_utf8(content) {
+        // Validate UTF-8 in place, without copying the bytes
+        let content_str = match self.timed(Phase::Process, || std::str::from_utf8(&content)) {
             Ok(s) => s,
             Err(_) => {
                 debug!("Skipping file with invalid UTF-8: {}", path.display());
-                return Ok(ProcessingResult {
-                    lines_modified: vec![],
-                    had_changes: false,
-                    error: Some("Invalid UTF-8 encoding".to_string()),
-                });
+                return Ok(ProcessingResult::skip(SkipReason::InvalidEncoding));
             }
         };
 
-        // Process content
-        let (processed_content, modified_lines, _) = self.process_content(&content_str);
-        let had_changes = !modified_lines.is_empty();
+        // Scripts whose name says nothing of their type are recognized by their shebang
+        let rules = match content_str.lines().next() {
+            Some(first_line) if self.is_untyped_script(path, first_line) => self.rules_for(path, Some(first_line)),
+            _ => rules,
+        };
+
+        if self.sniffs_for_diff(path) && looks_like_diff(content_str.lines()) {
+            debug!("Skipping diff file: {}", path.display());
+            return Ok(ProcessingResult::skip(SkipReason::Diff));
+        }
+
+        if self.is_generated(content_str.lines()) {
+            debug!("Skipping generated file: {}", path.display());
+            return Ok(ProcessingResult::skip(SkipReason::Generated));
+        }
+
+        if self.config.skip_conflicts && has_conflict_markers(content_str.lines()) {
+            return Ok(conflict(path));
+        }
+
+        // Scan first so clean files and dry runs never build a rewritten copy
+        let mut changes = self.timed(Phase::Process, || scan_lines(content_str, &rules));
+        warn_kept_in_fields(path, &changes);
+
+        // Custom rules see the content as the built-in rules left it
+        let mut custom_rules = self.custom_rules.for_file(path);
+        custom_rules.retain(|rule| rules.runs(&rule.name));
+        let mut rewritten = None;
+        if !custom_rules.is_empty() && rules.line_ranges.is_some() {
+            debug!("Custom rules don't run on line ranges: {}", path.display());
+        } else if !custom_rules.is_empty() {
+            let (cleaned, origins) = self.timed(Phase::Process, || cleaned_with_origins(content_str, &rules));
+            let transformed = self.timed(Phase::Process, || custom::apply(&custom_rules, path, &cleaned));
+            let transformed = match transformed {
+                Ok(transformed) => transformed,
+                Err(e) => return Ok(failure(path, "Custom rule failed", e)),
+            };
+            // Custom rules number the lines the built-in rules kept; a line added at the end
+            // counts against the file's last line
+            let original = |line: usize| {
+                let last = content_str.lines().count().max(1);
+                origins.get(line.saturating_sub(1)).copied().unwrap_or(last)
+            };
+            changes
+                .lines
+                .extend(transformed.lines.iter().map(|&line| original(line)));
+            changes.lines.sort_unstable();
+            changes.lines.dedup();
+            for rule in &transformed.rules {
+                for &line in &rule.lines {
+                    changes.attribute(&rule.rule, original(line));
+                }
+            }
+            // What the built-in rules saved, less anything custom rules added back
+            changes.bytes_removed = (changes.bytes_removed + cleaned.len()).saturating_sub(transformed.content.len());
+            rewritten = Some(transformed.content);
+        }
+
+        // Files with nothing but whitespace are emptied or deleted instead, when asked
+        let whitespace_only = self.clears_whitespace_only(&rules) && is_whitespace_only(content_str);
+        if whitespace_only {
+            changes = LineChanges::whole_file(content_str.lines().count(), content_str.len(), WHITESPACE_ONLY_RULE);
+            rewritten = Some(String::new());
+        }
+        let delete = whitespace_only && self.config.whitespace_only_files == WhitespaceOnlyPolicy::Delete;
+        let had_changes = !changes.lines.is_empty();
+        // Skipping a file the cache knows would skip its notices too
+        let cacheable = changes.notices.is_empty();
+
+        if had_changes
+            && readonly
+            && let Some(skipped) = self.skip_readonly(path)
+        {
+            return Ok(skipped);
+        }
+        if had_changes
+            && hard_linked
+            && let Some(skipped) = self.skip_hard_linked(path)
+        {
+            return Ok(skipped);
+        }
 
         // Write back if not dry run and there are changes
-        if !dry_run && had_changes {
-            if let Err(e) = self.fs.write(path, processed_content.as_bytes()) {
-                let error_msg = format!("Failed to write file: {}", e);
-                warn!("{}: {}", error_msg, path.display());
-                return Ok(ProcessingResult {
-                    lines_modified: modified_lines,
-                    had_changes,
-                    error: Some(error_msg),
-                });
+        // Custom rules may report findings without changing anything, leaving nothing to write
+        if !dry_run && had_changes && rewritten.as_deref() != Some(content_str) {
+            let processed_content = match rewritten {
+                Some(rewritten) => rewritten,
+                None => self.timed(Phase::Process, || process_lines(content_str, &rules)).0,
+            };
+            // Release any memory map before the file is rewritten
+            drop(content);
+            if let Err(e) = self.timed(Phase::Write, || self.keep_original(path)) {
+                return Ok(failure(path, "Failed to keep the original", e));
+            }
+            if delete {
+                if let Err(e) = self.timed(Phase::Write, || self.fs.remove_file(path)) {
+                    return Ok(failure(path, "Failed to delete whitespace-only file", e));
+                }
+                warn!("Deleted whitespace-only file: {}", path.display());
+                return Ok(changes.into_result());
             }
-            debug!("Wrote cleaned file: {}", path.display());
+            let content = processed_content.as_bytes();
+            let write = || {
+                if hard_linked {
+                    self.write_hard_linked(path, &mut |writer| Ok(writer.write_all(content)?))
+                } else {
+                    self.fs.write(path, content)
+                }
+            };
+            if let Err(e) = self.timed(Phase::Write, || self.write_readonly(path, readonly, write)) {
+                return Ok(failure(path, "Failed to write file", e));
+            }
+            if whitespace_only {
+                warn!("Emptied whitespace-only file: {}", path.display());
+            } else {
+                debug!("Wrote cleaned file: {}", path.display());
+            }
+            if self.cache.is_some() && cacheable {
+                self.remember_clean(path, None, hash_content(processed_content.as_bytes()));
+            }
+        } else if !dry_run && self.write_unchanged {
+            if let Err(e) = self.timed(Phase::Write, || self.fs.write(path, &content)) {
+                return Ok(failure(path, "Failed to write file", e));
+            }
+        } else if let (false, true, Some(hash)) = (had_changes, cacheable, content_hash) {
+            self.remember_clean(path, metadata, hash);
         }
 
         if had_changes {
-            debug!("File processed: {} lines modified", modified_lines.len());
+            debug!("File processed: {} lines modified", changes.lines.len());
         }
 
-        Ok(ProcessingResult {
-            lines_modified: modified_lines,
-            had_changes,
-            error: None,
-        })
+        Ok(changes.into_result())
     }
 
+    /// Return the 1-based numbers of lines that need cleaning, without rewriting anything.
+    pub fn scan_content(&self, content: &str) -> Vec<usize> {
+        scan_lines(content, self.rules.base()).lines
+    }
+
+    /// Clean `content` with the base rules, returning the new content, the 1-based numbers of
+    /// changed or removed lines, and the number of bytes saved.
     pub fn process_content(&self, content: &str) -> (String, Vec<usize>, usize) {
-        let mut processed_lines = Vec::new();
-        let mut modified_line_numbers = Vec::new();
-        let mut total_bytes_saved = 0;
-
-        for (line_num, line) in content.lines().enumerate() {
-            let original_len = line.len();
-            let trimmed_line = line.trim_end();
-            let trimmed_len = trimmed_line.len();
-
-            if trimmed_len < original_len {
-                // Line had trailing whitespace
-                modified_line_numbers.push(line_num + 1); // 1-based line numbers
-                total_bytes_saved += original_len - trimmed_len;
-                debug!(
-                    "Line {}: removed {} trailing bytes",
-                    line_num + 1,
-                    original_len - trimmed_len
-                );
+        process_lines(content, self.rules.base())
+    }
+
+    /// Process a file line-by-line without reading it fully into memory.
+    ///
+    /// A first pass scans for binary content, invalid UTF-8 and trailing whitespace;
+    /// only when changes are needed (and this isn't a dry run) is a second pass made
+    /// that streams the cleaned lines into a replacement file.
+    fn process_file_streaming<'a>(
+        &'a self,
+        path: &Path,
+        mut rules: Cow<'a, Rules>,
+        metadata: Option<FsMetadata>,
+        dry_run: bool,
+    ) -> Result<ProcessingResult> {
+        debug!("Streaming large file: {}", path.display());
+        if !self.custom_rules.for_file(path).is_empty() {
+            debug!("Custom rules don't run on streamed files: {}", path.display());
+        }
+
+        let untyped = self.rules.file_type(path, None).is_none();
+        if self.config.diff_detection.enabled || self.config.generated_detection.enabled || untyped {
+            let head = match self.timed(Phase::Read, || self.read_head(path)) {
+                Ok(head) => head,
+                Err(e) => return Ok(failure(path, "Failed to read file", e)),
+            };
+            if self.sniffs_for_diff(path) && looks_like_diff(head.iter().map(String::as_str)) {
+                debug!("Skipping diff file: {}", path.display());
+                return Ok(ProcessingResult::skip(SkipReason::Diff));
+            }
+            if self.is_generated(head.iter().map(String::as_str)) {
+                debug!("Skipping generated file: {}", path.display());
+                return Ok(ProcessingResult::skip(SkipReason::Generated));
+            }
+            if let Some(first_line) = head.first()
+                && self.is_untyped_script(path, first_line)
+            {
+                rules = self.rules_for(path, Some(first_line));
             }
+        }
+
+        // Streamed files are scanned while they are read, so the scan counts as reading
+        let (changes, content_hash, whitespace_only) =
+            match self.timed(Phase::Read, || self.scan_streaming(path, &rules)) {
+                Ok(Ok(scan)) => scan,
+                Ok(Err(SkipReason::Conflict)) => return Ok(conflict(path)),
+                Ok(Err(skip_reason)) => {
+                    debug!("Skipping file ({}): {}", skip_reason, path.display());
+                    return Ok(ProcessingResult::skip(skip_reason));
+                }
+                Err(e) => {
+                    return Ok(failure(path, "Failed to read file", e));
+                }
+            };
+        warn_kept_in_fields(path, &changes);
+        let had_changes = !changes.lines.is_empty();
+        let cacheable = changes.notices.is_empty();
+        let readonly = metadata.as_ref().is_some_and(|metadata| metadata.readonly);
+        let hard_linked = metadata.as_ref().is_some_and(|metadata| metadata.links > 1);
 
-            processed_lines.push(trimmed_line);
+        if had_changes
+            && readonly
+            && let Some(skipped) = self.skip_readonly(path)
+        {
+            return Ok(skipped);
+        }
+        if had_changes
+            && hard_linked
+            && let Some(skipped) = self.skip_hard_linked(path)
+        {
+            return Ok(skipped);
         }
 
-        // Reconstruct content preserving original line endings
-        let processed_content = if content.ends_with('\n') {
-            format!("{}\n", processed_lines.join("\n"))
-        } else {
-            processed_lines.join("\n")
+        if !had_changes && cacheable && self.cache.is_some() {
+            self.remember_clean(path, metadata, content_hash);
+        }
+
+        if !dry_run && had_changes {
+            let mut written = ContentHasher::new();
+            let mut rewrite = |writer: &mut dyn Write| -> Result<()> {
+                if whitespace_only {
+                    return Ok(());
+                }
+                let mut reader = self.fs.reader(path)?;
+                let mut pipeline = Pipeline::new(&rules);
+                let mut emit = |cleaned: CleanedLine<'_>| -> std::io::Result<()> {
+                    if let Some(text) = cleaned.text {
+                        let ending = cleaned.ending.as_str().as_bytes();
+                        writer.write_all(text.as_bytes())?;
+                        writer.write_all(ending)?;
+                        written.update(text.as_bytes());
+                        written.update(ending);
+                    }
+                    Ok(())
+                };
+                let mut line = Vec::new();
+                let mut line_num = 0;
+                while reader.read_until(b'\n', &mut line)? > 0 {
+                    line_num += 1;
+                    let (body, ending) = split_line_ending(&line);
+                    pipeline.push(line_num, std::str::from_utf8(body)?, ending, &mut emit)?;
+                    line.clear();
+                }
+                pipeline.finish(&mut emit)?;
+                Ok(())
+            };
+            if let Err(e) = self.timed(Phase::Write, || self.keep_original(path)) {
+                return Ok(failure(path, "Failed to keep the original", e));
+            }
+            if whitespace_only && self.config.whitespace_only_files == WhitespaceOnlyPolicy::Delete {
+                if let Err(e) = self.timed(Phase::Write, || self.fs.remove_file(path)) {
+                    return Ok(failure(path, "Failed to delete whitespace-only file", e));
+                }
+                warn!("Deleted whitespace-only file: {}", path.display());
+                return Ok(changes.into_result());
+            }
+            let write = || {
+                if hard_linked {
+                    self.write_hard_linked(path, &mut rewrite)
+                } else {
+                    self.fs.write_with(path, &mut rewrite)
+                }
+            };
+            if let Err(e) = self.timed(Phase::Write, || self.write_readonly(path, readonly, write)) {
+                return Ok(failure(path, "Failed to write file", e));
+            }
+            if whitespace_only {
+                warn!("Emptied whitespace-only file: {}", path.display());
+            } else {
+                debug!("Wrote cleaned file: {}", path.display());
+            }
+            if self.cache.is_some() && cacheable {
+                self.remember_clean(path, None, written.finish());
+            }
+        } else if let Some(failed) = self.copy_unchanged(path, dry_run) {
+            return Ok(failed);
+        }
+
+        Ok(changes.into_result())
+    }
+
+    /// Keep the original of the file at `path` before it is rewritten: as a backup copy, in the
+    /// trash, or both, as asked.
+    fn keep_original(&self, path: &Path) -> Result<()> {
+        if let Some(suffix) = &self.backup {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(suffix);
+            let backup = PathBuf::from(backup);
+            self.copy(path, &backup)?;
+            debug!("Backed up {} to {}", path.display(), backup.display());
+        }
+        if self.trash {
+            self.fs.trash_copy(path)?;
+            debug!("Put the original of {} in the trash", path.display());
+        }
+        Ok(())
+    }
+
+    /// Copy the file at `path` through as it is when unchanged files are written too, returning
+    /// the failure if that fails.
+    fn copy_unchanged(&self, path: &Path, dry_run: bool) -> Option<ProcessingResult> {
+        if dry_run || !self.write_unchanged {
+            return None;
+        }
+        let written = self.timed(Phase::Write, || self.copy(path, path));
+        written.err().map(|e| failure(path, "Failed to write file", e))
+    }
+
+    /// Write the file at `from` to `to` as it is, without reading it all into memory.
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut copy = |writer: &mut dyn Write| -> Result<()> {
+            std::io::copy(&mut self.fs.reader(from)?, writer)?;
+            Ok(())
         };
+        self.fs.write_with(to, &mut copy)
+    }
+
+    /// Scan a file for lines the rules would change or remove and the bytes that would save,
+    /// also hashing its content and telling whether it is a whitespace-only file to clear. The
+    /// inner `Err` carries a skip reason.
+    fn scan_streaming(&self, path: &Path, rules: &Rules) -> Result<std::result::Result<StreamingScan, SkipReason>> {
+        let mut reader = self.fs.reader(path)?;
+        let mut changes = LineChanges::default();
+        let mut pipeline = Pipeline::new(rules);
+        let mut record = |cleaned: CleanedLine<'_>| {
+            changes.record(&cleaned);
+            Ok::<_, Infallible>(())
+        };
+        let mut hasher = ContentHasher::new();
+        let mut line = Vec::new();
+        let mut bytes_seen = 0;
+        let mut line_num = 0;
+        let mut conflicts = ConflictScan::default();
+        let mut whitespace_only = self.clears_whitespace_only(rules);
+
+        // Settle what the start of the file can; otherwise look for null bytes line by line
+        let mut check_nulls = !self.is_text_file(path);
+        if check_nulls {
+            let head = reader.fill_buf()?;
+            let sample_len = self.config.binary_detection.sample_size.min(head.len());
+            match self.inspect_start(&head[..sample_len]) {
+                Some(true) => return Ok(Err(SkipReason::Binary)),
+                Some(false) => check_nulls = false,
+                None => {}
+            }
+        }
+
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            line_num += 1;
+
+            // Apply the binary check to the same leading sample as the in-memory path
+            let sample_size = self.config.binary_detection.sample_size;
+            if check_nulls && bytes_seen < sample_size {
+                let sample_len = (sample_size - bytes_seen).min(line.len());
+                if self.has_null_bytes(&line[..sample_len]) {
+                    return Ok(Err(SkipReason::Binary));
+                }
+            }
+            bytes_seen += line.len();
+            hasher.update(&line);
+
+            let (body, ending) = split_line_ending(&line);
+            let Ok(body) = std::str::from_utf8(body) else {
+                return Ok(Err(SkipReason::InvalidEncoding));
+            };
+            if self.config.skip_conflicts {
+                conflicts.feed(body);
+            }
+            whitespace_only &= body.is_empty() || is_whitespace_only(body);
+            let Ok(()) = pipeline.push(line_num, body, ending, &mut record);
+            line.clear();
+        }
+        let Ok(()) = pipeline.finish(&mut record);
+
+        if conflicts.found {
+            return Ok(Err(SkipReason::Conflict));
+        }
+        // An empty file has nothing to clear
+        let whitespace_only = whitespace_only && bytes_seen > 0;
+        if whitespace_only {
+            changes = LineChanges::whole_file(line_num, bytes_seen, WHITESPACE_ONLY_RULE);
+        }
+
+        Ok(Ok((changes, hasher.finish(), whitespace_only)))
+    }
+
+    /// True if files with nothing but whitespace are to be emptied or deleted under `rules`.
+    fn clears_whitespace_only(&self, rules: &Rules) -> bool {
+        self.config.whitespace_only_files != WhitespaceOnlyPolicy::Keep
+            && rules.line_ranges.is_none()
+            && rules.runs(WHITESPACE_ONLY_RULE)
+    }
+
+    /// Apply the `readonly` policy to a read-only file that needs changes: the skip result,
+    /// or None if it should be written anyway.
+    fn skip_readonly(&self, path: &Path) -> Option<ProcessingResult> {
+        match self.config.readonly {
+            ReadOnlyPolicy::Skip => debug!("Skipping read-only file: {}", path.display()),
+            ReadOnlyPolicy::Warn => warn!("Skipping read-only file: {}", path.display()),
+            ReadOnlyPolicy::Force => return None,
+        }
+        Some(ProcessingResult::skip(SkipReason::ReadOnly))
+    }
+
+    /// Apply the `hardlinks` policy to a file with other hard links that needs changes: the
+    /// skip result, or None if it should be written anyway.
+    fn skip_hard_linked(&self, path: &Path) -> Option<ProcessingResult> {
+        if self.config.hardlinks != HardLinkPolicy::Skip {
+            return None;
+        }
+        warn!("Skipping file with other hard links: {}", path.display());
+        Some(ProcessingResult::skip(SkipReason::HardLinked))
+    }
+
+    /// Replace the contents of a file with other hard links with what `fill` writes: in the
+    /// file itself, so every link sees them, or in a new file that only this link leads to.
+    fn write_hard_linked(&self, path: &Path, fill: &mut WriteFn) -> Result<()> {
+        if self.config.hardlinks == HardLinkPolicy::InPlace {
+            debug!("Writing hard-linked file in place: {}", path.display());
+            self.fs.write_in_place(path, fill)
+        } else {
+            debug!("Breaking hard link: {}", path.display());
+            self.fs.write_with(path, fill)
+        }
+    }
+
+    /// Run `write`, first making a read-only file writable and restoring it afterwards.
+    fn write_readonly(&self, path: &Path, readonly: bool, write: impl FnOnce() -> Result<()>) -> Result<()> {
+        if !readonly {
+            return write();
+        }
+        debug!("Temporarily clearing read-only bit: {}", path.display());
+        self.fs.set_readonly(path, false)?;
+        let written = write();
+        let restored = self.fs.set_readonly(path, true);
+        written.and(restored)
+    }
+
+    /// The first lines of a file, for content sniffing. Stops early at invalid UTF-8, which
+    /// the full scan reports.
+    fn read_head(&self, path: &Path) -> Result<Vec<String>> {
+        let reader = self.fs.reader(path)?;
+        Ok(reader
+            .lines()
+            .take(DIFF_SNIFF_LINES)
+            .map_while(|line| line.ok())
+            .collect())
+    }
 
-        (processed_content, modified_line_numbers, total_bytes_saved)
+    /// True if `path` starts with a shebang `first_line` and its name gives it no type, so that
+    /// only the shebang can.
+    fn is_untyped_script(&self, path: &Path, first_line: &str) -> bool {
+        first_line.starts_with("#!") && self.rules.file_type(path, None).is_none()
     }
 
-    fn is_binary_content(&self, content: &[u8]) -> bool {
-        if !self.config.binary_detection.check_null_bytes {
-            return false;
+    /// Whether the content of the file at `path` is checked for a patch. Files of a type known
+    /// by name, such as Markdown or Rust, never are, so a page or a test that shows a diff is
+    /// still cleaned.
+    fn sniffs_for_diff(&self, path: &Path) -> bool {
+        self.config.diff_detection.enabled && self.rules.file_type(path, None).is_none()
+    }
+
+    /// True if one of the first `lines` carries a marker of generated code.
+    fn is_generated<'a>(&self, lines: impl Iterator<Item = &'a str>) -> bool {
+        let detection = &self.config.generated_detection;
+        detection.enabled
+            && lines
+                .take(GENERATED_SNIFF_LINES)
+                .any(|line| detection.markers.iter().any(|marker| line.contains(marker.as_str())))
+    }
+
+    /// Record a clean file in the cache, re-reading metadata if the file was just rewritten.
+    fn remember_clean(&self, path: &Path, metadata: Option<FsMetadata>, hash: u64) {
+        let Some(cache) = &self.cache else {
+            return;
+        };
+        // Clean lines in range, or under some of the rules, say nothing about the rest
+        let base = self.rules.base();
+        if base.line_ranges.is_some() || base.selection.is_some() || self.changed_lines.is_some() {
+            return;
+        }
+        if let Some(metadata) = metadata.or_else(|| self.fs.metadata(path).ok()) {
+            cache.record_clean(path, &metadata, hash);
         }
+    }
 
+    /// True if `binary-detection.text-files` exempts `path` from content-based binary detection.
+    pub(crate) fn is_text_file(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.text_files.is_match(name))
+    }
+
+    /// Check the start of a file's content for signs that it is binary.
+    pub(crate) fn is_binary_content(&self, content: &[u8]) -> bool {
         let sample_size = self.config.binary_detection.sample_size.min(content.len());
         let sample = &content[..sample_size];
+        self.inspect_start(sample)
+            .unwrap_or_else(|| self.has_null_bytes(sample))
+    }
+
+    /// Decide from the first bytes of a file alone, if they settle it: a byte order mark means
+    /// text, a known magic number means binary.
+    fn inspect_start(&self, head: &[u8]) -> Option<bool> {
+        if !self.config.binary_detection.inspect_content {
+            return None;
+        }
+        // UTF-16/32 text is full of null bytes; the encoding check skips it as non-UTF-8
+        if UNICODE_BOMS.iter().any(|bom| head.starts_with(bom)) {
+            Some(false)
+        } else if BINARY_MAGIC.iter().any(|magic| head.starts_with(magic)) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn has_null_bytes(&self, sample: &[u8]) -> bool {
+        self.config.binary_detection.check_null_bytes && sample.contains(&0)
+    }
+}
+
+/// What [`WhitespaceProcessor::scan_streaming`] finds: the changes, the content hash, and
+/// whether the file is whitespace-only and to be cleared.
+type StreamingScan = (LineChanges, u64, bool);
+
+/// UTF-16 and UTF-32 byte order marks. UTF-32 LE starts with the UTF-16 LE mark, so it's covered.
+const UNICODE_BOMS: &[&[u8]] = &[b"\xFF\xFE", b"\xFE\xFF", b"\x00\x00\xFE\xFF"];
+
+/// Leading bytes of common binary formats, some of which contain no null bytes early on.
+const BINARY_MAGIC: &[&[u8]] = &[
+    b"\x89PNG\r\n\x1A\n",
+    b"\xFF\xD8\xFF", // JPEG
+    b"GIF87a",
+    b"GIF89a",
+    b"%PDF-",
+    b"PK\x03\x04",   // zip, jar, docx
+    b"\x1F\x8B",     // gzip
+    b"\xFD7zXZ\x00", // xz
+    b"7z\xBC\xAF\x27\x1C",
+    b"\x7FELF",
+    b"\xCA\xFE\xBA\xBE", // Java class, Mach-O universal
+    b"\xCF\xFA\xED\xFE", // Mach-O 64-bit
+    b"SQLite format 3\x00",
+];
+
+/// Lines inspected at the start of a file when looking for diff headers.
+const DIFF_SNIFF_LINES: usize = 100;
+
+/// Generators announce themselves at the top of the file, so markers further down, such as in
+/// code that mentions them, don't count.
+const GENERATED_SNIFF_LINES: usize = 5;
+
+/// True if the first lines look like a unified or git diff: a `diff --git` header, or a
+/// `--- ` line directly followed by a `+++ ` line.
+fn looks_like_diff<'a>(lines: impl Iterator<Item = &'a str>) -> bool {
+    let mut previous_was_old_file = false;
+    for line in lines.take(DIFF_SNIFF_LINES) {
+        if line.starts_with("diff --git ") || (previous_was_old_file && line.starts_with("+++ ")) {
+            return true;
+        }
+        previous_was_old_file = line.starts_with("--- ");
+    }
+    false
+}
+
+/// Follows git's merge conflict markers through a file: a `<<<<<<<` line, then `=======`,
+/// then `>>>>>>>`.
+#[derive(Default)]
+struct ConflictScan {
+    in_ours: bool,
+    in_theirs: bool,
+    found: bool,
+}
+
+impl ConflictScan {
+    fn feed(&mut self, line: &str) {
+        if line.starts_with("<<<<<<<") {
+            self.in_ours = true;
+            self.in_theirs = false;
+        } else if self.in_ours && line.starts_with("=======") {
+            self.in_ours = false;
+            self.in_theirs = true;
+        } else if self.in_theirs && line.starts_with(">>>>>>>") {
+            self.found = true;
+        }
+    }
+}
+
+fn has_conflict_markers<'a>(mut lines: impl Iterator<Item = &'a str>) -> bool {
+    let mut scan = ConflictScan::default();
+    lines.any(|line| {
+        scan.feed(line);
+        scan.found
+    })
+}
+
+/// Why a file with `metadata` can't be read or rewritten like an ordinary one, if it can't.
+pub fn special_or_sparse(metadata: &FsMetadata) -> Option<SkipReason> {
+    if metadata.special {
+        Some(SkipReason::Special)
+    } else if metadata.is_sparse() {
+        Some(SkipReason::Sparse)
+    } else {
+        None
+    }
+}
+
+/// Run `content` through `rules`, passing each cleaned line to `f` in order.
+fn clean_lines<'a>(content: &'a str, rules: &Rules, f: impl FnMut(CleanedLine<'a>)) {
+    Pipeline::new(rules).run(content, f);
+}
+
+/// The lines a file's rules change or remove, and what that saves.
+#[derive(Debug, Default)]
+pub(crate) struct LineChanges {
+    /// 1-based, in order
+    pub lines: Vec<usize>,
+    pub bytes_removed: usize,
+    /// The same lines by the rule that changed them, rules in the order they first did
+    pub rules: Vec<RuleLines>,
+    /// Lines rules reported without changing them, by rule
+    pub notices: Vec<RuleLines>,
+}
+
+impl LineChanges {
+    fn record(&mut self, cleaned: &CleanedLine) {
+        if cleaned.changed {
+            self.lines.push(cleaned.line_num);
+            self.bytes_removed += cleaned.bytes_saved;
+            for rule in &cleaned.changed_by {
+                self.attribute(rule, cleaned.line_num);
+            }
+        }
+        for rule in &cleaned.flagged_by {
+            add_rule_line(&mut self.notices, rule, cleaned.line_num);
+        }
+    }
+
+    /// Note that `rule` changed `line`, keeping each rule's lines in order.
+    fn attribute(&mut self, rule: &str, line: usize) {
+        add_rule_line(&mut self.rules, rule, line);
+    }
+
+    /// Every one of a file's `lines`, `bytes` long in all, removed by `rule`.
+    fn whole_file(lines: usize, bytes: usize, rule: &str) -> Self {
+        let mut changes = LineChanges {
+            lines: (1..=lines).collect(),
+            bytes_removed: bytes,
+            ..LineChanges::default()
+        };
+        for line in changes.lines.clone() {
+            changes.attribute(rule, line);
+        }
+        changes
+    }
 
-        // Check for null bytes
-        sample.contains(&0)
+    fn into_result(self) -> ProcessingResult {
+        ProcessingResult::from_lines(self.lines, self.bytes_removed)
+            .with_rules(self.rules)
+            .with_notices(self.notices)
+    }
+}
+
+/// Add `line` to `rule`'s lines in `rules`, keeping each rule's lines in order.
+fn add_rule_line(rules: &mut Vec<RuleLines>, rule: &str, line: usize) {
+    match rules.iter_mut().find(|rule_lines| rule_lines.rule == rule) {
+        Some(rule_lines) => {
+            if let Err(at) = rule_lines.lines.binary_search(&line) {
+                rule_lines.lines.insert(at, line);
+            }
+        }
+        None => rules.push(RuleLines {
+            rule: rule.to_string(),
+            lines: vec![line],
+        }),
+    }
+}
+
+/// The rule that empties or deletes whitespace-only files, as results attribute lines to it.
+pub const WHITESPACE_ONLY_RULE: &str = "whitespace-only-files";
+
+/// True if `content` isn't empty but holds nothing besides whitespace and a byte order mark.
+pub fn is_whitespace_only(content: &str) -> bool {
+    !content.is_empty() && content.chars().all(|c| c.is_whitespace() || c == '\u{FEFF}')
+}
+
+/// The lines `rules` would change or remove, and the bytes that would save.
+pub(crate) fn scan_lines(content: &str, rules: &Rules) -> LineChanges {
+    let mut changes = LineChanges::default();
+    clean_lines(content, rules, |cleaned| changes.record(&cleaned));
+    changes
+}
+
+pub(crate) fn process_lines(content: &str, rules: &Rules) -> (String, Vec<usize>, usize) {
+    let mut processed_content = String::with_capacity(content.len());
+    let mut modified_line_numbers = Vec::new();
+    let mut total_bytes_saved = 0;
+
+    clean_lines(content, rules, |cleaned| {
+        if cleaned.changed {
+            modified_line_numbers.push(cleaned.line_num);
+            total_bytes_saved += cleaned.bytes_saved;
+            debug!(
+                "Line {}: cleaned, {} bytes saved",
+                cleaned.line_num, cleaned.bytes_saved
+            );
+        }
+        if let Some(text) = cleaned.text {
+            processed_content.push_str(&text);
+            processed_content.push_str(cleaned.ending.as_str());
+        }
+    });
+
+    (processed_content, modified_line_numbers, total_bytes_saved)
+}
+
+/// `content` cleaned by `rules`, with the original number of each line it keeps.
+fn cleaned_with_origins(content: &str, rules: &Rules) -> (String, Vec<usize>) {
+    let mut cleaned = String::with_capacity(content.len());
+    let mut origins = Vec::new();
+    clean_lines(content, rules, |line| {
+        if let Some(text) = line.text {
+            cleaned.push_str(&text);
+            cleaned.push_str(line.ending.as_str());
+            origins.push(line.line_num);
+        }
+    });
+    (cleaned, origins)
+}
+
+/// Split a raw line into its body and line ending, treating `\r\n` the same way `str::lines` does.
+fn split_line_ending(line: &[u8]) -> (&[u8], LineEnding) {
+    if let Some(body) = line.strip_suffix(b"\r\n") {
+        (body, LineEnding::Crlf)
+    } else if let Some(body) = line.strip_suffix(b"\n") {
+        (body, LineEnding::Lf)
+    } else {
+        (line, LineEnding::None)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ports::fs::{MemFs, RealFs};
+    use crate::config::{CustomRule, IndentStyle, LineEndings, RuleOverride};
+    use crate::ports::fs::{MemFs, RealFs, temp_sibling};
+    use crate::rules::LineRanges;
     use std::fs;
+    use std::path::PathBuf;
     use tempfile::TempDir;
 
     fn create_test_config() -> Arc<Config> {
         Arc::new(Config::default())
     }
 
+    fn create_streaming_config() -> Arc<Config> {
+        let mut config = Config::default();
+        config.processing.streaming_threshold = 0;
+        Arc::new(config)
+    }
+
+    fn create_mmap_config() -> Arc<Config> {
+        let mut config = Config::default();
+        config.processing.streaming_threshold = 0;
+        config.processing.mmap = true;
+        Arc::new(config)
+    }
+
     #[test]
     fn test_process_content_trailing_spaces() {
         let config = create_test_config();
@@ -204,6 +1305,48 @@ mod tests {
         assert!(processor.is_binary_content(binary_content));
     }
 
+    #[test]
+    fn test_binary_detection_inspects_content() {
+        let utf16 = b"\xFF\xFEh\x00i\x00 \x00\n\x00";
+        let png = b"\x89PNG\r\n\x1A\nno nulls here  \n";
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/utf16.txt", utf16)
+                .with_file("/mem/image.bin", png)
+                .with_file("/mem/image.txt", png),
+        );
+        let mut config = Config::default();
+        config.binary_detection.text_files = vec!["image.txt".to_string()];
+        let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+
+        // UTF-16 isn't binary, just not UTF-8
+        let result = processor.process_file(Path::new("/mem/utf16.txt"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::InvalidEncoding));
+        let result = processor.process_file(Path::new("/mem/image.bin"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Binary));
+        let result = processor.process_file(Path::new("/mem/image.txt"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::InvalidEncoding));
+
+        // Magic numbers only count at the start of a streamed file
+        config.processing.streaming_threshold = 0;
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/image.bin", png)
+                .with_file("/mem/notes.txt", b"first\n%PDF- in text \n"),
+        );
+        let streaming = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+        let result = streaming.process_file(Path::new("/mem/image.bin"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Binary));
+        let result = streaming.process_file(Path::new("/mem/notes.txt"), true).unwrap();
+        assert_eq!(result.lines_modified(), &[2]);
+
+        // Null bytes alone decide when content inspection is off
+        config.binary_detection.inspect_content = false;
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::new(MemFs::new()));
+        assert!(!processor.is_binary_content(png));
+        assert!(processor.is_binary_content(utf16));
+    }
+
     #[test]
     fn test_process_file_with_memfs_dry_run() {
         let config = create_test_config();
@@ -213,9 +1356,9 @@ mod tests {
 
         let result = processor.process_file(Path::new("test.txt"), true).unwrap();
 
-        assert!(result.had_changes);
-        assert_eq!(result.lines_modified, vec![1, 2]);
-        assert!(result.error.is_none());
+        assert!(result.had_changes());
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert!(result.error().is_none());
 
         // File should not be modified in dry run
         let content = fs.get_content(Path::new("test.txt")).unwrap();
@@ -231,15 +1374,210 @@ mod tests {
 
         let result = processor.process_file(Path::new("test.txt"), false).unwrap();
 
-        assert!(result.had_changes);
-        assert_eq!(result.lines_modified, vec![1, 2]);
-        assert!(result.error.is_none());
+        assert!(result.had_changes());
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert!(result.error().is_none());
 
         // File should be modified
         let content = fs.get_content(Path::new("test.txt")).unwrap();
         assert_eq!(content, b"line1\nline2\n");
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_custom_rules_run_after_built_in_rules() {
+        let config = Config {
+            custom_rules: vec![CustomRule {
+                name: "tabs".to_string(),
+                command: vec!["sed".to_string(), "s/\t/ /g".to_string()],
+                files: vec!["*.txt".to_string()],
+            }],
+            ..Config::default()
+        };
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("test.txt", b"a\tb  \nclean\nc\td\n")
+                .with_file("test.md", b"a\tb\n"),
+        );
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs));
+
+        let result = processor.process_file(Path::new("test.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), [1, 3]);
+        assert_eq!(fs.get_content(Path::new("test.txt")).unwrap(), b"a b\nclean\nc d\n");
+
+        let result = processor.process_file(Path::new("test.md"), false).unwrap();
+        assert!(!result.had_changes());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_custom_rule_lines_and_bytes_match_the_original() {
+        let mut config = Config {
+            custom_rules: vec![CustomRule {
+                name: "shout".to_string(),
+                command: vec!["sed".to_string(), "s/b/bbb/".to_string()],
+                files: Vec::new(),
+            }],
+            ..Config::default()
+        };
+        config.rules.max_consecutive_blank_lines = Some(1);
+        let fs = Arc::new(MemFs::new().with_file("test.txt", b"a  \n\n\n\nb\n"));
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs));
+
+        // `b` is the third line once blank lines are squeezed, but the fifth of the file
+        let result = processor.process_file(Path::new("test.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), [1, 3, 4, 5]);
+        let shout = result.rules().iter().find(|rule| rule.rule == "shout").unwrap();
+        assert_eq!(shout.lines, [5]);
+        // Four bytes trimmed and squeezed away, two added back
+        assert_eq!(result.bytes_removed(), 2);
+        assert_eq!(fs.get_content(Path::new("test.txt")).unwrap(), b"a\n\nbbb\n");
+    }
+
+    #[test]
+    fn test_whitespace_only_files_policy() {
+        assert!(is_whitespace_only(" \n\t\r\n"));
+        assert!(is_whitespace_only("\u{FEFF}"));
+        assert!(!is_whitespace_only(""));
+        assert!(!is_whitespace_only(" .\n"));
+
+        let cases = [
+            (WhitespaceOnlyPolicy::Keep, Some(&b"\n\n"[..])),
+            (WhitespaceOnlyPolicy::Empty, Some(&b""[..])),
+            (WhitespaceOnlyPolicy::Delete, None),
+        ];
+        // Streamed files follow the policy too
+        for (streaming_threshold, (policy, expected)) in [u64::MAX, 0].into_iter().flat_map(|t| cases.map(|c| (t, c))) {
+            let mut config = Config {
+                whitespace_only_files: policy,
+                ..Config::default()
+            };
+            config.processing.streaming_threshold = streaming_threshold;
+            let fs = Arc::new(
+                MemFs::new()
+                    .with_file("blank.txt", b"  \n\t\n")
+                    .with_file("text.txt", b"text  \n"),
+            );
+            let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs));
+
+            let result = processor.process_file(Path::new("blank.txt"), true).unwrap();
+            assert!(result.had_changes(), "{:?}", policy);
+            assert!(fs.get_content(Path::new("blank.txt")).is_some());
+
+            let result = processor.process_file(Path::new("blank.txt"), false).unwrap();
+            assert_eq!(
+                fs.get_content(Path::new("blank.txt")).as_deref(),
+                expected,
+                "{:?} streaming above {}",
+                policy,
+                streaming_threshold
+            );
+            if policy != WhitespaceOnlyPolicy::Keep {
+                assert_eq!(result.lines_modified(), [1, 2]);
+                assert_eq!(result.bytes_removed(), 5);
+                assert_eq!(result.rules()[0].rule, WHITESPACE_ONLY_RULE);
+            }
+
+            processor.process_file(Path::new("text.txt"), false).unwrap();
+            assert_eq!(fs.get_content(Path::new("text.txt")).unwrap(), b"text\n");
+        }
+    }
+
+    #[test]
+    fn test_quoted_fields_kept_and_reported() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("people.csv", b"id,note  \n1,\"two  \nlines\"  \n")
+                .with_file("people.tsv", b"id\tnote\t  \n1\t\"x \n\"\n"),
+        );
+        let processor = WhitespaceProcessor::new(Arc::new(Config::default()), Arc::clone(&fs));
+
+        let result = processor.process_file(Path::new("people.csv"), false).unwrap();
+        assert_eq!(result.lines_modified(), [1, 3]);
+        assert_eq!(result.notices[0].rule, KEPT_IN_LITERAL);
+        assert_eq!(result.notices[0].lines, [2]);
+        assert_eq!(
+            fs.get_content(Path::new("people.csv")).unwrap(),
+            b"id,note\n1,\"two  \nlines\"\n"
+        );
+
+        // A trailing tab ends an empty field
+        let result = processor.process_file(Path::new("people.tsv"), false).unwrap();
+        assert_eq!(result.notices[0].lines, [2]);
+        assert_eq!(
+            fs.get_content(Path::new("people.tsv")).unwrap(),
+            b"id\tnote\t\n1\t\"x \n\"\n"
+        );
+    }
+
+    #[test]
+    fn test_long_lines_reported_not_changed() {
+        for streaming_threshold in [u64::MAX, 0] {
+            let mut config = Config::default();
+            config.rules.max_line_length = Some(5);
+            config.processing.streaming_threshold = streaming_threshold;
+            let fs = Arc::new(
+                MemFs::new()
+                    .with_file("clean.txt", b"short\nlonger line\n")
+                    .with_file("dirty.txt", b"short   \nlonger line  \n"),
+            );
+            let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs));
+
+            let result = processor.process_file(Path::new("clean.txt"), false).unwrap();
+            assert!(!result.had_changes());
+            assert_eq!(result.notices[0].rule, "max-line-length");
+            assert_eq!(result.notices[0].lines, [2]);
+            assert_eq!(fs.get_content(Path::new("clean.txt")).unwrap(), b"short\nlonger line\n");
+
+            let result = processor.process_file(Path::new("dirty.txt"), false).unwrap();
+            assert_eq!(result.lines_modified(), [1, 2]);
+            assert_eq!(result.notices[0].lines, [2]);
+            assert_eq!(fs.get_content(Path::new("dirty.txt")).unwrap(), b"short\nlonger line\n");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lines_attributed_to_rules() {
+        let rules = |result: &ProcessingResult| -> Vec<(String, Vec<usize>)> {
+            result
+                .rules()
+                .iter()
+                .map(|rule| (rule.rule.clone(), rule.lines.clone()))
+                .collect()
+        };
+        let expected = |custom: &[(&str, &[usize])]| -> Vec<(String, Vec<usize>)> {
+            [("trailing-whitespace", &[1, 2][..]), ("indent-style", &[2][..])]
+                .iter()
+                .chain(custom)
+                .map(|(rule, lines)| (rule.to_string(), lines.to_vec()))
+                .collect()
+        };
+        let content = b"a  \n \tb \nc\td\n";
+        let mut config = Config {
+            custom_rules: vec![CustomRule {
+                name: "tabs".to_string(),
+                command: vec!["sed".to_string(), "s/\t/ /g".to_string()],
+                files: Vec::new(),
+            }],
+            ..Config::default()
+        };
+        config.rules.indent_style = Some(IndentStyle::Spaces);
+
+        let fs = Arc::new(MemFs::new().with_file("test.txt", content));
+        let processor = WhitespaceProcessor::new(Arc::new(config.clone()), fs);
+        let result = processor.process_file(Path::new("test.txt"), true).unwrap();
+        assert_eq!(result.lines_modified(), [1, 2, 3]);
+        assert_eq!(rules(&result), expected(&[("tabs", &[3])]));
+
+        // Streamed files skip custom rules, but attribute the built-in ones the same way
+        config.processing.streaming_threshold = 0;
+        let fs = Arc::new(MemFs::new().with_file("test.txt", content));
+        let processor = WhitespaceProcessor::new(Arc::new(config), fs);
+        let result = processor.process_file(Path::new("test.txt"), true).unwrap();
+        assert_eq!(rules(&result), expected(&[]));
+    }
+
     #[test]
     fn test_process_file_dry_run_with_real_fs() {
         let temp_dir = TempDir::new().unwrap();
@@ -249,14 +1587,14 @@ mod tests {
         fs::write(&test_file, original_content).unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let processor = WhitespaceProcessor::new(config, real_fs);
 
         let result = processor.process_file(&test_file, true).unwrap();
 
-        assert!(result.had_changes);
-        assert_eq!(result.lines_modified, vec![1, 2]);
-        assert!(result.error.is_none());
+        assert!(result.had_changes());
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert!(result.error().is_none());
 
         // File should not be modified in dry run
         let file_content = fs::read_to_string(&test_file).unwrap();
@@ -272,17 +1610,711 @@ mod tests {
         fs::write(&test_file, original_content).unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let processor = WhitespaceProcessor::new(config, real_fs);
 
         let result = processor.process_file(&test_file, false).unwrap();
 
-        assert!(result.had_changes);
-        assert_eq!(result.lines_modified, vec![1, 2]);
-        assert!(result.error.is_none());
+        assert!(result.had_changes());
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert!(result.error().is_none());
 
         // File should be modified
         let file_content = fs::read_to_string(&test_file).unwrap();
         assert_eq!(file_content, "line1\nline2\n");
     }
+
+    #[test]
+    fn test_streaming_matches_in_memory() {
+        let inputs: [&[u8]; 5] = [
+            b"line1   \nline2\t\t\nline3\n",
+            b"line1   \nline2\t\t",
+            b"crlf  \r\nclean\r\n",
+            b"\n\n  \n",
+            b"no changes\n",
+        ];
+
+        for input in inputs {
+            let in_memory_fs = Arc::new(MemFs::new().with_file("test.txt", input));
+            let in_memory = WhitespaceProcessor::new(create_test_config(), Arc::clone(&in_memory_fs));
+            let expected = in_memory.process_file(Path::new("test.txt"), false).unwrap();
+
+            let streaming_fs = Arc::new(MemFs::new().with_file("test.txt", input));
+            let streaming = WhitespaceProcessor::new(create_streaming_config(), Arc::clone(&streaming_fs));
+            let result = streaming.process_file(Path::new("test.txt"), false).unwrap();
+
+            assert_eq!(result.lines_modified(), expected.lines_modified());
+            assert_eq!(result.had_changes(), expected.had_changes());
+            assert_eq!(
+                streaming_fs.get_content(Path::new("test.txt")),
+                in_memory_fs.get_content(Path::new("test.txt"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_streaming_dry_run_leaves_file() {
+        let original_content = b"line1   \nline2\t\t\n";
+        let fs = Arc::new(MemFs::new().with_file("test.txt", original_content));
+        let processor = WhitespaceProcessor::new(create_streaming_config(), Arc::clone(&fs));
+
+        let result = processor.process_file(Path::new("test.txt"), true).unwrap();
+
+        assert!(result.had_changes());
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert_eq!(fs.get_content(Path::new("test.txt")).unwrap(), original_content);
+    }
+
+    #[test]
+    fn test_streaming_skips_binary_and_invalid_utf8() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("binary.txt", b"text  \nbin\0ary\n")
+                .with_file("latin1.txt", b"caf\xe9  \n"),
+        );
+        let processor = WhitespaceProcessor::new(create_streaming_config(), Arc::clone(&fs));
+
+        let result = processor.process_file(Path::new("binary.txt"), false).unwrap();
+        assert!(!result.had_changes());
+        assert_eq!(result.skip_reason(), Some(SkipReason::Binary));
+        assert!(result.is_skipped() && !result.is_failure());
+
+        let result = processor.process_file(Path::new("latin1.txt"), false).unwrap();
+        assert!(!result.had_changes());
+        assert_eq!(result.skip_reason(), Some(SkipReason::InvalidEncoding));
+        assert_eq!(fs.get_content(Path::new("latin1.txt")).unwrap(), b"caf\xe9  \n");
+    }
+
+    #[test]
+    fn test_outcomes_are_distinguished() {
+        let fs = Arc::new(MemFs::new().with_file("clean.txt", b"clean\n"));
+        let processor = WhitespaceProcessor::new(create_test_config(), fs);
+
+        let result = processor.process_file(Path::new("clean.txt"), false).unwrap();
+        assert!(matches!(result.outcome, Outcome::Clean));
+        assert!(result.lines_modified().is_empty());
+
+        let result = processor.process_file(Path::new("missing.txt"), false).unwrap();
+        assert!(result.is_failure() && !result.is_skipped() && !result.had_changes());
+        assert_eq!(result.error().unwrap().to_string(), "Failed to read file");
+    }
+
+    #[test]
+    fn test_carriage_return_in_literal_keeps_ending() {
+        let mut config = Config::default();
+        config.rules.line_endings = LineEndings::Lf;
+        config.rules.preserve_string_literals = true;
+        let fs = Arc::new(MemFs::new().with_file("lib.rs", b"fn f() {}  \nlet s = \"a\r\r\nb\";\n"));
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs));
+
+        // `a\r\n` would read back as `a` with a CRLF ending, and change again on the next run
+        let result = processor.process_file(Path::new("lib.rs"), false).unwrap();
+        assert_eq!(result.lines_modified(), [1]);
+        let content = fs.get_content(Path::new("lib.rs")).unwrap();
+        assert_eq!(content, b"fn f() {}\nlet s = \"a\r\r\nb\";\n");
+        assert!(
+            !processor
+                .process_file(Path::new("lib.rs"), false)
+                .unwrap()
+                .had_changes()
+        );
+    }
+
+    #[test]
+    fn test_line_ranges() {
+        let content = "one  \r\ntwo  \r\nthree  \r\n\r\n";
+        let mut config = Config::default();
+        config.rules.line_endings = LineEndings::Lf;
+        config.rules.trim_trailing_blank_lines = true;
+        let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::new(MemFs::new()))
+            .with_line_ranges(LineRanges::new([2..=2]));
+
+        // Lines outside the range keep their whitespace, endings and blank lines
+        let (processed, lines, saved) = processor.process_content(content);
+        assert_eq!(processed, "one  \r\ntwo\nthree  \r\n\r\n");
+        assert_eq!(lines, [2]);
+        assert_eq!(saved, 2);
+        assert_eq!(processor.scan_content(content), [2]);
+
+        // Streamed files the same
+        config.processing.streaming_threshold = 0;
+        let fs = Arc::new(MemFs::new().with_file("test.txt", content.as_bytes()));
+        let processor =
+            WhitespaceProcessor::new(Arc::new(config), Arc::clone(&fs)).with_line_ranges(LineRanges::new([2..=4]));
+        let result = processor.process_file(Path::new("test.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), [2, 3, 4]);
+        assert_eq!(fs.get_content(Path::new("test.txt")).unwrap(), b"one  \r\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_rule_selection() {
+        let mut config = Config {
+            whitespace_only_files: WhitespaceOnlyPolicy::Empty,
+            ..Config::default()
+        };
+        config.rules.max_consecutive_blank_lines = Some(2);
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("blank.txt", b"  \n")
+                .with_file("text.txt", b"text  \n\n\n\nend  \n"),
+        );
+        let only = |rules: &[&str]| {
+            WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&fs)).with_rule_selection(RuleSelection {
+                only: rules.iter().map(ToString::to_string).collect(),
+                skip: Vec::new(),
+            })
+        };
+
+        let processor = only(&["max-consecutive-blank-lines"]);
+        let result = processor.process_file(Path::new("text.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), [4]);
+        assert_eq!(fs.get_content(Path::new("text.txt")).unwrap(), b"text  \n\n\nend  \n");
+
+        // A file of whitespace is trimmed like any other unless its own rule is selected
+        let result = processor.process_file(Path::new("blank.txt"), true).unwrap();
+        assert!(!result.had_changes());
+        let result = only(&["trailing-whitespace"])
+            .process_file(Path::new("blank.txt"), true)
+            .unwrap();
+        assert!(result.had_changes());
+        assert!(result.rules().iter().all(|rule| rule.rule == "trailing-whitespace"));
+        let result = only(&["whitespace-only-files"])
+            .process_file(Path::new("blank.txt"), true)
+            .unwrap();
+        assert_eq!(result.rules()[0].rule, WHITESPACE_ONLY_RULE);
+    }
+
+    #[test]
+    fn test_backup() {
+        for config in [create_test_config(), create_streaming_config()] {
+            let fs = Arc::new(
+                MemFs::new()
+                    .with_file("dirty.txt", b"dirty  \n")
+                    .with_file("dirty.txt.bak", b"stale\n")
+                    .with_file("clean.txt", b"clean\n"),
+            );
+            let processor = WhitespaceProcessor::new(config, Arc::clone(&fs)).with_backup(".bak");
+            for path in ["dirty.txt", "clean.txt"] {
+                processor.process_file(Path::new(path), false).unwrap();
+            }
+            assert_eq!(fs.get_content(Path::new("dirty.txt")).unwrap(), b"dirty\n");
+            assert_eq!(fs.get_content(Path::new("dirty.txt.bak")).unwrap(), b"dirty  \n");
+            assert_eq!(fs.get_content(Path::new("clean.txt.bak")), None);
+        }
+    }
+
+    #[test]
+    fn test_trash() {
+        for config in [create_test_config(), create_streaming_config()] {
+            let fs = Arc::new(
+                MemFs::new()
+                    .with_file("dirty.txt", b"dirty  \n")
+                    .with_file("clean.txt", b"clean\n"),
+            );
+            let processor = WhitespaceProcessor::new(config, Arc::clone(&fs)).with_trash();
+            for path in ["dirty.txt", "clean.txt"] {
+                processor.process_file(Path::new(path), false).unwrap();
+            }
+            assert_eq!(fs.get_content(Path::new("dirty.txt")).unwrap(), b"dirty\n");
+            assert_eq!(fs.get_trashed(Path::new("dirty.txt")).unwrap(), b"dirty  \n");
+            assert_eq!(fs.get_trashed(Path::new("clean.txt")), None);
+        }
+    }
+
+    #[test]
+    fn test_changed_lines_only() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/changed.txt", b"a  \nb  \nc  \n")
+                .with_file("/repo/untouched.txt", b"a  \n"),
+        );
+        let changed_lines = ChangedLines::new([(PathBuf::from("/repo/changed.txt"), LineRanges::new([2..=3]))]);
+        let processor =
+            WhitespaceProcessor::new(create_test_config(), Arc::clone(&fs)).with_changed_lines(Arc::new(changed_lines));
+
+        let result = processor.process_file(Path::new("/repo/changed.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), [2, 3]);
+        assert_eq!(fs.get_content(Path::new("/repo/changed.txt")).unwrap(), b"a  \nb\nc\n");
+
+        let result = processor.process_file(Path::new("/repo/untouched.txt"), false).unwrap();
+        assert!(!result.had_changes());
+        assert_eq!(fs.get_content(Path::new("/repo/untouched.txt")).unwrap(), b"a  \n");
+    }
+
+    #[test]
+    fn test_normalize_mixed_indentation() {
+        let config = |style| {
+            let mut config = Config::default();
+            config.rules.indent_style = Some(style);
+            config
+        };
+        let content = "fn main() {\n  \tmixed();\n\ttabs();\n    spaces();\n\t  aligned(); \n}\n";
+
+        let spaces = WhitespaceProcessor::new(Arc::new(config(IndentStyle::Spaces)), Arc::new(MemFs::new()));
+        let (processed, lines, _) = spaces.process_content(content);
+        assert_eq!(
+            processed,
+            "fn main() {\n    mixed();\n\ttabs();\n    spaces();\n      aligned();\n}\n"
+        );
+        assert_eq!(lines, vec![2, 5]);
+        assert_eq!(spaces.scan_content(content), lines);
+
+        let tabs = WhitespaceProcessor::new(Arc::new(config(IndentStyle::Tabs)), Arc::new(MemFs::new()));
+        let (processed, lines, _) = tabs.process_content(content);
+        assert_eq!(
+            processed,
+            "fn main() {\n\tmixed();\n\ttabs();\n    spaces();\n\t  aligned();\n}\n"
+        );
+        assert_eq!(lines, vec![2, 5]);
+
+        // Off by default
+        let (processed, lines, _) =
+            WhitespaceProcessor::new(create_test_config(), Arc::new(MemFs::new())).process_content(content);
+        assert_eq!(processed, content.replace("aligned(); ", "aligned();"));
+        assert_eq!(lines, vec![5]);
+    }
+
+    #[test]
+    fn test_diff_files_are_skipped() {
+        let patch = "From 1234 Mon Sep 17 00:00:00 2001\n---\n a.txt | 2 +-\n\n\
+                     --- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-old \n+new \n";
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/change.txt", patch.as_bytes())
+                .with_file("/mem/git.txt", b"diff --git a/x b/x\n context \n")
+                .with_file("/mem/notes.txt", b"--- heading \ntext\n")
+                .with_file("/mem/CONTRIBUTING.md", b"```\ndiff --git a/x b/x\n```\nSend patches \n"),
+        );
+        let processor = WhitespaceProcessor::new(create_test_config(), Arc::clone(&memfs));
+
+        for path in ["/mem/change.txt", "/mem/git.txt"] {
+            let result = processor.process_file(Path::new(path), false).unwrap();
+            assert_eq!(result.skip_reason(), Some(SkipReason::Diff), "{}", path);
+        }
+        assert_eq!(
+            memfs.get_content(Path::new("/mem/change.txt")).unwrap(),
+            patch.as_bytes()
+        );
+        // A page that shows a diff isn't one
+        for path in ["/mem/notes.txt", "/mem/CONTRIBUTING.md"] {
+            assert!(
+                processor.process_file(Path::new(path), true).unwrap().had_changes(),
+                "{}",
+                path
+            );
+        }
+
+        // Streamed files are sniffed too
+        let mut config = Config::default();
+        config.processing.streaming_threshold = 0;
+        let streaming = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+        let result = streaming.process_file(Path::new("/mem/change.txt"), false).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Diff));
+        let result = streaming.process_file(Path::new("/mem/CONTRIBUTING.md"), true).unwrap();
+        assert!(result.had_changes());
+
+        // --force turns detection off
+        config.diff_detection.enabled = false;
+        let forced = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+        let result = forced.process_file(Path::new("/mem/change.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[8, 9]);
+    }
+
+    #[test]
+    fn test_generated_files_are_skipped() {
+        let generated = "// Code generated by protoc-gen-go. DO NOT EDIT.\npackage api \n";
+        let late = "package api \n\n\n\n\n// @generated by a tool mentioned in passing\n";
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/api.go", generated.as_bytes())
+                .with_file("/mem/late.go", late.as_bytes()),
+        );
+        let processor = WhitespaceProcessor::new(create_test_config(), Arc::clone(&memfs));
+
+        let result = processor.process_file(Path::new("/mem/api.go"), false).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Generated));
+        assert_eq!(
+            memfs.get_content(Path::new("/mem/api.go")).unwrap(),
+            generated.as_bytes()
+        );
+        // Only the first lines are searched
+        assert_eq!(
+            processor
+                .process_file(Path::new("/mem/late.go"), true)
+                .unwrap()
+                .lines_modified(),
+            &[1]
+        );
+
+        let mut config = Config::default();
+        config.processing.streaming_threshold = 0;
+        let streaming = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+        let result = streaming.process_file(Path::new("/mem/api.go"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Generated));
+
+        config.generated_detection.enabled = false;
+        let included = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+        let result = included.process_file(Path::new("/mem/api.go"), true).unwrap();
+        assert_eq!(result.lines_modified(), &[2]);
+    }
+
+    #[test]
+    fn test_merge_conflicts_are_skipped() {
+        let conflicted = "fn main() {\n<<<<<<< HEAD\n    ours(); \n=======\n    theirs();\n>>>>>>> branch\n}\n";
+        let heading = "Title \n=======\n\n>>>>>>> not a conflict\n";
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/main.rs", conflicted.as_bytes())
+                .with_file("/mem/README.txt", heading.as_bytes()),
+        );
+        let processor = WhitespaceProcessor::new(create_test_config(), Arc::clone(&memfs));
+
+        let result = processor.process_file(Path::new("/mem/main.rs"), false).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Conflict));
+        assert_eq!(
+            memfs.get_content(Path::new("/mem/main.rs")).unwrap(),
+            conflicted.as_bytes()
+        );
+        assert!(
+            processor
+                .process_file(Path::new("/mem/README.txt"), true)
+                .unwrap()
+                .had_changes()
+        );
+
+        let mut config = Config::default();
+        config.processing.streaming_threshold = 0;
+        let streaming = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+        let result = streaming.process_file(Path::new("/mem/main.rs"), false).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Conflict));
+
+        // --allow-conflicts cleans them like any other file
+        config.skip_conflicts = false;
+        let allowed = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+        let result = allowed.process_file(Path::new("/mem/main.rs"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[3]);
+    }
+
+    #[test]
+    fn test_readonly_policy() {
+        let processor = |policy| {
+            let memfs = Arc::new(MemFs::new().with_file("/mem/locked.txt", b"dirty  \n"));
+            memfs.set_readonly(Path::new("/mem/locked.txt"), true).unwrap();
+            let config = Config {
+                readonly: policy,
+                ..Config::default()
+            };
+            (WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs)), memfs)
+        };
+        let locked = Path::new("/mem/locked.txt");
+
+        for policy in [ReadOnlyPolicy::Skip, ReadOnlyPolicy::Warn] {
+            let (processor, memfs) = processor(policy);
+            let result = processor.process_file(locked, false).unwrap();
+            assert_eq!(result.skip_reason(), Some(SkipReason::ReadOnly));
+            assert_eq!(memfs.get_content(locked).unwrap(), b"dirty  \n");
+        }
+
+        let (processor, memfs) = processor(ReadOnlyPolicy::Force);
+        let result = processor.process_file(locked, false).unwrap();
+        assert_eq!(result.lines_modified(), &[1]);
+        assert_eq!(memfs.get_content(locked).unwrap(), b"dirty\n");
+        assert!(memfs.metadata(locked).unwrap().readonly);
+    }
+
+    #[test]
+    fn test_force_readonly_on_disk() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("locked.txt");
+        fs::write(&path, "dirty  \n").unwrap();
+        RealFs::default().set_readonly(&path, true).unwrap();
+
+        let config = Config {
+            readonly: ReadOnlyPolicy::Force,
+            ..Config::default()
+        };
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::new(RealFs::default()));
+        assert!(processor.process_file(&path, false).unwrap().had_changes());
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dirty\n");
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+        RealFs::default().set_readonly(&path, false).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlinks_policy() {
+        for streaming in [false, true] {
+            let processor = |hardlinks| {
+                let mut config = Config {
+                    hardlinks,
+                    ..Config::default()
+                };
+                if streaming {
+                    config.processing.streaming_threshold = 0;
+                }
+                WhitespaceProcessor::new(Arc::new(config), Arc::new(RealFs::default()))
+            };
+            let temp_dir = TempDir::new().unwrap();
+            let path = temp_dir.path().join("a.txt");
+            let link = temp_dir.path().join("backup.txt");
+            fs::write(&path, "dirty  \n").unwrap();
+            fs::hard_link(&path, &link).unwrap();
+
+            let result = processor(HardLinkPolicy::Skip).process_file(&path, false).unwrap();
+            assert_eq!(result.skip_reason(), Some(SkipReason::HardLinked));
+            assert_eq!(fs::read_to_string(&path).unwrap(), "dirty  \n");
+
+            processor(HardLinkPolicy::InPlace).process_file(&path, false).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "dirty\n");
+            assert_eq!(fs::read_to_string(&link).unwrap(), "dirty\n");
+            assert!(!fs::exists(temp_sibling(&path)).unwrap());
+
+            fs::write(&path, "dirty  \n").unwrap();
+            processor(HardLinkPolicy::Break).process_file(&path, false).unwrap();
+            assert_eq!(fs::read_to_string(&path).unwrap(), "dirty\n");
+            assert_eq!(fs::read_to_string(&link).unwrap(), "dirty  \n");
+        }
+    }
+
+    #[test]
+    fn test_overrides_by_shebang() {
+        let script = "#!/usr/bin/env bash\necho  \n\n\n\necho\n";
+        for streaming in [false, true] {
+            let mut config = Config::default();
+            config.rules.overrides = vec![RuleOverride {
+                types: vec!["shell".to_string()],
+                max_consecutive_blank_lines: Some(1),
+                ..RuleOverride::default()
+            }];
+            config.file_types.names.insert("*.env".to_string(), "shell".to_string());
+            if streaming {
+                config.processing.streaming_threshold = 0;
+            }
+            let memfs = Arc::new(
+                MemFs::new()
+                    .with_file("/mem/deploy", script.as_bytes())
+                    .with_file("/mem/prod.env", b"A=1\n\n\nB=2\n")
+                    .with_file("/mem/notes", b"echo  \n\n\n\necho\n"),
+            );
+            let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+            let process = |path: &str| processor.process_file(Path::new(path), false).unwrap();
+
+            assert_eq!(process("/mem/deploy").lines_modified(), &[2, 4, 5]);
+            assert_eq!(
+                memfs.get_content(Path::new("/mem/deploy")).unwrap(),
+                b"#!/usr/bin/env bash\necho\n\necho\n"
+            );
+            assert_eq!(process("/mem/prod.env").lines_modified(), &[3]);
+            assert_eq!(process("/mem/notes").lines_modified(), &[1]);
+        }
+    }
+
+    #[test]
+    fn test_squeeze_blank_lines_with_overrides() {
+        let content = "a\n\n\n\nb\n  \n\nc";
+        let mut config = Config::default();
+        config.rules.max_consecutive_blank_lines = Some(1);
+        config.rules.overrides = vec![RuleOverride {
+            files: vec!["*.py".to_string()],
+            max_consecutive_blank_lines: Some(2),
+            ..RuleOverride::default()
+        }];
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/notes.txt", content.as_bytes())
+                .with_file("/mem/app.py", content.as_bytes()),
+        );
+
+        let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+        let (processed, lines, saved) = processor.process_content(content);
+        assert_eq!(processed, "a\n\nb\n\nc");
+        assert_eq!(lines, vec![3, 4, 6, 7]);
+        assert_eq!(saved, 5);
+        assert_eq!(processor.scan_content(content), lines);
+
+        let result = processor.process_file(Path::new("/mem/notes.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[3, 4, 6, 7]);
+        assert_eq!(memfs.get_content(Path::new("/mem/notes.txt")).unwrap(), b"a\n\nb\n\nc");
+
+        let result = processor.process_file(Path::new("/mem/app.py"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[4, 6]);
+        assert_eq!(memfs.get_content(Path::new("/mem/app.py")).unwrap(), b"a\n\n\nb\n\n\nc");
+
+        // Streaming squeezes the same lines
+        config.processing.streaming_threshold = 0;
+        let memfs = Arc::new(MemFs::new().with_file("/mem/notes.txt", content.as_bytes()));
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+        let result = processor.process_file(Path::new("/mem/notes.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[3, 4, 6, 7]);
+        assert_eq!(memfs.get_content(Path::new("/mem/notes.txt")).unwrap(), b"a\n\nb\n\nc");
+    }
+
+    #[test]
+    fn test_trim_trailing_blank_lines_at_eof() {
+        let content = "a\nb  \n\n \n\t";
+        let mut config = Config::default();
+        config.rules.overrides = vec![RuleOverride {
+            files: vec!["*.txt".to_string()],
+            trim_trailing_blank_lines: Some(true),
+            ..RuleOverride::default()
+        }];
+        let memfs = Arc::new(
+            MemFs::new()
+                .with_file("/mem/notes.txt", content.as_bytes())
+                .with_file("/mem/notes.rs", content.as_bytes()),
+        );
+        let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+
+        let result = processor.process_file(Path::new("/mem/notes.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[2, 3, 4, 5]);
+        assert_eq!(memfs.get_content(Path::new("/mem/notes.txt")).unwrap(), b"a\nb\n");
+
+        // Other files only lose their trailing whitespace
+        let result = processor.process_file(Path::new("/mem/notes.rs"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[2, 4, 5]);
+        assert_eq!(memfs.get_content(Path::new("/mem/notes.rs")).unwrap(), b"a\nb\n\n\n");
+
+        config.processing.streaming_threshold = 0;
+        let memfs = Arc::new(MemFs::new().with_file("/mem/notes.txt", content.as_bytes()));
+        let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+        let result = processor.process_file(Path::new("/mem/notes.txt"), false).unwrap();
+        assert_eq!(result.lines_modified(), &[2, 3, 4, 5]);
+        assert_eq!(memfs.get_content(Path::new("/mem/notes.txt")).unwrap(), b"a\nb\n");
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let content = "a  \r\nb\r\nc \n";
+        for streaming in [false, true] {
+            let mut config = Config::default();
+            if streaming {
+                config.processing.streaming_threshold = 0;
+            }
+
+            config.rules.line_endings = LineEndings::Preserve;
+            let memfs = Arc::new(MemFs::new().with_file("/mem/a.txt", content.as_bytes()));
+            let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+            let result = processor.process_file(Path::new("/mem/a.txt"), false).unwrap();
+            assert_eq!(result.lines_modified(), &[1, 3]);
+            assert_eq!(memfs.get_content(Path::new("/mem/a.txt")).unwrap(), b"a\r\nb\r\nc\n");
+
+            config.rules.line_endings = LineEndings::Lf;
+            let memfs = Arc::new(MemFs::new().with_file("/mem/a.txt", content.as_bytes()));
+            let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+            processor.process_file(Path::new("/mem/a.txt"), false).unwrap();
+            assert_eq!(memfs.get_content(Path::new("/mem/a.txt")).unwrap(), b"a\nb\nc\n");
+        }
+    }
+
+    #[test]
+    fn test_gitattributes() {
+        let attributes = b"*.bat eol=crlf\n*.sh eol=lf\n*.bin binary\nkeep.txt -text\n";
+        for streaming in [false, true] {
+            let mut config = Config::default();
+            config.rules.line_endings = LineEndings::Preserve;
+            if streaming {
+                config.processing.streaming_threshold = 0;
+            }
+            let memfs = Arc::new(
+                MemFs::new()
+                    .with_dir("/repo/.git")
+                    .with_file("/repo/.gitattributes", attributes)
+                    .with_file("/repo/run.bat", b"a  \nb\r\n")
+                    .with_file("/repo/run.sh", b"a  \r\nb\r\n")
+                    .with_file("/repo/data.bin", b"a  \n")
+                    .with_file("/repo/keep.txt", b"a  \n"),
+            );
+            let processor = WhitespaceProcessor::new(Arc::new(config.clone()), Arc::clone(&memfs));
+            let process = |path: &str| processor.process_file(Path::new(path), false).unwrap();
+
+            process("/repo/run.bat");
+            assert_eq!(memfs.get_content(Path::new("/repo/run.bat")).unwrap(), b"a\r\nb\r\n");
+            process("/repo/run.sh");
+            assert_eq!(memfs.get_content(Path::new("/repo/run.sh")).unwrap(), b"a\nb\n");
+            for path in ["/repo/data.bin", "/repo/keep.txt"] {
+                assert_eq!(process(path).skip_reason(), Some(SkipReason::Binary));
+                assert_eq!(memfs.get_content(Path::new(path)).unwrap(), b"a  \n");
+            }
+
+            config.gitattributes = false;
+            let processor = WhitespaceProcessor::new(Arc::new(config), Arc::clone(&memfs));
+            processor.process_file(Path::new("/repo/keep.txt"), false).unwrap();
+            assert_eq!(memfs.get_content(Path::new("/repo/keep.txt")).unwrap(), b"a\n");
+        }
+    }
+
+    #[test]
+    fn test_streaming_with_real_fs() {
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("large.txt");
+        fs::write(&test_file, "line1   \nline2\t\t\n").unwrap();
+
+        let processor = WhitespaceProcessor::new(create_streaming_config(), Arc::new(RealFs::default()));
+        let result = processor.process_file(&test_file, false).unwrap();
+
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert_eq!(fs::read_to_string(&test_file).unwrap(), "line1\nline2\n");
+    }
+
+    #[test]
+    fn test_scan_content_matches_process_content() {
+        let processor = WhitespaceProcessor::new(create_test_config(), Arc::new(MemFs::new()));
+        let content = "clean\ndirty  \r\n\t\nlast ";
+
+        let (_, modified_lines, _) = processor.process_content(content);
+        assert_eq!(processor.scan_content(content), modified_lines);
+    }
+
+    #[test]
+    fn test_mmap_with_real_fs() {
+        let temp_dir = TempDir::new().unwrap();
+        let dirty_file = temp_dir.path().join("dirty.txt");
+        let clean_file = temp_dir.path().join("clean.txt");
+        fs::write(&dirty_file, "line1   \nline2\t\t\n").unwrap();
+        fs::write(&clean_file, "line1\nline2\n").unwrap();
+
+        let processor = WhitespaceProcessor::new(create_mmap_config(), Arc::new(RealFs::default()));
+
+        let result = processor.process_file(&dirty_file, true).unwrap();
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert_eq!(fs::read_to_string(&dirty_file).unwrap(), "line1   \nline2\t\t\n");
+
+        let result = processor.process_file(&dirty_file, false).unwrap();
+        assert_eq!(result.lines_modified(), vec![1, 2]);
+        assert_eq!(fs::read_to_string(&dirty_file).unwrap(), "line1\nline2\n");
+
+        let result = processor.process_file(&clean_file, false).unwrap();
+        assert!(!result.had_changes());
+        assert!(result.error().is_none());
+    }
+
+    #[test]
+    fn test_cache_records_and_skips_clean_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let clean_file = temp_dir.path().join("clean.txt");
+        let dirty_file = temp_dir.path().join("dirty.txt");
+        fs::write(&clean_file, "clean\n").unwrap();
+        fs::write(&dirty_file, "dirty  \n").unwrap();
+
+        let config = create_test_config();
+        let cache = Arc::new(Cache::load_from(temp_dir.path(), &temp_dir.path().join("cache.json"), &config).unwrap());
+        let real_fs = Arc::new(RealFs::default());
+        let processor = WhitespaceProcessor::new(config, Arc::clone(&real_fs)).with_cache(Arc::clone(&cache));
+
+        // Dry runs don't cache dirty files
+        assert!(processor.process_file(&dirty_file, true).unwrap().had_changes());
+        assert!(!cache.is_fresh(&dirty_file, &real_fs.metadata(&dirty_file).unwrap()));
+
+        processor.process_file(&clean_file, false).unwrap();
+        assert!(cache.is_fresh(&clean_file, &real_fs.metadata(&clean_file).unwrap()));
+
+        // Once cleaned, the rewritten file is cached too
+        assert!(processor.process_file(&dirty_file, false).unwrap().had_changes());
+        assert!(cache.is_fresh(&dirty_file, &real_fs.metadata(&dirty_file).unwrap()));
+
+        // Editing the file invalidates its entry
+        fs::write(&clean_file, "now dirty  \n").unwrap();
+        assert!(processor.process_file(&clean_file, true).unwrap().had_changes());
+    }
 }
@@ -0,0 +1,275 @@
+use super::{RunReport, milliseconds, outcome_name};
+use crate::filetypes::FileTypes;
+use crate::output::escape_xml;
+use crate::processor::Outcome;
+use crate::rules::{RuleSet, clean_line, trim_line};
+use crate::timings::Phase;
+use std::fmt::Write;
+
+/// Modified lines shown per file in a dry run; further lines are only counted.
+const SNIPPET_LINES: usize = 20;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+td, th { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; vertical-align: top; }
+table.sortable th { cursor: pointer; user-select: none; background: #f4f4f4; }
+table.sortable th[data-order="asc"]::after { content: " \25B2"; }
+table.sortable th[data-order="desc"]::after { content: " \25BC"; }
+td.number { text-align: right; }
+tr.modified td { background: #fff6d5; }
+tr.failed td { background: #fde2e2; }
+table.snippet td { font-family: monospace; white-space: pre; }
+table.snippet mark { background: #f8b4b4; }
+.ws { color: #999; }
+details { margin-bottom: 0.5em; }
+"#;
+
+/// Sort a `table.sortable` by the clicked column, numerically when both cells are numbers.
+const SCRIPT: &str = r#"
+document.querySelectorAll("table.sortable th").forEach((th, column) => {
+  th.addEventListener("click", () => {
+    const table = th.closest("table");
+    const body = table.tBodies[0];
+    const ascending = th.dataset.order !== "asc";
+    table.querySelectorAll("th").forEach((other) => delete other.dataset.order);
+    th.dataset.order = ascending ? "asc" : "desc";
+    const key = (row) => row.cells[column].textContent;
+    const rows = Array.from(body.rows).sort((a, b) => {
+      const [x, y] = [key(a), key(b)];
+      const order = x !== "" && y !== "" && !isNaN(x) && !isNaN(y) ? x - y : x.localeCompare(y);
+      return ascending ? order : -order;
+    });
+    body.append(...rows);
+  });
+});
+"#;
+
+/// Render a self-contained page: a summary, a sortable table of every file and, for dry runs,
+/// each modified line before and after cleaning.
+pub(super) fn render(report: &RunReport) -> String {
+    let counts = report.counts();
+    let mut page = String::new();
+
+    page.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n");
+    page.push_str("<title>whitespace report</title>\n");
+    let _ = writeln!(page, "<style>{}</style>\n</head>\n<body>", STYLE);
+    page.push_str("<h1>whitespace report</h1>\n");
+
+    let directories: Vec<String> = report
+        .directories
+        .iter()
+        .map(|directory| directory.display().to_string())
+        .collect();
+    let _ = writeln!(
+        page,
+        "<p>{} of {} with whitespace {}, in {:.1}ms.</p>",
+        if report.dry_run { "Dry run" } else { "Run" },
+        escape_xml(&directories.join(", ")),
+        env!("GIT_DESCRIBE"),
+        milliseconds(report.elapsed)
+    );
+    let _ = writeln!(
+        page,
+        "<p>{} files: {} {}, {} clean, {} skipped, {} failed.</p>",
+        report.file_results.len(),
+        counts.modified,
+        if report.dry_run { "to clean" } else { "cleaned" },
+        counts.clean,
+        counts.skipped,
+        counts.failed
+    );
+
+    render_files(report, &mut page);
+    if report.dry_run {
+        render_snippets(report, &mut page);
+    }
+    render_details(report, &mut page);
+
+    let _ = writeln!(page, "<script>{}</script>\n</body>\n</html>", SCRIPT);
+    page
+}
+
+fn render_files(report: &RunReport, page: &mut String) {
+    page.push_str("<h2>Files</h2>\n<table class=\"sortable\">\n");
+    page.push_str("<thead><tr><th>File</th><th>Outcome</th><th>Lines</th><th>Details</th></tr></thead>\n<tbody>\n");
+    for (path, result) in report.file_results {
+        let lines = result.lines_modified();
+        let detail = match &result.outcome {
+            Outcome::Modified { lines, .. } => crate::format_line_numbers(lines).trim().to_string(),
+            Outcome::Skipped(reason) => reason.to_string(),
+            Outcome::Failed(error) => format!("{:#}", error),
+            Outcome::Clean => String::new(),
+        };
+        let _ = writeln!(
+            page,
+            "<tr class=\"{outcome}\"><td>{}</td><td>{outcome}</td><td class=\"number\">{}</td><td>{}</td></tr>",
+            escape_xml(&path.display().to_string()),
+            lines.len(),
+            escape_xml(&detail),
+            outcome = outcome_name(&result.outcome),
+        );
+    }
+    page.push_str("</tbody>\n</table>\n");
+}
+
+/// The files are still untouched after a dry run, so their modified lines can be shown as
+/// they are and as they would be cleaned.
+fn render_snippets(report: &RunReport, page: &mut String) {
+    let file_types = FileTypes::new(&report.config.file_types);
+    let rules = RuleSet::new(&report.config.rules).with_file_types(file_types);
+    let mut heading = false;
+
+    for (path, result) in report.file_results {
+        let lines = result.lines_modified();
+        if lines.is_empty() {
+            continue;
+        }
+        let source = report.relative_to.map_or_else(|| path.clone(), |base| base.join(path));
+        let Some(content) = report
+            .fs
+            .read(&source)
+            .ok()
+            .and_then(|content| String::from_utf8(content).ok())
+        else {
+            continue;
+        };
+        if !heading {
+            page.push_str("<h2>Findings</h2>\n");
+            heading = true;
+        }
+
+        let rules = rules.for_script(path, content.lines().next().unwrap_or_default());
+        let text: Vec<&str> = content.lines().collect();
+        let _ = writeln!(
+            page,
+            "<details><summary>{} ({} lines)</summary>\n<table class=\"snippet\">",
+            escape_xml(&path.display().to_string()),
+            lines.len()
+        );
+        page.push_str("<tr><th>Line</th><th>Before</th><th>After</th></tr>\n");
+        for &line_num in lines.iter().take(SNIPPET_LINES) {
+            let Some(line) = text.get(line_num - 1) else {
+                continue;
+            };
+            let (kept, trailing) = line.split_at(trim_line(line, &rules).len());
+            let cleaned = clean_line(line, &rules);
+            let (cleaned_kept, cleaned_trailing) = cleaned.split_at(trim_line(&cleaned, &rules).len());
+            let _ = writeln!(
+                page,
+                "<tr><td class=\"number\">{}</td><td>{}<mark>{}</mark></td><td>{}{}</td></tr>",
+                line_num,
+                escape_xml(kept),
+                visible(trailing),
+                escape_xml(cleaned_kept),
+                visible(cleaned_trailing)
+            );
+        }
+        page.push_str("</table>\n");
+        if lines.len() > SNIPPET_LINES {
+            let _ = writeln!(page, "<p>…and {} more lines.</p>", lines.len() - SNIPPET_LINES);
+        }
+        page.push_str("</details>\n");
+    }
+}
+
+fn render_details(report: &RunReport, page: &mut String) {
+    page.push_str("<h2>Run</h2>\n");
+    if let Some(timings) = report.timings {
+        page.push_str(
+            "<table class=\"sortable\">\n<thead><tr><th>Phase</th><th>Time (ms)</th></tr></thead>\n<tbody>\n",
+        );
+        for phase in Phase::ALL {
+            let _ = writeln!(
+                page,
+                "<tr><td>{}</td><td class=\"number\">{:.1}</td></tr>",
+                phase,
+                milliseconds(timings.get(phase))
+            );
+        }
+        page.push_str("</tbody>\n</table>\n");
+    }
+    if let Ok(config) = serde_yaml::to_string(report.config) {
+        let _ = writeln!(
+            page,
+            "<details><summary>Configuration</summary>\n<pre>{}</pre>\n</details>",
+            escape_xml(&config)
+        );
+    }
+}
+
+/// Show whitespace that would otherwise be invisible: `·` for spaces, `→` for tabs and `␣`
+/// for anything else.
+fn visible(whitespace: &str) -> String {
+    if whitespace.is_empty() {
+        return String::new();
+    }
+    let shown: String = whitespace
+        .chars()
+        .map(|c| match c {
+            ' ' => '·',
+            '\t' => '→',
+            _ => '␣',
+        })
+        .collect();
+    format!("<span class=\"ws\">{}</span>", shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::ports::fs::MemFs;
+    use crate::processor::{ProcessingResult, SkipReason};
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn render_dir(dir: &Path, dry_run: bool) -> String {
+        let fs = MemFs::new().with_file(dir.join("a.txt"), b"one\nx < y \t\n");
+        let config = Config::default();
+        let directories = vec![dir.to_path_buf()];
+        let file_results = vec![
+            (dir.join("a.txt"), ProcessingResult::from_lines(vec![2], 2)),
+            (dir.join("<b>.txt"), ProcessingResult::clean()),
+            (dir.join("logo.png"), ProcessingResult::skip(SkipReason::Binary)),
+        ];
+        render(&RunReport {
+            dry_run,
+            directories: &directories,
+            config: &config,
+            file_results: &file_results,
+            relative_to: None,
+            fs: &fs,
+            elapsed: Duration::from_millis(5),
+            timings: None,
+        })
+    }
+
+    #[test]
+    fn test_html_report_lists_files() {
+        let html = render_dir(Path::new("/repo"), false);
+
+        assert!(html.contains("3 files: 1 cleaned, 1 clean, 1 skipped, 0 failed."));
+        assert!(html.contains("<table class=\"sortable\">"));
+        assert!(html.contains("&lt;b&gt;.txt</td><td>clean</td>"));
+        assert!(html.contains("<td>skipped</td><td class=\"number\">0</td><td>binary</td>"));
+        // The file has already been cleaned, so there is nothing to compare
+        assert!(!html.contains("Findings"));
+    }
+
+    #[test]
+    fn test_html_report_shows_dry_run_snippets() {
+        let html = render_dir(Path::new("/repo"), true);
+
+        assert!(html.contains("<h2>Findings</h2>"));
+        assert!(html.contains(
+            "<td class=\"number\">2</td><td>x &lt; y<mark><span class=\"ws\">·→</span></mark></td><td>x &lt; y</td>"
+        ));
+    }
+
+    #[test]
+    fn test_visible_whitespace() {
+        assert_eq!(visible(""), "");
+        assert_eq!(visible(" \t\u{a0}"), "<span class=\"ws\">·→␣</span>");
+    }
+}
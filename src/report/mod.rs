@@ -0,0 +1,437 @@
+mod html;
+
+use crate::config::Config;
+use crate::output::display_path;
+use crate::ports::fs::FileSystem;
+use crate::processor::{Outcome, ProcessingResult, RuleLines};
+use crate::timings::{Phase, Timings};
+use eyre::{Context, Result};
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File formats `--report` can write, chosen by the file's extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// `.json`: the whole run in this tool's own schema
+    Json,
+    /// `.sarif`: SARIF 2.1.0, for code scanning dashboards
+    Sarif,
+    /// `.html` or `.htm`: a self-contained page
+    Html,
+}
+
+impl ReportFormat {
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(Self::Json),
+            Some("sarif") => Ok(Self::Sarif),
+            Some("html" | "htm") => Ok(Self::Html),
+            _ => eyre::bail!(
+                "Unknown report format for {}: use a .json, .sarif or .html file",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Everything a report records about a run.
+pub struct RunReport<'a> {
+    pub dry_run: bool,
+    pub directories: &'a [PathBuf],
+    /// The effective file configuration
+    pub config: &'a Config,
+    /// Every file processed or skipped, directory by directory in `--sort` order
+    pub file_results: &'a [(PathBuf, ProcessingResult)],
+    /// The directory `file_results` paths are relative to, if not the current one
+    pub relative_to: Option<&'a Path>,
+    /// Where the run read its files, for the snippets of dry runs
+    pub fs: &'a dyn FileSystem,
+    /// Wall-clock time of the whole run
+    pub elapsed: Duration,
+    pub timings: Option<&'a Timings>,
+}
+
+/// Counts of each outcome across a report's files.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Counts {
+    modified: usize,
+    clean: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+impl RunReport<'_> {
+    fn counts(&self) -> Counts {
+        let mut counts = Counts::default();
+        for (_, result) in self.file_results {
+            match result.outcome {
+                Outcome::Modified { .. } => counts.modified += 1,
+                Outcome::Clean => counts.clean += 1,
+                Outcome::Skipped(_) => counts.skipped += 1,
+                Outcome::Failed(_) => counts.failed += 1,
+            }
+        }
+        counts
+    }
+}
+
+/// Write `report` to `path` in the format its extension names.
+pub fn write_report(path: &Path, report: &RunReport) -> Result<()> {
+    let content = match ReportFormat::from_path(path)? {
+        ReportFormat::Json => serde_json::to_vec_pretty(&json_report(report))?,
+        ReportFormat::Sarif => serde_json::to_vec_pretty(&sarif_report(report))?,
+        ReportFormat::Html => html::render(report).into_bytes(),
+    };
+    std::fs::write(path, content).with_context(|| format!("Failed to write report: {}", path.display()))
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+pub(crate) fn outcome_name(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Modified { .. } => "modified",
+        Outcome::Clean => "clean",
+        Outcome::Skipped(_) => "skipped",
+        Outcome::Failed(_) => "failed",
+    }
+}
+
+/// One file's outcome as JSON, shared by JSON reports and the daemon's replies.
+pub(crate) fn file_json(path: &Path, result: &ProcessingResult) -> Value {
+    let mut file = json!({
+        "path": path,
+        "outcome": outcome_name(&result.outcome),
+    });
+    match &result.outcome {
+        Outcome::Modified { lines, rules, .. } => {
+            file["lines"] = json!(lines);
+            file["rules"] = json!(rules);
+        }
+        Outcome::Skipped(reason) => file["reason"] = json!(reason.to_string()),
+        Outcome::Failed(error) => file["error"] = json!(format!("{:#}", error)),
+        Outcome::Clean => {}
+    }
+    if !result.notices.is_empty() {
+        file["notices"] = json!(result.notices);
+    }
+    file
+}
+
+fn json_report(report: &RunReport) -> Value {
+    let counts = report.counts();
+    let files: Vec<Value> = report
+        .file_results
+        .iter()
+        .map(|(path, result)| file_json(path, result))
+        .collect();
+
+    let mut timing = json!({ "total_ms": milliseconds(report.elapsed) });
+    if let Some(timings) = report.timings {
+        for phase in Phase::ALL {
+            timing[format!("{}_ms", phase)] = json!(milliseconds(timings.get(phase)));
+        }
+    }
+
+    json!({
+        "version": env!("GIT_DESCRIBE"),
+        "dry_run": report.dry_run,
+        "directories": report.directories,
+        "summary": {
+            "files": report.file_results.len(),
+            "modified": counts.modified,
+            "clean": counts.clean,
+            "skipped": counts.skipped,
+            "failed": counts.failed,
+        },
+        "timing": timing,
+        "config": report.config,
+        "files": files,
+    })
+}
+
+/// The rule results are reported under when a file's lines aren't attributed to any.
+const SARIF_RULE: &str = "trailing-whitespace";
+
+/// What a finding of `rule` says, and what the rule asks for.
+fn rule_text(rule: &str) -> (String, String) {
+    let (message, description) = match rule {
+        "trailing-whitespace" => ("Trailing whitespace", "Lines must not end in whitespace"),
+        "indent-style" => ("Mixed indentation", "Indentation must not mix tabs and spaces"),
+        "max-consecutive-blank-lines" => (
+            "Too many consecutive blank lines",
+            "Runs of blank lines must not be too long",
+        ),
+        "trim-trailing-blank-lines" => ("Blank line at end of file", "Files must not end in blank lines"),
+        "whitespace-only-files" => (
+            "File holds nothing but whitespace",
+            "Files must not hold nothing but whitespace",
+        ),
+        "max-line-length" => ("Line too long", "Lines should not be wider than `max-line-length`"),
+        "preserve-string-literals" => (
+            "Trailing whitespace kept inside a quoted field",
+            "Trailing whitespace inside quoted fields is data, so it is reported rather than removed",
+        ),
+        "end-of-file" => (
+            "File doesn't end as its type requires",
+            "Files must end as `end-of-file` requires",
+        ),
+        custom => {
+            return (
+                format!("Flagged by custom rule `{}`", custom),
+                format!("Custom rule `{}`", custom),
+            );
+        }
+    };
+    (message.to_string(), description.to_string())
+}
+
+fn sarif_report(report: &RunReport) -> Value {
+    let location = |path: &Path, line: usize| {
+        json!({
+            "physicalLocation": {
+                "artifactLocation": { "uri": display_path(path).replace('\\', "/") },
+                "region": { "startLine": line },
+            }
+        })
+    };
+
+    let mut results = Vec::new();
+    let mut notifications = Vec::new();
+    let mut rule_ids = vec![SARIF_RULE.to_string()];
+    for (path, result) in report.file_results {
+        match &result.outcome {
+            Outcome::Modified { lines, rules, .. } => {
+                let unattributed = [RuleLines {
+                    rule: SARIF_RULE.to_string(),
+                    lines: lines.clone(),
+                }];
+                let rules = if rules.is_empty() { &unattributed[..] } else { rules };
+                for rule in rules {
+                    if !rule_ids.contains(&rule.rule) {
+                        rule_ids.push(rule.rule.clone());
+                    }
+                    let (message, _) = rule_text(&rule.rule);
+                    results.extend(rule.lines.iter().map(|&line| {
+                        json!({
+                            "ruleId": rule.rule,
+                            "level": "warning",
+                            "message": { "text": message },
+                            "locations": [location(path, line)],
+                        })
+                    }));
+                }
+            }
+            Outcome::Failed(error) => notifications.push(json!({
+                "level": "error",
+                "message": { "text": format!("{:#}", error) },
+                "locations": [location(path, 1)],
+            })),
+            Outcome::Clean | Outcome::Skipped(_) => {}
+        }
+        for notice in &result.notices {
+            if !rule_ids.contains(&notice.rule) {
+                rule_ids.push(notice.rule.clone());
+            }
+            let (message, _) = rule_text(&notice.rule);
+            results.extend(notice.lines.iter().map(|&line| {
+                json!({
+                    "ruleId": notice.rule,
+                    "level": "note",
+                    "message": { "text": message },
+                    "locations": [location(path, line)],
+                })
+            }));
+        }
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "whitespace",
+                    "version": env!("GIT_DESCRIBE"),
+                    "rules": rule_ids.iter().map(|rule| json!({
+                        "id": rule,
+                        "shortDescription": { "text": rule_text(rule).1 },
+                    })).collect::<Vec<_>>(),
+                }
+            },
+            "invocations": [{
+                "executionSuccessful": notifications.is_empty(),
+                "toolExecutionNotifications": notifications,
+                "properties": {
+                    "dryRun": report.dry_run,
+                    "durationMs": milliseconds(report.elapsed),
+                },
+            }],
+            "results": results,
+            "properties": { "config": report.config },
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+    use crate::processor::SkipReason;
+
+    fn file_results() -> Vec<(PathBuf, ProcessingResult)> {
+        vec![
+            (PathBuf::from("./src/a.rs"), ProcessingResult::from_lines(vec![2, 5], 4)),
+            (PathBuf::from("./src/b.rs"), ProcessingResult::clean()),
+            (PathBuf::from("./logo.png"), ProcessingResult::skip(SkipReason::Binary)),
+            (
+                PathBuf::from("./locked.txt"),
+                ProcessingResult::failed(eyre::eyre!("Permission denied")),
+            ),
+        ]
+    }
+
+    fn run_report<'a>(
+        config: &'a Config,
+        directories: &'a [PathBuf],
+        file_results: &'a [(PathBuf, ProcessingResult)],
+        fs: &'a MemFs,
+    ) -> RunReport<'a> {
+        RunReport {
+            dry_run: true,
+            directories,
+            config,
+            file_results,
+            relative_to: None,
+            fs,
+            elapsed: Duration::from_millis(12),
+            timings: None,
+        }
+    }
+
+    #[test]
+    fn test_report_format_from_extension() {
+        assert_eq!(
+            ReportFormat::from_path(Path::new("out.json")).unwrap(),
+            ReportFormat::Json
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("ci/out.SARIF")).unwrap(),
+            ReportFormat::Sarif
+        );
+        assert_eq!(
+            ReportFormat::from_path(Path::new("out.htm")).unwrap(),
+            ReportFormat::Html
+        );
+        assert!(ReportFormat::from_path(Path::new("out.txt")).is_err());
+        assert!(ReportFormat::from_path(Path::new("report")).is_err());
+    }
+
+    #[test]
+    fn test_json_report() {
+        let (config, directories, file_results) = (Config::default(), vec![PathBuf::from(".")], file_results());
+        let json = json_report(&run_report(&config, &directories, &file_results, &MemFs::new()));
+
+        assert_eq!(json["summary"]["files"], 4);
+        assert_eq!(json["summary"]["modified"], 1);
+        assert_eq!(json["summary"]["failed"], 1);
+        assert_eq!(json["files"][0]["lines"], json!([2, 5]));
+        assert_eq!(json["files"][2]["reason"], "binary");
+        assert_eq!(json["files"][3]["error"], "Permission denied");
+        assert_eq!(json["timing"]["total_ms"], 12.0);
+        assert_eq!(json["config"]["follow-symlinks"], false);
+    }
+
+    #[test]
+    fn test_sarif_report() {
+        let (config, directories, file_results) = (Config::default(), vec![PathBuf::from(".")], file_results());
+        let sarif = sarif_report(&run_report(&config, &directories, &file_results, &MemFs::new()));
+
+        let run = &sarif["runs"][0];
+        assert_eq!(run["results"].as_array().unwrap().len(), 2);
+        let location = &run["results"][1]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/a.rs");
+        assert_eq!(location["region"]["startLine"], 5);
+        assert_eq!(run["invocations"][0]["executionSuccessful"], false);
+    }
+
+    #[test]
+    fn test_rules_in_reports() {
+        let rule = |rule: &str, lines: &[usize]| RuleLines {
+            rule: rule.to_string(),
+            lines: lines.to_vec(),
+        };
+        let file_results = [(
+            PathBuf::from("./src/a.rs"),
+            ProcessingResult::from_lines(vec![2, 5], 4)
+                .with_rules(vec![rule("trailing-whitespace", &[2, 5]), rule("indent-style", &[5])]),
+        )];
+        let (config, directories, fs) = (Config::default(), vec![PathBuf::from(".")], MemFs::new());
+        let report = run_report(&config, &directories, &file_results, &fs);
+
+        let json = json_report(&report);
+        assert_eq!(
+            json["files"][0]["rules"],
+            json!([
+                { "rule": "trailing-whitespace", "lines": [2, 5] },
+                { "rule": "indent-style", "lines": [5] },
+            ])
+        );
+
+        let sarif = sarif_report(&report);
+        let run = &sarif["runs"][0];
+        let results: Vec<(&str, u64)> = run["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| {
+                let line = &result["locations"][0]["physicalLocation"]["region"]["startLine"];
+                (result["ruleId"].as_str().unwrap(), line.as_u64().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            results,
+            [
+                ("trailing-whitespace", 2),
+                ("trailing-whitespace", 5),
+                ("indent-style", 5)
+            ]
+        );
+        assert_eq!(run["results"][2]["message"]["text"], "Mixed indentation");
+        assert_eq!(run["tool"]["driver"]["rules"][1]["id"], "indent-style");
+    }
+
+    #[test]
+    fn test_notices_in_reports() {
+        let notices = vec![RuleLines {
+            rule: "max-line-length".to_string(),
+            lines: vec![3, 9],
+        }];
+        let file_results = [(
+            PathBuf::from("./src/a.rs"),
+            ProcessingResult::clean().with_notices(notices),
+        )];
+        let (config, directories, fs) = (Config::default(), vec![PathBuf::from(".")], MemFs::new());
+        let report = run_report(&config, &directories, &file_results, &fs);
+
+        let json = json_report(&report);
+        assert_eq!(json["summary"]["clean"], 1);
+        assert_eq!(json["files"][0]["outcome"], "clean");
+        assert_eq!(
+            json["files"][0]["notices"],
+            json!([{ "rule": "max-line-length", "lines": [3, 9] }])
+        );
+
+        let sarif = sarif_report(&report);
+        let results = sarif["runs"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0]["ruleId"], "max-line-length");
+        assert_eq!(results[0]["level"], "note");
+        assert_eq!(results[0]["message"]["text"], "Line too long");
+    }
+}
@@ -0,0 +1,308 @@
+use eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// The `--resume` id that picks the most recently started run.
+pub const LATEST: &str = "latest";
+
+/// Journals of runs never resumed are removed once there are more than this many...
+const KEEP_RUNS: usize = 20;
+/// ...or once they haven't been written to for this long.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The state of a run that rewrites files, kept in a journal so that a run over a huge tree
+/// which is cancelled, crashes or stops at a limit can be resumed with `--resume <run-id>`
+/// instead of starting again.
+///
+/// The journal is a file of JSON lines, appended to as the run goes: the targets it was started
+/// with, each target's file list once it is walked, and each file once it is done. Appending
+/// means a crash loses at most the line being written. A run that gets through every target
+/// removes its journal.
+#[derive(Debug)]
+pub struct RunJournal {
+    id: String,
+    path: PathBuf,
+    targets: Vec<PathBuf>,
+    /// Each walked target's file list, as a resumed run left it
+    pending: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Files a resumed run already finished
+    done: HashSet<PathBuf>,
+    file: Mutex<File>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Entry {
+    Started { cwd: PathBuf, targets: Vec<PathBuf> },
+    Pending { target: PathBuf, files: Vec<PathBuf> },
+    Done { path: PathBuf },
+}
+
+impl RunJournal {
+    /// Start a journal for a run over `targets` in the default journal directory.
+    pub fn start(targets: &[PathBuf]) -> Result<Self> {
+        Self::start_in(&default_journal_dir()?, targets)
+    }
+
+    /// Start a journal for a run over `targets` in `dir`, first removing the journals of old
+    /// runs: those older than [`MAX_AGE`] and all but the latest [`KEEP_RUNS`].
+    pub fn start_in(dir: &Path, targets: &[PathBuf]) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        prune(dir, SystemTime::now());
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let id = format!("{}-{}", secs, std::process::id());
+        let path = dir.join(format!("{}.jsonl", id));
+        let file = File::options()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create run state {}", path.display()))?;
+        let journal = Self {
+            id,
+            path,
+            targets: targets.to_vec(),
+            pending: HashMap::new(),
+            done: HashSet::new(),
+            file: Mutex::new(file),
+        };
+        let cwd = std::env::current_dir().context("Failed to read the current directory")?;
+        journal.append(&Entry::Started {
+            cwd,
+            targets: targets.to_vec(),
+        })?;
+        debug!("Recording run {} in {}", journal.id, journal.path.display());
+        Ok(journal)
+    }
+
+    /// Reopen the journal of run `id`, or of the latest run for [`LATEST`], in the default
+    /// journal directory.
+    pub fn resume(id: &str) -> Result<Self> {
+        Self::resume_in(&default_journal_dir()?, id)
+    }
+
+    /// Reopen the journal of run `id`, or of the latest run for [`LATEST`], in `dir`. The run
+    /// must be resumed from the directory it was started in, as its paths are relative to it.
+    pub fn resume_in(dir: &Path, id: &str) -> Result<Self> {
+        let id = if id == LATEST { latest_run(dir)? } else { id.to_string() };
+        let path = dir.join(format!("{}.jsonl", id));
+        let journal = fs::read_to_string(&path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => eyre::eyre!("No run {} to resume: it finished, or never started", id),
+            _ => eyre::Report::new(e).wrap_err(format!("Failed to read run state {}", path.display())),
+        })?;
+
+        let mut started = None;
+        let mut pending = HashMap::new();
+        let mut done = HashSet::new();
+        for line in journal.lines() {
+            // A crash can leave the last line half written; everything before it still holds
+            let Ok(entry) = serde_json::from_str::<Entry>(line) else {
+                continue;
+            };
+            match entry {
+                Entry::Started { cwd, targets } => started = Some((cwd, targets)),
+                Entry::Pending { target, files } => {
+                    pending.insert(target, files);
+                }
+                Entry::Done { path } => {
+                    done.insert(path);
+                }
+            }
+        }
+        let (cwd, targets) = started.ok_or_else(|| eyre::eyre!("Run state {} is empty", path.display()))?;
+        if std::env::current_dir().ok().as_deref() != Some(cwd.as_path()) {
+            eyre::bail!("Run {} was started in {}; resume it from there", id, cwd.display());
+        }
+
+        let mut file = File::options()
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open run state {}", path.display()))?;
+        if !journal.is_empty() && !journal.ends_with('\n') {
+            // Start clear of the half-written line, so the next entry isn't lost with it
+            file.write_all(b"\n")
+                .with_context(|| format!("Failed to write run state {}", path.display()))?;
+        }
+        info!("Resuming run {}: {} files already done", id, done.len());
+        Ok(Self {
+            id,
+            path,
+            targets,
+            pending,
+            done,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// The id `--resume` takes to resume this run.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The targets the run was started with.
+    pub fn targets(&self) -> &[PathBuf] {
+        &self.targets
+    }
+
+    /// The files an earlier attempt at the run found in `target`, if it walked it.
+    pub fn walked(&self, target: &Path) -> Option<Vec<PathBuf>> {
+        self.pending.get(target).cloned()
+    }
+
+    /// `files` without those already done.
+    pub fn not_done(&self, mut files: Vec<PathBuf>) -> Vec<PathBuf> {
+        files.retain(|path| !self.done.contains(path));
+        files
+    }
+
+    /// Record the files walked in `target`, so a resumed run need not walk it again.
+    pub fn record_pending(&self, target: &Path, files: &[PathBuf]) -> Result<()> {
+        self.append(&Entry::Pending {
+            target: target.to_path_buf(),
+            files: files.to_vec(),
+        })
+    }
+
+    /// Record that `path` is done, so a resumed run leaves it alone.
+    pub fn record_done(&self, path: &Path) -> Result<()> {
+        self.append(&Entry::Done {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Remove the journal of a run that got through every target: there is nothing to resume.
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path).with_context(|| format!("Failed to remove run state {}", self.path.display()))
+    }
+
+    fn append(&self, entry: &Entry) -> Result<()> {
+        // One write per line, so lines from parallel workers never interleave
+        let mut line = serde_json::to_vec(entry).context("Failed to serialize run state")?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)
+            .with_context(|| format!("Failed to write run state {}", self.path.display()))
+    }
+}
+
+/// Where run journals live: `~/.local/share/whitespace/runs`, beside the cache.
+pub fn default_journal_dir() -> Result<PathBuf> {
+    Ok(dirs::data_local_dir()
+        .ok_or_else(|| eyre::eyre!("Could not determine local data directory"))?
+        .join("whitespace")
+        .join("runs"))
+}
+
+/// Remove the journals in `dir` that are older than [`MAX_AGE`] at `now`, and all but the
+/// latest of the rest, leaving room for one more within [`KEEP_RUNS`]. Journals that can't be
+/// removed are only warned about: they cost disk space, not correctness.
+fn prune(dir: &Path, now: SystemTime) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut journals: Vec<(SystemTime, PathBuf)> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "jsonl"))
+        .filter_map(|path| Some((fs::metadata(&path).and_then(|metadata| metadata.modified()).ok()?, path)))
+        .collect();
+    // Newest first
+    journals.sort_by(|a, b| b.cmp(a));
+    for (index, (modified, path)) in journals.into_iter().enumerate() {
+        let expired = now.duration_since(modified).is_ok_and(|age| age > MAX_AGE);
+        if index + 1 < KEEP_RUNS && !expired {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => debug!("Removed old run state {}", path.display()),
+            Err(e) => warn!("Failed to remove old run state {}: {}", path.display(), e),
+        }
+    }
+}
+
+/// The id of the most recently started run with a journal in `dir`.
+fn latest_run(dir: &Path) -> Result<String> {
+    let entries = fs::read_dir(dir).map_err(|_| eyre::eyre!("No run to resume"))?;
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let id = path.file_name()?.to_str()?.strip_suffix(".jsonl")?.to_string();
+            let modified = fs::metadata(&path).and_then(|metadata| metadata.created().or(metadata.modified()));
+            Some((modified.ok()?, id))
+        })
+        .max()
+        .map(|(_, id)| id)
+        .ok_or_else(|| eyre::eyre!("No run to resume"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resume_skips_done_files() {
+        let dir = TempDir::new().unwrap();
+        let targets = vec![PathBuf::from("src"), PathBuf::from("docs")];
+        let files = vec![
+            PathBuf::from("src/a.rs"),
+            PathBuf::from("src/b.rs"),
+            PathBuf::from("src/c.rs"),
+        ];
+
+        let journal = RunJournal::start_in(dir.path(), &targets).unwrap();
+        journal.record_pending(Path::new("src"), &files).unwrap();
+        journal.record_done(Path::new("src/b.rs")).unwrap();
+        let id = journal.id().to_string();
+        drop(journal);
+        // As a crash mid-write would leave it
+        let path = dir.path().join(format!("{}.jsonl", id));
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"{\"done\":{\"pa").unwrap();
+
+        let journal = RunJournal::resume_in(dir.path(), LATEST).unwrap();
+        assert_eq!(journal.id(), id);
+        assert_eq!(journal.targets(), targets);
+        assert_eq!(
+            journal.not_done(journal.walked(Path::new("src")).unwrap()),
+            vec![PathBuf::from("src/a.rs"), PathBuf::from("src/c.rs")]
+        );
+        assert_eq!(journal.walked(Path::new("docs")), None);
+
+        journal.finish().unwrap();
+        assert!(!path.exists());
+        assert!(RunJournal::resume_in(dir.path(), &id).is_err());
+        assert!(RunJournal::resume_in(dir.path(), LATEST).is_err());
+    }
+
+    #[test]
+    fn test_old_journals_are_pruned() {
+        let dir = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        let journal = |name: &str, age: Duration| {
+            let path = dir.path().join(name);
+            File::create(&path).unwrap().set_modified(now - age).unwrap();
+            path
+        };
+        let recent: Vec<PathBuf> = (0..KEEP_RUNS)
+            .map(|i| journal(&format!("{}.jsonl", i), Duration::from_secs(i as u64 * 60)))
+            .collect();
+        let expired = journal("expired.jsonl", MAX_AGE + Duration::from_secs(60));
+        let other = journal("notes.txt", MAX_AGE * 2);
+
+        prune(dir.path(), now);
+
+        // Room is left for the run starting now
+        assert!(recent[..KEEP_RUNS - 1].iter().all(|path| path.exists()));
+        assert!(!recent[KEEP_RUNS - 1].exists());
+        assert!(!expired.exists());
+        assert!(other.exists());
+    }
+}
@@ -0,0 +1,533 @@
+use crate::config::{IndentStyle, RuleOverride, Rules};
+use crate::filetypes::FileTypes;
+use crate::literals::Syntax;
+use crate::walker::compile_globs;
+use globset::GlobSet;
+use std::borrow::Cow;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// [`Rules`] with their per-file overrides compiled, so each file's rules resolve cheaply.
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    base: Rules,
+    overrides: Vec<(GlobSet, RuleOverride)>,
+    /// The `files` of each of the base rules' replacements, None for those without
+    replacement_files: Vec<Option<GlobSet>>,
+    file_types: FileTypes,
+}
+
+impl RuleSet {
+    pub fn new(rules: &Rules) -> Self {
+        let overrides = rules
+            .overrides
+            .iter()
+            .map(|rule_override| (compile_globs(&rule_override.files), rule_override.clone()))
+            .collect();
+        let replacement_files = rules
+            .replacements
+            .iter()
+            .map(|replacement| (!replacement.files.is_empty()).then(|| compile_globs(&replacement.files)))
+            .collect();
+        Self {
+            base: rules.clone(),
+            overrides,
+            replacement_files,
+            file_types: FileTypes::default(),
+        }
+    }
+
+    /// Recognize file types with `file_types` instead of the built-in mappings alone.
+    pub fn with_file_types(mut self, file_types: FileTypes) -> Self {
+        self.file_types = file_types;
+        self
+    }
+
+    /// Rules that apply when no override matches.
+    pub fn base(&self) -> &Rules {
+        &self.base
+    }
+
+    /// How file types are recognized.
+    pub fn file_types(&self) -> &FileTypes {
+        &self.file_types
+    }
+
+    /// The type of the file at `path`, from its name or else the shebang on its `first_line`.
+    pub fn file_type<'a>(&'a self, path: &Path, first_line: Option<&'a str>) -> Option<&'a str> {
+        self.file_types.detect(path, first_line).map(|(file_type, _)| file_type)
+    }
+
+    /// The overrides whose `files` match the file name, or whose `types` include `file_type`,
+    /// in the order they apply.
+    pub fn overrides_for<'a>(
+        &'a self,
+        path: &'a Path,
+        file_type: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a RuleOverride> {
+        let name = path.file_name();
+        self.overrides
+            .iter()
+            .filter(move |(globs, rule_override)| {
+                name.is_some_and(|name| globs.is_match(name))
+                    || file_type.is_some_and(|file_type| rule_override.types.iter().any(|t| t == file_type))
+            })
+            .map(|(_, rule_override)| rule_override)
+    }
+
+    /// The base rules with every override for the file applied, in order, its type recognized
+    /// by name alone.
+    pub fn for_file(&self, path: &Path) -> Cow<'_, Rules> {
+        self.for_type(path, self.file_type(path, None))
+    }
+
+    /// Like [`RuleSet::for_file`], but a file whose name says nothing of its type is recognized
+    /// by the shebang on its `first_line`, if that is one.
+    pub fn for_script(&self, path: &Path, first_line: &str) -> Cow<'_, Rules> {
+        self.for_type(path, self.file_type(path, Some(first_line)))
+    }
+
+    fn for_type(&self, path: &Path, file_type: Option<&str>) -> Cow<'_, Rules> {
+        let mut rules = Cow::Borrowed(&self.base);
+        for rule_override in self.overrides_for(path, file_type) {
+            rule_override.apply(rules.to_mut());
+        }
+        if rules.preserve_string_literals {
+            rules.to_mut().literal_syntax = file_type.and_then(Syntax::for_type);
+        }
+        let name = path.file_name();
+        let applies: Vec<bool> = self
+            .replacement_files
+            .iter()
+            .map(|globs| {
+                globs
+                    .as_ref()
+                    .is_none_or(|globs| name.is_some_and(|name| globs.is_match(name)))
+            })
+            .collect();
+        if applies.contains(&false) {
+            let mut applies = applies.into_iter();
+            rules.to_mut().replacements.retain(|_| applies.next().unwrap_or(false));
+        }
+        rules
+    }
+}
+
+/// How a line is terminated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    /// The last line of a file without a final newline
+    None,
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::None => "",
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+        }
+    }
+}
+
+/// 1-based, inclusive line ranges, such as `--lines 100-200,250` names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineRanges(Vec<RangeInclusive<usize>>);
+
+impl LineRanges {
+    pub fn new(ranges: impl IntoIterator<Item = RangeInclusive<usize>>) -> Self {
+        Self(ranges.into_iter().collect())
+    }
+
+    /// True if no line is in range.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn contains(&self, line: usize) -> bool {
+        self.0.iter().any(|range| range.contains(&line))
+    }
+}
+
+/// Parse line ranges such as `100-200`, `7` or `1-10,40-45`. Lines are 1-based and ranges
+/// include both ends.
+pub fn parse_line_ranges(input: &str) -> Result<LineRanges, String> {
+    let line = |number: &str| match number.trim().parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!(
+            "invalid line range '{}', expected line numbers from 1, such as 100-200",
+            input
+        )),
+        Ok(line) => Ok(line),
+    };
+    let ranges = input
+        .split(',')
+        .map(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let (start, end) = (line(start)?, line(end)?);
+            if start > end {
+                return Err(format!("invalid line range '{}': {} comes after {}", input, start, end));
+            }
+            Ok(start..=end)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(LineRanges(ranges))
+}
+
+/// One line of output from a [`Pipeline`](crate::transform::Pipeline).
+#[derive(Debug, PartialEq, Eq)]
+pub struct CleanedLine<'a> {
+    /// 1-based number of the line in the original file
+    pub line_num: usize,
+    /// The cleaned line, or None if it is removed
+    pub text: Option<Cow<'a, str>>,
+    /// The line ending to write after the line
+    pub ending: LineEnding,
+    /// Whether the line was changed or removed
+    pub changed: bool,
+    /// Bytes removed from the line (including its line ending, if the line was removed); 0 if
+    /// a replacement lengthened it
+    pub bytes_saved: usize,
+    /// Names of the rules that changed or removed the line, in the order they applied
+    pub changed_by: Vec<&'static str>,
+    /// Names of the rules that reported the line without changing it, such as `max-line-length`
+    pub flagged_by: Vec<&'static str>,
+}
+
+/// Strip trailing whitespace, as `rules` defines it, from a single line (without its line ending).
+pub(crate) fn trim_line<'a>(line: &'a str, rules: &Rules) -> &'a str {
+    line.trim_end_matches(|c: char| rules.trailing_whitespace.matches(c) || rules.extra_trailing_chars.contains(&c))
+}
+
+/// Apply the rules that only look at one line: trailing whitespace removal and, when
+/// configured, normalization of indentation that mixes tabs and spaces.
+pub fn clean_line<'a>(line: &'a str, rules: &Rules) -> Cow<'a, str> {
+    let mut trimmed = trim_line(line, rules);
+    if rules.preserve_hard_breaks && !trimmed.is_empty() && is_hard_break(&line[trimmed.len()..]) {
+        trimmed = &line[..trimmed.len() + 2];
+    }
+    match rules
+        .indent_style
+        .and_then(|style| normalize_indent(trimmed, style, rules.indent_width))
+    {
+        Some(normalized) => Cow::Owned(normalized),
+        None => Cow::Borrowed(trimmed),
+    }
+}
+
+/// Columns `line` takes up, with tab stops every `tab_width` columns. Each character counts as
+/// one column, however wide a terminal draws it.
+pub(crate) fn line_width(line: &str, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    line.chars().fold(0, |column, c| {
+        if c == '\t' { column + tab_width - column % tab_width } else { column + 1 }
+    })
+}
+
+/// Two or more trailing spaces (and nothing else), which Markdown renders as a line break.
+/// Runs longer than two are shortened to two.
+pub(crate) fn is_hard_break(trailing: &str) -> bool {
+    trailing.len() >= 2 && trailing.bytes().all(|b| b == b' ')
+}
+
+/// Rewrite leading indentation that mixes tabs and spaces in `style`, with tab stops every
+/// `width` columns. Returns None when the indentation isn't mixed or is already normalized.
+pub(crate) fn normalize_indent(line: &str, style: IndentStyle, width: usize) -> Option<String> {
+    let body = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - body.len()];
+    if !(indent.contains(' ') && indent.contains('\t')) {
+        return None;
+    }
+
+    let width = width.max(1);
+    let columns = indent.chars().fold(
+        0,
+        |column, c| if c == '\t' { column + width - column % width } else { column + 1 },
+    );
+    let normalized = match style {
+        IndentStyle::Spaces => " ".repeat(columns),
+        // Columns short of a full tab stop stay as alignment spaces
+        IndentStyle::Tabs => format!("{}{}", "\t".repeat(columns / width), " ".repeat(columns % width)),
+    };
+
+    (normalized != indent).then(|| format!("{}{}", normalized, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TrailingWhitespace;
+    use crate::transform::Pipeline;
+
+    /// The text of each line in order, None for removed lines. Every line ends in a newline.
+    fn clean_all(rules: &Rules, lines: &[&str]) -> Vec<Option<String>> {
+        let mut pipeline = Pipeline::new(rules);
+        let mut cleaned = Vec::new();
+        let mut emit = |line: CleanedLine<'_>| {
+            cleaned.push(line.text.map(Cow::into_owned));
+            Ok::<_, std::convert::Infallible>(())
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let Ok(()) = pipeline.push(i + 1, line, LineEnding::Lf, &mut emit);
+        }
+        let Ok(()) = pipeline.finish(&mut emit);
+        cleaned
+    }
+
+    #[test]
+    fn test_parse_line_ranges() {
+        let ranges = parse_line_ranges("100-200, 7").unwrap();
+        assert_eq!(ranges, LineRanges::new([100..=200, 7..=7]));
+        assert!(ranges.contains(7) && ranges.contains(100) && ranges.contains(200));
+        assert!(!ranges.contains(8) && !ranges.contains(201));
+        assert!(parse_line_ranges("0-5").is_err());
+        assert!(parse_line_ranges("20-10").is_err());
+        assert!(parse_line_ranges("10-").is_err());
+        assert!(parse_line_ranges("").is_err());
+    }
+
+    #[test]
+    fn test_squeeze_blank_lines() {
+        let rules = Rules {
+            max_consecutive_blank_lines: Some(1),
+            ..Rules::default()
+        };
+        let cleaned = clean_all(&rules, &["a", "", "  ", "\t", "b", "", "c"]);
+        assert_eq!(
+            cleaned,
+            vec![
+                Some("a".to_string()),
+                Some(String::new()),
+                None,
+                None,
+                Some("b".to_string()),
+                Some(String::new()),
+                Some("c".to_string()),
+            ]
+        );
+
+        // Off by default
+        assert!(clean_all(&Rules::default(), &["", "", ""]).iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn test_trailing_character_class() {
+        let line = "text\u{A0}\u{3000} \u{200B}";
+        assert_eq!(trim_line(line, &Rules::default()), "text\u{A0}\u{3000} \u{200B}");
+
+        let unicode = Rules {
+            extra_trailing_chars: vec!['\u{200B}'],
+            ..Rules::default()
+        };
+        assert_eq!(trim_line(line, &unicode), "text");
+
+        let ascii = Rules {
+            trailing_whitespace: TrailingWhitespace::Ascii,
+            ..unicode
+        };
+        assert_eq!(trim_line(line, &ascii), "text\u{A0}\u{3000}");
+    }
+
+    #[test]
+    fn test_preserve_hard_breaks() {
+        let rules = Rules {
+            preserve_hard_breaks: true,
+            ..Rules::default()
+        };
+        assert_eq!(clean_line("line break  ", &rules), "line break  ");
+        assert_eq!(clean_line("long break    ", &rules), "long break  ");
+        assert_eq!(clean_line("one space ", &rules), "one space");
+        assert_eq!(clean_line("mixed \t ", &rules), "mixed");
+        assert_eq!(clean_line("    ", &rules), "");
+        assert_eq!(clean_line("line break  ", &Rules::default()), "line break");
+
+        // On for Markdown files by default
+        let rule_set = RuleSet::new(&Rules::default());
+        assert!(rule_set.for_file(Path::new("docs/README.md")).preserve_hard_breaks);
+        assert!(rule_set.for_file(Path::new("notes.markdown")).preserve_hard_breaks);
+        assert!(!rule_set.for_file(Path::new("main.rs")).preserve_hard_breaks);
+    }
+
+    #[test]
+    fn test_preserve_string_literals() {
+        let rules = Rules {
+            preserve_string_literals: true,
+            max_consecutive_blank_lines: Some(0),
+            indent_style: Some(IndentStyle::Spaces),
+            ..Rules::default()
+        };
+        let rule_set = RuleSet::new(&rules);
+        let rust = rule_set.for_file(Path::new("src/lib.rs"));
+        assert_eq!(rust.literal_syntax, Some(Syntax::Rust));
+
+        let cleaned = clean_all(&rust, &["let s = \"a  ", "", " \tb  ", "\";  ", "x  ", ""]);
+        assert_eq!(
+            cleaned,
+            vec![
+                Some("let s = \"a  ".to_string()),
+                Some(String::new()),
+                Some(" \tb  ".to_string()),
+                Some("\";".to_string()),
+                Some("x".to_string()),
+                None,
+            ]
+        );
+
+        // Files in other languages, and every file when the rule is off, are cleaned as usual
+        assert_eq!(rule_set.for_file(Path::new("notes.txt")).literal_syntax, None);
+        let cleaned = clean_all(&Rules::default(), &["let s = \"a  ", "\";"]);
+        assert_eq!(cleaned[0], Some("let s = \"a".to_string()));
+    }
+
+    #[test]
+    fn test_yaml_block_scalars_by_default() {
+        let rules = Rules {
+            trim_trailing_blank_lines: true,
+            ..Rules::default()
+        };
+        let rule_set = RuleSet::new(&rules);
+        let yaml = rule_set.for_file(Path::new(".github/workflows/ci.yaml"));
+        assert_eq!(yaml.literal_syntax, Some(Syntax::Yaml));
+
+        // `|+` keeps the scalar's trailing blank lines, so they stay even at the end of the file
+        let cleaned = clean_all(&yaml, &["name: ci  ", "body: |+", "  line  ", "", "  "]);
+        assert_eq!(
+            cleaned,
+            vec![
+                Some("name: ci".to_string()),
+                Some("body: |+".to_string()),
+                Some("  line  ".to_string()),
+                Some(String::new()),
+                Some("  ".to_string()),
+            ]
+        );
+
+        // Without a block scalar, YAML files are cleaned like any other
+        let cleaned = clean_all(&yaml, &["name: ci  ", "", "  "]);
+        assert_eq!(cleaned, vec![Some("name: ci".to_string()), None, None]);
+    }
+
+    #[test]
+    fn test_trim_trailing_blank_lines() {
+        let rules = Rules {
+            trim_trailing_blank_lines: true,
+            ..Rules::default()
+        };
+        let cleaned = clean_all(&rules, &["a", "", "b ", "", " ", "\t"]);
+        assert_eq!(
+            cleaned,
+            vec![
+                Some("a".to_string()),
+                Some(String::new()),
+                Some("b".to_string()),
+                None,
+                None,
+                None
+            ]
+        );
+
+        // Squeezing and trimming combine, and held-back lines still come out in order
+        let rules = Rules {
+            max_consecutive_blank_lines: Some(1),
+            ..rules
+        };
+        let cleaned = clean_all(&rules, &["a", "", "", "", "b", "", ""]);
+        assert_eq!(
+            cleaned,
+            vec![
+                Some("a".to_string()),
+                Some(String::new()),
+                None,
+                None,
+                Some("b".to_string()),
+                None,
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overrides_by_file_name() {
+        let rules = Rules {
+            max_consecutive_blank_lines: Some(1),
+            overrides: vec![
+                RuleOverride {
+                    files: vec!["*.py".to_string()],
+                    max_consecutive_blank_lines: Some(2),
+                    ..RuleOverride::default()
+                },
+                RuleOverride {
+                    files: vec!["setup.py".to_string()],
+                    max_consecutive_blank_lines: Some(3),
+                    ..RuleOverride::default()
+                },
+            ],
+            ..Rules::default()
+        };
+        let rule_set = RuleSet::new(&rules);
+
+        assert_eq!(
+            rule_set.for_file(Path::new("src/app.py")).max_consecutive_blank_lines,
+            Some(2)
+        );
+        assert_eq!(
+            rule_set.for_file(Path::new("setup.py")).max_consecutive_blank_lines,
+            Some(3)
+        );
+        assert!(matches!(rule_set.for_file(Path::new("config.yml")), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_replacements_by_file_name() {
+        let yaml = r#"
+replacements:
+  - name: smart-quotes
+    pattern: "[“”]"
+    replace: '"'
+  - name: no-tabs
+    files: ["*.rs"]
+    pattern: "\t"
+    replace: "    "
+"#;
+        let rules: Rules = serde_yaml::from_str(yaml).unwrap();
+        let rule_set = RuleSet::new(&rules);
+        let names = |path: &str| -> Vec<&str> {
+            let rules = rule_set.for_file(Path::new(path));
+            rules.replacements.iter().map(|replacement| replacement.name).collect()
+        };
+
+        assert_eq!(names("src/main.rs"), ["smart-quotes", "no-tabs"]);
+        assert_eq!(names("README.md"), ["smart-quotes"]);
+        assert!(matches!(rule_set.for_file(Path::new("src/main.rs")), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_overrides_by_file_type() {
+        let rules = Rules {
+            preserve_string_literals: true,
+            overrides: vec![RuleOverride {
+                types: vec!["shell".to_string(), "make".to_string()],
+                max_consecutive_blank_lines: Some(1),
+                ..RuleOverride::default()
+            }],
+            ..Rules::default()
+        };
+        let rule_set = RuleSet::new(&rules);
+        let blank_lines = |rules: Cow<'_, Rules>| rules.max_consecutive_blank_lines;
+
+        assert_eq!(blank_lines(rule_set.for_file(Path::new("Makefile"))), Some(1));
+        assert_eq!(blank_lines(rule_set.for_file(Path::new("/home/me/.bashrc"))), Some(1));
+        assert_eq!(blank_lines(rule_set.for_file(Path::new("deploy"))), None);
+        assert_eq!(
+            blank_lines(rule_set.for_script(Path::new("deploy"), "#!/bin/bash")),
+            Some(1)
+        );
+        // The name decides before the shebang
+        assert_eq!(blank_lines(rule_set.for_script(Path::new("run.py"), "#!/bin/sh")), None);
+
+        // Scripts get the literal handling of their language
+        let script = rule_set.for_script(Path::new("bin/tool"), "#!/usr/bin/env python3");
+        assert_eq!(script.literal_syntax, Some(Syntax::Python));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::config::{Config, Rules};
+use crate::ports::fs::FileSystem;
+use crate::processor::WhitespaceProcessor;
+use crate::rules::trim_line;
+use colored::*;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Whitespace metrics for a single file's content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentStats {
+    /// Lines ending in trailing whitespace
+    pub offending_lines: usize,
+    /// Total trailing whitespace bytes
+    pub trailing_bytes: usize,
+    /// Lines terminated by `\n`
+    pub lf_lines: usize,
+    /// Lines terminated by `\r\n`
+    pub crlf_lines: usize,
+}
+
+/// Metrics for one scanned file.
+#[derive(Debug, Clone)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub stats: ContentStats,
+}
+
+/// Per-extension aggregate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtensionStats {
+    pub files: usize,
+    pub offending_files: usize,
+    pub offending_lines: usize,
+    pub trailing_bytes: usize,
+}
+
+/// How a file terminates its lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndingStyle {
+    Lf,
+    Crlf,
+    Mixed,
+    None,
+}
+
+/// Aggregated metrics for a whole scan.
+#[derive(Debug, Default)]
+pub struct StatsReport {
+    pub files_scanned: usize,
+    pub files_skipped: usize,
+    pub offending_files: usize,
+    pub offending_lines: usize,
+    pub trailing_bytes: usize,
+    pub lf_lines: usize,
+    pub crlf_lines: usize,
+    pub lf_files: usize,
+    pub crlf_files: usize,
+    pub mixed_files: usize,
+    pub by_extension: BTreeMap<String, ExtensionStats>,
+    /// Offending files, worst first
+    pub worst_offenders: Vec<FileStats>,
+}
+
+impl ContentStats {
+    pub fn line_ending_style(&self) -> LineEndingStyle {
+        match (self.lf_lines > 0, self.crlf_lines > 0) {
+            (true, true) => LineEndingStyle::Mixed,
+            (true, false) => LineEndingStyle::Lf,
+            (false, true) => LineEndingStyle::Crlf,
+            (false, false) => LineEndingStyle::None,
+        }
+    }
+}
+
+/// Measure trailing whitespace, as `rules` defines it, and line endings in `content`.
+pub fn analyze_content(content: &str, rules: &Rules) -> ContentStats {
+    let mut stats = ContentStats::default();
+
+    for raw_line in content.split_inclusive('\n') {
+        let line = if let Some(line) = raw_line.strip_suffix("\r\n") {
+            stats.crlf_lines += 1;
+            line
+        } else if let Some(line) = raw_line.strip_suffix('\n') {
+            stats.lf_lines += 1;
+            line
+        } else {
+            raw_line
+        };
+
+        let trailing = line.len() - trim_line(line, rules).len();
+        if trailing > 0 {
+            stats.offending_lines += 1;
+            stats.trailing_bytes += trailing;
+        }
+    }
+
+    stats
+}
+
+/// Scan `files` in parallel and aggregate their metrics without modifying anything.
+pub fn collect_stats<F: FileSystem>(files: &[PathBuf], config: Arc<Config>, fs: Arc<F>) -> StatsReport {
+    let processor = WhitespaceProcessor::new(Arc::clone(&config), Arc::clone(&fs));
+
+    let scanned: Vec<Option<FileStats>> = files
+        .par_iter()
+        .map(|path| {
+            let bytes = match fs.read(path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    warn!("Failed to read {}: {}", path.display(), e);
+                    return None;
+                }
+            };
+            if !processor.is_text_file(path) && processor.is_binary_content(&bytes) {
+                debug!("Skipping binary file: {}", path.display());
+                return None;
+            }
+            let Ok(content) = std::str::from_utf8(&bytes) else {
+                debug!("Skipping file with invalid UTF-8: {}", path.display());
+                return None;
+            };
+            Some(FileStats {
+                path: path.clone(),
+                stats: analyze_content(content, &config.rules),
+            })
+        })
+        .collect();
+
+    let mut report = StatsReport::default();
+    for file in scanned {
+        let Some(file) = file else {
+            report.files_skipped += 1;
+            continue;
+        };
+        report.add(file);
+    }
+
+    report.worst_offenders.sort_by(|a, b| {
+        b.stats
+            .offending_lines
+            .cmp(&a.stats.offending_lines)
+            .then(a.path.cmp(&b.path))
+    });
+    report
+}
+
+impl StatsReport {
+    fn add(&mut self, file: FileStats) {
+        let stats = &file.stats;
+        self.files_scanned += 1;
+        self.lf_lines += stats.lf_lines;
+        self.crlf_lines += stats.crlf_lines;
+        match stats.line_ending_style() {
+            LineEndingStyle::Lf => self.lf_files += 1,
+            LineEndingStyle::Crlf => self.crlf_files += 1,
+            LineEndingStyle::Mixed => self.mixed_files += 1,
+            LineEndingStyle::None => {}
+        }
+
+        let extension = self.by_extension.entry(extension_key(&file.path)).or_default();
+        extension.files += 1;
+
+        if stats.offending_lines > 0 {
+            self.offending_files += 1;
+            self.offending_lines += stats.offending_lines;
+            self.trailing_bytes += stats.trailing_bytes;
+            extension.offending_files += 1;
+            extension.offending_lines += stats.offending_lines;
+            extension.trailing_bytes += stats.trailing_bytes;
+            self.worst_offenders.push(file);
+        }
+    }
+}
+
+/// Extension used for grouping, e.g. `.rs`, or `(none)` for extension-less files.
+fn extension_key(path: &Path) -> String {
+    path.extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+/// Print a stats report to the console, listing at most `top` worst offenders.
+pub fn display_stats(report: &StatsReport, top: usize) {
+    println!("{}", "Whitespace Statistics".cyan().bold());
+    println!("  Files scanned:        {}", report.files_scanned);
+    println!("  Files skipped:        {}", report.files_skipped);
+    println!(
+        "  Offending files:      {}",
+        report.offending_files.to_string().yellow()
+    );
+    println!("  Offending lines:      {}", report.offending_lines);
+    println!("  Trailing bytes:       {}", report.trailing_bytes);
+
+    println!("\n{}", "Line endings".cyan().bold());
+    println!(
+        "  LF lines:   {:>10}    LF files:    {}",
+        report.lf_lines, report.lf_files
+    );
+    println!(
+        "  CRLF lines: {:>10}    CRLF files:  {}",
+        report.crlf_lines, report.crlf_files
+    );
+    println!("  {:>22}    Mixed files: {}", "", report.mixed_files);
+
+    let mut offending_extensions: Vec<_> = report
+        .by_extension
+        .iter()
+        .filter(|(_, stats)| stats.offending_files > 0)
+        .collect();
+    if !offending_extensions.is_empty() {
+        offending_extensions.sort_by(|a, b| b.1.offending_files.cmp(&a.1.offending_files).then(a.0.cmp(b.0)));
+        println!("\n{}", "Offending files by extension".cyan().bold());
+        for (extension, stats) in offending_extensions {
+            println!(
+                "  {:<12} {:>6} / {:<6} files  {:>8} lines  {:>10} bytes",
+                extension, stats.offending_files, stats.files, stats.offending_lines, stats.trailing_bytes
+            );
+        }
+    }
+
+    if top > 0 && !report.worst_offenders.is_empty() {
+        println!("\n{}", "Worst offenders".cyan().bold());
+        for file in report.worst_offenders.iter().take(top) {
+            println!(
+                "  {:>8} lines  {}",
+                file.stats.offending_lines,
+                file.path.display().to_string().blue()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ports::fs::MemFs;
+
+    #[test]
+    fn test_analyze_content_counts() {
+        let stats = analyze_content("a  \r\nb\nc\t\nd", &Rules::default());
+        assert_eq!(
+            stats,
+            ContentStats {
+                offending_lines: 2,
+                trailing_bytes: 3,
+                lf_lines: 2,
+                crlf_lines: 1,
+            }
+        );
+        assert_eq!(stats.line_ending_style(), LineEndingStyle::Mixed);
+    }
+
+    #[test]
+    fn test_analyze_content_crlf_is_not_trailing_whitespace() {
+        let stats = analyze_content("a\r\nb\r\n", &Rules::default());
+        assert_eq!(stats.offending_lines, 0);
+        assert_eq!(stats.line_ending_style(), LineEndingStyle::Crlf);
+    }
+
+    #[test]
+    fn test_collect_stats_aggregates() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("a.rs", b"x  \ny  \n")
+                .with_file("b.rs", b"clean\n")
+                .with_file("c.md", b"z \r\n")
+                .with_file("bin.dat", b"\0\0"),
+        );
+        let files: Vec<PathBuf> = ["a.rs", "b.rs", "c.md", "bin.dat"].iter().map(PathBuf::from).collect();
+
+        let report = collect_stats(&files, Arc::new(Config::default()), fs);
+
+        assert_eq!(report.files_scanned, 3);
+        assert_eq!(report.files_skipped, 1);
+        assert_eq!(report.offending_files, 2);
+        assert_eq!(report.offending_lines, 3);
+        assert_eq!(report.trailing_bytes, 5);
+        assert_eq!(report.lf_files, 2);
+        assert_eq!(report.crlf_files, 1);
+        assert_eq!(report.by_extension[".rs"].files, 2);
+        assert_eq!(report.by_extension[".rs"].offending_files, 1);
+        assert_eq!(report.worst_offenders[0].path, PathBuf::from("a.rs"));
+    }
+}
@@ -0,0 +1,255 @@
+//! Property-test helpers for rules, enabled by the `testing` feature.
+//!
+//! A rule is any function from a file's content to its cleaned content: built-in [`Rules`],
+//! a [`Pipeline`] of [`Transform`]s, or a plugin's own. [`check`] runs such a function over
+//! generated content and asserts a [`Property`] of every result, shrinking a failing input to
+//! the fewest lines that still fail before panicking with it.
+
+use crate::config::Rules;
+use crate::transform::{Pipeline, Transform};
+
+/// A property of `clean` on one input, as `Err` with what went wrong if the input breaks it.
+pub type Property = fn(&dyn Fn(&str) -> String, &str) -> Result<(), String>;
+
+/// Generates arbitrary file content that exercises whitespace rules: words, runs of spaces and
+/// tabs, Unicode spaces, blank lines, quotes, stray carriage returns, and LF and CRLF endings,
+/// with or without a final newline. The same seed always generates the same content.
+#[derive(Debug, Clone)]
+pub struct ContentGen {
+    state: u64,
+}
+
+impl ContentGen {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// The next pseudo-random number (SplitMix64).
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `bound`.
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[self.below(choices.len())]
+    }
+
+    /// The content of one arbitrary file.
+    pub fn content(&mut self) -> String {
+        let mut content = String::new();
+        let lines = self.below(12);
+        for line in 0..lines {
+            for _ in 0..self.below(6) {
+                content.push_str(self.pick(&[
+                    "word", "x", "fn", "\"", "\"\"\"", "'", "#", "-", " ", "  ", "\t", " \t", "\u{a0}", "\u{3000}",
+                    "\u{200b}", "\r", "é",
+                ]));
+            }
+            if line + 1 < lines || self.below(2) == 0 {
+                content.push_str(self.pick(&["\n", "\n", "\r\n"]));
+            }
+        }
+        content
+    }
+}
+
+/// The content `rules` make of `content`, as a run would write it.
+pub fn clean_with_rules(rules: &Rules, content: &str) -> String {
+    clean_with(&mut Pipeline::new(rules), content)
+}
+
+/// The content `transforms`, applied in order, make of `content`.
+pub fn clean_with_transforms<'r>(transforms: Vec<Box<dyn Transform + 'r>>, content: &str) -> String {
+    clean_with(&mut Pipeline::from_transforms(transforms), content)
+}
+
+fn clean_with(pipeline: &mut Pipeline, content: &str) -> String {
+    let mut cleaned = String::with_capacity(content.len());
+    pipeline.run(content, |line| {
+        if let Some(text) = line.text {
+            cleaned.push_str(&text);
+            cleaned.push_str(line.ending.as_str());
+        }
+    });
+    cleaned
+}
+
+/// Cleaning clean content changes nothing, so a second run never finds more to do.
+pub fn idempotent(clean: &dyn Fn(&str) -> String, content: &str) -> Result<(), String> {
+    let once = clean(content);
+    let twice = clean(&once);
+    if once == twice {
+        Ok(())
+    } else {
+        Err(format!("cleaning again changed {:?} to {:?}", once, twice))
+    }
+}
+
+/// Cleaning only removes bytes: the cleaned content is the input with some bytes left out.
+/// Rules that convert line endings to CRLF or indentation to spaces add bytes, and don't hold it.
+pub fn only_removes_bytes(clean: &dyn Fn(&str) -> String, content: &str) -> Result<(), String> {
+    let cleaned = clean(content);
+    let mut input = content.bytes();
+    if cleaned.bytes().all(|byte| input.any(|other| other == byte)) {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not {:?} with bytes removed", cleaned, content))
+    }
+}
+
+/// Cleaning keeps every character that isn't whitespace, in order.
+pub fn preserves_non_whitespace(clean: &dyn Fn(&str) -> String, content: &str) -> Result<(), String> {
+    let cleaned = clean(content);
+    let visible = |text: &str| text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    if visible(&cleaned) == visible(content) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{:?} lost or gained non-whitespace from {:?}",
+            cleaned, content
+        ))
+    }
+}
+
+/// Check `property` of `clean` on `cases` contents generated from `seed`.
+///
+/// # Panics
+///
+/// On the first content that breaks the property, with the content cut down to the fewest
+/// lines that still break it, and the seed and case to generate the original again.
+pub fn check(clean: impl Fn(&str) -> String, property: Property, cases: usize, seed: u64) {
+    let mut generator = ContentGen::new(seed);
+    for case in 0..cases {
+        let content = generator.content();
+        if property(&clean, &content).is_err() {
+            let content = shrink(&clean, property, content);
+            let error = property(&clean, &content).expect_err("shrinking keeps the failure");
+            panic!(
+                "property failed on case {} of seed {}: {}\ninput: {:?}",
+                case, seed, error, content
+            );
+        }
+    }
+}
+
+/// Drop lines from `content` one at a time, as long as it still breaks `property`.
+fn shrink(clean: &dyn Fn(&str) -> String, property: Property, content: String) -> String {
+    let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut i = 0;
+    while i < lines.len() {
+        let mut fewer = lines.clone();
+        fewer.remove(i);
+        if property(clean, &fewer.concat()).is_err() {
+            lines = fewer;
+        } else {
+            i += 1;
+        }
+    }
+    lines.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{IndentStyle, LineEndings};
+    use crate::literals::Syntax;
+    use crate::transform::{SqueezeBlankLines, TrailingBlankLines};
+
+    const CASES: usize = 500;
+
+    #[test]
+    fn test_content_gen_is_reproducible() {
+        let first: Vec<String> = (0..20)
+            .map({
+                let mut generator = ContentGen::new(7);
+                move |_| generator.content()
+            })
+            .collect();
+        let mut generator = ContentGen::new(7);
+        assert!(first.iter().all(|content| *content == generator.content()));
+        assert!(first.iter().any(|content| content.contains("\r\n")));
+        assert!(first.iter().any(|content| content.contains(" \n")));
+    }
+
+    #[test]
+    fn test_rules_properties() {
+        let removing = [
+            Rules::default(),
+            Rules {
+                preserve_hard_breaks: true,
+                ..Rules::default()
+            },
+            Rules {
+                preserve_string_literals: true,
+                literal_syntax: Some(Syntax::Python),
+                ..Rules::default()
+            },
+            Rules {
+                max_consecutive_blank_lines: Some(1),
+                trim_trailing_blank_lines: true,
+                line_endings: LineEndings::Lf,
+                ..Rules::default()
+            },
+        ];
+        for rules in &removing {
+            let clean = |content: &str| clean_with_rules(rules, content);
+            check(clean, idempotent, CASES, 1);
+            check(clean, only_removes_bytes, CASES, 2);
+            check(clean, preserves_non_whitespace, CASES, 3);
+        }
+
+        let rewriting = [
+            Rules {
+                line_endings: LineEndings::Crlf,
+                ..Rules::default()
+            },
+            Rules {
+                indent_style: Some(IndentStyle::Spaces),
+                ..Rules::default()
+            },
+            Rules {
+                indent_style: Some(IndentStyle::Tabs),
+                ..Rules::default()
+            },
+        ];
+        for rules in &rewriting {
+            let clean = |content: &str| clean_with_rules(rules, content);
+            check(clean, idempotent, CASES, 4);
+            check(clean, preserves_non_whitespace, CASES, 5);
+        }
+    }
+
+    #[test]
+    fn test_transforms_properties() {
+        let clean = |content: &str| {
+            clean_with_transforms(
+                vec![
+                    Box::new(SqueezeBlankLines::new(0)),
+                    Box::new(TrailingBlankLines::default()),
+                ],
+                content,
+            )
+        };
+        check(clean, idempotent, CASES, 6);
+        check(clean, only_removes_bytes, CASES, 7);
+        check(clean, preserves_non_whitespace, CASES, 8);
+    }
+
+    #[test]
+    fn test_check_shrinks_failures() {
+        // Appending a space adds a byte to anything, even an empty file
+        let clean = |content: &str| format!("{} ", content);
+        let panic = std::panic::catch_unwind(|| check(clean, only_removes_bytes, CASES, 9)).unwrap_err();
+        let message = panic.downcast_ref::<String>().unwrap();
+        assert!(message.contains("property failed on case 0 of seed 9"), "{}", message);
+        assert!(message.ends_with("input: \"\""), "{}", message);
+    }
+}
@@ -0,0 +1,109 @@
+use colored::*;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// A phase of a run that `--timings` reports on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking directories and filtering paths
+    Walk,
+    /// Reading file contents (streamed files also scan while reading)
+    Read,
+    /// Binary and UTF-8 checks, scanning and trimming
+    Process,
+    /// Writing cleaned files back
+    Write,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [Phase::Walk, Phase::Read, Phase::Process, Phase::Write];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Walk => "walk",
+            Self::Read => "read",
+            Self::Process => "process",
+            Self::Write => "write",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Time accumulated per phase. Worker threads record concurrently, so the read, process and
+/// write totals are summed across threads and can exceed the wall-clock time of the run.
+#[derive(Debug, Default)]
+pub struct Timings {
+    nanos: [AtomicU64; 4],
+}
+
+impl Timings {
+    pub fn record(&self, phase: Phase, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.nanos[phase.index()].fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Run `f`, adding the time it took to `phase`.
+    pub fn time<T>(&self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.record(phase, start.elapsed());
+        value
+    }
+
+    pub fn get(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase.index()].load(Ordering::Relaxed))
+    }
+}
+
+/// Print the per-phase breakdown and the total wall-clock time to stderr.
+pub fn display_timings(timings: &Timings, total: Duration) {
+    eprintln!(
+        "\n{} {}",
+        "Timings".bold(),
+        "(read, process and write are summed across threads)".dimmed()
+    );
+    for line in timing_lines(timings, total) {
+        eprintln!("  {}", line);
+    }
+}
+
+fn timing_lines(timings: &Timings, total: Duration) -> Vec<String> {
+    let mut lines: Vec<String> = Phase::ALL
+        .iter()
+        .map(|&phase| format!("{:<8} {:>10}", phase.to_string(), format_duration(timings.get(phase))))
+        .collect();
+    lines.push(format!("{:<8} {:>10} (wall clock)", "total", format_duration(total)));
+    lines
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_accumulate_per_phase() {
+        let timings = Timings::default();
+        timings.record(Phase::Read, Duration::from_millis(2));
+        timings.record(Phase::Read, Duration::from_millis(3));
+        assert_eq!(timings.time(Phase::Write, || 42), 42);
+
+        assert_eq!(timings.get(Phase::Read), Duration::from_millis(5));
+        assert_eq!(timings.get(Phase::Walk), Duration::ZERO);
+
+        let lines = timing_lines(&timings, Duration::from_micros(12_345));
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[1], "read          5.0ms");
+        assert_eq!(lines[4], "total        12.3ms (wall clock)");
+    }
+}
@@ -0,0 +1,833 @@
+use crate::config::{EndOfFile, IndentStyle, LineEndings, Replacement, Rules};
+use crate::literals::{LineSpan, LiteralScanner, Syntax};
+use crate::rules::{CleanedLine, LineEnding, LineRanges, is_hard_break, line_width, normalize_indent, trim_line};
+use std::borrow::Cow;
+use std::convert::Infallible;
+
+/// A line on its way through a [`Pipeline`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line<'a> {
+    /// 1-based number of the line in the original file
+    pub line_num: usize,
+    /// The line as it was read, without its ending
+    pub original: Cow<'a, str>,
+    /// The ending the line was read with
+    pub original_ending: LineEnding,
+    /// The line as the transforms so far have left it, or None once one has removed it
+    pub text: Option<Cow<'a, str>>,
+    /// The ending to write after the line
+    pub ending: LineEnding,
+    /// Where the line starts and ends relative to multi-line string literals
+    pub span: LineSpan,
+    /// Names of the transforms that changed the line, in the order they did
+    pub changed_by: Vec<&'static str>,
+    /// Names of the transforms that reported the line without changing it
+    pub flagged_by: Vec<&'static str>,
+}
+
+impl<'a> Line<'a> {
+    pub fn new(line_num: usize, text: &'a str, ending: LineEnding) -> Self {
+        Self {
+            line_num,
+            original: Cow::Borrowed(text),
+            original_ending: ending,
+            text: Some(Cow::Borrowed(text)),
+            ending,
+            span: LineSpan::default(),
+            changed_by: Vec::new(),
+            flagged_by: Vec::new(),
+        }
+    }
+
+    /// Empty or removed, and not part of a string literal.
+    pub fn is_blank(&self) -> bool {
+        self.text.as_deref().is_none_or(str::is_empty) && !self.span.starts_inside
+    }
+
+    /// Replace the line's text on behalf of the transform `by`.
+    fn rewrite(&mut self, text: Cow<'a, str>, by: &'static str) {
+        self.text = Some(text);
+        self.changed_by.push(by);
+    }
+
+    /// Remove the line on behalf of the transform `by`, unless an earlier one already has.
+    fn remove(&mut self, by: &'static str) {
+        if self.text.take().is_some() {
+            self.changed_by.push(by);
+        }
+    }
+
+    /// An owned copy, for transforms that hold lines back across calls.
+    fn detach(self) -> Line<'static> {
+        Line {
+            line_num: self.line_num,
+            original: Cow::Owned(self.original.into_owned()),
+            original_ending: self.original_ending,
+            text: self.text.map(|text| Cow::Owned(text.into_owned())),
+            ending: self.ending,
+            span: self.span,
+            changed_by: self.changed_by,
+            flagged_by: self.flagged_by,
+        }
+    }
+
+    /// Undo every transform's changes, and forget what they reported.
+    fn restore(&mut self) {
+        self.text = Some(self.original.clone());
+        self.ending = self.original_ending;
+        self.changed_by.clear();
+        self.flagged_by.clear();
+    }
+
+    fn into_cleaned(self) -> CleanedLine<'a> {
+        // A line a replacement lengthened saves nothing, rather than a negative amount
+        let bytes_saved = match &self.text {
+            Some(text) => self.original.len().saturating_sub(text.len()),
+            None => self.original.len() + self.original_ending.as_str().len(),
+        };
+        CleanedLine {
+            line_num: self.line_num,
+            text: self.text,
+            ending: self.ending,
+            changed: !self.changed_by.is_empty(),
+            bytes_saved,
+            changed_by: self.changed_by,
+            flagged_by: self.flagged_by,
+        }
+    }
+}
+
+/// One rule, applied to a file's lines in order. A transform may pass a line on at once or
+/// hold it back until later lines, or the end of the file, decide its fate; either way every
+/// line is passed on exactly once, in order.
+pub trait Transform {
+    /// The rule's name, as the config spells it, for change reports.
+    fn name(&self) -> &'static str;
+
+    /// Take the next line, passing it and any lines it releases to `next`.
+    fn push<'a>(&mut self, line: Line<'a>, next: &mut dyn FnMut(Line<'a>));
+
+    /// The file has ended: pass any lines still held back to `next`.
+    fn finish<'a>(&mut self, _next: &mut dyn FnMut(Line<'a>)) {}
+}
+
+/// Follows the file's string literals so later transforms leave their contents alone. It never
+/// changes a line itself.
+pub struct StringLiterals {
+    scanner: LiteralScanner,
+}
+
+impl StringLiterals {
+    pub fn new(syntax: Syntax) -> Self {
+        Self {
+            scanner: LiteralScanner::new(syntax),
+        }
+    }
+}
+
+impl Transform for StringLiterals {
+    fn name(&self) -> &'static str {
+        "preserve-string-literals"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if let Some(text) = &line.text {
+            line.span = self.scanner.scan(text);
+        }
+        next(line)
+    }
+}
+
+/// Strips trailing whitespace, keeping Markdown hard breaks when asked to. Whitespace at the
+/// end of a line that ends inside a string literal is part of the string and stays; inside a
+/// quoted CSV or TSV field the line is reported too, since data there may be wrong either way.
+/// In TSV files a trailing tab ends an empty last field, so tabs stay. Lines matching one of
+/// `preserve-line-patterns` are left as they are.
+pub struct TrimTrailing<'r> {
+    rules: &'r Rules,
+}
+
+impl<'r> TrimTrailing<'r> {
+    pub fn new(rules: &'r Rules) -> Self {
+        Self { rules }
+    }
+}
+
+impl Transform for TrimTrailing<'_> {
+    fn name(&self) -> &'static str {
+        "trailing-whitespace"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        let syntax = self.rules.literal_syntax;
+        if line.span.ends_inside {
+            if syntax.is_some_and(Syntax::is_tabular)
+                && let Some(text) = &line.text
+                && trim_line(text, self.rules).len() < text.len()
+            {
+                line.flagged_by.push(KEPT_IN_LITERAL);
+            }
+        } else if !line
+            .text
+            .as_deref()
+            .is_some_and(|text| self.rules.preserve_line_patterns.matches(text))
+            && let Some(text) = line.text.take()
+        {
+            let mut kept = trim_line(&text, self.rules).len();
+            if syntax == Some(Syntax::Tsv)
+                && let Some(tab) = text[kept..].rfind('\t')
+            {
+                kept += tab + 1;
+            }
+            let hard_break = self.rules.preserve_hard_breaks && !line.span.starts_inside && kept > 0;
+            if hard_break && is_hard_break(&text[kept..]) {
+                kept += 2;
+            }
+            if kept == text.len() {
+                line.text = Some(text);
+            } else {
+                let trimmed = match text {
+                    Cow::Borrowed(text) => Cow::Borrowed(&text[..kept]),
+                    Cow::Owned(mut text) => {
+                        text.truncate(kept);
+                        Cow::Owned(text)
+                    }
+                };
+                line.rewrite(trimmed, self.name());
+            }
+        }
+        next(line)
+    }
+}
+
+/// The rule CSV and TSV lines whose trailing whitespace is kept inside a quoted field are
+/// reported under.
+pub const KEPT_IN_LITERAL: &str = "preserve-string-literals";
+
+/// Rewrites indentation that mixes tabs and spaces in one style. Lines that start inside a
+/// string literal have no indentation of their own.
+pub struct Indentation {
+    style: IndentStyle,
+    width: usize,
+}
+
+impl Indentation {
+    pub fn new(style: IndentStyle, width: usize) -> Self {
+        Self { style, width }
+    }
+}
+
+impl Transform for Indentation {
+    fn name(&self) -> &'static str {
+        "indent-style"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if !line.span.starts_inside
+            && let Some(text) = &line.text
+            && let Some(normalized) = normalize_indent(text, self.style, self.width)
+        {
+            line.rewrite(Cow::Owned(normalized), self.name());
+        }
+        next(line)
+    }
+}
+
+/// Writes every line ending as `\n`, or every one as `\r\n`. A different ending alone doesn't
+/// make a line need cleaning: files are rewritten for the other rules, and their endings
+/// converted when they are. A line whose text still ends in a carriage return, as one inside a
+/// string literal can, keeps its ending: a `\n` after it would read back as `\r\n`.
+pub struct FixedEndings(pub LineEnding);
+
+impl Transform for FixedEndings {
+    fn name(&self) -> &'static str {
+        "line-endings"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if line.original_ending != LineEnding::None && !line.text.as_deref().is_some_and(|text| text.ends_with('\r')) {
+            line.ending = self.0;
+        }
+        next(line)
+    }
+}
+
+/// Replaces every match of a regular expression in each line, for `rules.replacements`. Lines
+/// that start or end inside a string literal are left alone.
+pub struct Replace<'r> {
+    replacement: &'r Replacement,
+}
+
+impl<'r> Replace<'r> {
+    pub fn new(replacement: &'r Replacement) -> Self {
+        Self { replacement }
+    }
+}
+
+impl Transform for Replace<'_> {
+    fn name(&self) -> &'static str {
+        self.replacement.name
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if !line.span.starts_inside
+            && !line.span.ends_inside
+            && let Some(text) = &line.text
+            && let Cow::Owned(replaced) = self.replacement.pattern.replace_all(text, &self.replacement.replace)
+            && replaced != **text
+        {
+            line.rewrite(Cow::Owned(replaced), self.name());
+        }
+        next(line)
+    }
+}
+
+/// Removes blank lines past the first `max` of each run.
+pub struct SqueezeBlankLines {
+    max: usize,
+    run: usize,
+}
+
+impl SqueezeBlankLines {
+    pub fn new(max: usize) -> Self {
+        Self { max, run: 0 }
+    }
+}
+
+impl Transform for SqueezeBlankLines {
+    fn name(&self) -> &'static str {
+        "max-consecutive-blank-lines"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if line.is_blank() {
+            self.run += 1;
+            if self.run > self.max {
+                line.remove(self.name());
+            }
+        } else {
+            self.run = 0;
+        }
+        next(line)
+    }
+}
+
+/// Removes blank lines at the end of the file. Blank lines are held back until a line with
+/// content shows they aren't at the end.
+#[derive(Default)]
+pub struct TrailingBlankLines {
+    held: Vec<Line<'static>>,
+}
+
+impl Transform for TrailingBlankLines {
+    fn name(&self) -> &'static str {
+        "trim-trailing-blank-lines"
+    }
+
+    fn push<'a>(&mut self, line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if line.is_blank() {
+            self.held.push(line.detach());
+            return;
+        }
+        for held in self.held.drain(..) {
+            next(held);
+        }
+        next(line)
+    }
+
+    fn finish<'a>(&mut self, next: &mut dyn FnMut(Line<'a>)) {
+        let name = self.name();
+        for mut held in self.held.drain(..) {
+            held.remove(name);
+            next(held);
+        }
+    }
+}
+
+/// Checks how the file ends: adds a line break to a last line without one, and reports a last
+/// line ending in a backslash, which continues it into nothing. The last line with content is
+/// held back, with any removed lines after it, until the file ends.
+pub struct EndOfFileChecks {
+    final_newline: bool,
+    no_continuation: bool,
+    /// The ending to add: the one lines before the last were written with
+    ending: LineEnding,
+    held: Vec<Line<'static>>,
+}
+
+impl EndOfFileChecks {
+    pub fn new(checks: &[EndOfFile], line_endings: LineEndings) -> Self {
+        Self {
+            final_newline: checks.contains(&EndOfFile::FinalNewline),
+            no_continuation: checks.contains(&EndOfFile::NoContinuation),
+            ending: match line_endings {
+                LineEndings::Crlf => LineEnding::Crlf,
+                LineEndings::Lf | LineEndings::Preserve => LineEnding::Lf,
+            },
+            held: Vec::new(),
+        }
+    }
+}
+
+impl Transform for EndOfFileChecks {
+    fn name(&self) -> &'static str {
+        "end-of-file"
+    }
+
+    fn push<'a>(&mut self, line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if line.ending != LineEnding::None {
+            self.ending = line.ending;
+        }
+        // An empty last line without an ending adds nothing to the end of the file
+        let has_content = line
+            .text
+            .as_deref()
+            .is_some_and(|text| !text.is_empty() || line.ending != LineEnding::None);
+        if has_content {
+            for held in self.held.drain(..) {
+                next(held);
+            }
+        } else if self.held.is_empty() {
+            return next(line);
+        }
+        self.held.push(line.detach());
+    }
+
+    fn finish<'a>(&mut self, next: &mut dyn FnMut(Line<'a>)) {
+        let name = self.name();
+        let mut held = std::mem::take(&mut self.held).into_iter();
+        if let Some(mut last) = held.next() {
+            if self.final_newline && last.ending == LineEnding::None {
+                last.ending = self.ending;
+                last.changed_by.push(name);
+            }
+            if self.no_continuation && last.text.as_deref().is_some_and(|text| text.ends_with('\\')) {
+                last.flagged_by.push(name);
+            }
+            next(last);
+        }
+        for line in held {
+            next(line);
+        }
+    }
+}
+
+/// Reports lines wider than `max` columns as the earlier transforms left them, so trailing
+/// whitespace about to be stripped doesn't count. It never changes a line: there is no telling
+/// where one should be broken.
+pub struct LineLength {
+    max: usize,
+    tab_width: usize,
+}
+
+impl LineLength {
+    pub fn new(max: usize, tab_width: usize) -> Self {
+        Self { max, tab_width }
+    }
+}
+
+impl Transform for LineLength {
+    fn name(&self) -> &'static str {
+        "max-line-length"
+    }
+
+    fn push<'a>(&mut self, mut line: Line<'a>, next: &mut dyn FnMut(Line<'a>)) {
+        if let Some(text) = &line.text
+            && line_width(text, self.tab_width) > self.max
+        {
+            line.flagged_by.push(self.name());
+        }
+        next(line)
+    }
+}
+
+/// The transforms a file's rules call for, applied to its lines in order. Each line goes
+/// through every transform before it comes out, in order, through the callback given to
+/// [`push`](Self::push) or [`finish`](Self::finish); transforms that hold lines back, such
+/// as trimming trailing blank lines, may release several at once.
+///
+/// Lines outside the rules' `line_ranges` still go through every transform, so that runs of
+/// blank lines are counted the same way, but come out as they went in.
+pub struct Pipeline<'r> {
+    transforms: Vec<Box<dyn Transform + 'r>>,
+    line_ranges: Option<&'r LineRanges>,
+}
+
+impl<'r> Pipeline<'r> {
+    /// The pipeline for `rules`, with a transform for each rule that is on.
+    pub fn new(rules: &'r Rules) -> Self {
+        let mut transforms: Vec<Box<dyn Transform + 'r>> = Vec::new();
+        if let Some(syntax) = rules.literal_syntax {
+            transforms.push(Box::new(StringLiterals::new(syntax)));
+        }
+        transforms.push(Box::new(TrimTrailing::new(rules)));
+        if let Some(style) = rules.indent_style {
+            transforms.push(Box::new(Indentation::new(style, rules.indent_width)));
+        }
+        match rules.line_endings {
+            LineEndings::Lf => transforms.push(Box::new(FixedEndings(LineEnding::Lf))),
+            LineEndings::Crlf => transforms.push(Box::new(FixedEndings(LineEnding::Crlf))),
+            LineEndings::Preserve => {}
+        }
+        for replacement in &rules.replacements {
+            transforms.push(Box::new(Replace::new(replacement)));
+        }
+        if let Some(max) = rules.max_consecutive_blank_lines {
+            transforms.push(Box::new(SqueezeBlankLines::new(max)));
+        }
+        if rules.trim_trailing_blank_lines {
+            transforms.push(Box::new(TrailingBlankLines::default()));
+        }
+        if !rules.end_of_file.is_empty() {
+            transforms.push(Box::new(EndOfFileChecks::new(&rules.end_of_file, rules.line_endings)));
+        }
+        if let Some(max) = rules.max_line_length {
+            transforms.push(Box::new(LineLength::new(max, rules.indent_width)));
+        }
+        // Finding string literals is no rule of its own, but the rules that remain rely on it
+        transforms.retain(|transform| transform.name() == "preserve-string-literals" || rules.runs(transform.name()));
+        Self {
+            transforms,
+            line_ranges: rules.line_ranges.as_ref(),
+        }
+    }
+
+    /// A pipeline of exactly these transforms.
+    pub fn from_transforms(transforms: Vec<Box<dyn Transform + 'r>>) -> Self {
+        Self {
+            transforms,
+            line_ranges: None,
+        }
+    }
+
+    /// The names of the transforms, in the order they apply.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.transforms.iter().map(|transform| transform.name()).collect()
+    }
+
+    /// Clean the next line (without its line ending, which is passed separately), passing every
+    /// line that is ready to `emit`.
+    pub fn push<'a, E>(
+        &mut self,
+        line_num: usize,
+        line: &'a str,
+        ending: LineEnding,
+        mut emit: impl FnMut(CleanedLine<'a>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut result = Ok(());
+        let line_ranges = self.line_ranges;
+        feed(&mut self.transforms, Line::new(line_num, line, ending), &mut |line| {
+            if result.is_ok() {
+                result = emit(cleaned(line, line_ranges));
+            }
+        });
+        result
+    }
+
+    /// Clean the whole of `content`, passing each cleaned line to `f` in order.
+    pub fn run<'a>(&mut self, content: &'a str, mut f: impl FnMut(CleanedLine<'a>)) {
+        let mut emit = |cleaned| {
+            f(cleaned);
+            Ok::<_, Infallible>(())
+        };
+        for (line_num, raw) in content.split_inclusive('\n').enumerate() {
+            let (line, ending) = match raw.strip_suffix('\n') {
+                Some(body) => match body.strip_suffix('\r') {
+                    Some(body) => (body, LineEnding::Crlf),
+                    None => (body, LineEnding::Lf),
+                },
+                None => (raw, LineEnding::None),
+            };
+            let Ok(()) = self.push(line_num + 1, line, ending, &mut emit);
+        }
+        let Ok(()) = self.finish(&mut emit);
+    }
+
+    /// Signal the end of the file, passing any lines still held back to `emit`.
+    pub fn finish<'a, E>(&mut self, mut emit: impl FnMut(CleanedLine<'a>) -> Result<(), E>) -> Result<(), E> {
+        let mut result = Ok(());
+        let line_ranges = self.line_ranges;
+        let mut out = |line: Line<'a>| {
+            if result.is_ok() {
+                result = emit(cleaned(line, line_ranges));
+            }
+        };
+        for i in 0..self.transforms.len() {
+            let (transform, rest) = self.transforms[i..].split_first_mut().expect("i is in bounds");
+            transform.finish(&mut |line| feed(rest, line, &mut out));
+        }
+        result
+    }
+}
+
+/// The line a pipeline's transforms made of `line`, or the original if it is outside `line_ranges`.
+fn cleaned<'a>(mut line: Line<'a>, line_ranges: Option<&LineRanges>) -> CleanedLine<'a> {
+    if line_ranges.is_some_and(|line_ranges| !line_ranges.contains(line.line_num)) {
+        line.restore();
+    }
+    line.into_cleaned()
+}
+
+/// Pass `line` through `transforms` in order, and whatever comes out of the last to `out`.
+fn feed<'a>(transforms: &mut [Box<dyn Transform + '_>], line: Line<'a>, out: &mut dyn FnMut(Line<'a>)) {
+    match transforms.split_first_mut() {
+        Some((transform, rest)) => transform.push(line, &mut |line| feed(rest, line, out)),
+        None => out(line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LinePatterns, RuleSelection};
+
+    /// Run `lines` through a single transform, returning each line's text (None if removed)
+    /// and the transforms that changed it.
+    fn run(transform: impl Transform, lines: &[&str]) -> Vec<(Option<String>, Vec<&'static str>)> {
+        let mut pipeline = Pipeline::from_transforms(vec![Box::new(transform)]);
+        let mut out = Vec::new();
+        let mut emit = |line: CleanedLine<'_>| {
+            out.push((line.text.map(Cow::into_owned), line.changed_by));
+            Ok::<_, std::convert::Infallible>(())
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let Ok(()) = pipeline.push(i + 1, line, LineEnding::Lf, &mut emit);
+        }
+        let Ok(()) = pipeline.finish(&mut emit);
+        out
+    }
+
+    #[test]
+    fn test_transforms_in_isolation() {
+        let rules = Rules::default();
+        assert_eq!(
+            run(TrimTrailing::new(&rules), &["a  ", "b"]),
+            [
+                (Some("a".to_string()), vec!["trailing-whitespace"]),
+                (Some("b".to_string()), vec![])
+            ]
+        );
+        assert_eq!(
+            run(Indentation::new(IndentStyle::Tabs, 4), &["  \tx", "\tx"]),
+            [
+                (Some("\tx".to_string()), vec!["indent-style"]),
+                (Some("\tx".to_string()), vec![])
+            ]
+        );
+        assert_eq!(
+            run(SqueezeBlankLines::new(1), &["", "", "x", ""]),
+            [
+                (Some(String::new()), vec![]),
+                (None, vec!["max-consecutive-blank-lines"]),
+                (Some("x".to_string()), vec![]),
+                (Some(String::new()), vec![])
+            ]
+        );
+        assert_eq!(
+            run(TrailingBlankLines::default(), &["", "x", "", ""]),
+            [
+                (Some(String::new()), vec![]),
+                (Some("x".to_string()), vec![]),
+                (None, vec!["trim-trailing-blank-lines"]),
+                (None, vec!["trim-trailing-blank-lines"])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pipeline_runs_selected_rules() {
+        let selected = |only: &[&str], skip: &[&str]| Rules {
+            literal_syntax: Some(Syntax::Rust),
+            line_endings: LineEndings::Lf,
+            trim_trailing_blank_lines: true,
+            end_of_file: vec![EndOfFile::FinalNewline],
+            selection: Some(RuleSelection {
+                only: only.iter().map(ToString::to_string).collect(),
+                skip: skip.iter().map(ToString::to_string).collect(),
+            }),
+            ..Rules::default()
+        };
+
+        let rules = selected(&["end-of-file"], &[]);
+        assert_eq!(
+            Pipeline::new(&rules).names(),
+            ["preserve-string-literals", "end-of-file"]
+        );
+        let rules = selected(&[], &["trailing-whitespace", "line-endings"]);
+        assert_eq!(
+            Pipeline::new(&rules).names(),
+            ["preserve-string-literals", "trim-trailing-blank-lines", "end-of-file"]
+        );
+        let rules = selected(&["end-of-file"], &["end-of-file"]);
+        assert_eq!(Pipeline::new(&rules).names(), ["preserve-string-literals"]);
+    }
+
+    #[test]
+    fn test_pipeline_reports_each_rule() {
+        let rules = Rules {
+            indent_style: Some(IndentStyle::Spaces),
+            line_endings: LineEndings::Lf,
+            max_consecutive_blank_lines: Some(0),
+            trim_trailing_blank_lines: true,
+            ..Rules::default()
+        };
+        let mut pipeline = Pipeline::new(&rules);
+        assert_eq!(
+            pipeline.names(),
+            [
+                "trailing-whitespace",
+                "indent-style",
+                "line-endings",
+                "max-consecutive-blank-lines",
+                "trim-trailing-blank-lines"
+            ]
+        );
+
+        let mut changes = Vec::new();
+        let mut emit = |line: CleanedLine<'_>| {
+            changes.push((line.line_num, line.changed_by, line.bytes_saved));
+            Ok::<_, std::convert::Infallible>(())
+        };
+        for (i, line) in [" \tx  ", "  ", "y"].iter().enumerate() {
+            let Ok(()) = pipeline.push(i + 1, line, LineEnding::Crlf, &mut emit);
+        }
+        let Ok(()) = pipeline.finish(&mut emit);
+
+        // A line removed by one rule isn't claimed by a later one
+        assert_eq!(
+            changes,
+            [
+                (1, vec!["trailing-whitespace", "indent-style"], 0),
+                (2, vec!["trailing-whitespace", "max-consecutive-blank-lines"], 4),
+                (3, vec![], 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_replacements() {
+        let yaml = r#"
+line-endings: lf
+replacements:
+  - name: smart-quotes
+    pattern: "[“”]"
+    replace: '"'
+  - pattern: "(\\w+)!!"
+    replace: "$1!"
+  - name: arrows
+    pattern: "->"
+    replace: "→"
+"#;
+        let rules: Rules = serde_yaml::from_str(yaml).unwrap();
+        let mut pipeline = Pipeline::new(&rules);
+        assert_eq!(
+            pipeline.names(),
+            [
+                "trailing-whitespace",
+                "line-endings",
+                "smart-quotes",
+                "(\\w+)!!",
+                "arrows"
+            ]
+        );
+
+        let mut lines = Vec::new();
+        pipeline.run("say “hi”  \nwow!!\nplain\na->b\n", |line| {
+            lines.push((line.text.map(Cow::into_owned), line.changed_by, line.bytes_saved))
+        });
+        assert_eq!(
+            lines,
+            [
+                (
+                    Some("say \"hi\"".to_string()),
+                    vec!["trailing-whitespace", "smart-quotes"],
+                    6
+                ),
+                (Some("wow!".to_string()), vec!["(\\w+)!!"], 1),
+                (Some("plain".to_string()), vec![], 0),
+                // Lengthened, so it saves nothing
+                (Some("a→b".to_string()), vec!["arrows"], 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preserve_line_patterns() {
+        let rules = Rules {
+            preserve_line_patterns: LinePatterns::new(vec!["^-- $".to_string(), r"\\ +$".to_string()]).unwrap(),
+            ..Rules::default()
+        };
+        assert_eq!(
+            run(TrimTrailing::new(&rules), &["-- ", "--  ", "a \\  ", "b  "]),
+            [
+                (Some("-- ".to_string()), vec![]),
+                (Some("--".to_string()), vec!["trailing-whitespace"]),
+                (Some("a \\  ".to_string()), vec![]),
+                (Some("b".to_string()), vec!["trailing-whitespace"])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_end_of_file_checks() {
+        let checks = |content: &str, line_endings| {
+            let rules = Rules {
+                line_endings,
+                end_of_file: vec![EndOfFile::FinalNewline, EndOfFile::NoContinuation],
+                ..Rules::default()
+            };
+            let mut cleaned = String::new();
+            let mut changed = Vec::new();
+            let mut flagged = Vec::new();
+            Pipeline::new(&rules).run(content, |line| {
+                if let Some(text) = &line.text {
+                    cleaned.push_str(text);
+                    cleaned.push_str(line.ending.as_str());
+                }
+                if line.changed_by.contains(&"end-of-file") {
+                    changed.push(line.line_num);
+                }
+                if line.flagged_by.contains(&"end-of-file") {
+                    flagged.push(line.line_num);
+                }
+            });
+            (cleaned, changed, flagged)
+        };
+
+        assert_eq!(checks("a\nb", LineEndings::Lf), ("a\nb\n".to_string(), vec![2], vec![]));
+        assert_eq!(
+            checks("a\r\nb", LineEndings::Preserve),
+            ("a\r\nb\r\n".to_string(), vec![2], vec![])
+        );
+        assert_eq!(checks("a", LineEndings::Crlf), ("a\r\n".to_string(), vec![1], vec![]));
+        // Trailing whitespace after the last line break leaves the file ending in it
+        assert_eq!(checks("a\n  ", LineEndings::Lf), ("a\n".to_string(), vec![], vec![]));
+        assert_eq!(checks("", LineEndings::Lf), (String::new(), vec![], vec![]));
+        assert_eq!(checks("#define A \\\n", LineEndings::Lf).2, [1]);
+        assert_eq!(checks("a \\\n\n", LineEndings::Lf).2, Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_line_length_reports_without_changing() {
+        let rules = Rules {
+            max_line_length: Some(4),
+            ..Rules::default()
+        };
+        let mut pipeline = Pipeline::new(&rules);
+        let mut lines = Vec::new();
+        pipeline.run("abcd   \nabcde\n\tab\nab\n", |line| {
+            lines.push((line.line_num, line.changed, line.flagged_by))
+        });
+
+        // Trailing whitespace is measured after it is stripped; tabs reach the next tab stop
+        assert_eq!(
+            lines,
+            [
+                (1, true, vec![]),
+                (2, false, vec!["max-line-length"]),
+                (3, false, vec!["max-line-length"]),
+                (4, false, vec![])
+            ]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use crate::config::{Config, ConfigFormat};
+use crate::walker::build_glob;
+use colored::*;
+use eyre::{Context, Result};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// How serious a config problem is. Errors fail `config validate`; warnings do not.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem found in a config file, with its 1-based line and column when known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    fn error(message: String, location: Option<(usize, usize)>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message,
+            location,
+        }
+    }
+
+    fn warning(message: String, location: Option<(usize, usize)>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            location,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Read and check a config file.
+pub fn validate_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut diagnostics = validate_str(&content, ConfigFormat::from_path(path));
+    check_extends(path, &content, &mut diagnostics);
+    Ok(diagnostics)
+}
+
+/// Check config `source` for parse errors, unknown keys, invalid globs and conflicting settings.
+///
+/// Unknown keys are otherwise silently dropped by `serde(default)`, so a typo such as
+/// `exclude-path:` would never take effect.
+pub fn validate_str(source: &str, format: ConfigFormat) -> Vec<Diagnostic> {
+    let mut unknown_keys = Vec::new();
+    let parsed = parse_tracking_unknown(source, format, &mut unknown_keys);
+
+    let config = match parsed {
+        Ok(config) => config,
+        Err((message, location)) => return vec![Diagnostic::error(message, location)],
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = unknown_keys
+        .into_iter()
+        .map(|key| {
+            let name = key.rsplit('.').next().unwrap_or(&key).to_string();
+            Diagnostic::error(format!("unknown key `{}`", key), find_key(source, &name))
+        })
+        .collect();
+
+    check_globs(source, &config, &mut diagnostics);
+    check_custom_rules(source, &config, &mut diagnostics);
+    check_metrics(source, &config, &mut diagnostics);
+    check_conflicts(source, &config, &mut diagnostics);
+    diagnostics
+}
+
+type ParseError = (String, Option<(usize, usize)>);
+
+fn parse_tracking_unknown(source: &str, format: ConfigFormat, unknown: &mut Vec<String>) -> Result<Config, ParseError> {
+    let mut track = |path: serde_ignored::Path| unknown.push(path.to_string());
+    match format {
+        ConfigFormat::Yaml => {
+            // An empty YAML document is a valid, all-defaults config
+            if source.trim().is_empty() {
+                return Ok(Config::default());
+            }
+            serde_ignored::deserialize(serde_yaml::Deserializer::from_str(source), &mut track).map_err(|e| {
+                let location = e.location().map(|l| (l.line(), l.column()));
+                (e.to_string(), location)
+            })
+        }
+        ConfigFormat::Toml => {
+            let deserializer = toml::de::Deserializer::parse(source).map_err(|e| toml_error(source, e))?;
+            serde_ignored::deserialize(deserializer, &mut track).map_err(|e| toml_error(source, e))
+        }
+        ConfigFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(source);
+            let config = serde_ignored::deserialize(&mut deserializer, &mut track).map_err(|e| json_error(&e))?;
+            deserializer.end().map_err(|e| json_error(&e))?;
+            Ok(config)
+        }
+    }
+}
+
+fn toml_error(source: &str, e: toml::de::Error) -> ParseError {
+    let location = e.span().map(|span| offset_to_location(source, span.start));
+    (e.message().to_string(), location)
+}
+
+fn json_error(e: &serde_json::Error) -> ParseError {
+    (e.to_string(), Some((e.line(), e.column())))
+}
+
+fn offset_to_location(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().map_or(0, |l| l.chars().count()) + 1;
+    (line, column)
+}
+
+/// Locate `key:` (YAML), `key =` / `[key]` (TOML) or `"key":` (JSON) at the start of a line.
+fn find_key(source: &str, key: &str) -> Option<(usize, usize)> {
+    source.lines().enumerate().find_map(|(index, line)| {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let matches = if let Some(table) = trimmed.strip_prefix('[') {
+            table.trim_end().trim_end_matches(']').trim() == key
+        } else {
+            let unquoted = trimmed.strip_prefix('"').unwrap_or(trimmed);
+            unquoted.strip_prefix(key).is_some_and(|rest| {
+                let rest = rest.strip_prefix('"').unwrap_or(rest).trim_start();
+                rest.starts_with(':') || rest.starts_with('=')
+            })
+        };
+        matches.then_some((index + 1, indent + 1))
+    })
+}
+
+/// Locate the first occurrence of a quoted or bare `value`.
+fn find_value(source: &str, value: &str) -> Option<(usize, usize)> {
+    source
+        .lines()
+        .enumerate()
+        .find_map(|(index, line)| line.find(value).map(|column| (index + 1, column + 1)))
+}
+
+/// A file that extends another is only usable if everything down the chain loads, so load it
+/// the way a run would. Problems in the file itself have been reported already.
+fn check_extends(path: &Path, source: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let Some(location) = find_key(source, "extends") else {
+        return;
+    };
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return;
+    }
+    if let Err(e) = Config::load_from_file(path) {
+        diagnostics.push(Diagnostic::error(format!("`extends`: {:#}", e), Some(location)));
+    }
+}
+
+fn check_globs(source: &str, config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let file_type_names: Vec<String> = config.file_types.names.keys().cloned().collect();
+    let mut lists = vec![
+        ("exclude-paths".to_string(), &config.exclude_paths),
+        ("exclude-files".to_string(), &config.exclude_files),
+        (
+            "exclude-binary-extensions".to_string(),
+            &config.exclude_binary_extensions,
+        ),
+    ];
+    for (i, rule_override) in config.rules.overrides.iter().enumerate() {
+        lists.push((format!("rules.overrides[{}].files", i), &rule_override.files));
+    }
+    for (i, replacement) in config.rules.replacements.iter().enumerate() {
+        lists.push((format!("rules.replacements[{}].files", i), &replacement.files));
+    }
+    lists.push(("file-types.names".to_string(), &file_type_names));
+    for (i, custom_rule) in config.custom_rules.iter().enumerate() {
+        lists.push((format!("custom-rules[{}].files", i), &custom_rule.files));
+    }
+
+    for (key, patterns) in lists {
+        let mut seen = HashSet::new();
+        for pattern in patterns {
+            if let Err(e) = build_glob(pattern) {
+                diagnostics.push(Diagnostic::error(
+                    format!("invalid glob in `{}`: {}", key, e),
+                    find_value(source, pattern),
+                ));
+            }
+            if !seen.insert(pattern) {
+                diagnostics.push(Diagnostic::warning(
+                    format!("duplicate pattern `{}` in `{}`", pattern, key),
+                    find_value(source, pattern),
+                ));
+            }
+        }
+    }
+}
+
+fn check_custom_rules(source: &str, config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    for (i, custom_rule) in config.custom_rules.iter().enumerate() {
+        if custom_rule.command.is_empty() {
+            diagnostics.push(Diagnostic::error(
+                format!("`custom-rules[{}]` has no `command`", i),
+                find_key(source, "custom-rules"),
+            ));
+        }
+        if custom_rule.name.is_empty() {
+            diagnostics.push(Diagnostic::warning(
+                format!("`custom-rules[{}]` has no `name` to report it by", i),
+                find_key(source, "custom-rules"),
+            ));
+        }
+    }
+}
+
+fn check_metrics(source: &str, config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let metrics = &config.metrics;
+    if let Some(address) = &metrics.statsd
+        && !address
+            .rsplit_once(':')
+            .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok())
+    {
+        diagnostics.push(Diagnostic::error(
+            format!("`metrics.statsd` '{}' is not a host:port address", address),
+            find_key(source, "statsd"),
+        ));
+    }
+    if metrics.prefix.is_empty() {
+        diagnostics.push(Diagnostic::error(
+            "`metrics.prefix` is empty".to_string(),
+            find_key(source, "prefix"),
+        ));
+    }
+}
+
+fn check_conflicts(source: &str, config: &Config, diagnostics: &mut Vec<Diagnostic>) {
+    let processing = &config.processing;
+    if processing.streaming_threshold >= processing.max_file_size {
+        let effect = if processing.mmap { "`mmap` has no effect" } else { "files are never streamed" };
+        diagnostics.push(Diagnostic::warning(
+            format!(
+                "`streaming-threshold` ({}) is not below `max-file-size` ({}); larger files are skipped, so {}",
+                processing.streaming_threshold, processing.max_file_size, effect
+            ),
+            find_key(source, "streaming-threshold"),
+        ));
+    }
+
+    let rules = &config.rules;
+    if rules.indent_style.is_some() && rules.indent_width == 0 {
+        diagnostics.push(Diagnostic::warning(
+            "`indent-width` is 0, so tab stops are treated as 1 column wide".to_string(),
+            find_key(source, "indent-width"),
+        ));
+    }
+
+    let detection = &config.binary_detection;
+    if detection.check_null_bytes && detection.sample_size == 0 {
+        diagnostics.push(Diagnostic::warning(
+            "`check-null-bytes` is enabled but `sample-size` is 0, so no bytes are checked".to_string(),
+            find_key(source, "sample-size"),
+        ));
+    }
+}
+
+/// Print diagnostics as `path:line:column: severity: message`. Returns the number of errors.
+pub fn display_diagnostics(path: &Path, diagnostics: &[Diagnostic]) -> usize {
+    for diagnostic in diagnostics {
+        let location = match diagnostic.location {
+            Some((line, column)) => format!("{}:{}:{}", path.display(), line, column),
+            None => path.display().to_string(),
+        };
+        let severity = match diagnostic.severity {
+            Severity::Error => diagnostic.severity.to_string().red().bold(),
+            Severity::Warning => diagnostic.severity.to_string().yellow().bold(),
+        };
+        println!("{}: {}: {}", location.blue(), severity, diagnostic.message);
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    if diagnostics.is_empty() {
+        println!(
+            "{}",
+            format!("{}{} is valid", crate::output::icon("✅"), path.display())
+                .green()
+                .bold()
+        );
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_keys_with_locations() {
+        let yaml = "exclude-path:\n  - foo\nprocessing:\n  threads: 2\nbinary-detection:\n  check-null-bytes: true\n  sample-size: 10\n  sample: 3\n";
+        let diagnostics = validate_str(yaml, ConfigFormat::Yaml);
+
+        assert_eq!(
+            diagnostics,
+            vec![
+                Diagnostic::error("unknown key `exclude-path`".to_string(), Some((1, 1))),
+                Diagnostic::error("unknown key `binary-detection.sample`".to_string(), Some((8, 3))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let diagnostics = validate_str("processing:\n  threads: many\n", ConfigFormat::Yaml);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].location.map(|(line, _)| line), Some(2));
+
+        let diagnostics = validate_str("exclude-files = []\nfollow-symlinks = 3\n", ConfigFormat::Toml);
+        assert_eq!(diagnostics[0].location.map(|(line, _)| line), Some(2));
+
+        let diagnostics = validate_str("{\n  \"follow-symlinks\": 3\n}", ConfigFormat::Json);
+        assert_eq!(diagnostics[0].location.map(|(line, _)| line), Some(2));
+    }
+
+    #[test]
+    fn test_invalid_globs_and_conflicts() {
+        let toml = "exclude-files = [\"[abc\", \"*.log\", \"*.log\"]\n\n[processing]\nmax-file-size = 100\nstreaming-threshold = 200\n";
+        let diagnostics = validate_str(toml, ConfigFormat::Toml);
+
+        let severities: Vec<Severity> = diagnostics.iter().map(|d| d.severity).collect();
+        assert_eq!(severities, vec![Severity::Error, Severity::Warning, Severity::Warning]);
+        assert!(diagnostics[0].message.contains("exclude-files"));
+        assert_eq!(diagnostics[0].location, Some((1, 19)));
+        assert!(diagnostics[2].message.contains("streaming-threshold"));
+        assert_eq!(diagnostics[2].location, Some((5, 1)));
+    }
+
+    #[test]
+    fn test_custom_rules() {
+        let yaml = "custom-rules:\n  - name: header\n    command: [./add-header]\n    files: [\"[rs\"]\n  - files: [\"*.md\"]\n";
+        let diagnostics = validate_str(yaml, ConfigFormat::Yaml);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("invalid glob in `custom-rules[0].files`"));
+        assert_eq!(messages[1], "`custom-rules[1]` has no `command`");
+        assert_eq!(diagnostics[1].location, Some((1, 1)));
+        assert_eq!(diagnostics[2].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let yaml = "metrics:\n  statsd: localhost\n  prefix: \"\"\n";
+        let diagnostics = validate_str(yaml, ConfigFormat::Yaml);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            [
+                "`metrics.statsd` 'localhost' is not a host:port address",
+                "`metrics.prefix` is empty"
+            ]
+        );
+        assert_eq!(diagnostics[0].location, Some((2, 3)));
+
+        let yaml = "metrics:\n  statsd: \"[::1]:8125\"\n  prometheus-textfile: /tmp/whitespace.prom\n";
+        assert!(validate_str(yaml, ConfigFormat::Yaml).is_empty());
+    }
+
+    #[test]
+    fn test_extends_must_load() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("whitespace.yml");
+        fs::write(&path, "follow-symlinks: true\nextends: base.yml\n").unwrap();
+        let diagnostics = validate_file(&path).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("base.yml, which"));
+        assert_eq!(diagnostics[0].location, Some((2, 1)));
+
+        fs::write(temp_dir.path().join("base.yml"), "extends: markdown-safe\n").unwrap();
+        assert!(validate_file(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_default_config_is_valid() {
+        let yaml = serde_yaml::to_string(&Config::default()).unwrap();
+        assert!(validate_str(&yaml, ConfigFormat::Yaml).is_empty());
+        assert!(validate_str("", ConfigFormat::Yaml).is_empty());
+        assert!(validate_str(include_str!("../whitespace.yml"), ConfigFormat::Yaml).is_empty());
+    }
+}
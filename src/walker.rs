@@ -1,168 +1,672 @@
 use crate::config::Config;
+use crate::gitattributes::{ATTRIBUTES_FILE_NAME, AttrMatch, AttrValue, GitAttributes};
+use crate::ignore_file::{IGNORE_FILE_NAME, IgnoreStack};
 use crate::ports::fs::FileSystem;
+use crate::processor::SkipReason;
 use eyre::Result;
-use log::{debug, warn};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use walkdir::WalkDir;
+use std::time::Instant;
+use tracing::{debug, warn};
+
+/// Files found by [`FileWalker::walk`], and the paths it passed over.
+#[derive(Debug, Default)]
+pub struct WalkResult {
+    pub files: Vec<PathBuf>,
+    pub skipped: Vec<(PathBuf, SkipReason)>,
+    /// True if a file-count or time limit ended the walk before it was complete
+    pub stopped: bool,
+}
+
+/// The configuration rule that makes the walk pass over a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalkRule {
+    /// A pattern in a `.whitespaceignore` file
+    IgnoreFile { file: PathBuf, pattern: String },
+    /// An `exclude-paths` pattern
+    ExcludePath(String),
+    /// An `exclude-files` pattern
+    ExcludeFile(String),
+    /// A `diff-detection.files` pattern
+    DiffFile(String),
+    /// An `exclude-binary-extensions` pattern
+    BinaryExtension(String),
+    /// Larger than `processing.max-file-size`
+    TooLarge { size: u64, max_file_size: u64 },
+    /// A FIFO, socket or device node
+    Special,
+    /// Mostly holes: `size` bytes long, but taking up only `allocated` on disk
+    Sparse { size: u64, allocated: u64 },
+    /// `-text` (or `binary`), `linguist-generated` or `linguist-vendored` set in a
+    /// `.gitattributes` file
+    GitAttribute {
+        attribute: &'static str,
+        file: PathBuf,
+        pattern: String,
+    },
+    /// A `generated-detection.files` pattern
+    GeneratedFile(String),
+    /// A `generated-detection.vendored-dirs` pattern
+    VendoredDir(String),
+}
+
+impl WalkRule {
+    /// How a file passed over by this rule is reported.
+    pub fn skip_reason(&self) -> SkipReason {
+        match self {
+            Self::IgnoreFile { .. } | Self::ExcludePath(_) | Self::ExcludeFile(_) => SkipReason::Excluded,
+            Self::DiffFile(_) => SkipReason::Diff,
+            Self::BinaryExtension(_) => SkipReason::Binary,
+            Self::TooLarge { .. } => SkipReason::TooLarge,
+            Self::Special => SkipReason::Special,
+            Self::Sparse { .. } => SkipReason::Sparse,
+            Self::GitAttribute { attribute, .. } if *attribute == NOT_TEXT => SkipReason::Binary,
+            Self::GitAttribute { attribute, .. } if *attribute == VENDORED => SkipReason::Vendored,
+            Self::GitAttribute { .. } | Self::GeneratedFile(_) => SkipReason::Generated,
+            Self::VendoredDir(_) => SkipReason::Vendored,
+        }
+    }
+}
+
+impl fmt::Display for WalkRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IgnoreFile { file, pattern } => write!(f, "matches `{}` in {}", pattern, file.display()),
+            Self::ExcludePath(pattern) => write!(f, "matches exclude-paths pattern `{}`", pattern),
+            Self::ExcludeFile(pattern) => write!(f, "matches exclude-files pattern `{}`", pattern),
+            Self::DiffFile(pattern) => write!(f, "matches diff-detection.files pattern `{}`", pattern),
+            Self::BinaryExtension(pattern) => write!(f, "matches exclude-binary-extensions pattern `{}`", pattern),
+            Self::TooLarge { size, max_file_size } => {
+                write!(f, "{} bytes, over max-file-size of {} bytes", size, max_file_size)
+            }
+            Self::Special => f.write_str("a FIFO, socket or device node"),
+            Self::Sparse { size, allocated } => write!(f, "{} bytes, but only {} on disk", size, allocated),
+            Self::GitAttribute {
+                attribute,
+                file,
+                pattern,
+            } => write!(f, "{} set by `{}` in {}", attribute, pattern, file.display()),
+            Self::GeneratedFile(pattern) => write!(f, "matches generated-detection.files pattern `{}`", pattern),
+            Self::VendoredDir(pattern) => write!(f, "matches generated-detection.vendored-dirs pattern `{}`", pattern),
+        }
+    }
+}
 
 pub struct FileWalker<F: FileSystem> {
     config: Arc<Config>,
     fs: Arc<F>,
+    exclude_paths: ExcludePaths,
+    exclude_files: GlobSet,
+    binary_extensions: GlobSet,
+    diff_files: GlobSet,
+    generated_files: GlobSet,
+    vendored_dirs: GlobSet,
+    attributes: GitAttributes,
+    max_files: Option<usize>,
+    deadline: Option<Instant>,
 }
 
 impl<F: FileSystem> FileWalker<F> {
     pub fn new(config: Arc<Config>, fs: Arc<F>) -> Self {
-        Self { config, fs }
+        // Patterns are compiled once here; the walk matches every file against them
+        let exclude_paths = ExcludePaths::new(&config.exclude_paths);
+        let exclude_files = compile_globs(&config.exclude_files);
+        let binary_extensions = compile_globs(&config.exclude_binary_extensions);
+        let diff_files = compile_globs(&config.diff_detection.files);
+        let generated_files = compile_globs(&config.generated_detection.files);
+        let vendored_dirs = compile_globs(&config.generated_detection.vendored_dirs);
+        Self {
+            config,
+            fs,
+            exclude_paths,
+            exclude_files,
+            binary_extensions,
+            diff_files,
+            generated_files,
+            vendored_dirs,
+            attributes: GitAttributes::new(),
+            max_files: None,
+            deadline: None,
+        }
+    }
+
+    /// Forget the `.gitattributes` files read so far, so that the next walk reads them afresh.
+    pub fn clear_attributes(&self) {
+        self.attributes.clear();
+    }
+
+    /// Stop walking once `max_files` files have been collected or `deadline` has passed.
+    pub fn with_limits(mut self, max_files: Option<usize>, deadline: Option<Instant>) -> Self {
+        self.max_files = max_files;
+        self.deadline = deadline;
+        self
+    }
+
+    /// Collect processable files under `root`, descending at most `max_depth` levels
+    /// (1 = only files directly in `root`, None = unlimited).
+    pub fn collect_files(&self, root: &Path, max_depth: Option<usize>) -> Result<Vec<PathBuf>> {
+        Ok(self.walk(root, max_depth)?.files)
     }
 
-    pub fn collect_files(&self, root: &Path, recursive: bool) -> Result<Vec<PathBuf>> {
+    /// Like [`collect_files`](Self::collect_files), but also report what was skipped and why.
+    /// Excluded directories are reported once rather than file by file.
+    pub fn walk(&self, root: &Path, max_depth: Option<usize>) -> Result<WalkResult> {
         debug!("Starting file collection from: {}", root.display());
 
-        let mut files = Vec::new();
-        let walker = if recursive { WalkDir::new(root) } else { WalkDir::new(root).max_depth(1) };
-
-        for entry in walker.into_iter() {
-            match entry {
-                Ok(entry) => {
-                    let path = entry.path();
-
-                    // Skip directories
-                    if self.fs.is_dir(path) {
-                        continue;
-                    }
-
-                    // Skip symlinks
-                    if self.fs.is_symlink(path) {
-                        debug!("Skipping symlink: {}", path.display());
-                        continue;
-                    }
-
-                    if self.should_process_file(path) {
-                        debug!("Adding file for processing: {}", path.display());
-                        files.push(path.to_path_buf());
-                    } else {
-                        debug!("Filtering out file: {}", path.display());
-                    }
+        let mut walk = WalkResult::default();
+
+        // With symlinks followed, the same file or directory can be reached by several
+        // paths; visited (device, inode) pairs catch both loops and duplicates.
+        let follow_symlinks = self.config.follow_symlinks;
+        let mut visited = HashSet::new();
+        let mut ignores = IgnoreStack::new();
+        let detect_generated = self.config.generated_detection.enabled;
+
+        // Depth-first, in sorted order: children are pushed in reverse so the first pops first
+        let mut pending = vec![(root.to_path_buf(), 0)];
+        while let Some((path, depth)) = pending.pop() {
+            let path = path.as_path();
+            if self.max_files.is_some_and(|max_files| walk.files.len() >= max_files)
+                || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                debug!("Walk limit reached with {} files collected", walk.files.len());
+                walk.stopped = true;
+                break;
+            }
+
+            // Prune directories already reached through another link, symlinked ones we don't
+            // follow, and excluded ones; otherwise pick up their own ignore file and descend
+            if self.fs.is_dir(path) {
+                if depth > 0 && !follow_symlinks && self.fs.is_symlink(path) {
+                    debug!("Skipping symlinked directory: {}", path.display());
+                    continue;
+                } else if follow_symlinks && !self.first_visit(path, &mut visited) {
+                    debug!("Skipping already visited directory: {}", path.display());
+                    continue;
+                } else if depth > 0 && ignores.is_ignored(path, true) {
+                    debug!("Directory excluded by .whitespaceignore: {}", path.display());
+                    walk.skipped.push((path.to_path_buf(), SkipReason::Excluded));
+                    continue;
+                } else if depth > 0 && self.is_excluded_path(root, path, true) {
+                    debug!("Directory excluded by exclude-paths pattern: {}", path.display());
+                    walk.skipped.push((path.to_path_buf(), SkipReason::Excluded));
+                    continue;
+                } else if depth > 0 && detect_generated && self.is_vendored_dir(path) {
+                    debug!("Directory vendored: {}", path.display());
+                    walk.skipped.push((path.to_path_buf(), SkipReason::Vendored));
+                    continue;
+                }
+
+                ignores.enter_dir(path, self.fs.as_ref());
+                if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                    continue;
+                }
+                match self.fs.read_dir(path) {
+                    Ok(children) => pending.extend(children.into_iter().rev().map(|child| (child, depth + 1))),
+                    Err(e) => warn!("Error accessing path during walk: {}", e),
+                }
+                continue;
+            }
+
+            if ignores.is_ignored(path, false) {
+                debug!("File excluded by .whitespaceignore: {}", path.display());
+                walk.skipped.push((path.to_path_buf(), SkipReason::Excluded));
+                continue;
+            }
+
+            if follow_symlinks {
+                if !self.first_visit(path, &mut visited) {
+                    debug!("Skipping already visited file: {}", path.display());
+                    continue;
                 }
-                Err(e) => {
-                    warn!("Error accessing path during walk: {}", e);
+            } else if self.fs.is_symlink(path) {
+                debug!("Skipping symlink: {}", path.display());
+                continue;
+            }
+
+            match self.skip_reason(root, path) {
+                Ok(None) => {
+                    debug!("Adding file for processing: {}", path.display());
+                    walk.files.push(path.to_path_buf());
                 }
+                Ok(Some(reason)) => walk.skipped.push((path.to_path_buf(), reason)),
+                Err(e) => warn!("Could not read metadata for {}: {}", path.display(), e),
             }
         }
 
-        debug!("Collected {} files for processing", files.len());
-        Ok(files)
+        debug!(
+            "Collected {} files for processing, skipped {}",
+            walk.files.len(),
+            walk.skipped.len()
+        );
+        Ok(walk)
+    }
+
+    /// Record the file's (device, inode) pair, returning false if it was already seen.
+    /// Files without an identity are always treated as new.
+    fn first_visit(&self, path: &Path, visited: &mut HashSet<(u64, u64)>) -> bool {
+        match self.fs.metadata(path).ok().and_then(|metadata| metadata.file_id) {
+            Some(id) => visited.insert(id),
+            None => true,
+        }
     }
 
-    fn should_process_file(&self, path: &Path) -> bool {
+    /// Why `path` should not be processed, if it shouldn't. Fails if its metadata is unreadable.
+    fn skip_reason(&self, root: &Path, path: &Path) -> Result<Option<SkipReason>> {
         // Check if path matches exclusion patterns
-        if self.is_excluded_path(path) {
+        if self.is_excluded_path(root, path, false) {
             debug!("Path excluded by exclude-paths pattern: {}", path.display());
-            return false;
+            return Ok(Some(SkipReason::Excluded));
         }
 
         // Check if filename matches exclusion patterns
         if self.is_excluded_file(path) {
             debug!("File excluded by exclude-files pattern: {}", path.display());
-            return false;
+            return Ok(Some(SkipReason::Excluded));
+        }
+
+        // Patches are recognized by content too, but the common names need no read
+        if self.config.diff_detection.enabled && path.file_name().is_some_and(|name| self.diff_files.is_match(name)) {
+            debug!("Skipping diff file: {}", path.display());
+            return Ok(Some(SkipReason::Diff));
         }
 
         // Check if file has binary extension
         if self.has_binary_extension(path) {
             debug!("File excluded by binary extension: {}", path.display());
-            return false;
+            return Ok(Some(SkipReason::Binary));
+        }
+
+        let (rule, by_name) = self.attribute_rule(path);
+        let by_name = by_name && self.config.generated_detection.enabled;
+        if let Some(rule) = rule.or_else(|| by_name.then(|| self.generated_file_rule(path)).flatten()) {
+            debug!("Skipping {} file ({}): {}", rule.skip_reason(), rule, path.display());
+            return Ok(Some(rule.skip_reason()));
         }
 
         // Check file size using FileSystem trait
-        match self.fs.metadata(path) {
-            Ok(metadata) => {
-                if metadata.len > self.config.processing.max_file_size {
-                    debug!("File too large ({}): {}", metadata.len, path.display());
-                    return false;
-                }
-            }
-            Err(_) => {
-                warn!("Could not read metadata for: {}", path.display());
-                return false;
-            }
+        let metadata = self.fs.metadata(path)?;
+        if metadata.special {
+            debug!("Skipping special file: {}", path.display());
+            return Ok(Some(SkipReason::Special));
+        }
+        if metadata.len > self.config.processing.max_file_size {
+            debug!("File too large ({}): {}", metadata.len, path.display());
+            return Ok(Some(SkipReason::TooLarge));
+        }
+        if metadata.is_sparse() {
+            debug!("Skipping sparse file: {}", path.display());
+            return Ok(Some(SkipReason::Sparse));
         }
 
-        true
+        Ok(None)
     }
 
-    fn is_excluded_path(&self, path: &Path) -> bool {
-        let path_str = path.to_string_lossy();
+    /// The rule that makes the walk pass over `path` under `root`, or None if the walk keeps it.
+    pub fn rule_for(&self, root: &Path, path: &Path) -> Result<Option<WalkRule>> {
+        Ok(self.checks(root, path)?.pop().and_then(|(_, rule)| rule))
+    }
 
-        for pattern in &self.config.exclude_paths {
-            if let Ok(glob_pattern) = glob::Pattern::new(pattern)
-                && glob_pattern.matches(&path_str)
-            {
-                return true;
-            }
+    /// The checks the walk makes on `path` under `root`, in order, each named by the setting
+    /// it applies and with the rule that matched, if one did. Like the walk, the list stops
+    /// at the first match. Unlike it, patterns are tried one at a time and the
+    /// `.whitespaceignore` files above `path` are read afresh, so the pattern responsible can
+    /// be named; this is for explaining decisions, not for making them.
+    pub fn checks(&self, root: &Path, path: &Path) -> Result<Vec<(&'static str, Option<WalkRule>)>> {
+        let is_dir = self.fs.is_dir(path);
+        let mut checks = Vec::new();
+        let mut check = |name, rule: Option<WalkRule>| {
+            let matched = rule.is_some();
+            checks.push((name, rule));
+            matched
+        };
 
-            // Also check if any parent directory matches the pattern
-            for ancestor in path.ancestors() {
-                let ancestor_str = ancestor.to_string_lossy();
-                if let Ok(glob_pattern) = glob::Pattern::new(pattern)
-                    && glob_pattern.matches(&ancestor_str)
-                {
-                    return true;
-                }
+        let mut ignores = IgnoreStack::new();
+        let dirs: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.starts_with(root))
+            .collect();
+        for dir in dirs.into_iter().rev() {
+            ignores.enter_dir(dir, self.fs.as_ref());
+        }
+        let ignore_rule = ignores
+            .ignore_rule(path, is_dir)
+            .map(|(file, pattern)| WalkRule::IgnoreFile { file, pattern });
+        if check(IGNORE_FILE_NAME, ignore_rule) {
+            return Ok(checks);
+        }
 
-                // Special handling for patterns like ".git/**"
-                if pattern.ends_with("/**") {
-                    let base_pattern = &pattern[..pattern.len() - 3]; // Remove "/**"
-                    if ancestor.file_name().is_some_and(|name| {
-                        glob::Pattern::new(base_pattern)
-                            .is_ok_and(|base_glob| base_glob.matches(&name.to_string_lossy()))
-                    }) {
-                        return true;
-                    }
-                }
+        let excluded_by = |pattern: &&String| {
+            let exclude_paths = ExcludePaths::new(std::slice::from_ref(*pattern));
+            matches_exclude_paths(&exclude_paths, root, path, is_dir)
+        };
+        let exclude_path = self.config.exclude_paths.iter().find(excluded_by);
+        if check("exclude-paths", exclude_path.cloned().map(WalkRule::ExcludePath)) {
+            return Ok(checks);
+        }
+        if is_dir {
+            if self.config.generated_detection.enabled {
+                let vendored_dir = path
+                    .file_name()
+                    .and_then(|name| first_match(&self.config.generated_detection.vendored_dirs, name));
+                check(
+                    "generated-detection.vendored-dirs",
+                    vendored_dir.cloned().map(WalkRule::VendoredDir),
+                );
             }
+            return Ok(checks);
         }
 
-        false
+        let name = path.file_name().unwrap_or_default();
+        let exclude_file = first_match(&self.config.exclude_files, name);
+        if check("exclude-files", exclude_file.cloned().map(WalkRule::ExcludeFile)) {
+            return Ok(checks);
+        }
+        if self.config.diff_detection.enabled {
+            let diff_file = first_match(&self.config.diff_detection.files, name);
+            if check("diff-detection.files", diff_file.cloned().map(WalkRule::DiffFile)) {
+                return Ok(checks);
+            }
+        }
+        let binary_extension = first_match(&self.config.exclude_binary_extensions, name);
+        if check(
+            "exclude-binary-extensions",
+            binary_extension.cloned().map(WalkRule::BinaryExtension),
+        ) {
+            return Ok(checks);
+        }
+
+        let (rule, by_name) = self.attribute_rule(path);
+        if (self.config.gitattributes || self.config.generated_detection.enabled) && check(ATTRIBUTES_FILE_NAME, rule) {
+            return Ok(checks);
+        }
+        if self.config.generated_detection.enabled
+            && by_name
+            && check("generated-detection.files", self.generated_file_rule(path))
+        {
+            return Ok(checks);
+        }
+
+        let metadata = self.fs.metadata(path)?;
+        if check("special-file", metadata.special.then_some(WalkRule::Special)) {
+            return Ok(checks);
+        }
+        let size = metadata.len;
+        let max_file_size = self.config.processing.max_file_size;
+        if check(
+            "processing.max-file-size",
+            (size > max_file_size).then_some(WalkRule::TooLarge { size, max_file_size }),
+        ) {
+            return Ok(checks);
+        }
+        let allocated = metadata.allocated.unwrap_or(size);
+        check(
+            "sparse-file",
+            metadata.is_sparse().then_some(WalkRule::Sparse { size, allocated }),
+        );
+        Ok(checks)
+    }
+
+    fn is_excluded_path(&self, root: &Path, path: &Path, is_dir: bool) -> bool {
+        matches_exclude_paths(&self.exclude_paths, root, path, is_dir)
     }
 
     fn is_excluded_file(&self, path: &Path) -> bool {
-        if let Some(filename) = path.file_name() {
-            let filename_str = filename.to_string_lossy();
-
-            for pattern in &self.config.exclude_files {
-                if let Ok(glob_pattern) = glob::Pattern::new(pattern)
-                    && glob_pattern.matches(&filename_str)
-                {
-                    return true;
+        path.file_name().is_some_and(|name| self.exclude_files.is_match(name))
+    }
+
+    fn has_binary_extension(&self, path: &Path) -> bool {
+        path.file_name()
+            .is_some_and(|name| self.binary_extensions.is_match(name))
+    }
+
+    fn is_vendored_dir(&self, path: &Path) -> bool {
+        path.file_name().is_some_and(|name| self.vendored_dirs.is_match(name))
+    }
+
+    /// The `.gitattributes` line marking `path` as binary, generated or vendored, if one does,
+    /// and whether the file-name patterns still get a say: `-linguist-generated` keeps a file
+    /// they would skip.
+    fn attribute_rule(&self, path: &Path) -> (Option<WalkRule>, bool) {
+        if self.config.gitattributes
+            && let Some(AttrMatch { file, pattern, value }) = self.attributes.get(self.fs.as_ref(), path, "text")
+            && value == AttrValue::Unset
+        {
+            let rule = WalkRule::GitAttribute {
+                attribute: NOT_TEXT,
+                file,
+                pattern,
+            };
+            return (Some(rule), false);
+        }
+        let mut by_name = true;
+        if !self.config.generated_detection.enabled {
+            return (None, by_name);
+        }
+        for attribute in [GENERATED, VENDORED] {
+            match self.attributes.get(self.fs.as_ref(), path, attribute) {
+                Some(AttrMatch { file, pattern, value }) if value.is_true() => {
+                    let rule = WalkRule::GitAttribute {
+                        attribute,
+                        file,
+                        pattern,
+                    };
+                    return (Some(rule), false);
                 }
+                Some(_) if attribute == GENERATED => by_name = false,
+                _ => {}
             }
         }
+        (None, by_name)
+    }
 
-        false
+    /// The `generated-detection.files` pattern `path` matches, if any.
+    fn generated_file_rule(&self, path: &Path) -> Option<WalkRule> {
+        let name = path.file_name()?;
+        if !self.generated_files.is_match(name) {
+            return None;
+        }
+        first_match(&self.config.generated_detection.files, name)
+            .cloned()
+            .map(WalkRule::GeneratedFile)
     }
+}
 
-    fn has_binary_extension(&self, path: &Path) -> bool {
-        if let Some(filename) = path.file_name() {
-            let filename_str = filename.to_string_lossy();
-
-            for pattern in &self.config.exclude_binary_extensions {
-                if let Ok(glob_pattern) = glob::Pattern::new(pattern)
-                    && glob_pattern.matches(&filename_str)
-                {
-                    return true;
+/// How a file that `binary` or `-text` marks as not text shows in a [`WalkRule`].
+const NOT_TEXT: &str = "-text";
+/// Linguist's attribute for generated files.
+const GENERATED: &str = "linguist-generated";
+/// Linguist's attribute for vendored files.
+const VENDORED: &str = "linguist-vendored";
+
+/// Match `exclude-paths` against `path` relative to the walk `root`, so results do not
+/// depend on where the root itself lives or where the tool was invoked from.
+fn matches_exclude_paths(exclude_paths: &ExcludePaths, root: &Path, path: &Path, is_dir: bool) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    // Resolving the absolute path costs a syscall for relative roots; only pay it when needed
+    let absolute = if exclude_paths.has_absolute() {
+        Cow::Owned(std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()))
+    } else {
+        Cow::Borrowed(path)
+    };
+    exclude_paths.is_match(relative, &absolute, is_dir)
+}
+
+/// The first of the filename `patterns` that matches `name` on its own.
+pub(crate) fn first_match<'a>(patterns: &'a [String], name: &OsStr) -> Option<&'a String> {
+    patterns
+        .iter()
+        .find(|pattern| build_glob(pattern).is_ok_and(|glob| glob.compile_matcher().is_match(name)))
+}
+
+/// Parse a path list as given to `--files-from`: one path per line, or NUL-separated when
+/// `null_delimited`. Empty entries are ignored, as is a `\r` ending a line.
+pub fn parse_file_list(list: &[u8], null_delimited: bool) -> Vec<PathBuf> {
+    let separator = if null_delimited { b'\0' } else { b'\n' };
+    list.split(|&byte| byte == separator)
+        .map(|entry| match entry {
+            [rest @ .., b'\r'] if !null_delimited => rest,
+            _ => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(path_from_bytes)
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Drop targets that resolve to the same directory as an earlier one, such as `src` and a
+/// `link-to-src` symlink, so nothing is processed twice. Targets are walked under the name they
+/// were given; ones that can't be resolved are kept so the caller reports them.
+pub fn dedupe_targets<F: FileSystem>(targets: &[PathBuf], fs: &F) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    targets
+        .iter()
+        .filter(|target| match fs.canonicalize(target) {
+            Ok(resolved) if !seen.insert(resolved.clone()) => {
+                warn!(
+                    "Skipping {}: it resolves to {}, which is already a target",
+                    target.display(),
+                    resolved.display()
+                );
+                false
+            }
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// Compiled `exclude-paths` patterns.
+///
+/// Patterns starting with `/` are absolute and matched against the absolute path. All others
+/// are relative and match at any depth below the walk root, so `target/**` excludes both
+/// `target/debug` and `crates/foo/target/debug`. `*` never crosses a `/`. A `dir/**` pattern
+/// also matches `dir` itself so the walk can prune the whole directory.
+struct ExcludePaths {
+    relative: GlobSet,
+    absolute: GlobSet,
+    relative_dirs: GlobSet,
+    absolute_dirs: GlobSet,
+}
+
+impl ExcludePaths {
+    fn new(patterns: &[String]) -> Self {
+        let mut relative = GlobSetBuilder::new();
+        let mut absolute = GlobSetBuilder::new();
+        let mut relative_dirs = GlobSetBuilder::new();
+        let mut absolute_dirs = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let pattern = &*normalize_separators(pattern);
+            let dir_pattern = pattern.strip_suffix("/**");
+            if is_absolute_pattern(pattern) {
+                add_glob(&mut absolute, pattern);
+                if let Some(dir_pattern) = dir_pattern {
+                    add_glob(&mut absolute_dirs, dir_pattern);
+                }
+            } else {
+                add_glob(&mut relative, pattern);
+                add_glob(&mut relative, &format!("**/{}", pattern));
+                if let Some(dir_pattern) = dir_pattern {
+                    add_glob(&mut relative_dirs, dir_pattern);
+                    add_glob(&mut relative_dirs, &format!("**/{}", dir_pattern));
                 }
             }
         }
 
-        false
+        Self {
+            relative: build_glob_set(relative),
+            absolute: build_glob_set(absolute),
+            relative_dirs: build_glob_set(relative_dirs),
+            absolute_dirs: build_glob_set(absolute_dirs),
+        }
+    }
+
+    fn has_absolute(&self) -> bool {
+        !self.absolute.is_empty() || !self.absolute_dirs.is_empty()
     }
+
+    fn is_match(&self, relative: &Path, absolute: &Path, is_dir: bool) -> bool {
+        self.relative.is_match(relative)
+            || self.absolute.is_match(absolute)
+            || (is_dir && (self.relative_dirs.is_match(relative) || self.absolute_dirs.is_match(absolute)))
+    }
+}
+
+/// Windows users write paths with backslashes, but patterns are matched against `/`-separated
+/// paths (and `\` escapes elsewhere), so convert them there.
+fn normalize_separators(pattern: &str) -> Cow<'_, str> {
+    if cfg!(windows) && pattern.contains('\\') {
+        Cow::Owned(pattern.replace('\\', "/"))
+    } else {
+        Cow::Borrowed(pattern)
+    }
+}
+
+/// `/…` patterns, and on Windows `C:/…` too, are matched against absolute paths.
+fn is_absolute_pattern(pattern: &str) -> bool {
+    let has_drive = || {
+        let bytes = pattern.as_bytes();
+        bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && bytes[2] == b'/'
+    };
+    pattern.starts_with('/') || (cfg!(windows) && has_drive())
+}
+
+/// Build a glob with the matching rules used throughout: `*` doesn't cross `/`, and matching
+/// ignores case on Windows, whose filesystems do.
+pub(crate) fn build_glob(pattern: &str) -> Result<Glob, globset::Error> {
+    GlobBuilder::new(&normalize_separators(pattern))
+        .literal_separator(true)
+        .case_insensitive(cfg!(windows))
+        .build()
+}
+
+fn add_glob(builder: &mut GlobSetBuilder, pattern: &str) {
+    match build_glob(pattern) {
+        Ok(glob) => {
+            builder.add(glob);
+        }
+        Err(e) => warn!("Ignoring invalid glob pattern '{}': {}", pattern, e),
+    }
+}
+
+/// Compile filename patterns such as `exclude-files` into a single set.
+pub(crate) fn compile_globs(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        add_glob(&mut builder, pattern);
+    }
+    build_glob_set(builder)
+}
+
+fn build_glob_set(builder: GlobSetBuilder) -> GlobSet {
+    builder.build().unwrap_or_else(|e| {
+        warn!("Failed to compile glob patterns: {}", e);
+        GlobSet::empty()
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ports::fs::RealFs;
+    use crate::ports::fs::{MemFs, RealFs};
+    use crate::processor::WhitespaceProcessor;
     use std::fs;
     use tempfile::TempDir;
 
@@ -181,10 +685,10 @@ mod tests {
         fs::write(root.join("subdir").join("nested.rs"), "content").unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let walker = FileWalker::new(config, real_fs);
 
-        let files = walker.collect_files(root, true).unwrap();
+        let files = walker.collect_files(root, None).unwrap();
         assert_eq!(files.len(), 2);
 
         let filenames: Vec<String> = files
@@ -207,16 +711,42 @@ mod tests {
         fs::write(root.join("subdir").join("nested.rs"), "content").unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let walker = FileWalker::new(config, real_fs);
 
-        let files = walker.collect_files(root, false).unwrap();
+        let files = walker.collect_files(root, Some(1)).unwrap();
         assert_eq!(files.len(), 1);
 
         let filename = files[0].file_name().unwrap().to_string_lossy();
         assert_eq!(filename, "test.txt");
     }
 
+    #[test]
+    fn test_collect_files_max_depth() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::write(root.join("top.txt"), "content").unwrap();
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        fs::write(root.join("a").join("mid.txt"), "content").unwrap();
+        fs::write(root.join("a").join("b").join("deep.txt"), "content").unwrap();
+
+        let config = create_test_config();
+        let real_fs = Arc::new(RealFs::default());
+        let walker = FileWalker::new(config, real_fs);
+
+        let mut filenames: Vec<String> = walker
+            .collect_files(root, Some(2))
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        filenames.sort();
+
+        assert_eq!(filenames, vec!["mid.txt", "top.txt"]);
+        assert_eq!(walker.collect_files(root, None).unwrap().len(), 3);
+    }
+
     #[test]
     fn test_binary_extension_filtering() {
         let temp_dir = TempDir::new().unwrap();
@@ -228,10 +758,10 @@ mod tests {
         fs::write(root.join("library.so"), "binary").unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let walker = FileWalker::new(config, real_fs);
 
-        let files = walker.collect_files(root, false).unwrap();
+        let files = walker.collect_files(root, Some(1)).unwrap();
         assert_eq!(files.len(), 1);
 
         let filename = files[0].file_name().unwrap().to_string_lossy();
@@ -249,13 +779,455 @@ mod tests {
         fs::write(root.join(".git").join("config"), "git config").unwrap();
 
         let config = create_test_config();
-        let real_fs = Arc::new(RealFs);
+        let real_fs = Arc::new(RealFs::default());
         let walker = FileWalker::new(config, real_fs);
 
-        let files = walker.collect_files(root, true).unwrap();
+        let files = walker.collect_files(root, None).unwrap();
         assert_eq!(files.len(), 1);
 
         let filename = files[0].file_name().unwrap().to_string_lossy();
         assert_eq!(filename, "test.txt");
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_skipped_by_default() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real").join("file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("linked_dir")).unwrap();
+        std::os::unix::fs::symlink(root.join("real").join("file.txt"), root.join("linked_file.txt")).unwrap();
+
+        let walker = FileWalker::new(create_test_config(), Arc::new(RealFs::default()));
+        let files = walker.collect_files(root, None).unwrap();
+
+        assert_eq!(files, vec![root.join("real").join("file.txt")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks_dedupes_and_survives_cycles() {
+        let temp_dir = TempDir::new().unwrap();
+        let outside = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real").join("file.txt"), "content").unwrap();
+        fs::write(outside.path().join("external.txt"), "content").unwrap();
+        // Two routes to the same directory, a file link, a cycle, and a link out of the tree
+        std::os::unix::fs::symlink(root.join("real"), root.join("alias")).unwrap();
+        std::os::unix::fs::symlink(root.join("real").join("file.txt"), root.join("file_link.txt")).unwrap();
+        std::os::unix::fs::symlink(root, root.join("real").join("loop")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("external")).unwrap();
+
+        let config = Config {
+            follow_symlinks: true,
+            ..Config::default()
+        };
+        let walker = FileWalker::new(Arc::new(config), Arc::new(RealFs::default()));
+        let files = walker.collect_files(root, None).unwrap();
+
+        // file.txt is reachable as real/file.txt, alias/file.txt and file_link.txt; only one survives
+        let filenames: Vec<String> = files
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert!(filenames.contains(&"external.txt".to_string()));
+    }
+
+    #[test]
+    fn test_parse_file_list() {
+        assert_eq!(
+            parse_file_list(b"src/a.rs\r\n\nmy file.txt\n", false),
+            vec![PathBuf::from("src/a.rs"), PathBuf::from("my file.txt")]
+        );
+        assert_eq!(
+            parse_file_list(b"line\nbreak.txt\0b.txt\0", true),
+            vec![PathBuf::from("line\nbreak.txt"), PathBuf::from("b.txt")]
+        );
+        assert!(parse_file_list(b"", false).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_targets_are_deduped() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::create_dir(root.join("src")).unwrap();
+        std::os::unix::fs::symlink(root.join("src"), root.join("link-to-src")).unwrap();
+        let targets = vec![
+            root.join("link-to-src"),
+            root.join("src"),
+            root.join(".").join("src"),
+            root.join("missing"),
+        ];
+
+        // The first name given wins; unresolvable targets are left for the caller to report
+        assert_eq!(
+            dedupe_targets(&targets, &RealFs::default()),
+            vec![root.join("link-to-src"), root.join("missing")]
+        );
+    }
+
+    #[test]
+    fn test_whitespaceignore_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::write(root.join(".whitespaceignore"), "*.snap\nfixtures/\n").unwrap();
+        fs::write(root.join("main.rs"), "content").unwrap();
+        fs::write(root.join("output.snap"), "content").unwrap();
+        fs::create_dir_all(root.join("fixtures")).unwrap();
+        fs::write(root.join("fixtures").join("data.txt"), "content").unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join(".whitespaceignore"), "!keep.snap\n").unwrap();
+        fs::write(root.join("sub").join("keep.snap"), "content").unwrap();
+        fs::write(root.join("sub").join("drop.snap"), "content").unwrap();
+
+        let walker = FileWalker::new(create_test_config(), Arc::new(RealFs::default()));
+        let mut files: Vec<PathBuf> = walker
+            .collect_files(root, None)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![
+                PathBuf::from(".whitespaceignore"),
+                PathBuf::from("main.rs"),
+                PathBuf::from("sub/.whitespaceignore"),
+                PathBuf::from("sub/keep.snap"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_exclude_paths_relative_to_nested_root() {
+        let temp_dir = TempDir::new().unwrap();
+        // The root itself sits below directories that match default exclude patterns
+        let root = temp_dir.path().join("build").join("target").join("project");
+
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.rs"), "content").unwrap();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+        fs::write(root.join("target").join("debug").join("out.rs"), "content").unwrap();
+        fs::create_dir_all(root.join("crates").join("a").join("target")).unwrap();
+        fs::write(root.join("crates").join("a").join("target").join("gen.rs"), "content").unwrap();
+        fs::write(root.join("crates").join("a").join("lib.rs"), "content").unwrap();
+
+        let walker = FileWalker::new(create_test_config(), Arc::new(RealFs::default()));
+        let mut files: Vec<PathBuf> = walker
+            .collect_files(&root, None)
+            .unwrap()
+            .into_iter()
+            .map(|p| p.strip_prefix(&root).unwrap().to_path_buf())
+            .collect();
+        files.sort();
+
+        assert_eq!(
+            files,
+            vec![PathBuf::from("crates/a/lib.rs"), PathBuf::from("src/main.rs")]
+        );
+    }
+
+    #[test]
+    fn test_exclude_paths_absolute_pattern() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::create_dir_all(root.join("generated")).unwrap();
+        fs::write(root.join("generated").join("api.rs"), "content").unwrap();
+        fs::create_dir_all(root.join("src").join("generated")).unwrap();
+        fs::write(root.join("src").join("generated").join("keep.rs"), "content").unwrap();
+
+        let config = Config {
+            exclude_paths: vec![format!("{}/generated/**", root.display())],
+            ..Config::default()
+        };
+        let walker = FileWalker::new(Arc::new(config), Arc::new(RealFs::default()));
+        let files = walker.collect_files(root, None).unwrap();
+
+        assert_eq!(files, vec![root.join("src").join("generated").join("keep.rs")]);
+    }
+
+    #[test]
+    fn test_exclude_paths_star_does_not_cross_separator() {
+        let exclude = ExcludePaths::new(&["docs/*.md".to_string(), "*.tmp/**".to_string()]);
+        let abs = Path::new("/unused");
+
+        assert!(exclude.is_match(Path::new("docs/a.md"), abs, false));
+        assert!(exclude.is_match(Path::new("sub/docs/a.md"), abs, false));
+        assert!(!exclude.is_match(Path::new("docs/nested/a.md"), abs, false));
+        assert!(exclude.is_match(Path::new("x/cache.tmp"), abs, true));
+        assert!(!exclude.is_match(Path::new("x/cache.tmp"), abs, false));
+        assert!(!exclude.is_match(Path::new("a/b.tmp.d/c"), abs, false));
+    }
+
+    #[test]
+    fn test_windows_style_patterns() {
+        let exclude = ExcludePaths::new(&["Build\\Out\\**".to_string(), "C:/Temp/**".to_string()]);
+        let abs = Path::new("C:/Temp/x.rs");
+
+        // Backslash separators and case-insensitive matching only apply on Windows
+        assert_eq!(
+            exclude.is_match(Path::new("build/out/a.rs"), Path::new("/unused"), false),
+            cfg!(windows)
+        );
+        assert_eq!(exclude.is_match(Path::new("x.rs"), abs, false), cfg!(windows));
+        assert_eq!(is_absolute_pattern("D:/src/**"), cfg!(windows));
+        assert!(is_absolute_pattern("/src/**"));
+        assert_eq!(
+            build_glob("*.RS").unwrap().compile_matcher().is_match("main.rs"),
+            cfg!(windows)
+        );
+    }
+
+    #[test]
+    fn test_filename_patterns_compiled_once() {
+        let walker = FileWalker::new(create_test_config(), Arc::new(RealFs::default()));
+
+        assert!(walker.is_excluded_file(Path::new("web/app.min.js")));
+        assert!(walker.is_excluded_file(Path::new("vendor.bundle.css")));
+        assert!(!walker.is_excluded_file(Path::new("app.js")));
+        assert!(walker.has_binary_extension(Path::new("assets/logo.png")));
+        assert!(!walker.has_binary_extension(Path::new("src/png.rs")));
+    }
+
+    #[test]
+    fn test_walk_reports_skip_reasons() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        fs::write(root.join("main.rs"), "content").unwrap();
+        fs::write(root.join("app.min.js"), "content").unwrap();
+        fs::write(root.join("logo.png"), "binary").unwrap();
+        fs::write(root.join("fix.patch"), "content").unwrap();
+        fs::write(root.join("huge.txt"), "x".repeat(64)).unwrap();
+        fs::create_dir_all(root.join("target").join("debug")).unwrap();
+        fs::write(root.join("target").join("debug").join("out.rs"), "content").unwrap();
+
+        let config = Config {
+            processing: crate::config::ProcessingSettings {
+                max_file_size: 32,
+                ..Default::default()
+            },
+            ..Config::default()
+        };
+        let walker = FileWalker::new(Arc::new(config), Arc::new(RealFs::default()));
+        let mut walk = walker.walk(root, None).unwrap();
+        walk.skipped.sort();
+
+        assert_eq!(walk.files, vec![root.join("main.rs")]);
+        assert_eq!(
+            walk.skipped,
+            vec![
+                (root.join("app.min.js"), SkipReason::Excluded),
+                (root.join("fix.patch"), SkipReason::Diff),
+                (root.join("huge.txt"), SkipReason::TooLarge),
+                (root.join("logo.png"), SkipReason::Binary),
+                (root.join("target"), SkipReason::Excluded),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_skips_special_and_sparse_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::write(root.join("main.rs"), "content").unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(root.join("daemon.sock")).unwrap();
+        let sparse = fs::File::create(root.join("disk.img")).unwrap();
+        sparse.set_len(64 * 1024 * 1024).unwrap();
+
+        let walker = FileWalker::new(create_test_config(), Arc::new(RealFs::default()));
+        let walk = walker.walk(root, None).unwrap();
+        assert_eq!(walk.files, vec![root.join("main.rs")]);
+        assert!(walk.skipped.contains(&(root.join("daemon.sock"), SkipReason::Special)));
+        // Some filesystems don't do holes, and give the file all its space up front
+        if RealFs::default().metadata(&root.join("disk.img")).unwrap().is_sparse() {
+            assert!(walk.skipped.contains(&(root.join("disk.img"), SkipReason::Sparse)));
+        }
+
+        // Named directly, as in a `--files-from` list, a special file is never read
+        let processor = WhitespaceProcessor::new(create_test_config(), Arc::new(RealFs::default()));
+        let result = processor.process_file(&root.join("daemon.sock"), true).unwrap();
+        assert_eq!(result.skip_reason(), Some(SkipReason::Special));
+    }
+
+    #[test]
+    fn test_walk_skips_generated_and_vendored() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_dir("/repo/.git")
+                .with_file(
+                    "/repo/.gitattributes",
+                    b"keep.pb.go -linguist-generated\ngen/** linguist-generated\nlib/** linguist-vendored=true\n*.fixture -text\n",
+                )
+                .with_file("/repo/main.rs", b"content")
+                .with_file("/repo/crlf.fixture", b"content")
+                .with_file("/repo/api.pb.go", b"content")
+                .with_file("/repo/keep.pb.go", b"content")
+                .with_file("/repo/gen/schema.rs", b"content")
+                .with_file("/repo/lib/dep.rs", b"content")
+                .with_file("/repo/third_party/zlib/zlib.c", b"content"),
+        );
+        let walker = FileWalker::new(Arc::new(Config::default()), Arc::clone(&fs));
+        let mut walk = walker.walk(Path::new("/repo"), None).unwrap();
+        walk.skipped.sort();
+
+        assert_eq!(
+            walk.files,
+            ["/repo/.gitattributes", "/repo/keep.pb.go", "/repo/main.rs"].map(PathBuf::from)
+        );
+        assert_eq!(
+            walk.skipped,
+            [
+                ("/repo/.git", SkipReason::Excluded),
+                ("/repo/api.pb.go", SkipReason::Generated),
+                ("/repo/crlf.fixture", SkipReason::Binary),
+                ("/repo/gen/schema.rs", SkipReason::Generated),
+                ("/repo/lib/dep.rs", SkipReason::Vendored),
+                ("/repo/third_party", SkipReason::Vendored),
+            ]
+            .map(|(path, reason)| (PathBuf::from(path), reason))
+        );
+        assert_eq!(
+            walker
+                .rule_for(Path::new("/repo"), Path::new("/repo/lib/dep.rs"))
+                .unwrap(),
+            Some(WalkRule::GitAttribute {
+                attribute: "linguist-vendored",
+                file: PathBuf::from("/repo/.gitattributes"),
+                pattern: "lib/**".to_string()
+            })
+        );
+
+        let mut config = Config::default();
+        config.generated_detection.enabled = false;
+        let walker = FileWalker::new(Arc::new(config.clone()), Arc::clone(&fs));
+        assert_eq!(walker.walk(Path::new("/repo"), None).unwrap().files.len(), 7);
+        config.gitattributes = false;
+        let walker = FileWalker::new(Arc::new(config), fs);
+        assert_eq!(walker.walk(Path::new("/repo"), None).unwrap().files.len(), 8);
+    }
+
+    #[test]
+    fn test_walk_memfs_tree() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/.whitespaceignore", b"*.snap\n")
+                .with_file("/repo/main.rs", b"content")
+                .with_file("/repo/out.snap", b"content")
+                .with_file("/repo/app.min.js", b"content")
+                .with_file("/repo/src/lib.rs", b"content")
+                .with_file("/repo/src/deep/mod.rs", b"content")
+                .with_file("/repo/node_modules/pkg/index.js", b"content")
+                .with_dir("/repo/empty"),
+        );
+        let walker = FileWalker::new(create_test_config(), fs);
+        let root = Path::new("/repo");
+
+        let walk = walker.walk(root, None).unwrap();
+        assert_eq!(
+            walk.files,
+            vec![
+                PathBuf::from("/repo/.whitespaceignore"),
+                PathBuf::from("/repo/main.rs"),
+                PathBuf::from("/repo/src/deep/mod.rs"),
+                PathBuf::from("/repo/src/lib.rs"),
+            ]
+        );
+        assert_eq!(
+            walk.skipped,
+            vec![
+                (PathBuf::from("/repo/app.min.js"), SkipReason::Excluded),
+                (PathBuf::from("/repo/node_modules"), SkipReason::Excluded),
+                (PathBuf::from("/repo/out.snap"), SkipReason::Excluded),
+            ]
+        );
+
+        let files = walker.collect_files(root, Some(2)).unwrap();
+        assert!(files.contains(&PathBuf::from("/repo/src/lib.rs")));
+        assert!(!files.contains(&PathBuf::from("/repo/src/deep/mod.rs")));
+    }
+
+    #[test]
+    fn test_walk_limits() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/a.txt", b"content")
+                .with_file("/repo/b.txt", b"content")
+                .with_file("/repo/c.txt", b"content"),
+        );
+        let root = Path::new("/repo");
+
+        let walker = FileWalker::new(create_test_config(), Arc::clone(&fs)).with_limits(Some(2), None);
+        let walk = walker.walk(root, None).unwrap();
+        assert_eq!(walk.files.len(), 2);
+        assert!(walk.stopped);
+
+        let walker = FileWalker::new(create_test_config(), Arc::clone(&fs)).with_limits(Some(3), None);
+        assert!(!walker.walk(root, None).unwrap().stopped);
+
+        let walker = FileWalker::new(create_test_config(), fs).with_limits(None, Some(Instant::now()));
+        let walk = walker.walk(root, None).unwrap();
+        assert!(walk.files.is_empty());
+        assert!(walk.stopped);
+    }
+
+    #[test]
+    fn test_rule_for() {
+        let fs = Arc::new(
+            MemFs::new()
+                .with_file("/repo/.whitespaceignore", b"*.snap\n")
+                .with_file("/repo/src/out.snap", b"content")
+                .with_file("/repo/main.rs", b"content")
+                .with_file("/repo/app.min.js", b"content")
+                .with_file("/repo/fix.patch", b"content")
+                .with_file("/repo/logo.png", b"content")
+                .with_file("/repo/big.txt", &[b'x'; 64])
+                .with_file("/repo/node_modules/pkg/index.js", b"content"),
+        );
+        let mut config = Config::default();
+        config.processing.max_file_size = 32;
+        let walker = FileWalker::new(Arc::new(config), fs);
+        let rule = |path: &str| walker.rule_for(Path::new("/repo"), Path::new(path)).unwrap();
+
+        assert_eq!(rule("/repo/main.rs"), None);
+        assert_eq!(
+            rule("/repo/src/out.snap"),
+            Some(WalkRule::IgnoreFile {
+                file: PathBuf::from("/repo/.whitespaceignore"),
+                pattern: "*.snap".to_string()
+            })
+        );
+        assert_eq!(
+            rule("/repo/node_modules"),
+            Some(WalkRule::ExcludePath("node_modules/**".to_string()))
+        );
+        assert_eq!(
+            rule("/repo/app.min.js"),
+            Some(WalkRule::ExcludeFile("*.min.js".to_string()))
+        );
+        assert_eq!(rule("/repo/fix.patch"), Some(WalkRule::DiffFile("*.patch".to_string())));
+        assert_eq!(
+            rule("/repo/logo.png"),
+            Some(WalkRule::BinaryExtension("*.png".to_string()))
+        );
+        assert_eq!(
+            rule("/repo/big.txt"),
+            Some(WalkRule::TooLarge {
+                size: 64,
+                max_file_size: 32
+            })
+        );
+    }
 }